@@ -0,0 +1,500 @@
+//! A BackupEngine keeps incremental backups of a database in a directory. Table files are
+//! immutable, so they are shared by all backups containing them and copied only once; the manifest
+//! and log files are copied for every backup. The backup directory contains:
+//!
+//! - `shared/`: table files, named after their number and size.
+//! - `private/<id>/`: the manifest and log files of backup `<id>`.
+//! - `meta/<id>`: the description of backup `<id>`, i.e. its creation time and files.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use db_impl::DB;
+use env::{copy_file, path_to_string, Env, WritableFile};
+use error::{err, Result, StatusCode};
+use options::Options;
+use table_reader::Table;
+use types::{parse_file_name, FileType};
+use version_set::set_current_file;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const SHARED_DIR: &str = "shared";
+const PRIVATE_DIR: &str = "private";
+const META_DIR: &str = "meta";
+
+pub type BackupID = u32;
+
+/// BackupInfo describes a backup.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupInfo {
+    pub id: BackupID,
+    /// The time the backup was created at, in microseconds since the epoch.
+    pub timestamp: u64,
+    /// The total size of the files in the backup.
+    pub size: usize,
+    pub num_files: usize,
+}
+
+/// BackupFile is a file of a backup.
+struct BackupFile {
+    // The location of the file, relative to the backup directory.
+    path: String,
+    // The name of the file in the database directory.
+    name: String,
+    size: usize,
+}
+
+struct Backup {
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+impl Backup {
+    /// encode returns the contents of a backup's meta file: the timestamp on the first line,
+    /// followed by one line of `size path name` per file.
+    fn encode(&self) -> String {
+        let mut s = format!("{}\n", self.timestamp);
+        for f in &self.files {
+            s.push_str(&format!("{} {} {}\n", f.size, f.path, f.name));
+        }
+        s
+    }
+
+    fn decode(s: &str) -> Result<Backup> {
+        let corrupted = || err(StatusCode::Corruption, "invalid backup meta file");
+        let mut lines = s.lines();
+        let timestamp = match lines.next().map(|l| l.parse()) {
+            Some(Ok(t)) => t,
+            _ => return corrupted(),
+        };
+        let mut files = vec![];
+        for line in lines {
+            let parts: Vec<&str> = line.split(' ').collect();
+            if parts.len() != 3 {
+                return corrupted();
+            }
+            let size = match parts[0].parse() {
+                Ok(size) => size,
+                Err(_) => return corrupted(),
+            };
+            files.push(BackupFile {
+                path: parts[1].to_string(),
+                name: parts[2].to_string(),
+                size: size,
+            });
+        }
+        Ok(Backup {
+            timestamp: timestamp,
+            files: files,
+        })
+    }
+
+    fn info(&self, id: BackupID) -> BackupInfo {
+        BackupInfo {
+            id: id,
+            timestamp: self.timestamp,
+            size: self.files.iter().map(|f| f.size).sum(),
+            num_files: self.files.len(),
+        }
+    }
+}
+
+/// BackupEngine creates, verifies and restores backups stored in a directory.
+pub struct BackupEngine {
+    dir: PathBuf,
+    opt: Options,
+    backups: BTreeMap<BackupID, Backup>,
+}
+
+impl BackupEngine {
+    /// Opens the backup directory `dir`, creating it if necessary. The directory is accessed
+    /// through the `Env` of `opt`; the other options are used for reading table files when
+    /// verifying backups.
+    pub fn open<P: AsRef<Path>>(dir: P, opt: Options) -> Result<BackupEngine> {
+        let dir = dir.as_ref().to_owned();
+        for sub in &[SHARED_DIR, PRIVATE_DIR, META_DIR] {
+            opt.env.mkdir(&dir.join(sub))?;
+        }
+
+        let mut backups = BTreeMap::new();
+        for name in opt.env.children(&dir.join(META_DIR))? {
+            // Meta files that haven't been completely written are ignored.
+            let id = match path_to_string(&name).parse() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            let mut contents = String::new();
+            opt.env
+                .open_sequential_file(&dir.join(META_DIR).join(&name))?
+                .read_to_string(&mut contents)?;
+            backups.insert(id, Backup::decode(&contents)?);
+        }
+
+        Ok(BackupEngine {
+            dir: dir,
+            opt: opt,
+            backups: backups,
+        })
+    }
+
+    fn env(&self) -> &dyn Env {
+        self.opt.env.as_ref().as_ref()
+    }
+
+    fn meta_file_name(&self, id: BackupID) -> PathBuf {
+        self.dir.join(META_DIR).join(id.to_string())
+    }
+
+    /// create_backup backs up the current state of an open database, including entries that
+    /// haven't been written to table files yet. Table files already contained in another backup
+    /// are not copied again. It returns the new backup's id.
+    pub fn create_backup(&mut self, db: &DB) -> Result<BackupID> {
+        let id = self.backups.keys().next_back().map_or(1, |id| id + 1);
+        let private = Path::new(PRIVATE_DIR).join(id.to_string());
+        let timestamp = self.env().micros();
+
+        let files = db.with_live_files(|env, files| {
+            self.env().mkdir(&self.dir.join(&private))?;
+            let mut backup_files = vec![];
+            for f in files {
+                let name = path_to_string(Path::new(f.path.file_name().unwrap()));
+                let path = if f.typ == FileType::Table {
+                    Path::new(SHARED_DIR).join(format!("{:06}_{}.ldb", f.num, f.size))
+                } else {
+                    private.join(&name)
+                };
+                let dst = self.dir.join(&path);
+                if f.typ != FileType::Table || !self.env().exists(&dst)? {
+                    // Copy to a temporary file first, so that incomplete table files are never
+                    // shared.
+                    let tmp = dst.with_extension("tmp");
+                    if let Err(e) = copy_file(env, &f.path, self.env(), &tmp, f.size) {
+                        let _ = self.env().delete(&tmp);
+                        return Err(e);
+                    }
+                    self.env().rename(&tmp, &dst)?;
+                }
+                backup_files.push(BackupFile {
+                    path: path_to_string(&path),
+                    name: name,
+                    size: f.size,
+                });
+            }
+            Ok(backup_files)
+        });
+        let backup = match files {
+            Ok(files) => Backup {
+                timestamp: timestamp,
+                files: files,
+            },
+            Err(e) => {
+                self.delete_private_files(id);
+                return Err(e);
+            }
+        };
+
+        // The backup exists once its meta file has been written.
+        let meta = self.meta_file_name(id);
+        let tmp = meta.with_extension("tmp");
+        {
+            let mut f = self.env().open_writable_file(&tmp)?;
+            f.write_all(backup.encode().as_bytes())?;
+            f.sync()?;
+        }
+        self.env().rename(&tmp, &meta)?;
+        self.backups.insert(id, backup);
+        Ok(id)
+    }
+
+    /// list_backups returns information about all backups, from oldest to newest.
+    pub fn list_backups(&self) -> Vec<BackupInfo> {
+        self.backups.iter().map(|(id, b)| b.info(*id)).collect()
+    }
+
+    /// purge_old_backups deletes all but the `keep` newest backups, and the table files that
+    /// aren't part of any remaining backup.
+    pub fn purge_old_backups(&mut self, keep: usize) -> Result<()> {
+        while self.backups.len() > keep {
+            let id = *self.backups.keys().next().unwrap();
+            self.env().delete(&self.meta_file_name(id))?;
+            self.delete_private_files(id);
+            self.backups.remove(&id);
+        }
+
+        let shared: HashSet<&str> = self
+            .backups
+            .values()
+            .flat_map(|b| b.files.iter().map(|f| f.path.as_str()))
+            .collect();
+        let shared_dir = self.dir.join(SHARED_DIR);
+        for name in self.env().children(&shared_dir)? {
+            let path = Path::new(SHARED_DIR).join(&name);
+            if !shared.contains(path_to_string(&path).as_str()) {
+                self.env().delete(&shared_dir.join(&name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// delete_private_files deletes the files that belong only to the backup `id`.
+    fn delete_private_files(&self, id: BackupID) {
+        let private = self.dir.join(PRIVATE_DIR).join(id.to_string());
+        if let Ok(children) = self.env().children(&private) {
+            for name in children {
+                let _ = self.env().delete(&private.join(&name));
+            }
+        }
+        let _ = self.env().rmdir(&private);
+    }
+
+    fn backup(&self, id: BackupID) -> Result<&Backup> {
+        match self.backups.get(&id) {
+            Some(b) => Ok(b),
+            None => err(
+                StatusCode::NotFound,
+                &format!("backup {} doesn't exist", id),
+            ),
+        }
+    }
+
+    /// verify_backup checks that all files of a backup exist with the expected size, and reads
+    /// every table file, verifying the checksums of all blocks.
+    pub fn verify_backup(&self, id: BackupID) -> Result<()> {
+        for f in &self.backup(id)?.files {
+            let path = self.dir.join(&f.path);
+            let size = self.env().size_of(&path)?;
+            if size != f.size {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("{} has size {}, expected {}", f.path, size, f.size),
+                );
+            }
+            if let Ok((_, FileType::Table)) = parse_file_name(&f.name) {
+                let file = Arc::new(self.env().open_random_access_file(&path)?);
+                Table::new(self.opt.clone(), file, size)
+                    .and_then(|t| t.verify())
+                    .map_err(|mut e| {
+                        e.err = format!("{} ({})", e.err, f.path);
+                        e
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// restore restores a backup into the directory `dest`, which must be empty or not exist
+    /// yet. The restored database can be opened with `DB::open()`.
+    pub fn restore<P: AsRef<Path>>(&self, id: BackupID, dest: P) -> Result<()> {
+        let dest = dest.as_ref();
+        let backup = self.backup(id)?;
+        if self
+            .env()
+            .children(dest)
+            .map(|c| !c.is_empty())
+            .unwrap_or(false)
+        {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("restore directory {} is not empty", dest.display()),
+            );
+        }
+
+        let restore = || -> Result<()> {
+            self.env().mkdir(dest)?;
+            let mut manifest_num = None;
+            for f in &backup.files {
+                if let Ok((num, FileType::Descriptor)) = parse_file_name(&f.name) {
+                    manifest_num = Some(num);
+                }
+                copy_file(
+                    self.env(),
+                    &self.dir.join(&f.path),
+                    self.env(),
+                    &dest.join(&f.name),
+                    f.size,
+                )?;
+            }
+            // copy_file() synced the files themselves; make their directory entries durable
+            // before CURRENT points to them.
+            self.env().sync_dir(dest)?;
+            match manifest_num {
+                Some(num) => set_current_file(&self.opt.env, dest, num),
+                None => err(StatusCode::Corruption, "backup doesn't contain a manifest"),
+            }
+        };
+        let result = restore();
+        if result.is_err() {
+            // dest was empty before, so everything in it now was written by this restore, including
+            // a partial copy or a temporary CURRENT file.
+            for f in self.env().children(dest).unwrap_or_default() {
+                let _ = self.env().delete(&dest.join(f));
+            }
+            let _ = self.env().rmdir(dest);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fault_injection_env::FaultInjectionEnv;
+    use mem_env::MemEnv;
+    use options;
+    use test_util::{corrupt_file, SyncCountingEnv};
+
+    fn shared_files(be: &BackupEngine) -> Vec<PathBuf> {
+        let mut files = be.env().children(&be.dir.join(SHARED_DIR)).unwrap();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_backup_engine_create_restore() {
+        let opt = options::for_test();
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.put(b"abd", b"efg").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+        // Only in the log.
+        db.put(b"abe", b"fgh").unwrap();
+
+        let mut be = BackupEngine::open("backup", opt.clone()).unwrap();
+        assert_eq!(1, be.create_backup(&db).unwrap());
+        assert_eq!(1, shared_files(&be).len());
+
+        // A new table file is added, while the existing one is shared.
+        db.delete(b"abc").unwrap();
+        db.put(b"xyz", b"123").unwrap();
+        db.compact_range(b"x", b"y").unwrap();
+        assert_eq!(2, be.create_backup(&db).unwrap());
+        let shared = shared_files(&be);
+        assert_eq!(2, shared.len());
+
+        let backups = be.list_backups();
+        assert_eq!(vec![1, 2], backups.iter().map(|b| b.id).collect::<Vec<_>>());
+        assert!(backups[0].timestamp <= backups[1].timestamp);
+        be.verify_backup(1).unwrap();
+        be.verify_backup(2).unwrap();
+
+        be.restore(1, "restored1").unwrap();
+        {
+            let db = DB::open("restored1", opt.clone()).unwrap();
+            assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+            assert_eq!(Some(b"fgh".to_vec()), db.get(b"abe"));
+            assert_eq!(None, db.get(b"xyz"));
+        }
+        be.restore(2, "restored2").unwrap();
+        {
+            let db = DB::open("restored2", opt.clone()).unwrap();
+            assert_eq!(None, db.get(b"abc"));
+            assert_eq!(Some(b"efg".to_vec()), db.get(b"abd"));
+            assert_eq!(Some(b"123".to_vec()), db.get(b"xyz"));
+        }
+        assert_eq!(
+            StatusCode::AlreadyExists,
+            be.restore(2, "restored2").unwrap_err().code
+        );
+
+        // Backups are found when the engine is opened again.
+        let mut be = BackupEngine::open("backup", opt.clone()).unwrap();
+        assert_eq!(backups, be.list_backups());
+
+        // Only the table file that isn't part of backup 2 anymore is deleted.
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(3, be.create_backup(&db).unwrap());
+        be.purge_old_backups(1).unwrap();
+        assert_eq!(
+            vec![3],
+            be.list_backups().iter().map(|b| b.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            StatusCode::NotFound,
+            be.restore(1, "restored3").unwrap_err().code
+        );
+        for name in shared_files(&be) {
+            assert!(!shared.contains(&name));
+        }
+        assert!(be
+            .env()
+            .children(&be.dir.join(PRIVATE_DIR).join("1"))
+            .unwrap()
+            .is_empty());
+        be.verify_backup(3).unwrap();
+    }
+
+    #[test]
+    fn test_backup_engine_restore_failure() {
+        let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+        let mut opt = options::for_test();
+        opt.env = Arc::new(Box::new(env.clone()));
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+        let mut be = BackupEngine::open("backup", opt.clone()).unwrap();
+        let id = be.create_backup(&db).unwrap();
+
+        // Fails in the middle of copying the files, and when CURRENT is installed after all of
+        // them have been copied. Neither leaves anything behind.
+        let dest = Path::new("restored");
+        env.fail_nth_write(2);
+        assert!(be.restore(id, dest).is_err());
+        assert!(be.env().children(dest).unwrap().is_empty());
+        env.fail_nth_rename(1);
+        assert!(be.restore(id, dest).is_err());
+        assert!(be.env().children(dest).unwrap().is_empty());
+
+        let env = SyncCountingEnv::new(Box::new(env));
+        let mut opt = opt;
+        opt.env = Arc::new(Box::new(env.clone()));
+        let be = BackupEngine::open("backup", opt.clone()).unwrap();
+        be.restore(id, dest).unwrap();
+        assert!(env.synced_dirs().contains(&dest.to_owned()));
+        let db = DB::open(dest, opt).unwrap();
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+    }
+
+    #[test]
+    fn test_backup_engine_verify() {
+        let opt = options::for_test();
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+
+        let mut be = BackupEngine::open("backup", opt.clone()).unwrap();
+        let id = be.create_backup(&db).unwrap();
+        be.verify_backup(id).unwrap();
+        assert_eq!(
+            StatusCode::NotFound,
+            be.verify_backup(id + 1).unwrap_err().code
+        );
+
+        let table = be.dir.join(SHARED_DIR).join(&shared_files(&be)[0]);
+        corrupt_file(be.env(), &table, 10);
+        let e = be.verify_backup(id).unwrap_err();
+        assert_eq!(StatusCode::Corruption, e.code);
+        assert!(e.err.contains("shared/"));
+
+        // A truncated file is detected as well.
+        let size = be.env().size_of(&table).unwrap();
+        let mut buf = vec![0; size - 1];
+        be.env()
+            .open_random_access_file(&table)
+            .unwrap()
+            .read_at(0, &mut buf)
+            .unwrap();
+        be.env()
+            .open_writable_file(&table)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+        assert_eq!(
+            StatusCode::Corruption,
+            be.verify_backup(id).unwrap_err().code
+        );
+    }
+}
@@ -13,7 +13,7 @@ use rusty_leveldb::DefaultCmp;
 use rusty_leveldb::SkipMap;
 
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
 fn gen_key_val<R: Rng>(gen: &mut R, keylen: usize, vallen: usize) -> (Vec<u8>, Vec<u8>) {
     let mut key = Vec::with_capacity(keylen);
@@ -39,7 +39,7 @@ fn bench_gen_key_val(b: &mut Bencher) {
 fn bench_skipmap_insert(b: &mut Bencher) {
     let mut gen = rand::thread_rng();
 
-    let mut skm = SkipMap::new(Rc::new(Box::new(DefaultCmp)));
+    let mut skm = SkipMap::new(Arc::new(Box::new(DefaultCmp)));
 
     b.iter(|| {
         let (mut k, v) = gen_key_val(&mut gen, 10, 10);
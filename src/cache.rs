@@ -91,21 +91,20 @@ impl<T> LRUList<T> {
 
     fn remove(&mut self, node_handle: LRUHandle<T>) -> T {
         unsafe {
-            // If has next
-            if let Some(ref mut nextp) = (*node_handle).next {
-                swap(&mut (**nextp).prev, &mut (*node_handle).prev);
-            }
-            // If has prev
-            if let Some(ref mut prevp) = (*node_handle).prev {
-                // swap prev.next
-                // (node_handle will own itself now)
-                swap(&mut (**prevp).next, &mut (*node_handle).next);
+            // Every node has a prev, which may be the head node. It owns the node to be removed.
+            let prevp = (*node_handle).prev.unwrap();
+            let mut node = replace(&mut (*prevp).next, None).unwrap();
+
+            if let Some(mut next) = node.next.take() {
+                next.prev = Some(prevp);
+                (*prevp).next = Some(next);
+            } else {
+                // If last node, update tail
+                self.head.prev = Some(prevp);
             }
 
             self.count -= 1;
-            // node_handle now only has references/objects that point to itself,
-            // so it's safe to drop
-            replace(&mut (*node_handle).data, None).unwrap()
+            replace(&mut node.data, None).unwrap()
         }
     }
 
@@ -171,6 +170,11 @@ pub struct Cache<T> {
     id: u64,
 }
 
+// The raw pointers of the LRU list only point into the list itself; a `Cache` is only accessed
+// through `&mut self` (usually behind a lock), so sharing it between threads is safe.
+unsafe impl<T: Send> Send for Cache<T> {}
+unsafe impl<T: Sync> Sync for Cache<T> {}
+
 impl<T> Cache<T> {
     pub fn new(capacity: usize) -> Cache<T> {
         assert!(capacity > 0);
@@ -228,6 +232,11 @@ impl<T> Cache<T> {
         }
     }
 
+    /// Returns an iterator over all elements in the cache, without affecting the LRU order.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.map.values().map(|&(ref elem, _)| elem)
+    }
+
     /// Remove an element from the cache (for invalidation).
     pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
         match self.map.remove(key) {
@@ -275,6 +284,10 @@ mod tests {
         assert_eq!(cache.remove(&h_521), None);
 
         assert_eq!(cache.count(), 4);
+
+        let mut values: Vec<_> = cache.values().cloned().collect();
+        values.sort();
+        assert_eq!(vec![123, 332, 372, 899], values);
     }
 
     #[test]
@@ -320,6 +333,12 @@ mod tests {
         assert_eq!(lru.count(), 4);
         assert_eq!(56, lru.remove(h_56));
         assert_eq!(lru.count(), 3);
+
+        // The remaining nodes are still linked in order.
+        assert_eq!(Some(22), lru.remove_last());
+        assert_eq!(Some(223), lru.remove_last());
+        assert_eq!(Some(1111), lru.remove_last());
+        assert_eq!(None, lru.remove_last());
     }
 
     #[test]
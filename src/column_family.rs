@@ -0,0 +1,45 @@
+//! Column families partition a database into several keyspaces. Each family has its own
+//! memtables, levels and options (comparator, filter policy, compression), while all families
+//! share the write-ahead log, so that a WriteBatch spanning several families is applied
+//! atomically.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+/// The id of the default column family, which exists in every database and can't be dropped.
+pub const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+/// The name of the default column family.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// A ColumnFamilyHandle refers to a column family of a DB. It is obtained from
+/// `DB::create_column_family()` or `DB::column_family()`, and becomes invalid once the family
+/// has been dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnFamilyHandle {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamilyHandle {
+    pub fn new(id: u32, name: &str) -> ColumnFamilyHandle {
+        ColumnFamilyHandle {
+            id: id,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The default handle refers to the default column family.
+impl Default for ColumnFamilyHandle {
+    fn default() -> ColumnFamilyHandle {
+        ColumnFamilyHandle::new(DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME)
+    }
+}
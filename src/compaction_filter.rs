@@ -0,0 +1,30 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use std::sync::Arc;
+
+/// Decision is returned by a CompactionFilter for each entry it is called for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Keep,
+    /// Removes the entry, as if it had been deleted.
+    Remove,
+    /// Replaces the entry's value.
+    ChangeValue(Vec<u8>),
+}
+
+/// A CompactionFilter is called during compactions for the newest value of each key, and may
+/// remove it or change its value. This allows e.g. dropping expired entries without deleting them
+/// explicitly.
+///
+/// Entries that are visible to a snapshot are never passed to the filter, so that reads using a
+/// snapshot keep returning the same results.
+pub trait CompactionFilter: Send + Sync {
+    /// Returns a string identifying this filter.
+    fn name(&self) -> &'static str;
+    /// Decides what happens to an entry found while compacting `level` (into `level + 1`).
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> Decision;
+}
+
+/// A boxed and refcounted compaction filter, as stored in `Options`.
+pub type BoxedCompactionFilter = Arc<Box<dyn CompactionFilter>>;
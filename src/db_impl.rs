@@ -11,23 +11,35 @@ use std::untrusted::path::PathEx;
 use db_iter::DBIterator;
 
 use cmp::{Cmp, InternalKeyCmp};
-use env::{Env, FileLock};
-use error::{err, Result, StatusCode};
+use column_family::{ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
+use compaction_filter::Decision;
+use env::{copy_file, Env, FileLock, WritableFile};
+use error::{err, Result, Status, StatusCode};
 use filter::{BoxedFilterPolicy, InternalFilterPolicy};
 use infolog::Logger;
-use key_types::{parse_internal_key, InternalKey, LookupKey, ValueType};
+use key_types::{
+    internal_bound, parse_internal_key, split_expiry, InternalKey, LookupKey, ValueType,
+};
 use log::{LogReader, LogWriter};
 use memtable::MemTable;
+use merge_operator::{self, BoxedMergeOperator};
 use merging_iter::MergingIter;
-use options::Options;
+use options::{Options, ReadOptions, WriteOptions};
+use range_tombstone::{extend_file_bounds, max_covering_seq, RangeTombstone};
+use secondary::{read_new_records, SecondaryState};
 use snapshot::{Snapshot, SnapshotList};
+use sst_file_writer::ExternalFile;
+use statistics::{self, Histogram, StatisticsEnv, Ticker};
 use table_builder::TableBuilder;
 use table_cache::{table_file_name, TableCache};
+use table_reader::Table;
+use transaction::Transaction;
 use types::{
-    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber, Shared,
-    MAX_SEQUENCE_NUMBER, NUM_LEVELS,
+    current_key_val, parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Range,
+    SequenceNumber, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS,
 };
-use version::Version;
+use update_iter::{first_sequence, UpdateIterator};
+use version::{total_size, KeyLookup, Version};
 use version_edit::VersionEdit;
 use version_set::{
     manifest_file_name, read_current_file, set_current_file, Compaction, VersionSet,
@@ -35,34 +47,125 @@ use version_set::{
 use write_batch::WriteBatch;
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::io::{self, BufWriter, Write};
 use std::mem;
 use std::ops::Drop;
 use std::path::Path;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        use std::sync::{SgxCondvar as Condvar, SgxMutex as Mutex};
+    } else {
+        use std::sync::{Condvar, Mutex};
+    }
+}
 
-/// DB contains the actual database implemenation. As opposed to the original, this implementation
-/// is not concurrent (yet).
+/// DB contains the actual database implemenation.
+///
+/// A DB is a cheap handle to the shared database state: it can be cloned and sent to other
+/// threads. Reads (`get()`, `new_iter()`, snapshots) from different threads run concurrently,
+/// while writes are serialized internally. Memtable flushes and compactions are run by a
+/// background thread. The database is closed once the last handle is dropped.
+#[derive(Clone)]
 pub struct DB {
     name: PathBuf,
     path: PathBuf,
-    lock: Option<FileLock>,
 
-    internal_cmp: Rc<Box<dyn Cmp>>,
     fpol: InternalFilterPolicy<BoxedFilterPolicy>,
     opt: Options,
+    // Set by open_read_only(): nothing is written to the database directory.
+    read_only: bool,
+    // Set by open_as_secondary(): how far the files of the primary instance have been read.
+    secondary: Option<Shared<SecondaryState>>,
+
+    // Lock order: writer, compactor, secondary, bg, mem, imm, vset, (current version), cache,
+    // cstats.
+    writer: Shared<WriterState>,
+    compactor: Shared<CompactorState>,
+    bg: Arc<Background>,
+    // Only set in handles given out to users, not in the background thread's handle.
+    bg_thread: Option<Arc<BackgroundThread>>,
+
+    // The memtables of all column families, by column family id.
+    mem: Shared<BTreeMap<u32, MemTable>>,
+    // The immutable memtables, and the number of the log file that was started when they were
+    // replaced. Once the memtables have been written to tables, older log files can be deleted.
+    imm: Shared<Option<(BTreeMap<u32, MemTable>, FileNum)>>,
 
-    mem: MemTable,
-    imm: Option<MemTable>,
-
-    log: Option<LogWriter<BufWriter<Box<dyn Write>>>>,
-    log_num: Option<FileNum>,
     cache: Shared<TableCache>,
     vset: Shared<VersionSet>,
     snaps: SnapshotList,
+    // While positive, obsolete files are not deleted, e.g. because a checkpoint is copying them.
+    deletions_paused: Shared<usize>,
+    // The last sequence number acknowledged by consumers of get_updates_since(). With
+    // Options::retain_log_files, log files with later updates are kept.
+    updates_acked: Shared<SequenceNumber>,
+    // Kept outside of CompactorState so that statistics can be read while a compaction runs.
+    cstats: Shared<[CompactionStats; NUM_LEVELS]>,
+}
+
+/// A LiveFile is part of a consistent state of the database, as passed to the closure of
+/// `DB::with_live_files()`. Only the first `size` bytes belong to that state: log files and the
+/// manifest may be appended to later.
+pub struct LiveFile {
+    pub path: PathBuf,
+    pub typ: FileType,
+    pub num: FileNum,
+    pub size: usize,
+}
+
+/// WriterState contains the parts of a DB that are only used by writes. Holding its lock grants
+/// exclusive write access to the database.
+struct WriterState {
+    env: Arc<Box<dyn Env>>,
+    lock: Option<FileLock>,
+
+    log: Option<LogWriter<BufWriter<Box<dyn WritableFile>>>>,
+    log_num: Option<FileNum>,
+}
 
-    cstats: [CompactionStats; NUM_LEVELS],
+/// CompactorState is held by whoever is compacting the database (usually the background thread);
+/// holding its lock serializes compactions.
+struct CompactorState;
+
+/// Background is used to schedule work on the background thread and to wait for it.
+struct Background {
+    state: Mutex<BackgroundState>,
+    // Signalled when work has been scheduled, or when a unit of work has finished.
+    cv: Condvar,
+}
+
+#[derive(Default)]
+struct BackgroundState {
+    scheduled: bool,
+    running: bool,
+    shutdown: bool,
+    // The first error encountered by a background compaction. Once set, writes fail with it.
+    error: Option<Status>,
+}
+
+/// BackgroundThread shuts down the background thread once the last DB handle is dropped.
+struct BackgroundThread {
+    bg: Arc<Background>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for BackgroundThread {
+    fn drop(&mut self) {
+        {
+            let mut state = self.bg.state.lock().unwrap();
+            state.shutdown = true;
+            self.bg.cv.notify_all();
+        }
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
 }
 
 impl DB {
@@ -71,6 +174,10 @@ impl DB {
     /// new initializes a new DB object, but doesn't touch disk.
     fn new<P: AsRef<Path>>(name: P, mut opt: Options) -> DB {
         let name = name.as_ref();
+        if let Some(stats) = opt.statistics.clone() {
+            // Count the bytes of all files read and written by the database.
+            opt.env = Arc::new(Box::new(StatisticsEnv::new(opt.env.clone(), stats)));
+        }
         if opt.log.is_none() {
             let log = open_info_log(opt.env.as_ref().as_ref(), name);
             opt.log = Some(share(log));
@@ -81,26 +188,40 @@ impl DB {
 
         let cache = share(TableCache::new(&name, opt.clone(), opt.max_open_files - 10));
         let vset = VersionSet::new(&name, opt.clone(), cache.clone());
+        let mut mem = BTreeMap::new();
+        mem.insert(DEFAULT_COLUMN_FAMILY_ID, MemTable::new(opt.cmp.clone()));
 
         DB {
             name: name.to_owned(),
             path: path,
-            lock: None,
-            internal_cmp: Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             fpol: InternalFilterPolicy::new(opt.filter_policy.clone()),
 
-            mem: MemTable::new(opt.cmp.clone()),
-            imm: None,
+            writer: share(WriterState {
+                env: opt.env.clone(),
+                lock: None,
+                log: None,
+                log_num: None,
+            }),
+            compactor: share(CompactorState),
+            bg: Arc::new(Background {
+                state: Mutex::new(BackgroundState::default()),
+                cv: Condvar::new(),
+            }),
+            bg_thread: None,
+
+            mem: share(mem),
+            imm: share(None),
 
             opt: opt,
+            read_only: false,
+            secondary: None,
 
-            log: None,
-            log_num: None,
             cache: cache,
             vset: share(vset),
             snaps: SnapshotList::new(),
-
-            cstats: Default::default(),
+            deletions_paused: share(0),
+            updates_acked: share(0),
+            cstats: share(Default::default()),
         }
     }
 
@@ -108,41 +229,131 @@ impl DB {
         self.vset.borrow().current()
     }
 
+    /// cf_current returns the current version of a column family.
+    fn cf_current(&self, cf: u32) -> Result<Shared<Version>> {
+        match self.vset.borrow().current_cf(cf) {
+            Some(v) => Ok(v),
+            None => unknown_column_family(cf),
+        }
+    }
+
+    /// cf_options returns the options of a column family.
+    fn cf_options(&self, cf: u32) -> Result<Options> {
+        match self.vset.borrow().column_family_options(cf) {
+            Some(opt) => Ok(opt),
+            None => unknown_column_family(cf),
+        }
+    }
+
+    /// new_memtables returns an empty memtable for every column family in `vset`. If `log_num` is
+    /// given, families whose entries in that log file have already been written to tables are left
+    /// out.
+    fn new_memtables(vset: &VersionSet, log_num: Option<FileNum>) -> BTreeMap<u32, MemTable> {
+        vset.column_families()
+            .into_iter()
+            .filter(|&(cf, _)| match log_num {
+                Some(n) => vset.column_family_log_num(cf).unwrap_or(0) <= n,
+                None => true,
+            })
+            .map(|(cf, _)| {
+                let opt = vset.column_family_options(cf).unwrap();
+                (cf, MemTable::new(opt.cmp))
+            })
+            .collect()
+    }
+
     /// Opens or creates a new or existing database. `name` is the name of the directory containing
     /// the database.
     ///
     /// Whether a new database is created and what happens if a database exists at the given path
     /// depends on the options set (`create_if_missing`, `error_if_exists`).
     pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        DB::open_with_column_families(name, opt, vec![])
+    }
+
+    /// Opens a database like `open()`, using the supplied options for the named column families.
+    /// Existing families that are not listed, and the default family, use `opt`. Database-wide
+    /// settings like `env` and `write_buffer_size` are always taken from `opt`.
+    pub fn open_with_column_families<P: AsRef<Path>>(
+        name: P,
+        opt: Options,
+        families: Vec<(&str, Options)>,
+    ) -> Result<DB> {
         let name = name.as_ref();
         let mut db = DB::new(name, opt);
-        let mut ve = VersionEdit::new();
-        let save_manifest = db.recover(&mut ve)?;
-
-        // Create log file if an old one is not being reused.
-        if db.log.is_none() {
-            let lognum = db.vset.borrow_mut().new_file_number();
-            let logfile = db
-                .opt
-                .env
-                .open_writable_file(Path::new(&log_file_name(&db.name, lognum)))?;
-            ve.set_log_num(lognum);
-            db.log = Some(LogWriter::new(BufWriter::new(logfile)));
-            db.log_num = Some(lognum);
+        for (cf, opt) in families {
+            db.vset.borrow_mut().set_column_family_options(cf, opt);
         }
+        {
+            let mut w = db.writer.borrow_mut();
+            let mut c = db.compactor.borrow_mut();
+            let mut edits = BTreeMap::new();
+            let save_manifest = db.recover(&mut w, &mut c, &mut edits)?;
+
+            // Create log file if an old one is not being reused.
+            if w.log.is_none() {
+                let lognum = db.vset.borrow_mut().new_file_number();
+                let logfile = db
+                    .opt
+                    .env
+                    .open_writable_file(Path::new(&log_file_name(&db.name, lognum)))?;
+                w.log = Some(LogWriter::new(BufWriter::new(logfile)));
+                w.log_num = Some(lognum);
+            }
+
+            if save_manifest {
+                // All column families have been recovered up to the current log file.
+                let families = db.vset.borrow().column_families();
+                for (cf, _) in families {
+                    let mut ve = edits.remove(&cf).unwrap_or_else(VersionEdit::new);
+                    ve.set_column_family(cf);
+                    ve.set_log_num(w.log_num.unwrap_or(0));
+                    db.vset.borrow_mut().log_and_apply(ve)?;
+                }
+            }
 
-        if save_manifest {
-            ve.set_log_num(db.log_num.unwrap_or(0));
-            db.vset.borrow_mut().log_and_apply(ve)?;
+            db.delete_obsolete_files()?;
         }
+        db.start_background_thread()?;
+        db.maybe_schedule_compaction();
+        Ok(db)
+    }
+
+    /// Opens an existing database for reading only. No lock is taken, so that a database can be
+    /// opened while another instance is using it; the returned handle sees the state at the time
+    /// it was opened. Log files are replayed into memory, and nothing is ever written to the
+    /// database directory: writes, compactions and other changes fail with
+    /// StatusCode::NotSupported. Unless `opt.log` is set, no info log is written.
+    pub fn open_read_only<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        let db = DB::new_read_only(name, opt);
+        db.catch_up(&mut SecondaryState::new())?;
+        Ok(db)
+    }
 
-        db.delete_obsolete_files()?;
-        db.maybe_do_compaction()?;
+    /// Opens a secondary instance of a database, which follows the primary instance writing to
+    /// it, e.g. in another process. Like `open_read_only()`, this takes no lock and never writes
+    /// to the database directory. The secondary sees the state at the time it was opened, until
+    /// `try_catch_up_with_primary()` is called.
+    pub fn open_as_secondary<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        let mut db = DB::new_read_only(name, opt);
+        db.secondary = Some(share(SecondaryState::new()));
+        db.try_catch_up_with_primary()?;
         Ok(db)
     }
 
+    /// new_read_only initializes a DB object that never writes to disk.
+    fn new_read_only<P: AsRef<Path>>(name: P, mut opt: Options) -> DB {
+        if opt.log.is_none() {
+            opt.log = Some(share(Logger(Box::new(io::sink()))));
+        }
+        opt.reuse_manifest = false;
+        let mut db = DB::new(name, opt);
+        db.read_only = true;
+        db
+    }
+
     /// initialize_db initializes a new database.
-    fn initialize_db(&mut self) -> Result<()> {
+    fn initialize_db(&self) -> Result<()> {
         let mut ve = VersionEdit::new();
         ve.set_comparator_name(self.opt.cmp.id());
         ve.set_log_num(0);
@@ -154,20 +365,26 @@ impl DB {
             let manifest_file = self.opt.env.open_writable_file(Path::new(&manifest))?;
             let mut lw = LogWriter::new(manifest_file);
             lw.add_record(&ve.encode())?;
-            lw.flush()?;
+            lw.sync()?;
         }
         set_current_file(&self.opt.env, &self.path, 1)
     }
 
     /// recover recovers from the existing state on disk. If the wrapped result is `true`, then
-    /// log_and_apply() should be called after recovery has finished.
-    fn recover(&mut self, ve: &mut VersionEdit) -> Result<bool> {
+    /// log_and_apply() should be called with the edits for every column family after recovery
+    /// has finished.
+    fn recover(
+        &self,
+        w: &mut WriterState,
+        c: &mut CompactorState,
+        edits: &mut BTreeMap<u32, VersionEdit>,
+    ) -> Result<bool> {
         if self.opt.error_if_exists && self.opt.env.exists(&self.path.as_ref()).unwrap_or(false) {
             return err(StatusCode::AlreadyExists, "database already exists");
         }
 
         let _ = self.opt.env.mkdir(Path::new(&self.path));
-        self.acquire_lock()?;
+        self.acquire_lock(w)?;
 
         if let Err(e) = read_current_file(&self.opt.env, &self.path) {
             if e.code == StatusCode::NotFound && self.opt.create_if_missing {
@@ -183,19 +400,44 @@ impl DB {
         // If save_manifest is true, we should log_and_apply() later in order to write the new
         // manifest.
         let mut save_manifest = self.vset.borrow_mut().recover()?;
+        let mems = DB::new_memtables(&self.vset.borrow(), None);
+        *self.mem.borrow_mut() = mems;
 
         // Recover from all log files not in the descriptor.
         let mut max_seq = 0;
+        let log_files = self.log_files_to_recover(&self.vset.borrow())?;
+        for i in 0..log_files.len() {
+            let (save_manifest_, max_seq_) =
+                self.recover_log_file(w, c, log_files[i], i == log_files.len() - 1, edits)?;
+            if save_manifest_ {
+                save_manifest = true;
+            }
+            if max_seq_ > max_seq {
+                max_seq = max_seq_;
+            }
+            self.vset.borrow_mut().mark_file_number_used(log_files[i]);
+        }
+
+        if self.vset.borrow().last_seq < max_seq {
+            self.vset.borrow_mut().last_seq = max_seq;
+        }
+
+        Ok(save_manifest)
+    }
+
+    /// log_files_to_recover returns the numbers of the log files whose contents may not have been
+    /// written to tables yet according to `vset`, in ascending order. It fails if a file of a
+    /// current version is missing; files of replaced versions may have been deleted by a primary
+    /// instance already.
+    fn log_files_to_recover(&self, vset: &VersionSet) -> Result<Vec<FileNum>> {
         let filenames = self.opt.env.children(&self.path)?;
-        let mut expected = self.vset.borrow().live_files();
+        let mut expected = vset.current_files();
         let mut log_files = vec![];
 
         for file in &filenames {
             if let Ok((num, typ)) = parse_file_name(&file) {
                 expected.remove(&num);
-                if typ == FileType::Log
-                    && (num >= self.vset.borrow().log_num || num == self.vset.borrow().prev_log_num)
-                {
+                if typ == FileType::Log && (num >= vset.log_num || num == vset.prev_log_num) {
                     log_files.push(num);
                 }
             }
@@ -206,110 +448,127 @@ impl DB {
         }
 
         log_files.sort();
-        for i in 0..log_files.len() {
-            let (save_manifest_, max_seq_) =
-                self.recover_log_file(log_files[i], i == log_files.len() - 1, ve)?;
-            if save_manifest_ {
-                save_manifest = true;
+        Ok(log_files)
+    }
+
+    /// read_log_file calls `f` with every batch in a log file, and returns the largest sequence
+    /// number it contains.
+    fn read_log_file<F: FnMut(&WriteBatch) -> Result<()>>(
+        &self,
+        log_num: FileNum,
+        mut f: F,
+    ) -> Result<SequenceNumber> {
+        let filename = log_file_name(&self.path, log_num);
+        let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
+        let mut logreader = LogReader::new(
+            logfile, // checksum=
+            true,
+        );
+        log!(self.opt.log, "Recovering log file {:?}", filename);
+        let mut scratch = vec![];
+        let mut batch = WriteBatch::new();
+        let mut max_seq = 0;
+
+        while let Ok(len) = logreader.read(&mut scratch) {
+            if len == 0 {
+                break;
             }
-            if max_seq_ > max_seq {
-                max_seq = max_seq_;
+            if len < 12 {
+                log!(
+                    self.opt.log,
+                    "corruption in log file {:06}: record shorter than 12B",
+                    log_num
+                );
+                continue;
             }
-            self.vset.borrow_mut().mark_file_number_used(log_files[i]);
-        }
 
-        if self.vset.borrow().last_seq < max_seq {
-            self.vset.borrow_mut().last_seq = max_seq;
-        }
+            batch.set_contents(&scratch);
+            f(&batch)?;
 
-        Ok(save_manifest)
+            let last_seq = batch.sequence() + batch.count() as u64 - 1;
+            if last_seq > max_seq {
+                max_seq = last_seq
+            }
+            batch.clear();
+        }
+        Ok(max_seq)
     }
 
-    /// recover_log_file reads a single log file into a memtable, writing new L0 tables if
-    /// necessary. If is_last is true, it checks whether the log file can be reused, and sets up
-    /// the database's logging handles appropriately if that's the case.
+    /// recover_log_file reads a single log file into the memtables of the column families,
+    /// writing new L0 tables if necessary. If is_last is true, it checks whether the log file can
+    /// be reused, and sets up the database's logging handles appropriately if that's the case.
     fn recover_log_file(
-        &mut self,
+        &self,
+        w: &mut WriterState,
+        c: &mut CompactorState,
         log_num: FileNum,
         is_last: bool,
-        ve: &mut VersionEdit,
+        edits: &mut BTreeMap<u32, VersionEdit>,
     ) -> Result<(bool, SequenceNumber)> {
         let filename = log_file_name(&self.path, log_num);
         let mut compactions = 0;
-        let mut max_seq = 0;
         let mut save_manifest = false;
-        let cmp: Rc<Box<dyn Cmp>> = self.opt.cmp.clone();
-        let mut mem = MemTable::new(cmp.clone());
-        {
-            let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
-            // Use the user-supplied comparator; it will be wrapped inside a MemtableKeyCmp.
-
-            let mut logreader = LogReader::new(
-                logfile, // checksum=
-                true,
-            );
-            log!(self.opt.log, "Recovering log file {:?}", filename);
-            let mut scratch = vec![];
-            let mut batch = WriteBatch::new();
-
-            while let Ok(len) = logreader.read(&mut scratch) {
-                if len == 0 {
-                    break;
+        let mut mems = DB::new_memtables(&self.vset.borrow(), Some(log_num));
+        let max_seq = self.read_log_file(log_num, |batch| {
+            batch.insert_into_memtables(batch.sequence(), &mut mems);
+
+            let usage: usize = mems.values().map(|m| m.approx_mem_usage()).sum();
+            if usage > self.opt.write_buffer_size {
+                compactions += 1;
+                for (cf, mem) in mems.iter() {
+                    let ve = edits.entry(*cf).or_insert_with(VersionEdit::new);
+                    self.write_l0_table(c, *cf, mem, ve, None)?;
                 }
-                if len < 12 {
-                    log!(
-                        self.opt.log,
-                        "corruption in log file {:06}: record shorter than 12B",
-                        log_num
-                    );
-                    continue;
-                }
-
-                batch.set_contents(&scratch);
-                batch.insert_into_memtable(batch.sequence(), &mut mem);
-
-                let last_seq = batch.sequence() + batch.count() as u64 - 1;
-                if last_seq > max_seq {
-                    max_seq = last_seq
-                }
-                if mem.approx_mem_usage() > self.opt.write_buffer_size {
-                    compactions += 1;
-                    self.write_l0_table(&mem, ve, None)?;
-                    save_manifest = true;
-                    mem = MemTable::new(cmp.clone());
-                }
-                batch.clear();
+                save_manifest = true;
+                mems = DB::new_memtables(&self.vset.borrow(), Some(log_num));
             }
-        }
+            Ok(())
+        })?;
 
         // Check if we can reuse the last log file.
         if self.opt.reuse_logs && is_last && compactions == 0 {
-            assert!(self.log.is_none());
+            assert!(w.log.is_none());
             log!(self.opt.log, "reusing log file {:?}", filename);
             let oldsize = self.opt.env.size_of(Path::new(&filename))?;
             let oldfile = self.opt.env.open_appendable_file(Path::new(&filename))?;
             let lw = LogWriter::new_with_off(BufWriter::new(oldfile), oldsize);
-            self.log = Some(lw);
-            self.log_num = Some(log_num);
-            self.mem = mem;
-        } else if mem.len() > 0 {
-            // Log is not reused, so write out the accumulated memtable.
-            save_manifest = true;
-            self.write_l0_table(&mem, ve, None)?;
+            w.log = Some(lw);
+            w.log_num = Some(log_num);
+            let mut mem = self.mem.borrow_mut();
+            for (cf, m) in mems {
+                mem.insert(cf, m);
+            }
+        } else {
+            // Log is not reused, so write out the accumulated memtables.
+            for (cf, mem) in mems.iter() {
+                if mem.len() > 0 {
+                    save_manifest = true;
+                    let ve = edits.entry(*cf).or_insert_with(VersionEdit::new);
+                    self.write_l0_table(c, *cf, mem, ve, None)?;
+                }
+            }
         }
 
         Ok((save_manifest, max_seq))
     }
 
     /// delete_obsolete_files removes files that are no longer needed from the file system.
-    fn delete_obsolete_files(&mut self) -> Result<()> {
+    fn delete_obsolete_files(&self) -> Result<()> {
+        if self.read_only || *self.deletions_paused.borrow() > 0 {
+            return Ok(());
+        }
         let files = self.vset.borrow().live_files();
         let filenames = self.opt.env.children(Path::new(&self.path))?;
+        let retained_logs = if self.opt.retain_log_files {
+            self.unacknowledged_logs()?
+        } else {
+            vec![]
+        };
         for name in filenames {
             if let Ok((num, typ)) = parse_file_name(&name) {
                 match typ {
                     FileType::Log => {
-                        if num >= self.vset.borrow().log_num {
+                        if num >= self.vset.borrow().log_num || retained_logs.contains(&num) {
                             continue;
                         }
                     }
@@ -335,7 +594,7 @@ impl DB {
 
                 // If we're here, delete this file.
                 if typ == FileType::Table {
-                    let _ = self.cache.borrow_mut().evict(num);
+                    self.vset.borrow().evict_table(num);
                 }
                 log!(self.opt.log, "Deleting file type={:?} num={}", typ, num);
                 if let Err(e) = self.opt.env.delete(&self.path.join(&name)) {
@@ -347,11 +606,11 @@ impl DB {
     }
 
     /// acquire_lock acquires the lock file.
-    fn acquire_lock(&mut self) -> Result<()> {
+    fn acquire_lock(&self, w: &mut WriterState) -> Result<()> {
         let lock_r = self.opt.env.lock(Path::new(&lock_file_name(&self.path)));
         match lock_r {
             Ok(lockfile) => {
-                self.lock = Some(lockfile);
+                w.lock = Some(lockfile);
                 Ok(())
             }
             Err(ref e) if e.code == StatusCode::LockError => err(
@@ -361,15 +620,6 @@ impl DB {
             Err(e) => Err(e),
         }
     }
-
-    /// release_lock releases the lock file, if it's currently held.
-    fn release_lock(&mut self) -> Result<()> {
-        if let Some(l) = self.lock.take() {
-            self.opt.env.unlock(l)
-        } else {
-            Ok(())
-        }
-    }
 }
 
 impl DB {
@@ -377,7 +627,7 @@ impl DB {
 
     /// Adds a single entry. It's a short, non-synchronous, form of `write()`; in order to make
     /// sure that the written entry is on disk, call `flush()` afterwards.
-    pub fn put(&mut self, k: &[u8], v: &[u8]) -> Result<()> {
+    pub fn put(&self, k: &[u8], v: &[u8]) -> Result<()> {
         let mut wb = WriteBatch::new();
         wb.put(k, v);
         self.write(wb, false)
@@ -385,103 +635,431 @@ impl DB {
 
     /// Deletes a single entry. Like with `put()`, you can call `flush()` to guarantee that
     /// the operation made it to disk.
-    pub fn delete(&mut self, k: &[u8]) -> Result<()> {
+    pub fn delete(&self, k: &[u8]) -> Result<()> {
         let mut wb = WriteBatch::new();
         wb.delete(k);
         self.write(wb, false)
     }
 
+    /// Deletes all entries with keys in [start; end). Like with `put()`, you can call `flush()`
+    /// to guarantee that the operation made it to disk.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.delete_range(start, end);
+        self.write(wb, false)
+    }
+
+    /// Adds a merge operand for a key, which is combined with the key's current value by the
+    /// configured `MergeOperator` when reading or compacting it. Fails if no merge operator is
+    /// configured.
+    pub fn merge(&self, k: &[u8], v: &[u8]) -> Result<()> {
+        if self.opt.merge_operator.is_none() {
+            return err(StatusCode::NotSupported, "no merge operator is configured");
+        }
+        let mut wb = WriteBatch::new();
+        wb.merge(k, v);
+        self.write(wb, false)
+    }
+
+    /// Adds a single entry that expires after `ttl`. Expired entries are no longer returned by
+    /// reads and iterators, and are removed by compactions.
+    pub fn put_with_ttl(&self, k: &[u8], v: &[u8], ttl: Duration) -> Result<()> {
+        self.put_cf_with_ttl(&ColumnFamilyHandle::default(), k, v, ttl)
+    }
+
+    /// Adds a single entry to a column family.
+    pub fn put_cf(&self, cf: &ColumnFamilyHandle, k: &[u8], v: &[u8]) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.put_cf(cf, k, v);
+        self.write(wb, false)
+    }
+
+    /// Adds a single entry that expires after `ttl` to a column family.
+    pub fn put_cf_with_ttl(
+        &self,
+        cf: &ColumnFamilyHandle,
+        k: &[u8],
+        v: &[u8],
+        ttl: Duration,
+    ) -> Result<()> {
+        let ttl_micros = ttl.as_secs() * 1000000 + ttl.subsec_micros() as u64;
+        let mut wb = WriteBatch::new();
+        wb.put_cf_with_expiry(cf, k, v, self.opt.env.micros() + ttl_micros);
+        self.write(wb, false)
+    }
+
+    /// Deletes a single entry from a column family.
+    pub fn delete_cf(&self, cf: &ColumnFamilyHandle, k: &[u8]) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.delete_cf(cf, k);
+        self.write(wb, false)
+    }
+
+    /// Adds a merge operand for a key of a column family, using the family's merge operator.
+    pub fn merge_cf(&self, cf: &ColumnFamilyHandle, k: &[u8], v: &[u8]) -> Result<()> {
+        if self.cf_options(cf.id())?.merge_operator.is_none() {
+            return err(StatusCode::NotSupported, "no merge operator is configured");
+        }
+        let mut wb = WriteBatch::new();
+        wb.merge_cf(cf, k, v);
+        self.write(wb, false)
+    }
+
     /// Writes an entire WriteBatch. `sync` determines whether the write should be flushed to
     /// disk.
-    pub fn write(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
-        assert!(self.log.is_some());
+    pub fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        let wo = WriteOptions {
+            sync: sync,
+            ..WriteOptions::default()
+        };
+        self.write_opt(&wo, batch)
+    }
+
+    /// Writes an entire WriteBatch, as specified by the supplied WriteOptions.
+    pub fn write_opt(&self, wo: &WriteOptions, batch: WriteBatch) -> Result<()> {
+        self.timed(Histogram::WriteMicros, || {
+            let mut w = self.writer.borrow_mut();
+            self.write_locked(&mut w, wo, batch)
+        })
+    }
+
+    /// write_if_unchanged writes a batch only if none of the given keys of the default column
+    /// family has been modified after the sequence number `seq`. Otherwise, it fails with
+    /// StatusCode::Busy. The check and the write are atomic with regard to other writes.
+    pub(crate) fn write_if_unchanged<'a, I: Iterator<Item = &'a Vec<u8>>>(
+        &self,
+        wo: &WriteOptions,
+        keys: I,
+        seq: SequenceNumber,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        let mut w = self.writer.borrow_mut();
+        for key in keys {
+            if self.latest_sequence(DEFAULT_COLUMN_FAMILY_ID, key)? > seq {
+                return err(
+                    StatusCode::Busy,
+                    &format!(
+                        "key {} has been modified by a concurrent write",
+                        String::from_utf8_lossy(key)
+                    ),
+                );
+            }
+        }
+        if batch.count() == 0 {
+            return Ok(());
+        }
+        self.write_locked(&mut w, wo, batch)
+    }
+
+    /// check_writable fails with StatusCode::NotSupported if the database has been opened
+    /// read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return err(
+                StatusCode::NotSupported,
+                "database has been opened read-only",
+            );
+        }
+        Ok(())
+    }
+
+    /// write_locked writes a batch while the writer lock is held.
+    fn write_locked(
+        &self,
+        w: &mut WriterState,
+        wo: &WriteOptions,
+        mut batch: WriteBatch,
+    ) -> Result<()> {
+        self.check_writable()?;
+        assert!(w.log.is_some());
+
+        self.make_room_for_write(w, false)?;
 
-        self.make_room_for_write(false)?;
+        {
+            let mem = self.mem.borrow();
+            if let Some(cf) = batch
+                .column_families()
+                .into_iter()
+                .find(|cf| !mem.contains_key(cf))
+            {
+                return unknown_column_family(cf);
+            }
+        }
 
         let entries = batch.count() as u64;
-        let log = self.log.as_mut().unwrap();
+        let log = w.log.as_mut().unwrap();
         let next = self.vset.borrow().last_seq + 1;
 
-        batch.insert_into_memtable(next, &mut self.mem);
-        log.add_record(&batch.encode(next))?;
-        if sync {
-            log.flush()?;
+        if !wo.disable_wal {
+            let result = log.add_record(batch.encode(next)).and_then(|_| {
+                if wo.sync {
+                    statistics::record(&self.opt.statistics, Ticker::WalSyncs, 1);
+                    log.sync()
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(e) = result {
+                // The log may or may not contain the batch now. Stop all further writes, like
+                // after a failed compaction, so that its sequence numbers aren't reused.
+                log!(self.opt.log, "Writing to the log failed: {}", e);
+                self.bg.state.lock().unwrap().error = Some(e.clone());
+                self.bg.cv.notify_all();
+                return Err(e);
+            }
         }
+        batch.insert_into_memtables(next, &mut self.mem.borrow_mut());
         self.vset.borrow_mut().last_seq += entries;
         Ok(())
     }
 
     /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
-    pub fn flush(&mut self) -> Result<()> {
-        assert!(self.log.is_some());
-        self.log.as_mut().unwrap().flush()
+    pub fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        let mut w = self.writer.borrow_mut();
+        assert!(w.log.is_some());
+        statistics::record(&self.opt.statistics, Ticker::WalSyncs, 1);
+        w.log.as_mut().unwrap().sync()
+    }
+
+    /// timed runs `f` and records its latency in a histogram if statistics are enabled.
+    fn timed<T, F: FnOnce() -> T>(&self, h: Histogram, f: F) -> T {
+        match self.opt.statistics {
+            Some(ref stats) => {
+                let start = self.opt.env.micros();
+                let result = f();
+                stats.measure(h, self.opt.env.micros().saturating_sub(start));
+                result
+            }
+            None => f(),
+        }
     }
 }
 
 impl DB {
     // READ //
 
-    fn get_internal(&mut self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    /// memtables returns handles to the current and the immutable memtable of a column family,
+    /// and to its current version. All of them are locked together, so that a concurrent
+    /// memtable switch or memtable compaction is either fully visible or not at all.
+    fn memtables(&self, cf: u32) -> Result<(MemTable, Option<MemTable>, Shared<Version>)> {
+        let mem = self.mem.borrow();
+        let imm = self.imm.borrow();
+        let current = self.cf_current(cf)?;
+        match mem.get(&cf) {
+            Some(mem) => Ok((
+                mem.clone(),
+                imm.as_ref().and_then(|&(ref imm, _)| imm.get(&cf).cloned()),
+                current,
+            )),
+            None => unknown_column_family(cf),
+        }
+    }
+
+    fn get_internal(
+        &self,
+        ro: &ReadOptions,
+        cf: u32,
+        seq: SequenceNumber,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        self.timed(Histogram::GetMicros, || self.lookup(ro, cf, seq, key))
+    }
+
+    fn lookup(
+        &self,
+        ro: &ReadOptions,
+        cf: u32,
+        seq: SequenceNumber,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let opt = self.cf_options(cf)?;
+        let op = opt.merge_operator.as_ref();
         // Using this lookup key will skip all entries with higher sequence numbers, because they
         // will compare "Lesser" using the InternalKeyCmp
         let lkey = LookupKey::new(key, seq);
+        let (mem, imm, current) = self.memtables(cf)?;
+        let now = self.opt.env.micros();
+        // Merge operands found so far, newest first.
+        let mut operands = vec![];
 
-        match self.mem.get(&lkey) {
-            (Some(v), _) => return Ok(Some(v)),
-            // deleted entry
-            (None, true) => return Ok(None),
-            // not found entry
-            (None, false) => {}
-        }
-
-        if let Some(imm) = self.imm.as_ref() {
-            match imm.get(&lkey) {
-                (Some(v), _) => return Ok(Some(v)),
-                // deleted entry
-                (None, true) => return Ok(None),
+        for m in Some(&mem).into_iter().chain(imm.as_ref()) {
+            match m.get(&lkey, now, &mut operands) {
                 // not found entry
                 (None, false) => {}
+                // found or deleted entry
+                (v, _) => {
+                    statistics::record(&self.opt.statistics, Ticker::MemtableHit, 1);
+                    return merge_value(op, key, v, &operands);
+                }
             }
         }
+        statistics::record(&self.opt.statistics, Ticker::MemtableMiss, 1);
 
         let mut do_compaction = false;
         let mut result = None;
 
-        // Limiting the borrow scope of self.current.
-        {
-            let current = self.current();
-            let mut current = current.borrow_mut();
-            if let Ok(Some((v, st))) = current.get(lkey.internal_key()) {
-                if current.update_stats(st) {
-                    do_compaction = true;
-                }
-                result = Some(v)
+        // Only read-lock the current version while looking up the key, so that other readers can
+        // proceed at the same time.
+        let r = current
+            .borrow()
+            .get(ro, lkey.internal_key(), now, &mut operands)?;
+        if let Some((v, st)) = r {
+            if current.borrow_mut().update_stats(st) {
+                do_compaction = true;
             }
+            result = Some(v)
         }
 
         if do_compaction {
-            if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "error while doing compaction in get: {}", e);
-            }
+            self.maybe_schedule_compaction();
         }
-        Ok(result)
+        merge_value(op, key, result, &operands)
     }
 
     /// get_at reads the value for a given key at or before snapshot. It returns Ok(None) if the
     /// entry wasn't found, and Err(_) if an error occurred.
-    pub fn get_at(&mut self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.get_internal(snapshot.sequence(), key)
+    pub fn get_at(&self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_internal(
+            &ReadOptions::default(),
+            DEFAULT_COLUMN_FAMILY_ID,
+            snapshot.sequence(),
+            key,
+        )
+    }
+
+    /// get_opt reads the value for a given key, as specified by the supplied ReadOptions. It
+    /// returns Ok(None) if the entry wasn't found, and Err(_) if an error occurred.
+    pub fn get_opt(&self, ro: &ReadOptions, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_cf_opt(ro, &ColumnFamilyHandle::default(), key)
     }
 
-    /// get is a simplified version of get_at(), translating errors to None.
-    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
-        let seq = self.vset.borrow().last_seq;
-        if let Ok(v) = self.get_internal(seq, key) {
+    /// get_cf_opt reads the value for a given key from a column family, as specified by the
+    /// supplied ReadOptions.
+    pub fn get_cf_opt(
+        &self,
+        ro: &ReadOptions,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let seq = match ro.snapshot {
+            Some(ref snapshot) => snapshot.sequence(),
+            None => self.vset.borrow().last_seq,
+        };
+        self.get_internal(ro, cf.id(), seq, key)
+    }
+
+    /// get_cf reads the value for a given key from a column family. It returns Ok(None) if the
+    /// entry wasn't found, and Err(_) if an error occurred, e.g. because the family has been
+    /// dropped.
+    pub fn get_cf(&self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_cf_opt(&ReadOptions::default(), cf, key)
+    }
+
+    /// get is a simplified version of get_opt(), translating errors to None. Use get_opt() or
+    /// get_at() in order to distinguish a missing entry from a failed read (e.g. a corrupted
+    /// table file).
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Ok(v) = self.get_opt(&ReadOptions::default(), key) {
             v
         } else {
             None
         }
     }
+
+    /// multi_get reads the values of several keys at once. All keys are read at the same
+    /// sequence number, and tables are only retrieved once for all keys looked up in them. The
+    /// results are returned in the order of `keys`.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_opt(&ReadOptions::default(), keys)
+    }
+
+    /// multi_get_opt works like multi_get(), as specified by the supplied ReadOptions.
+    pub fn multi_get_opt(&self, ro: &ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_cf_opt(ro, &ColumnFamilyHandle::default(), keys)
+    }
+
+    /// multi_get_cf_opt works like multi_get(), reading from a column family as specified by the
+    /// supplied ReadOptions.
+    pub fn multi_get_cf_opt(
+        &self,
+        ro: &ReadOptions,
+        cf: &ColumnFamilyHandle,
+        keys: &[&[u8]],
+    ) -> Vec<Result<Option<Vec<u8>>>> {
+        let seq = match ro.snapshot {
+            Some(ref snapshot) => snapshot.sequence(),
+            None => self.vset.borrow().last_seq,
+        };
+        match self.multi_get_internal(ro, cf.id(), seq, keys) {
+            Ok(results) => results,
+            Err(e) => keys.iter().map(|_| Err(e.clone())).collect(),
+        }
+    }
+
+    fn multi_get_internal(
+        &self,
+        ro: &ReadOptions,
+        cf: u32,
+        seq: SequenceNumber,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        let opt = self.cf_options(cf)?;
+        let op = opt.merge_operator.as_ref();
+        let (mem, imm, current) = self.memtables(cf)?;
+        let now = self.opt.env.micros();
+
+        // Looking up the keys in order lets the lookups in each level proceed file by file.
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| opt.cmp.cmp(keys[a], keys[b]));
+        let mut lookups: Vec<KeyLookup> = order
+            .iter()
+            .map(|&i| KeyLookup::new(LookupKey::new(keys[i], seq)))
+            .collect();
+
+        for l in lookups.iter_mut() {
+            for m in Some(&mem).into_iter().chain(imm.as_ref()) {
+                match m.get(&l.key, now, &mut l.operands) {
+                    (Some(v), _) => l.result = Some(Ok(Some(v))),
+                    // deleted entry
+                    (None, true) => l.result = Some(Ok(None)),
+                    // not found entry
+                    (None, false) => continue,
+                }
+                break;
+            }
+        }
+        let hits = lookups.iter().filter(|l| l.result.is_some()).count();
+        statistics::record(&self.opt.statistics, Ticker::MemtableHit, hits);
+        statistics::record(
+            &self.opt.statistics,
+            Ticker::MemtableMiss,
+            keys.len() - hits,
+        );
+
+        let mut do_compaction = false;
+        if lookups.iter().any(|l| l.result.is_none()) {
+            let stats = current.borrow().multi_get(ro, now, &mut lookups);
+            for st in stats {
+                if current.borrow_mut().update_stats(st) {
+                    do_compaction = true;
+                }
+            }
+        }
+        if do_compaction {
+            self.maybe_schedule_compaction();
+        }
+
+        let mut results: Vec<Option<Result<Option<Vec<u8>>>>> = keys.iter().map(|_| None).collect();
+        for (l, &i) in lookups.into_iter().zip(order.iter()) {
+            results[i] = Some(match l.result {
+                Some(Err(e)) => Err(e),
+                Some(Ok(v)) => merge_value(op, keys[i], v, &l.operands),
+                None => merge_value(op, keys[i], None, &l.operands),
+            });
+        }
+        Ok(results.into_iter().map(|r| r.unwrap()).collect())
+    }
 }
 
 impl DB {
@@ -489,40 +1067,682 @@ impl DB {
 
     /// new_iter returns a DBIterator over the current state of the database. The iterator will not
     /// return elements added to the database after its creation.
-    pub fn new_iter(&mut self) -> Result<DBIterator> {
-        let snapshot = self.get_snapshot();
-        self.new_iter_at(snapshot)
+    pub fn new_iter(&self) -> Result<DBIterator> {
+        self.new_iter_opt(&ReadOptions::default())
     }
 
     /// new_iter_at returns a DBIterator at the supplied snapshot.
-    pub fn new_iter_at(&mut self, ss: Snapshot) -> Result<DBIterator> {
+    pub fn new_iter_at(&self, ss: Snapshot) -> Result<DBIterator> {
+        let ro = ReadOptions {
+            snapshot: Some(ss),
+            ..ReadOptions::default()
+        };
+        self.new_iter_opt(&ro)
+    }
+
+    /// new_iter_opt returns a DBIterator as specified by the supplied ReadOptions. Without a
+    /// snapshot in `ro`, the iterator reads the current state of the database.
+    pub fn new_iter_opt(&self, ro: &ReadOptions) -> Result<DBIterator> {
+        self.new_iter_cf_opt(ro, &ColumnFamilyHandle::default())
+    }
+
+    /// new_iter_cf returns a DBIterator over the current state of a column family.
+    pub fn new_iter_cf(&self, cf: &ColumnFamilyHandle) -> Result<DBIterator> {
+        self.new_iter_cf_opt(&ReadOptions::default(), cf)
+    }
+
+    /// new_iter_cf_opt returns a DBIterator over a column family, as specified by the supplied
+    /// ReadOptions.
+    pub fn new_iter_cf_opt(&self, ro: &ReadOptions, cf: &ColumnFamilyHandle) -> Result<DBIterator> {
+        let opt = self.cf_options(cf.id())?;
+        let ss = match ro.snapshot {
+            Some(ref ss) => ss.clone(),
+            None => self.get_snapshot(),
+        };
+        let (iter, tombstones, version) = self.merge_iterators(ro, cf.id(), &opt)?;
         Ok(DBIterator::new(
-            self.opt.cmp.clone(),
+            opt.cmp.clone(),
             self.vset.clone(),
-            self.merge_iterators()?,
+            cf.id(),
+            version,
+            iter,
+            tombstones,
             ss,
+            ro.iterate_lower_bound.clone(),
+            ro.iterate_upper_bound.clone(),
+            opt.merge_operator.clone(),
+            self.opt.env.clone(),
+            self.opt.statistics.clone(),
         ))
     }
 
+    /// new_prefix_iter returns a DBIterator over the entries whose keys start with `prefix`. This
+    /// relies on the comparator ordering keys bytewise, like the default comparator does.
+    pub fn new_prefix_iter(&self, prefix: &[u8]) -> Result<DBIterator> {
+        let ro = ReadOptions {
+            iterate_lower_bound: Some(prefix.to_vec()),
+            iterate_upper_bound: prefix_upper_bound(prefix),
+            ..ReadOptions::default()
+        };
+        self.new_iter_opt(&ro)
+    }
+
+    /// latest_sequence returns the sequence number of the newest entry for a key, or of the newest
+    /// range tombstone covering it. It returns 0 if there is no such entry.
+    ///
+    /// Entries that were written after the oldest live snapshot are never removed by compactions,
+    /// so as long as a snapshot is held, any write to the key after it can be detected.
+    fn latest_sequence(&self, cf: u32, key: &[u8]) -> Result<SequenceNumber> {
+        let (mem, imm, version) = self.memtables(cf)?;
+        // Newer sources shadow older ones, so the first one mentioning the key has the newest
+        // entry.
+        for m in Some(&mem).into_iter().chain(imm.as_ref()) {
+            let seq = m.latest_sequence(key);
+            if seq > 0 {
+                return Ok(seq);
+            }
+        }
+        let version = version.borrow();
+        version.latest_sequence(key)
+    }
+
     /// merge_iterators produces a MergingIter merging the entries in the memtable, the immutable
-    /// memtable, and table files from all levels.
-    fn merge_iterators(&mut self) -> Result<MergingIter> {
+    /// memtable, and table files from all levels of a column family, together with the range
+    /// tombstones from the same sources and the version the table files belong to.
+    fn merge_iterators(
+        &self,
+        ro: &ReadOptions,
+        cf: u32,
+        opt: &Options,
+    ) -> Result<(MergingIter, Vec<RangeTombstone>, Shared<Version>)> {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
-        if self.mem.len() > 0 {
-            iters.push(Box::new(self.mem.iter()));
+        let mut tombstones = vec![];
+        let (mem, imm, version) = self.memtables(cf)?;
+        if mem.len() > 0 {
+            iters.push(Box::new(mem.iter()));
+            tombstones.extend(mem.range_tombstones());
         }
-        if let Some(ref imm) = self.imm {
+        if let Some(ref imm) = imm {
             if imm.len() > 0 {
                 iters.push(Box::new(imm.iter()));
+                tombstones.extend(imm.range_tombstones());
             }
         }
 
         // Add iterators for table files.
-        let current = self.current();
-        let current = current.borrow();
-        iters.extend(current.new_iters()?);
+        {
+            let current = version.borrow();
+            iters.extend(current.new_iters(ro)?);
+            tombstones.extend(current.range_tombstones()?);
+        }
+
+        let icmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        let mut iter = MergingIter::new(icmp, iters);
+        iter.set_bounds(
+            ro.iterate_lower_bound.as_ref().map(|b| internal_bound(b)),
+            ro.iterate_upper_bound.as_ref().map(|b| internal_bound(b)),
+        );
+        Ok((iter, tombstones, version))
+    }
+}
+
+impl DB {
+    // COLUMN FAMILIES //
+
+    /// create_column_family adds a new, empty column family using the supplied options (e.g.
+    /// comparator, filter policy and compression). Database-wide settings like `env` are taken
+    /// from the database's options.
+    pub fn create_column_family(&self, name: &str, opt: Options) -> Result<ColumnFamilyHandle> {
+        self.check_writable()?;
+        let w = self.writer.borrow_mut();
+        // Don't interfere with memtable flushes.
+        let _c = self.compactor.borrow_mut();
+        let log_num = w.log_num.unwrap_or(0);
+        let cf = self
+            .vset
+            .borrow_mut()
+            .create_column_family(name, opt, log_num)?;
+        let cmp = self.cf_options(cf)?.cmp;
+        self.mem.borrow_mut().insert(cf, MemTable::new(cmp));
+        log!(self.opt.log, "Created column family {} ({})", name, cf);
+        Ok(ColumnFamilyHandle::new(cf, name))
+    }
+
+    /// drop_column_family removes a column family and all its entries. Handles referring to it
+    /// become invalid. The default column family can't be dropped.
+    pub fn drop_column_family(&self, cf: &ColumnFamilyHandle) -> Result<()> {
+        self.check_writable()?;
+        let _w = self.writer.borrow_mut();
+        let _c = self.compactor.borrow_mut();
+        self.vset.borrow_mut().drop_column_family(cf.id())?;
+        self.mem.borrow_mut().remove(&cf.id());
+        log!(
+            self.opt.log,
+            "Dropped column family {} ({})",
+            cf.name(),
+            cf.id()
+        );
+        self.delete_obsolete_files()
+    }
+
+    /// column_family returns a handle to the column family with the given name, if it exists.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamilyHandle> {
+        self.vset
+            .borrow()
+            .column_family_id(name)
+            .map(|cf| ColumnFamilyHandle::new(cf, name))
+    }
+
+    /// column_families returns handles to all column families of the database, including the
+    /// default one.
+    pub fn column_families(&self) -> Vec<ColumnFamilyHandle> {
+        self.vset
+            .borrow()
+            .column_families()
+            .into_iter()
+            .map(|(cf, name)| ColumnFamilyHandle::new(cf, &name))
+            .collect()
+    }
+
+    /// get_table returns a table of a column family from its table cache.
+    fn get_table(&self, cf: u32, num: FileNum) -> Result<Table> {
+        let cache = self.vset.borrow().table_cache(cf);
+        match cache {
+            Some(cache) => cache.borrow_mut().get_table(num),
+            None => unknown_column_family(cf),
+        }
+    }
+}
+
+impl DB {
+    // TRANSACTIONS //
+
+    /// begin_transaction starts an optimistic transaction on the default column family. Its
+    /// reads observe a snapshot taken now, and its writes are buffered until it is committed.
+    pub fn begin_transaction(&self) -> Transaction {
+        Transaction::new(self, self.get_snapshot())
+    }
+}
+
+impl DB {
+    // CHECKPOINTS //
+
+    /// checkpoint creates a consistent copy of the open database in the directory `dest`, which
+    /// must be empty or not exist yet. The memtables are flushed first, so that the copy consists
+    /// of the live table files and the manifest; it can be opened like any other database.
+    ///
+    /// A read-only or secondary instance never writes to its directory, so it doesn't flush;
+    /// instead, the log files whose entries haven't been written to tables yet are copied too.
+    ///
+    /// Files are copied through the database's `Env`. With `PosixDiskEnv` in SGX mode, the copies
+    /// are protected files encrypted with the same key.
+    pub fn checkpoint<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        let dest = dest.as_ref();
+        let env = self.opt.env.as_ref().as_ref();
+        if env.children(dest).map(|c| !c.is_empty()).unwrap_or(false) {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("checkpoint directory {} is not empty", dest.display()),
+            );
+        }
+
+        if !self.read_only {
+            self.make_room_for_write(&mut self.writer.borrow_mut(), true)?;
+            let mut c = self.compactor.borrow_mut();
+            if self.imm.borrow().is_some() {
+                self.compact_memtable(&mut c)?;
+            }
+        }
+
+        let result = self.with_live_files(|env, files| {
+            env.mkdir(dest)?;
+            let mut manifest_num = 0;
+            for f in files {
+                match f.typ {
+                    FileType::Table => {}
+                    FileType::Descriptor => manifest_num = f.num,
+                    FileType::Log if self.read_only => {}
+                    // Entries written since the memtables were flushed are not included.
+                    _ => continue,
+                }
+                let name = dest.join(f.path.file_name().unwrap());
+                copy_file(env, &f.path, env, &name, f.size)?;
+            }
+            set_current_file(&self.opt.env, dest, manifest_num)
+        });
+
+        if result.is_err() {
+            let _ = env.rmdir(dest);
+        } else {
+            log!(self.opt.log, "Created checkpoint in {}", dest.display());
+        }
+        result
+    }
+
+    /// with_live_files calls `f` with the database's Env and the files making up the current
+    /// state of the database: the live table files, the manifest, and the log files with entries
+    /// that haven't been written to tables yet. No files are deleted while `f` runs.
+    pub(crate) fn with_live_files<T, F: FnOnce(&dyn Env, &[LiveFile]) -> Result<T>>(
+        &self,
+        f: F,
+    ) -> Result<T> {
+        *self.deletions_paused.borrow_mut() += 1;
+        let result = self
+            .live_files()
+            .and_then(|files| f(self.opt.env.as_ref().as_ref(), &files));
+        *self.deletions_paused.borrow_mut() -= 1;
+
+        // Delete files that became obsolete in the meantime.
+        let _c = self.compactor.borrow_mut();
+        self.delete_obsolete_files()?;
+        result
+    }
+
+    /// live_files returns the files making up the current state of the database.
+    fn live_files(&self) -> Result<Vec<LiveFile>> {
+        let env = self.opt.env.as_ref().as_ref();
+        let mut files = vec![];
+        let add = |files: &mut Vec<LiveFile>, path: PathBuf, typ, num| -> Result<()> {
+            let size = env.size_of(&path)?;
+            files.push(LiveFile {
+                path: path,
+                typ: typ,
+                num: num,
+                size: size,
+            });
+            Ok(())
+        };
+
+        // While the writer lock is held, the log files end with a complete record. While the
+        // version set is locked, the manifest ends with a complete edit, which refers to the live
+        // table files.
+        let mut w = self.writer.borrow_mut();
+        if let Some(ref mut log) = w.log {
+            log.flush()?;
+        }
+        let vset = self.vset.borrow();
+        for num in vset.live_files() {
+            add(
+                &mut files,
+                table_file_name(&self.name, num),
+                FileType::Table,
+                num,
+            )?;
+        }
+        let manifest = manifest_file_name(&self.name, vset.manifest_num);
+        add(
+            &mut files,
+            manifest,
+            FileType::Descriptor,
+            vset.manifest_num,
+        )?;
+        for name in env.children(&self.name)? {
+            if let Ok((num, FileType::Log)) = parse_file_name(&name) {
+                if num >= vset.log_num {
+                    add(&mut files, self.name.join(&name), FileType::Log, num)?;
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl DB {
+    // EXTERNAL FILES //
+
+    /// ingest_external_file adds table files written by an `SstFileWriter` to the default column
+    /// family. The files must not overlap each other. Their entries are assigned one sequence
+    /// number newer than all existing entries, i.e. they replace the current values of their keys.
+    /// Each file is added to the deepest level at which no newer data overlaps it.
+    ///
+    /// The files are copied into the database; the originals are left in place.
+    pub fn ingest_external_file<P: AsRef<Path>>(&self, paths: &[P]) -> Result<()> {
+        self.ingest_external_file_cf(&ColumnFamilyHandle::default(), paths)
+    }
+
+    /// ingest_external_file_cf works like ingest_external_file(), on the specified column family.
+    pub fn ingest_external_file_cf<P: AsRef<Path>>(
+        &self,
+        cf: &ColumnFamilyHandle,
+        paths: &[P],
+    ) -> Result<()> {
+        self.check_writable()?;
+        let cf = cf.id();
+        let opt = self.cf_options(cf)?;
+        let ucmp = opt.cmp.as_ref().as_ref();
+
+        let mut files = vec![];
+        for p in paths {
+            files.push(ExternalFile::open(&opt, p.as_ref())?);
+        }
+        files.sort_by(|a, b| ucmp.cmp(&a.smallest, &b.smallest));
+        for pair in files.windows(2) {
+            if ucmp.cmp(&pair[0].largest, &pair[1].smallest) != Ordering::Less {
+                return err(
+                    StatusCode::InvalidArgument,
+                    &format!(
+                        "external files {} and {} overlap",
+                        pair[0].path.display(),
+                        pair[1].path.display()
+                    ),
+                );
+            }
+        }
+
+        let mut w = self.writer.borrow_mut();
+        // Lookups consult the memtables before the tables, so entries overlapping the ingested
+        // files have to be flushed first.
+        let overlaps = {
+            let mem = self.mem.borrow();
+            let imm = self.imm.borrow();
+            let mut mems = mem
+                .get(&cf)
+                .into_iter()
+                .chain(imm.iter().filter_map(|&(ref imm, _)| imm.get(&cf)));
+            mems.any(|m| {
+                files
+                    .iter()
+                    .any(|f| memtable_overlaps(ucmp, m, &f.smallest, &f.largest))
+            })
+        };
+        if overlaps {
+            self.make_room_for_write(&mut w, true)?;
+        }
+        let mut c = self.compactor.borrow_mut();
+        if overlaps && self.imm.borrow().is_some() {
+            self.compact_memtable(&mut c)?;
+        }
+
+        let current = self.cf_current(cf)?;
+        let seq = self.vset.borrow().last_seq + 1;
+        let mut ve = VersionEdit::new();
+        ve.set_column_family(cf);
+        let mut written = vec![];
+        let mut result = Ok(());
+        for f in files.iter() {
+            let num = self.vset.borrow_mut().new_file_number();
+            match f.copy_to(&self.path, &opt, num, seq) {
+                Ok(md) => {
+                    let level = current
+                        .borrow()
+                        .pick_ingestion_level(&f.smallest, &f.largest);
+                    log!(
+                        self.opt.log,
+                        "Ingesting {} as table {:06} at level {}",
+                        f.path.display(),
+                        num,
+                        level
+                    );
+                    written.push(num);
+                    ve.add_file(level, md);
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            // The new sequence number is recorded in the manifest, but it must not be used if the
+            // files couldn't be installed.
+            let mut vset = self.vset.borrow_mut();
+            let last_seq = vset.last_seq;
+            vset.last_seq = seq;
+            result = vset.log_and_apply(ve);
+            if result.is_err() {
+                vset.last_seq = last_seq;
+            }
+        }
+        if let Err(e) = result {
+            for num in written {
+                let _ = self.opt.env.delete(&table_file_name(&self.path, num));
+            }
+            return Err(e);
+        }
+        self.maybe_schedule_compaction();
+        Ok(())
+    }
+}
+
+impl DB {
+    // SECONDARY INSTANCES //
+
+    /// try_catch_up_with_primary applies the changes made by the primary instance since the
+    /// secondary was opened or last caught up: new edits in the manifest, and new records in the
+    /// log files. Only updates that the primary has written to its log file (e.g. by writing
+    /// with `sync` or calling `flush()`) are seen. If this fails, e.g. because the primary
+    /// deleted a file while it was being read, it can simply be retried.
+    ///
+    /// Reads may fail with StatusCode::NotFound if the primary has deleted table files since the
+    /// last call, e.g. after a compaction; catching up fixes that as well.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        match self.secondary {
+            Some(ref s) => self.catch_up(&mut s.borrow_mut()),
+            None => err(
+                StatusCode::NotSupported,
+                "database has not been opened as a secondary instance",
+            ),
+        }
+    }
+
+    /// catch_up reads the changes to the manifest and log files since the state recorded in
+    /// `s`, which is updated accordingly.
+    fn catch_up(&self, s: &mut SecondaryState) -> Result<()> {
+        let env = self.opt.env.as_ref().as_ref();
+        let current = match read_current_file(&self.opt.env, &self.path) {
+            Ok(current) => current,
+            Err(ref e) if e.code == StatusCode::NotFound => {
+                return err(StatusCode::NotFound, "database does not exist")
+            }
+            Err(e) => return Err(e),
+        };
+        let manifest = self.path.join(current.trim());
+
+        // The new state is put together aside first. Readers must never see tables together with
+        // memtables that hold the same entries (merge operands would be applied twice), so the
+        // version set and the memtables are replaced at once.
+        let (mut vset, manifest_off) = if s.manifest.as_ref() != Some(&manifest) {
+            // A new manifest starts with the complete state of the database.
+            let (records, off) = read_new_records(env, &manifest, 0)?;
+            let mut vset = VersionSet::new(&self.name, self.opt.clone(), self.cache.clone());
+            vset.apply_manifest_edits(&decode_edits(&records)?, true)?;
+            if let Ok((num, FileType::Descriptor)) = parse_file_name(current.trim()) {
+                vset.manifest_num = num;
+            }
+            (vset, off)
+        } else {
+            let (records, off) = read_new_records(env, &manifest, s.manifest_off)?;
+            let mut vset = self.vset.borrow().duplicate();
+            vset.apply_manifest_edits(&decode_edits(&records)?, false)?;
+            (vset, off)
+        };
+
+        // Once the entries of a log file have been written to tables, they mustn't be read from
+        // the memtables as well. Then the memtables are rebuilt from the remaining log files.
+        let log_nums: Vec<(u32, FileNum)> = vset
+            .column_families()
+            .into_iter()
+            .map(|(cf, _)| (cf, vset.column_family_log_num(cf).unwrap_or(0)))
+            .collect();
+        let rebuild = log_nums != s.log_nums;
+
+        let mut logs = vec![];
+        for log_num in self.log_files_to_recover(&vset)? {
+            let filename = log_file_name(&self.path, log_num);
+            let off = match s.logs.get(&log_num) {
+                Some(&off) if !rebuild => off,
+                _ => 0,
+            };
+            let (records, off) = read_new_records(env, Path::new(&filename), off)?;
+            logs.push((log_num, records, off));
+        }
+        let mut rebuilt = None;
+        if rebuild {
+            let mut mems = DB::new_memtables(&vset, None);
+            for &(log_num, ref records, _) in &logs {
+                let seq = DB::replay_log_records(&vset, &mut mems, log_num, records);
+                if seq > vset.last_seq {
+                    vset.last_seq = seq;
+                }
+            }
+            rebuilt = Some(mems);
+        }
+
+        let mut mem = self.mem.borrow_mut();
+        let _imm = self.imm.borrow_mut();
+        let mut current = self.vset.borrow_mut();
+        match rebuilt {
+            Some(mems) => *mem = mems,
+            None => {
+                for &(log_num, ref records, _) in &logs {
+                    let seq = DB::replay_log_records(&vset, &mut mem, log_num, records);
+                    if seq > vset.last_seq {
+                        vset.last_seq = seq;
+                    }
+                }
+            }
+        }
+        if vset.last_seq < current.last_seq {
+            vset.last_seq = current.last_seq;
+        }
+        *current = vset;
+
+        s.manifest = Some(manifest);
+        s.manifest_off = manifest_off;
+        s.log_nums = log_nums;
+        s.logs = logs.into_iter().map(|(num, _, off)| (num, off)).collect();
+        Ok(())
+    }
+
+    /// replay_log_records inserts the batches read from a log file into the memtables, leaving
+    /// out column families whose entries in that log file are already contained in tables. It
+    /// returns the largest sequence number found.
+    fn replay_log_records(
+        vset: &VersionSet,
+        mems: &mut BTreeMap<u32, MemTable>,
+        log_num: FileNum,
+        records: &[Vec<u8>],
+    ) -> SequenceNumber {
+        let mut skipped = BTreeMap::new();
+        for (cf, _) in vset.column_families() {
+            if vset.column_family_log_num(cf).unwrap_or(0) > log_num {
+                if let Some(m) = mems.remove(&cf) {
+                    skipped.insert(cf, m);
+                }
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        let mut max_seq = 0;
+        for record in records {
+            if record.len() < 12 {
+                // Like during recovery, records too short to be a batch are skipped.
+                continue;
+            }
+            batch.set_contents(record);
+            batch.insert_into_memtables(batch.sequence(), mems);
+            let last_seq = batch.sequence() + batch.count() as u64 - 1;
+            if last_seq > max_seq {
+                max_seq = last_seq;
+            }
+        }
+        mems.extend(skipped);
+        max_seq
+    }
+}
+
+impl DB {
+    // UPDATES //
+
+    /// get_updates_since returns an iterator over the write batches containing updates with
+    /// sequence numbers from `seq` on, as recorded in the log files. It fails with
+    /// StatusCode::NotFound if some of these updates have already been removed from the logs;
+    /// use `Options::retain_log_files` to prevent that.
+    pub fn get_updates_since(&self, seq: SequenceNumber) -> Result<UpdateIterator> {
+        // Make sure that all batches written so far can be read from the log.
+        let last = {
+            let mut w = self.writer.borrow_mut();
+            if let Some(ref mut log) = w.log {
+                log.flush()?;
+            }
+            self.vset.borrow().last_seq
+        };
+        let logs = self.log_files()?;
+
+        let oldest = logs
+            .iter()
+            .filter_map(|&(_, first)| first)
+            .next()
+            .unwrap_or(last + 1);
+        if seq < oldest && oldest > 1 {
+            return err(
+                StatusCode::NotFound,
+                &format!(
+                    "updates before sequence number {} are not available anymore",
+                    oldest
+                ),
+            );
+        }
+        // Start with the last log beginning at or before seq.
+        let start = logs
+            .iter()
+            .rposition(|&(_, first)| first.map(|f| f <= seq).unwrap_or(false))
+            .unwrap_or(0);
+        let paths = logs[start..]
+            .iter()
+            .map(|&(num, _)| log_file_name(&self.path, num))
+            .collect();
+        Ok(UpdateIterator::new(self.opt.env.clone(), paths, seq, last))
+    }
+
+    /// acknowledge_updates tells the database that all updates up to and including `seq` have
+    /// been consumed. With `Options::retain_log_files`, log files only containing acknowledged
+    /// updates can then be deleted.
+    pub fn acknowledge_updates(&self, seq: SequenceNumber) -> Result<()> {
+        {
+            let mut acked = self.updates_acked.borrow_mut();
+            if seq <= *acked {
+                return Ok(());
+            }
+            *acked = seq;
+        }
+        let _c = self.compactor.borrow_mut();
+        self.delete_obsolete_files()
+    }
+
+    /// log_files returns the numbers of the database's log files in ascending order, together
+    /// with the sequence number of the first batch in each file (if any).
+    fn log_files(&self) -> Result<Vec<(FileNum, Option<SequenceNumber>)>> {
+        let env = self.opt.env.as_ref().as_ref();
+        let mut logs = vec![];
+        for name in env.children(&self.path)? {
+            if let Ok((num, FileType::Log)) = parse_file_name(&name) {
+                logs.push(num);
+            }
+        }
+        logs.sort();
+        Ok(logs
+            .into_iter()
+            .map(|num| (num, first_sequence(env, &log_file_name(&self.path, num))))
+            .collect())
+    }
 
-        Ok(MergingIter::new(self.internal_cmp.clone(), iters))
+    /// unacknowledged_logs returns the log files containing updates that haven't been
+    /// acknowledged yet. A log file ends right before the first batch of the next non-empty log.
+    fn unacknowledged_logs(&self) -> Result<Vec<FileNum>> {
+        let acked = *self.updates_acked.borrow();
+        let logs = self.log_files()?;
+        let mut end = self.vset.borrow().last_seq;
+        let mut retained = vec![];
+        for &(num, first) in logs.iter().rev() {
+            if end > acked {
+                retained.push(num);
+            }
+            if let Some(first) = first {
+                end = first.saturating_sub(1);
+            }
+        }
+        Ok(retained)
     }
 }
 
@@ -531,25 +1751,163 @@ impl DB {
 
     /// Returns a snapshot at the current state. It can be used to retrieve entries from the
     /// database as they were at an earlier point in time.
-    pub fn get_snapshot(&mut self) -> Snapshot {
+    pub fn get_snapshot(&self) -> Snapshot {
         self.snaps.new_snapshot(self.vset.borrow().last_seq)
     }
 }
 
 impl DB {
     // STATISTICS //
-    fn add_stats(&mut self, level: usize, cs: CompactionStats) {
+
+    /// get_property returns information about the state of the database, or None if the
+    /// property is unknown. Supported properties are:
+    ///
+    /// * `leveldb.num-files-at-level<N>`: the number of table files at level N.
+    /// * `leveldb.stats`: the sizes of all levels and statistics about compactions.
+    /// * `leveldb.sstables`: a description of all table files.
+    /// * `leveldb.approximate-memory-usage`: the approximate number of bytes used by the
+    ///   memtables and the block cache.
+    /// * `leveldb.statistics`: the text dump of `Options::statistics`, if set.
+    pub fn get_property(&self, property: &str) -> Option<String> {
+        const PREFIX: &str = "leveldb.";
+        if !property.starts_with(PREFIX) {
+            return None;
+        }
+        let property = &property[PREFIX.len()..];
+
+        if property.starts_with("num-files-at-level") {
+            let level = property["num-files-at-level".len()..]
+                .parse::<usize>()
+                .ok()?;
+            if level >= NUM_LEVELS {
+                return None;
+            }
+            let current = self.current();
+            let n = current.borrow().num_level_files(level);
+            return Some(n.to_string());
+        }
+
+        match property {
+            "stats" => {
+                let cstats = self.cstats.borrow().clone();
+                let current = self.current();
+                let current = current.borrow();
+                let mut acc = String::from(
+                    "                               Compactions\n\
+                     Level  Files Size(MB) Time(sec) Read(MB) Write(MB)\n\
+                     --------------------------------------------------\n",
+                );
+                for level in 0..NUM_LEVELS {
+                    let files = current.num_level_files(level);
+                    let stats = &cstats[level];
+                    if files == 0 && stats.micros == 0 {
+                        continue;
+                    }
+                    let mb = f64::from(1 << 20);
+                    acc.push_str(&format!(
+                        "{:>3} {:>8} {:>8.0} {:>9.0} {:>8.0} {:>9.0}\n",
+                        level,
+                        files,
+                        total_size(current.files[level].iter()) as f64 / mb,
+                        stats.micros as f64 / 1e6,
+                        stats.read as f64 / mb,
+                        stats.written as f64 / mb
+                    ));
+                }
+                Some(acc)
+            }
+            "sstables" => Some(self.current().borrow().sstables_summary()),
+            "approximate-memory-usage" => {
+                let mut usage: usize = memtables_usage(&self.mem.borrow());
+                if let Some((ref imm, _)) = *self.imm.borrow() {
+                    usage += memtables_usage(imm);
+                }
+                let cache = self.opt.block_cache.borrow();
+                usage += cache.values().map(|b| b.contents().len()).sum::<usize>();
+                Some(usage.to_string())
+            }
+            "statistics" => self.opt.statistics.as_ref().map(|s| s.to_string()),
+            _ => None,
+        }
+    }
+
+    /// get_approximate_sizes returns the approximate number of bytes that the table files use
+    /// for each of the given key ranges. Recently written entries that are still in the
+    /// memtables are not taken into account.
+    pub fn get_approximate_sizes(&self, ranges: &[Range]) -> Vec<u64> {
+        self.approximate_sizes(ranges, false)
+    }
+
+    /// get_approximate_sizes_with_memtables works like get_approximate_sizes(), but additionally
+    /// counts the entries in the memtables. This is more expensive, as it requires scanning the
+    /// memtables.
+    pub fn get_approximate_sizes_with_memtables(&self, ranges: &[Range]) -> Vec<u64> {
+        self.approximate_sizes(ranges, true)
+    }
+
+    fn approximate_sizes(&self, ranges: &[Range], include_memtables: bool) -> Vec<u64> {
+        let (mem, imm, current) = if include_memtables {
+            // The default column family can't be dropped.
+            let (mem, imm, current) = self.memtables(DEFAULT_COLUMN_FAMILY_ID).unwrap();
+            (Some(mem), imm, current)
+        } else {
+            (None, None, self.current())
+        };
+
+        let mut sizes = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            let start = LookupKey::new(r.start, MAX_SEQUENCE_NUMBER);
+            let limit = LookupKey::new(r.limit, MAX_SEQUENCE_NUMBER);
+            let (start_off, limit_off) = {
+                let current = current.borrow();
+                (
+                    current.approximate_offset(start.internal_key()),
+                    current.approximate_offset(limit.internal_key()),
+                )
+            };
+            let mut size = limit_off.saturating_sub(start_off);
+            for m in mem.iter().chain(imm.iter()) {
+                size += self.approximate_memtable_size(m, r);
+            }
+            sizes.push(size as u64);
+        }
+        sizes
+    }
+
+    /// approximate_memtable_size returns the number of bytes used by the entries of `mem` in the
+    /// given key range.
+    fn approximate_memtable_size(&self, mem: &MemTable, r: &Range) -> usize {
+        let mut iter = mem.iter();
+        iter.seek(LookupKey::new(r.start, MAX_SEQUENCE_NUMBER).internal_key());
+        let (mut key, mut val) = (vec![], vec![]);
+        let mut size = 0;
+        while iter.valid() && iter.current(&mut key, &mut val) {
+            if self.opt.cmp.cmp(parse_internal_key(&key).2, r.limit) != Ordering::Less {
+                break;
+            }
+            size += key.len() + val.len();
+            iter.advance();
+        }
+        size
+    }
+
+    fn add_stats(&self, level: usize, cs: CompactionStats) {
         assert!(level < NUM_LEVELS);
-        self.cstats[level].add(cs);
+        statistics::record(&self.opt.statistics, Ticker::CompactionBytesRead, cs.read);
+        statistics::record(
+            &self.opt.statistics,
+            Ticker::CompactionBytesWritten,
+            cs.written,
+        );
+        self.cstats.borrow_mut()[level].add(cs);
     }
 
     /// Trigger a compaction based on where this key is located in the different levels.
-    fn record_read_sample<'a>(&mut self, k: InternalKey<'a>) {
+    fn record_read_sample<'a>(&self, k: InternalKey<'a>) {
         let current = self.current();
-        if current.borrow_mut().record_read_sample(k) {
-            if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "record_read_sample: compaction failed: {}", e);
-            }
+        let needs_compaction = current.borrow_mut().record_read_sample(k);
+        if needs_compaction {
+            self.maybe_schedule_compaction();
         }
     }
 }
@@ -557,43 +1915,177 @@ impl DB {
 impl DB {
     // COMPACTIONS //
 
-    /// make_room_for_write checks if the memtable has become too large, and triggers a compaction
-    /// if it's the case.
-    fn make_room_for_write(&mut self, force: bool) -> Result<()> {
-        if !force && self.mem.approx_mem_usage() < self.opt.write_buffer_size {
-            Ok(())
-        } else if self.mem.len() == 0 {
-            Ok(())
-        } else {
-            // Create new memtable.
-            let logn = self.vset.borrow_mut().new_file_number();
-            let logf = self
-                .opt
-                .env
-                .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
-            if logf.is_err() {
-                self.vset.borrow_mut().reuse_file_number(logn);
-                Err(logf.err().unwrap())
+    /// make_room_for_write checks if the memtable has become too large, and replaces it with a
+    /// new one if it's the case. The old memtable is written to a table by the background thread.
+    /// If the background thread falls behind, writes are delayed or stopped.
+    fn make_room_for_write(&self, w: &mut WriterState, mut force: bool) -> Result<()> {
+        debug_assert!(!self.read_only);
+        let mut allow_delay = !force;
+        loop {
+            if let Some(ref e) = self.bg.state.lock().unwrap().error {
+                return Err(e.clone());
+            }
+
+            let (usage, len) = {
+                let mem = self.mem.borrow();
+                (
+                    memtables_usage(&mem),
+                    mem.values().map(|m| m.len()).sum::<usize>(),
+                )
+            };
+            let has_imm = self.imm.borrow().is_some();
+            // Every family's memtable is switched, so any family can have too many L0 files.
+            let l0_files = self.vset.borrow().max_level0_files();
+
+            if allow_delay && l0_files >= self.opt.l0_slowdown_writes_trigger {
+                // Delay every write a little bit instead of stalling a single write for a long
+                // time once the stop trigger has been reached.
+                thread::sleep(Duration::from_millis(1));
+                allow_delay = false;
+            } else if !force && usage < self.opt.write_buffer_size {
+                return Ok(());
+            } else if len == 0 {
+                return Ok(());
+            } else if has_imm {
+                log!(self.opt.log, "Memtable is full; waiting for compaction");
+                self.wait_for_background_work();
+            } else if l0_files >= self.opt.l0_stop_writes_trigger {
+                log!(self.opt.log, "Too many L0 files; waiting for compaction");
+                self.wait_for_background_work();
             } else {
-                self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
-                self.log_num = Some(logn);
+                // Create new memtable.
+                let logn = self.vset.borrow_mut().new_file_number();
+                let logf = self
+                    .opt
+                    .env
+                    .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
+                if logf.is_err() {
+                    self.vset.borrow_mut().reuse_file_number(logn);
+                    return Err(logf.err().unwrap());
+                }
+                w.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
+                w.log_num = Some(logn);
+
+                let mems = DB::new_memtables(&self.vset.borrow(), None);
+                {
+                    let mut mem = self.mem.borrow_mut();
+                    let mut imm = self.imm.borrow_mut();
+                    let full = mem::replace(&mut *mem, mems);
+                    *imm = Some((full, logn));
+                }
+                force = false;
+                self.maybe_schedule_compaction();
+            }
+        }
+    }
+
+    /// needs_compaction returns true if there is an immutable memtable to be written, or if the
+    /// current version should be compacted.
+    fn needs_compaction(&self) -> bool {
+        self.imm.borrow().is_some() || self.vset.borrow().needs_compaction()
+    }
+
+    /// maybe_schedule_compaction wakes up the background thread if there is work for it.
+    fn maybe_schedule_compaction(&self) {
+        if self.read_only {
+            return;
+        }
+        let mut state = self.bg.state.lock().unwrap();
+        if state.shutdown || state.error.is_some() || state.scheduled {
+            return;
+        }
+        if self.needs_compaction() {
+            state.scheduled = true;
+            self.bg.cv.notify_all();
+        }
+    }
+
+    /// wait_for_background_work blocks until the background thread has finished its current unit
+    /// of work. It returns immediately if no work is scheduled.
+    fn wait_for_background_work(&self) {
+        self.maybe_schedule_compaction();
+        let state = self.bg.state.lock().unwrap();
+        if (state.scheduled || state.running) && state.error.is_none() && !state.shutdown {
+            drop(self.bg.cv.wait(state).unwrap());
+        }
+    }
+
+    /// wait_for_compactions blocks until the background thread has finished all pending memtable
+    /// flushes and compactions. It returns the error of a failed background compaction, if any.
+    pub fn wait_for_compactions(&self) -> Result<()> {
+        self.maybe_schedule_compaction();
+        let mut state = self.bg.state.lock().unwrap();
+        while (state.scheduled || state.running) && state.error.is_none() && !state.shutdown {
+            state = self.bg.cv.wait(state).unwrap();
+        }
+        match state.error {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// start_background_thread spawns the thread running memtable flushes and compactions.
+    fn start_background_thread(&mut self) -> Result<()> {
+        // The background thread's handle doesn't keep the thread alive.
+        let db = DB {
+            bg_thread: None,
+            ..self.clone()
+        };
+        let handle = thread::Builder::new()
+            .name("leveldb-compaction".to_string())
+            .spawn(move || db.background_thread())?;
+        self.bg_thread = Some(Arc::new(BackgroundThread {
+            bg: self.bg.clone(),
+            handle: Some(handle),
+        }));
+        Ok(())
+    }
+
+    /// background_thread runs scheduled compactions until the database is closed.
+    fn background_thread(&self) {
+        loop {
+            {
+                let mut state = self.bg.state.lock().unwrap();
+                while !state.scheduled && !state.shutdown {
+                    state = self.bg.cv.wait(state).unwrap();
+                }
+                if state.shutdown {
+                    return;
+                }
+                state.scheduled = false;
+                state.running = true;
+            }
 
-                let mut imm = MemTable::new(self.opt.cmp.clone());
-                mem::swap(&mut imm, &mut self.mem);
-                self.imm = Some(imm);
-                self.maybe_do_compaction()
+            let result = self.maybe_do_compaction(&mut self.compactor.borrow_mut());
+
+            let mut state = self.bg.state.lock().unwrap();
+            state.running = false;
+            match result {
+                Err(e) => {
+                    log!(self.opt.log, "Background compaction failed: {}", e);
+                    state.error = Some(e);
+                }
+                Ok(()) => {
+                    // A compaction may produce enough data to make another one necessary.
+                    if !state.shutdown && self.needs_compaction() {
+                        state.scheduled = true;
+                    }
+                }
             }
+            self.bg.cv.notify_all();
         }
     }
 
-    /// maybe_do_compaction starts a blocking compaction if it makes sense.
-    fn maybe_do_compaction(&mut self) -> Result<()> {
-        if self.imm.is_some() {
-            self.compact_memtable()
-        } else if self.vset.borrow().needs_compaction() {
-            let c = self.vset.borrow_mut().pick_compaction();
-            if let Some(c) = c {
-                self.start_compaction(c)
+    /// maybe_do_compaction runs a single blocking compaction if it makes sense.
+    fn maybe_do_compaction(&self, c: &mut CompactorState) -> Result<()> {
+        let has_imm = self.imm.borrow().is_some();
+        let needs_compaction = self.vset.borrow().needs_compaction();
+        if has_imm {
+            self.compact_memtable(c)
+        } else if needs_compaction {
+            let compaction = self.vset.borrow_mut().pick_compaction();
+            if let Some(compaction) = compaction {
+                self.start_compaction(c, compaction)
             } else {
                 Ok(())
             }
@@ -607,10 +2099,17 @@ impl DB {
     ///
     /// Compactions in general will cause the database to find entries more quickly, and take up
     /// less space on disk.
-    pub fn compact_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+    pub fn compact_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.compact_range_cf(&ColumnFamilyHandle::default(), from, to)
+    }
+
+    /// compact_range_cf works like compact_range(), on the specified column family.
+    pub fn compact_range_cf(&self, cf: &ColumnFamilyHandle, from: &[u8], to: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let cf = cf.id();
         let mut max_level = 1;
         {
-            let v = self.vset.borrow().current();
+            let v = self.cf_current(cf)?;
             let v = v.borrow();
             for l in 1..NUM_LEVELS - 1 {
                 if v.overlap_in_level(l, from, to) {
@@ -620,7 +2119,12 @@ impl DB {
         }
 
         // Compact memtable.
-        self.make_room_for_write(true)?;
+        self.make_room_for_write(&mut self.writer.borrow_mut(), true)?;
+
+        let mut c = self.compactor.borrow_mut();
+        if self.imm.borrow().is_some() {
+            self.compact_memtable(&mut c)?;
+        }
 
         let mut ifrom = LookupKey::new(from, MAX_SEQUENCE_NUMBER)
             .internal_key()
@@ -629,15 +2133,15 @@ impl DB {
 
         for l in 0..max_level + 1 {
             loop {
-                let c_ = self
-                    .vset
-                    .borrow_mut()
-                    .compact_range(l, &ifrom, iend.internal_key());
-                if let Some(c) = c_ {
+                let compaction =
+                    self.vset
+                        .borrow_mut()
+                        .compact_range(cf, l, &ifrom, iend.internal_key());
+                if let Some(compaction) = compaction {
                     // Update ifrom to the largest key of the last file in this compaction.
-                    let ix = c.num_inputs(0) - 1;
-                    ifrom = c.input(0, ix).largest.clone();
-                    self.start_compaction(c)?;
+                    let ix = compaction.num_inputs(0) - 1;
+                    ifrom = compaction.input(0, ix).largest.clone();
+                    self.start_compaction(&mut c, compaction)?;
                 } else {
                     break;
                 }
@@ -648,7 +2152,7 @@ impl DB {
 
     /// start_compaction dispatches the different kinds of compactions depending on the current
     /// state of the database.
-    fn start_compaction(&mut self, mut compaction: Compaction) -> Result<()> {
+    fn start_compaction(&self, c: &mut CompactorState, mut compaction: Compaction) -> Result<()> {
         if compaction.is_trivial_move() {
             assert_eq!(1, compaction.num_inputs(0));
             let f = compaction.input(0, 0);
@@ -685,10 +2189,12 @@ impl DB {
             } else {
                 self.snaps.oldest()
             };
-            let mut state = CompactionState::new(compaction, smallest);
-            if let Err(e) = self.do_compaction_work(&mut state) {
+            let opt = self.cf_options(compaction.column_family())?;
+            let mut state = CompactionState::new(compaction, smallest, opt);
+            if let Err(e) = self.do_compaction_work(c, &mut state) {
                 state.cleanup(&self.opt.env, &self.path);
                 log!(self.opt.log, "Compaction work failed: {}", e);
+                return Err(e);
             }
             self.install_compaction_results(state)?;
             log!(
@@ -701,36 +2207,53 @@ impl DB {
         }
     }
 
-    fn compact_memtable(&mut self) -> Result<()> {
-        assert!(self.imm.is_some());
-
-        let mut ve = VersionEdit::new();
-        let base = self.current();
-
-        let imm = self.imm.take().unwrap();
-        if let Err(e) = self.write_l0_table(&imm, &mut ve, Some(&base.borrow())) {
-            self.imm = Some(imm);
-            return Err(e);
+    fn compact_memtable(&self, c: &mut CompactorState) -> Result<()> {
+        debug_assert!(!self.read_only);
+        let imm = self.imm.borrow().clone();
+        assert!(imm.is_some());
+
+        // The immutable memtable of a family stays visible to readers until the new version
+        // containing its table has been installed. Both happen while the immutable memtables are
+        // locked, so that readers (see memtables()) never see the memtable and its table at once.
+        let (mems, log_num) = imm.unwrap();
+        for (cf, mem) in mems.iter() {
+            // The column family may have been dropped in the meantime.
+            let base = match self.vset.borrow().current_cf(*cf) {
+                Some(base) => base,
+                None => continue,
+            };
+            let mut ve = VersionEdit::new();
+            ve.set_column_family(*cf);
+            self.write_l0_table(c, *cf, mem, &mut ve, Some(&base.borrow()))?;
+            ve.set_log_num(log_num);
+
+            let mut imm = self.imm.borrow_mut();
+            self.vset.borrow_mut().log_and_apply(ve)?;
+            if let Some((ref mut mems, _)) = *imm {
+                mems.remove(cf);
+            }
         }
-        ve.set_log_num(self.log_num.unwrap_or(0));
-        self.vset.borrow_mut().log_and_apply(ve)?;
+        *self.imm.borrow_mut() = None;
         if let Err(e) = self.delete_obsolete_files() {
             log!(self.opt.log, "Error deleting obsolete files: {}", e);
         }
         Ok(())
     }
 
-    /// write_l0_table writes the given memtable to a table file.
+    /// write_l0_table writes the given memtable of column family `cf` to a table file.
     fn write_l0_table(
-        &mut self,
+        &self,
+        _c: &mut CompactorState,
+        cf: u32,
         memt: &MemTable,
         ve: &mut VersionEdit,
         base: Option<&Version>,
     ) -> Result<()> {
+        let opt = self.cf_options(cf)?;
         let start_ts = self.opt.env.micros();
         let num = self.vset.borrow_mut().new_file_number();
         log!(self.opt.log, "Start write of L0 table {:06}", num);
-        let fmd = build_table(&self.path, &self.opt, memt.iter(), num)?;
+        let fmd = build_table(&self.path, &opt, memt.iter(), &memt.range_tombstones(), num)?;
         log!(self.opt.log, "L0 table {:06} has {} bytes", num, fmd.size);
 
         // Wrote empty table.
@@ -739,7 +2262,7 @@ impl DB {
             return Ok(());
         }
 
-        let cache_result = self.cache.borrow_mut().get_table(num);
+        let cache_result = self.get_table(cf, num);
         if let Err(e) = cache_result {
             log!(
                 self.opt.log,
@@ -772,9 +2295,10 @@ impl DB {
         Ok(())
     }
 
-    fn do_compaction_work(&mut self, cs: &mut CompactionState) -> Result<()> {
+    fn do_compaction_work(&self, _c: &mut CompactorState, cs: &mut CompactionState) -> Result<()> {
+        let opt = cs.opt.clone();
         {
-            let current = self.vset.borrow().current();
+            let current = self.cf_current(cs.compaction.column_family())?;
             assert!(current.borrow().num_level_files(cs.compaction.level()) > 0);
             assert!(cs.builder.is_none());
         }
@@ -788,6 +2312,26 @@ impl DB {
             cs.compaction.level() + 1
         );
 
+        // Entries covered by a range tombstone that is visible to all snapshots are dropped.
+        // Such tombstones are dropped as well if no older entries in their range may exist in
+        // levels below the ones being compacted; the others are written to the outputs.
+        let input_tombstones = self
+            .vset
+            .borrow()
+            .compaction_range_tombstones(&cs.compaction)?;
+        cs.tombstones = input_tombstones
+            .iter()
+            .filter(|t| {
+                t.seq > cs.smallest_seq || !cs.compaction.is_base_level_for_range(&t.start, &t.end)
+            })
+            .cloned()
+            .collect();
+
+        // The compaction filter must not change what snapshots observe, so it only sees entries
+        // newer than all snapshots.
+        let newest_snapshot = self.snaps.newest();
+        let now = self.opt.env.micros();
+
         let mut input = self.vset.borrow().make_input_iterator(&cs.compaction);
         input.seek_to_first();
 
@@ -801,8 +2345,18 @@ impl DB {
             // TODO: Do we need to do a memtable compaction here? Probably not, in the sequential
             // case.
             assert!(input.current(&mut key, &mut val));
-            if cs.compaction.should_stop_before(&key) && cs.builder.is_none() {
-                self.finish_compaction_output(cs, key.clone())?;
+            if parse_internal_key(&key).0 == ValueType::TypeValueWithTTL
+                && split_expiry(&val).1 <= now
+            {
+                // Expired values are compacted like deletions.
+                let del = {
+                    let (_, seq, ukey) = parse_internal_key(&key);
+                    LookupKey::new_full(ukey, seq, ValueType::TypeDeletion)
+                        .internal_key()
+                        .to_vec()
+                };
+                key = del;
+                val.clear();
             }
             let (ktyp, seq, ukey) = parse_internal_key(&key);
             if seq == 0 {
@@ -815,8 +2369,16 @@ impl DB {
                 continue;
             }
 
-            if !have_ukey || self.opt.cmp.cmp(ukey, &current_ukey) != Ordering::Equal {
-                // First occurrence of this key.
+            if !have_ukey || opt.cmp.cmp(ukey, &current_ukey) != Ordering::Equal {
+                // First occurrence of this key. Outputs are only split between different user
+                // keys, so that the range tombstones in them can be clipped to the outputs' ranges.
+                let stop_before = cs.compaction.should_stop_before(&key);
+                if let Some(ref b) = cs.builder {
+                    // NOTE: Adjust max file size based on level.
+                    if stop_before || b.size_estimate() > opt.max_file_size {
+                        self.finish_compaction_output(cs, Some(ukey))?;
+                    }
+                }
                 current_ukey.clear();
                 current_ukey.extend_from_slice(ukey);
                 have_ukey = true;
@@ -839,34 +2401,73 @@ impl DB {
                 input.advance();
                 continue;
             }
-
-            last_seq_for_key = seq;
-
-            if cs.builder.is_none() {
-                let fnum = self.vset.borrow_mut().new_file_number();
-                let mut fmd = FileMetaData::default();
-                fmd.num = fnum;
-
-                let fname = table_file_name(&self.path, fnum);
-                let f = self.opt.env.open_writable_file(Path::new(&fname))?;
-                let f = Box::new(BufWriter::new(f));
-                cs.builder = Some(TableBuilder::new(self.opt.clone(), f));
-                cs.outputs.push(fmd);
-            }
-            if cs.builder.as_ref().unwrap().entries() == 0 {
-                cs.current_output().smallest = key.clone();
+            // Entry is covered by a range tombstone that is observable by all snapshots.
+            let tombstone_seq = max_covering_seq(
+                input_tombstones.iter(),
+                opt.cmp.as_ref().as_ref(),
+                ukey,
+                cs.smallest_seq,
+            );
+            if seq < tombstone_seq {
+                last_seq_for_key = seq;
+                input.advance();
+                continue;
             }
-            cs.builder.as_mut().unwrap().add(&key, &val)?;
-            // NOTE: Adjust max file size based on level.
-            if cs.builder.as_ref().unwrap().size_estimate() > self.opt.max_file_size {
-                self.finish_compaction_output(cs, key.clone())?;
+
+            // The newest value of a key is passed to the compaction filter. Removed entries are
+            // replaced by a deletion, unless no older entries may survive: Older entries in this
+            // compaction are only kept if a snapshot older than `seq` exists.
+            if let (ValueType::TypeValue, Some(filter)) = (ktyp, &opt.compaction_filter) {
+                if last_seq_for_key == MAX_SEQUENCE_NUMBER && seq > newest_snapshot {
+                    match filter.filter(cs.compaction.level(), ukey, &val) {
+                        Decision::Keep => {}
+                        Decision::Remove => {
+                            last_seq_for_key = seq;
+                            if seq > cs.smallest_seq || !cs.compaction.is_base_level_for(ukey) {
+                                let del = LookupKey::new_full(ukey, seq, ValueType::TypeDeletion);
+                                self.add_compaction_entry(cs, del.internal_key(), &[])?;
+                            }
+                            input.advance();
+                            continue;
+                        }
+                        Decision::ChangeValue(v) => val = v,
+                    }
+                }
             }
 
-            input.advance();
+            if ktyp == ValueType::TypeMerge {
+                // Merge operands that are observable by all snapshots are combined with the
+                // entries below them. Otherwise, they don't hide older entries.
+                if let (true, Some(op)) = (seq <= cs.smallest_seq, &opt.merge_operator) {
+                    let entries = self.compact_merge_operands(
+                        cs,
+                        op,
+                        input.as_mut(),
+                        &input_tombstones,
+                        &key,
+                        &val,
+                        now,
+                    )?;
+                    for (k, v) in entries {
+                        self.add_compaction_entry(cs, &k, &v)?;
+                    }
+                    last_seq_for_key = seq;
+                    continue;
+                }
+            } else {
+                last_seq_for_key = seq;
+            }
+
+            self.add_compaction_entry(cs, &key, &val)?;
+            input.advance();
         }
 
+        if cs.builder.is_none() && cs.has_pending_tombstones(opt.cmp.as_ref().as_ref()) {
+            // Range tombstones are kept even if there are no entries left after them.
+            self.open_compaction_output(cs)?;
+        }
         if cs.builder.is_some() {
-            self.finish_compaction_output(cs, key)?;
+            self.finish_compaction_output(cs, None)?;
         }
 
         let mut stats = CompactionStats::default();
@@ -879,34 +2480,175 @@ impl DB {
         for output in &cs.outputs {
             stats.written += output.size;
         }
-        self.cstats[cs.compaction.level()].add(stats);
+        self.add_stats(cs.compaction.level(), stats);
+        Ok(())
+    }
+
+    /// compact_merge_operands is called with the input positioned at a merge operand that is
+    /// observable by all snapshots. It consumes the operands and the base value below it, and
+    /// returns the entries replacing them: a single value if the key's value can be determined
+    /// from the compaction's inputs, otherwise the operands, combined as far as the merge
+    /// operator allows. The input is left after the last consumed entry.
+    ///
+    /// Operands are not merged into a value that expires after `now`, because they outlive it.
+    /// Such a value is returned unchanged after the operands.
+    fn compact_merge_operands(
+        &self,
+        cs: &mut CompactionState,
+        op: &BoxedMergeOperator,
+        input: &mut dyn LdbIterator,
+        tombstones: &[RangeTombstone],
+        key: &[u8],
+        val: &[u8],
+        now: u64,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (_, newest_seq, ukey) = parse_internal_key(key);
+        // (seq, operand), newest first.
+        let mut operands = vec![(newest_seq, val.to_vec())];
+        let mut base = None;
+        let mut ttl_base = None;
+        let mut resolved = false;
+
+        let (mut k, mut v) = (vec![], vec![]);
+        input.advance();
+        while input.valid() {
+            assert!(input.current(&mut k, &mut v));
+            let (typ, seq, entry_ukey) = parse_internal_key(&k);
+            if cs.opt.cmp.cmp(entry_ukey, ukey) != Ordering::Equal {
+                break;
+            }
+            let tombstone_seq = max_covering_seq(
+                tombstones.iter(),
+                cs.opt.cmp.as_ref().as_ref(),
+                ukey,
+                cs.smallest_seq,
+            );
+            if seq < tombstone_seq {
+                // Older entries are deleted.
+                resolved = true;
+                break;
+            }
+            match typ {
+                ValueType::TypeMerge => operands.push((seq, v.clone())),
+                ValueType::TypeValue => {
+                    base = Some(v.clone());
+                    resolved = true;
+                    input.advance();
+                    break;
+                }
+                ValueType::TypeValueWithTTL if split_expiry(&v).1 > now => {
+                    ttl_base = Some((k.clone(), v.clone()));
+                    input.advance();
+                    break;
+                }
+                _ => {
+                    resolved = true;
+                    break;
+                }
+            }
+            input.advance();
+        }
+
+        if ttl_base.is_none() && (resolved || cs.compaction.is_base_level_for(ukey)) {
+            let operands: Vec<Vec<u8>> = operands.into_iter().map(|(_, o)| o).collect();
+            let merged = merge_operator::full_merge(
+                Some(op),
+                ukey,
+                base.as_ref().map(|b| b.as_slice()),
+                &operands,
+            )?;
+            let k = LookupKey::new_full(ukey, newest_seq, ValueType::TypeValue);
+            return Ok(vec![(k.internal_key().to_vec(), merged)]);
+        }
+
+        // Combine consecutive operands, starting with the oldest one. A combined operand takes
+        // the sequence number of the newest operand in it.
+        let mut combined = operands.pop().unwrap();
+        let mut result = vec![];
+        while let Some((seq, newer)) = operands.pop() {
+            match op.partial_merge(ukey, &combined.1, &newer) {
+                Some(m) => combined = (seq, m),
+                None => result.push(mem::replace(&mut combined, (seq, newer))),
+            }
+        }
+        result.push(combined);
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = result
+            .into_iter()
+            .rev()
+            .map(|(seq, o)| {
+                let k = LookupKey::new_full(ukey, seq, ValueType::TypeMerge);
+                (k.internal_key().to_vec(), o)
+            })
+            .collect();
+        entries.extend(ttl_base);
+        Ok(entries)
+    }
+
+    /// add_compaction_entry adds an entry to the current compaction output, opening a new one if
+    /// necessary.
+    fn add_compaction_entry(&self, cs: &mut CompactionState, key: &[u8], val: &[u8]) -> Result<()> {
+        if cs.builder.is_none() {
+            self.open_compaction_output(cs)?;
+        }
+        if cs.builder.as_ref().unwrap().entries() == 0 {
+            cs.current_output().smallest = key.to_vec();
+        }
+        cs.builder.as_mut().unwrap().add(key, val)?;
+        let output = cs.current_output();
+        output.largest.clear();
+        output.largest.extend_from_slice(key);
+        Ok(())
+    }
+
+    /// open_compaction_output starts a new output table for a compaction.
+    fn open_compaction_output(&self, cs: &mut CompactionState) -> Result<()> {
+        assert!(cs.builder.is_none());
+        let fnum = self.vset.borrow_mut().new_file_number();
+        let mut fmd = FileMetaData::default();
+        fmd.num = fnum;
+
+        let fname = table_file_name(&self.path, fnum);
+        let f = self.opt.env.open_writable_file(Path::new(&fname))?;
+        let f = Box::new(BufWriter::new(f));
+        cs.builder = Some(TableBuilder::new(cs.opt.clone(), f));
+        cs.outputs.push(fmd);
         Ok(())
     }
 
+    /// finish_compaction_output writes the current output table. `upper` is the first user key
+    /// that belongs to the next output, if any. The range tombstones overlapping the output's key
+    /// range are written to it, clipped to that range.
     fn finish_compaction_output(
-        &mut self,
+        &self,
         cs: &mut CompactionState,
-        largest: Vec<u8>,
+        upper: Option<&[u8]>,
     ) -> Result<()> {
         assert!(cs.builder.is_some());
         let output_num = cs.current_output().num;
         assert!(output_num > 0);
 
+        let tombstones = cs.output_tombstones(cs.opt.cmp.as_ref().as_ref(), upper);
+        let icmp = InternalKeyCmp(cs.opt.cmp.clone());
+        for t in tombstones.iter() {
+            cs.builder.as_mut().unwrap().add_range_tombstone(t);
+            extend_file_bounds(&icmp, cs.current_output(), t);
+        }
+        cs.output_lower = upper.map(|u| u.to_vec());
+
         // The original checks if the input iterator has an OK status. For this, we'd need to
         // extend the LdbIterator interface though -- let's see if we can without for now.
         // (it's not good for corruptions, in any case)
         let b = cs.builder.take().unwrap();
         let entries = b.entries();
-        let bytes = b.finish()?;
+        let bytes = b.finish_and_sync()?;
         cs.total_bytes += bytes;
 
-        cs.current_output().largest = largest;
         cs.current_output().size = bytes;
 
         if entries > 0 {
             // Verify that table can be used. (Separating get_table() because borrowing in an if
             // let expression is dangerous).
-            let r = self.cache.borrow_mut().get_table(output_num);
+            let r = self.get_table(cs.compaction.column_family(), output_num);
             if let Err(e) = r {
                 log!(self.opt.log, "New table can't be read: {}", e);
                 return Err(e);
@@ -922,7 +2664,7 @@ impl DB {
         Ok(())
     }
 
-    fn install_compaction_results(&mut self, mut cs: CompactionState) -> Result<()> {
+    fn install_compaction_results(&self, mut cs: CompactionState) -> Result<()> {
         log!(
             self.opt.log,
             "Compacted {} L{} files + {} L{} files => {}B",
@@ -943,31 +2685,500 @@ impl DB {
     }
 }
 
-impl Drop for DB {
+impl DB {
+    // MAINTENANCE //
+
+    /// destroy removes the database `name`: all files belonging to the database, the lock file,
+    /// and the database directory, unless it contains other files. The database must not be open.
+    pub fn destroy<P: AsRef<Path>>(name: P, opt: Options) -> Result<()> {
+        let name = name.as_ref();
+        let env = &opt.env;
+        let filenames = match env.children(name) {
+            Ok(f) => f,
+            // There is nothing to destroy.
+            Err(_) => return Ok(()),
+        };
+
+        let lockfile = lock_file_name(name);
+        let lock = env.lock(&lockfile)?;
+        let mut result = Ok(());
+        for file in &filenames {
+            if let Ok((_, typ)) = parse_file_name(file) {
+                if typ == FileType::DBLock {
+                    continue;
+                }
+                if let Err(e) = env.delete(&name.join(file)) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        let _ = env.unlock(lock);
+        let _ = env.delete(&lockfile);
+
+        if env.children(name).map(|c| c.is_empty()).unwrap_or(false) {
+            let _ = env.rmdir(name);
+        }
+        result
+    }
+
+    /// repair recovers as much data as possible from a database that can't be opened anymore,
+    /// e.g. because the MANIFEST or CURRENT file is missing or corrupted. It converts log files
+    /// to tables, and writes a new MANIFEST placing all readable tables in level 0. Files that
+    /// can't be read are moved to the `lost` subdirectory. Finally, the recovered tables are
+    /// compacted.
+    ///
+    /// Column families, and the family each table belongs to, are taken from the readable part of
+    /// the old MANIFEST files. If the database has other families than the default one, tables
+    /// whose family can't be determined are moved to `lost` as well. Log files end up there once
+    /// they have been converted, so entries of unknown families are kept only there. All families
+    /// are rebuilt using `opt`.
+    ///
+    /// Some data may be lost, and deleted entries may reappear if the table containing the
+    /// deletion couldn't be read. The database must not be open.
+    pub fn repair<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<()> {
+        let name = name.as_ref();
+        if opt.log.is_none() {
+            let log = open_info_log(opt.env.as_ref().as_ref(), name);
+            opt.log = Some(share(log));
+        }
+
+        let env = opt.env.clone();
+        let lock = env.lock(&lock_file_name(name))?;
+        let result = Repairer::new(name, opt.clone()).run();
+        let _ = env.unlock(lock);
+
+        // Lookups in level 0 prefer tables with higher file numbers, which doesn't necessarily
+        // match the age of the entries in tables recovered from different levels. A compaction
+        // merges the tables by sequence number, restoring the correct order.
+        let ranges = result?;
+        if !ranges.is_empty() {
+            let db = DB::open(name, opt)?;
+            for (cf, from, to) in ranges {
+                db.compact_range_cf(&cf, &from, &to)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Repairer reconstructs the MANIFEST of a database from its table and log files.
+struct Repairer {
+    name: PathBuf,
+    opt: Options,
+    cache: Shared<TableCache>,
+    vset: VersionSet,
+
+    // The non-default column families found in the old manifests, and the family of every table
+    // mentioned there or created from a log file.
+    families: BTreeMap<u32, String>,
+    max_column_family: u32,
+    table_families: BTreeMap<FileNum, u32>,
+    // Whether the database is known to have other families than the default one, from the
+    // manifests or from entries in log files.
+    other_families: bool,
+
+    tables: BTreeMap<u32, Vec<FileMetaData>>,
+    max_seq: SequenceNumber,
+}
+
+impl Repairer {
+    fn new(name: &Path, opt: Options) -> Repairer {
+        let cache = share(TableCache::new(name, opt.clone(), opt.max_open_files - 10));
+        let vset = VersionSet::new(name, opt.clone(), cache.clone());
+        Repairer {
+            name: name.to_owned(),
+            opt: opt,
+            cache: cache,
+            vset: vset,
+            families: BTreeMap::new(),
+            max_column_family: DEFAULT_COLUMN_FAMILY_ID,
+            table_families: BTreeMap::new(),
+            other_families: false,
+            tables: BTreeMap::new(),
+            max_seq: 0,
+        }
+    }
+
+    /// run repairs the database, and returns the range of user keys in the recovered tables of
+    /// each column family.
+    fn run(mut self) -> Result<Vec<(ColumnFamilyHandle, Vec<u8>, Vec<u8>)>> {
+        let (manifests, logs, mut tables) = self.find_files()?;
+        for num in manifests {
+            self.read_manifest(num);
+        }
+        self.other_families = !self.families.is_empty();
+
+        for log_num in logs {
+            match self.convert_log_to_tables(log_num) {
+                Ok(nums) => tables.extend(nums),
+                Err(e) => {
+                    log!(self.opt.log, "Converting log {:06} failed: {}", log_num, e);
+                }
+            }
+            self.archive_file(&log_file_name(&self.name, log_num));
+        }
+        for num in tables {
+            self.scan_table(num);
+        }
+        self.write_descriptor()
+    }
+
+    /// find_files returns the numbers of all manifest, log and table files in the database.
+    fn find_files(&mut self) -> Result<(Vec<FileNum>, Vec<FileNum>, Vec<FileNum>)> {
+        let filenames = self.opt.env.children(&self.name)?;
+        if filenames.is_empty() {
+            return err(StatusCode::NotFound, "repair found no files");
+        }
+
+        let (mut manifests, mut logs, mut tables) = (vec![], vec![], vec![]);
+        for file in &filenames {
+            if let Ok((num, typ)) = parse_file_name(file) {
+                self.vset.mark_file_number_used(num);
+                match typ {
+                    FileType::Descriptor => manifests.push(num),
+                    FileType::Log => logs.push(num),
+                    FileType::Table => tables.push(num),
+                    _ => {}
+                }
+            }
+        }
+        manifests.sort();
+        logs.sort();
+        Ok((manifests, logs, tables))
+    }
+
+    /// read_manifest collects the column families and the families of tables from the edits in
+    /// an old manifest, up to the first one that can't be read.
+    fn read_manifest(&mut self, num: FileNum) {
+        let filename = manifest_file_name(&self.name, num);
+        let file = match self.opt.env.open_sequential_file(&filename) {
+            Ok(f) => f,
+            Err(e) => {
+                log!(self.opt.log, "Manifest {:06} can't be opened: {}", num, e);
+                return;
+            }
+        };
+        let mut reader = LogReader::new(file, true);
+        let mut scratch = vec![];
+        let mut edits = 0;
+        while let Ok(len) = reader.read(&mut scratch) {
+            if len == 0 {
+                break;
+            }
+            let edit = match VersionEdit::decode_from(&scratch) {
+                Ok(edit) => edit,
+                Err(_) => break,
+            };
+            let cf = edit.column_family;
+            if cf != DEFAULT_COLUMN_FAMILY_ID {
+                if let Some(ref name) = edit.add_column_family {
+                    self.families.insert(cf, name.clone());
+                }
+                if edit.drop_column_family {
+                    self.families.remove(&cf);
+                }
+            }
+            if let Some(max) = edit.max_column_family {
+                if max > self.max_column_family {
+                    self.max_column_family = max;
+                }
+            }
+            for &(_, ref f) in &edit.new_files {
+                self.table_families.insert(f.num, cf);
+            }
+            edits += 1;
+        }
+        log!(self.opt.log, "Manifest {:06}: {} edits", num, edits);
+    }
+
+    /// convert_log_to_tables writes all readable entries of a log file to new tables, one per
+    /// column family, and returns the new tables' numbers. Entries of unknown families are left
+    /// in the log file, which is archived afterwards.
+    fn convert_log_to_tables(&mut self, log_num: FileNum) -> Result<Vec<FileNum>> {
+        let filename = log_file_name(&self.name, log_num);
+        let logfile = self.opt.env.open_sequential_file(&filename)?;
+        let mut logreader = LogReader::new(logfile, true);
+        let mut mems = BTreeMap::new();
+        mems.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            MemTable::new(self.opt.cmp.clone()),
+        );
+        for &cf in self.families.keys() {
+            mems.insert(cf, MemTable::new(self.opt.cmp.clone()));
+        }
+        let mut scratch = vec![];
+        let mut batch = WriteBatch::new();
+        let mut entries = 0;
+        let mut unknown = 0;
+
+        // Reading stops at the first corrupted record; everything after it is lost.
+        while let Ok(len) = logreader.read(&mut scratch) {
+            if len == 0 {
+                break;
+            }
+            if len < 12 {
+                log!(
+                    self.opt.log,
+                    "corruption in log file {:06}: record shorter than 12B",
+                    log_num
+                );
+                continue;
+            }
+            batch.set_contents(&scratch);
+            batch.insert_into_memtables(batch.sequence(), &mut mems);
+            for (cf, _, _, _) in batch.iter() {
+                if cf != DEFAULT_COLUMN_FAMILY_ID {
+                    self.other_families = true;
+                }
+                if mems.contains_key(&cf) {
+                    entries += 1;
+                } else {
+                    unknown += 1;
+                }
+            }
+            batch.clear();
+        }
+        if unknown > 0 {
+            log!(
+                self.opt.log,
+                "Log {:06}: {} entries of unknown column families",
+                log_num,
+                unknown
+            );
+        }
+
+        let mut tables = vec![];
+        for (cf, mem) in mems {
+            let num = self.vset.new_file_number();
+            let fmd = build_table(
+                &self.name,
+                &self.opt,
+                mem.iter(),
+                &mem.range_tombstones(),
+                num,
+            )?;
+            if fmd.size > 0 {
+                self.table_families.insert(num, cf);
+                tables.push(num);
+            }
+        }
+        log!(
+            self.opt.log,
+            "Log {:06}: {} entries saved to tables {:?}",
+            log_num,
+            entries,
+            tables
+        );
+        Ok(tables)
+    }
+
+    /// scan_table determines the column family, key range and maximum sequence number of a table.
+    /// Tables that can't be read or assigned to a family are archived.
+    fn scan_table(&mut self, num: FileNum) {
+        let filename = table_file_name(&self.name, num);
+        let cf = match self.table_families.get(&num) {
+            Some(&cf) => cf,
+            None if !self.other_families => DEFAULT_COLUMN_FAMILY_ID,
+            None => {
+                log!(self.opt.log, "Table {:06} has no known column family", num);
+                self.archive_file(&filename);
+                return;
+            }
+        };
+        if cf != DEFAULT_COLUMN_FAMILY_ID && !self.families.contains_key(&cf) {
+            log!(
+                self.opt.log,
+                "Table {:06} belongs to dropped column family {}",
+                num,
+                cf
+            );
+            self.archive_file(&filename);
+            return;
+        }
+
+        let table = match self.cache.borrow_mut().get_table(num) {
+            Ok(t) => t,
+            Err(e) => {
+                log!(self.opt.log, "Table {:06} can't be opened: {}", num, e);
+                self.archive_file(&filename);
+                return;
+            }
+        };
+
+        let mut fmd = FileMetaData::default();
+        fmd.num = num;
+        fmd.size = self.opt.env.size_of(&filename).unwrap_or(0);
+
+        let mut iter = table.iter();
+        let (mut key, mut val) = (vec![], vec![]);
+        let mut entries = 0;
+        while iter.advance() {
+            iter.current(&mut key, &mut val);
+            if key.len() < 8 {
+                continue;
+            }
+            let seq = parse_internal_key(&key).1;
+            if seq > self.max_seq {
+                self.max_seq = seq;
+            }
+            if entries == 0 {
+                fmd.smallest = key.clone();
+            }
+            fmd.largest = key.clone();
+            entries += 1;
+        }
+        let icmp = InternalKeyCmp(self.opt.cmp.clone());
+        for t in table.range_tombstones() {
+            if t.seq > self.max_seq {
+                self.max_seq = t.seq;
+            }
+            extend_file_bounds(&icmp, &mut fmd, t);
+            entries += 1;
+        }
+
+        log!(self.opt.log, "Table {:06}: {} entries", num, entries);
+        if entries == 0 {
+            self.archive_file(&filename);
+        } else {
+            self.tables.entry(cf).or_insert_with(Vec::new).push(fmd);
+        }
+    }
+
+    /// write_descriptor writes a new MANIFEST containing all column families and their recovered
+    /// tables, and points CURRENT to it. It returns the range of user keys in the recovered tables
+    /// of each family.
+    fn write_descriptor(&mut self) -> Result<Vec<(ColumnFamilyHandle, Vec<u8>, Vec<u8>)>> {
+        self.vset.manifest_num = self.vset.new_file_number();
+        self.vset.last_seq = self.max_seq;
+
+        let mut families = vec![(
+            DEFAULT_COLUMN_FAMILY_ID,
+            DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+        )];
+        families.extend(self.families.iter().map(|(&cf, name)| (cf, name.clone())));
+        let mut ranges = vec![];
+        for (cf, name) in families {
+            let mut ve = VersionEdit::new();
+            ve.set_column_family(cf);
+            ve.set_log_num(0);
+            if cf == DEFAULT_COLUMN_FAMILY_ID {
+                ve.set_comparator_name(self.opt.cmp.id());
+                ve.set_max_column_family(self.max_column_family);
+            } else {
+                ve.add_column_family(&name);
+            }
+            let tables = self.tables.remove(&cf).unwrap_or_default();
+            if let Some((from, to)) = self.add_tables(&mut ve, tables) {
+                ranges.push((ColumnFamilyHandle::new(cf, &name), from, to));
+            }
+            self.vset.log_and_apply(ve)?;
+        }
+        Ok(ranges)
+    }
+
+    /// add_tables adds tables to level 0 in `ve`, and returns the range of user keys in them.
+    fn add_tables(
+        &self,
+        ve: &mut VersionEdit,
+        tables: Vec<FileMetaData>,
+    ) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+        for t in tables {
+            let (smallest, largest) = (
+                parse_internal_key(&t.smallest).2,
+                parse_internal_key(&t.largest).2,
+            );
+            range = Some(match range {
+                None => (smallest.to_vec(), largest.to_vec()),
+                Some((from, to)) => (
+                    if self.opt.cmp.cmp(smallest, &from) == Ordering::Less {
+                        smallest.to_vec()
+                    } else {
+                        from
+                    },
+                    if self.opt.cmp.cmp(largest, &to) == Ordering::Greater {
+                        largest.to_vec()
+                    } else {
+                        to
+                    },
+                ),
+            });
+            ve.add_file(0, t);
+        }
+        range
+    }
+
+    /// archive_file moves a file that couldn't be recovered to the `lost` subdirectory.
+    fn archive_file(&self, file: &Path) {
+        let lost = self.name.join("lost");
+        let _ = self.opt.env.mkdir(&lost);
+        let newname = lost.join(file.file_name().unwrap_or_default());
+        match self.opt.env.rename(file, &newname) {
+            Ok(()) => {
+                log!(self.opt.log, "Archived {:?}", file);
+            }
+            Err(e) => {
+                log!(self.opt.log, "Archiving {:?} failed: {}", file, e);
+            }
+        }
+    }
+}
+
+impl Drop for WriterState {
+    /// Releases the database lock once the last DB handle has been dropped.
     fn drop(&mut self) {
-        let _ = self.release_lock();
+        if let Some(l) = self.lock.take() {
+            let _ = self.env.unlock(l);
+        }
     }
 }
 
 struct CompactionState {
     compaction: Compaction,
+    // The options of the compacted column family.
+    opt: Options,
     smallest_seq: SequenceNumber,
     outputs: Vec<FileMetaData>,
-    builder: Option<TableBuilder<Box<dyn Write>>>,
+    builder: Option<TableBuilder<Box<dyn WritableFile>>>,
     total_bytes: usize,
+    // Range tombstones to be written to the outputs, and the smallest user key belonging to the
+    // current output (None for the first one).
+    tombstones: Vec<RangeTombstone>,
+    output_lower: Option<Vec<u8>>,
 }
 
 impl CompactionState {
-    fn new(c: Compaction, smallest: SequenceNumber) -> CompactionState {
+    fn new(c: Compaction, smallest: SequenceNumber, opt: Options) -> CompactionState {
         CompactionState {
             compaction: c,
+            opt: opt,
             smallest_seq: smallest,
             outputs: vec![],
             builder: None,
             total_bytes: 0,
+            tombstones: vec![],
+            output_lower: None,
         }
     }
 
+    /// output_tombstones returns the range tombstones belonging to the current output, clipped
+    /// to [output_lower; upper).
+    fn output_tombstones(&self, ucmp: &dyn Cmp, upper: Option<&[u8]>) -> Vec<RangeTombstone> {
+        let lower = self.output_lower.as_ref().map(|l| l.as_slice());
+        self.tombstones
+            .iter()
+            .filter_map(|t| t.truncate(ucmp, lower, upper))
+            .collect()
+    }
+
+    /// has_pending_tombstones returns whether there are range tombstones that haven't been
+    /// written to an output yet.
+    fn has_pending_tombstones(&self, ucmp: &dyn Cmp) -> bool {
+        !self.output_tombstones(ucmp, None).is_empty()
+    }
+
     fn current_output(&mut self) -> &mut FileMetaData {
         let len = self.outputs.len();
         &mut self.outputs[len - 1]
@@ -982,7 +3193,7 @@ impl CompactionState {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 struct CompactionStats {
     micros: u64,
     read: usize,
@@ -1001,6 +3212,7 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     dbname: P,
     opt: &Options,
     mut from: I,
+    tombstones: &[RangeTombstone],
     num: FileNum,
 ) -> Result<FileMetaData> {
     from.reset();
@@ -1024,7 +3236,10 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
             }
             builder.add(&kbuf, &vbuf)?;
         }
-        builder.finish()?;
+        for t in tombstones {
+            builder.add_range_tombstone(t);
+        }
+        builder.finish_and_sync()?;
         Ok(())
     })();
 
@@ -1034,13 +3249,19 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     }
 
     let mut md = FileMetaData::default();
-    if firstkey.is_none() {
+    if firstkey.is_none() && tombstones.is_empty() {
         let _ = opt.env.delete(Path::new(&filename));
     } else {
         md.num = num;
         md.size = opt.env.size_of(Path::new(&filename))?;
-        md.smallest = firstkey.unwrap();
-        md.largest = kbuf;
+        if let Some(firstkey) = firstkey {
+            md.smallest = firstkey;
+            md.largest = kbuf;
+        }
+        let icmp = InternalKeyCmp(opt.cmp.clone());
+        for t in tombstones {
+            extend_file_bounds(&icmp, &mut md, t);
+        }
     }
     Ok(md)
 }
@@ -1053,6 +3274,72 @@ fn lock_file_name(db: &Path) -> PathBuf {
     db.join("LOCK")
 }
 
+/// memtable_overlaps returns true if the memtable contains entries or range tombstones within the
+/// range [smallest; largest].
+fn memtable_overlaps(ucmp: &dyn Cmp, mem: &MemTable, smallest: &[u8], largest: &[u8]) -> bool {
+    if mem.range_tombstones().iter().any(|t| {
+        ucmp.cmp(&t.start, largest) != Ordering::Greater
+            && ucmp.cmp(&t.end, smallest) == Ordering::Greater
+    }) {
+        return true;
+    }
+    let mut it = mem.iter();
+    it.seek(LookupKey::new(smallest, MAX_SEQUENCE_NUMBER).internal_key());
+    match current_key_val(&it) {
+        Some((k, _)) => ucmp.cmp(parse_internal_key(&k).2, largest) != Ordering::Greater,
+        None => false,
+    }
+}
+
+/// merge_value applies the merge operands (newest first) collected during a lookup to the value
+/// found for key. Without operands, the value is returned unchanged.
+fn merge_value(
+    op: Option<&BoxedMergeOperator>,
+    key: &[u8],
+    value: Option<Vec<u8>>,
+    operands: &[Vec<u8>],
+) -> Result<Option<Vec<u8>>> {
+    if operands.is_empty() {
+        return Ok(value);
+    }
+    let merged =
+        merge_operator::full_merge(op, key, value.as_ref().map(|v| v.as_slice()), operands)?;
+    Ok(Some(merged))
+}
+
+/// memtables_usage returns the approximate memory usage of a set of memtables.
+fn memtables_usage(mems: &BTreeMap<u32, MemTable>) -> usize {
+    mems.values().map(|m| m.approx_mem_usage()).sum()
+}
+
+fn unknown_column_family<T>(cf: u32) -> Result<T> {
+    err(
+        StatusCode::InvalidArgument,
+        &format!("column family {} doesn't exist or has been dropped", cf),
+    )
+}
+
+/// prefix_upper_bound returns the smallest key that is larger than all keys starting with
+/// `prefix`, or None if there is no such key (i.e. the prefix consists of 0xff bytes only).
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < 0xff {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
+/// decode_edits decodes the version edits read from a manifest.
+fn decode_edits(records: &[Vec<u8>]) -> Result<Vec<VersionEdit>> {
+    records
+        .iter()
+        .map(|r| VersionEdit::decode_from(r))
+        .collect()
+}
+
 /// open_info_log opens an info log file in the given database. It transparently returns a
 /// /dev/null logger in case the open fails.
 fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
@@ -1105,11 +3392,13 @@ pub mod testutil {
         lw.flush().unwrap();
         set_current_file(&opt.env, name, 10).unwrap();
 
-        (DB::open(name, opt.clone()).unwrap(), opt)
+        let db = DB::open(name, opt.clone()).unwrap();
+        db.wait_for_compactions().unwrap();
+        (db, opt)
     }
 
     /// set_file_to_compact ensures that the specified table file will be compacted next.
-    pub fn set_file_to_compact(db: &mut DB, num: FileNum) {
+    pub fn set_file_to_compact(db: &DB, num: FileNum) {
         let v = db.current();
         let mut v = v.borrow_mut();
 
@@ -1135,13 +3424,21 @@ mod tests {
     use super::testutil::{build_db, set_file_to_compact};
     use super::*;
 
-    use error::Status;
+    use compaction_filter::CompactionFilter;
+    use error::{Status, StatusCode};
+    use fault_injection_env::FaultInjectionEnv;
     use key_types::LookupKey;
     use mem_env::MemEnv;
+    use merge_operator::testutil::append_operator;
     use options;
-    use test_util::LdbIteratorIter;
+    use sst_file_writer::SstFileWriter;
+    use statistics::Statistics;
+    use test_util::{corrupt_file, LdbIteratorIter, SyncCountingEnv};
     use version::testutil::make_version;
 
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
     #[test]
     fn test_db_impl_open_info_log() {
         let e = MemEnv::new();
@@ -1210,7 +3507,7 @@ mod tests {
         {
             let mut opt = opt.clone();
             opt.reuse_manifest = true;
-            let mut db = DB::open("db", opt.clone()).unwrap();
+            let db = DB::open("db", opt.clone()).unwrap();
 
             println!(
                 "children after: {:?}",
@@ -1234,7 +3531,7 @@ mod tests {
             let mut opt = opt.clone();
             opt.reuse_manifest = false;
             opt.reuse_logs = false;
-            let mut db = DB::open("db", opt.clone()).unwrap();
+            let db = DB::open("db", opt.clone()).unwrap();
 
             println!(
                 "children after: {:?}",
@@ -1256,7 +3553,12 @@ mod tests {
                 "def".as_bytes(),
                 current
                     .borrow_mut()
-                    .get(LookupKey::new("abc".as_bytes(), 1).internal_key())
+                    .get(
+                        &ReadOptions::default(),
+                        LookupKey::new("abc".as_bytes(), 1).internal_key(),
+                        0,
+                        &mut vec![]
+                    )
                     .unwrap()
                     .unwrap()
                     .0
@@ -1287,11 +3589,12 @@ mod tests {
             // 000004 should be reused, no new log file should be created.
             assert!(!env.exists(Path::new("db/000006.log")).unwrap());
             // Log is reused, so memtable should contain last written entry from above.
-            assert_eq!(1, db.mem.len());
+            let mem = db.mem.borrow();
+            assert_eq!(1, mem[&0].len());
             assert_eq!(
                 "def".as_bytes(),
-                db.mem
-                    .get(&LookupKey::new("abe".as_bytes(), 3))
+                mem[&0]
+                    .get(&LookupKey::new("abe".as_bytes(), 3), 0, &mut vec![])
                     .0
                     .unwrap()
                     .as_slice()
@@ -1301,7 +3604,7 @@ mod tests {
 
     #[test]
     fn test_db_impl_compact_range() {
-        let (mut db, opt) = build_db();
+        let (db, opt) = build_db();
         let env = &opt.env;
 
         println!(
@@ -1335,7 +3638,7 @@ mod tests {
 
     #[test]
     fn test_db_impl_compact_range_memtable() {
-        let (mut db, opt) = build_db();
+        let (db, opt) = build_db();
         let env = &opt.env;
 
         db.put(b"xxx", b"123").unwrap();
@@ -1384,13 +3687,233 @@ mod tests {
         assert_eq!(want_err, DB::open("db", opt.clone()).err().unwrap());
     }
 
+    #[test]
+    fn test_db_impl_syncs() {
+        let env = SyncCountingEnv::new(Box::new(MemEnv::new()));
+        let mut opt = options::for_test();
+        opt.env = Arc::new(Box::new(env.clone()));
+        let db = DB::open("db", opt).unwrap();
+        let synced = || -> Vec<String> {
+            env.synced_files()
+                .iter()
+                .map(|f| f.file_name().unwrap().to_str().unwrap().to_string())
+                .collect()
+        };
+
+        // Creating the database syncs the manifest, the new CURRENT file and the directory.
+        assert!(synced().iter().any(|f| f.starts_with("MANIFEST-")));
+        assert!(synced().iter().any(|f| f.ends_with(".dbtmp")));
+        assert!(env.synced_dirs().contains(&PathBuf::from("db")));
+
+        env.clear();
+        db.put(b"abc", b"def").unwrap();
+        assert!(synced().is_empty());
+        let mut wb = WriteBatch::new();
+        wb.put(b"abd", b"deg");
+        db.write(wb, true).unwrap();
+        assert_eq!(1, synced().len());
+        assert!(synced()[0].ends_with(".log"));
+
+        // Writing a table syncs it before it's recorded in the manifest.
+        env.clear();
+        db.compact_range(b"a", b"z").unwrap();
+        assert!(synced().iter().any(|f| f.ends_with(".ldb")));
+        assert!(synced().iter().any(|f| f.starts_with("MANIFEST-")));
+    }
+
+    #[test]
+    fn test_db_impl_open_read_only() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        assert_eq!(
+            StatusCode::NotFound,
+            DB::open_read_only("db", opt.clone()).err().unwrap().code
+        );
+        assert!(!env.exists(Path::new("db")).unwrap());
+
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"1").unwrap();
+        db.put(b"abd", b"2").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        db.put(b"abe", b"3").unwrap();
+        db.delete(b"abc").unwrap();
+        db.flush().unwrap();
+
+        let list_files = || {
+            let mut files: Vec<(PathBuf, usize)> = env
+                .children(Path::new("db"))
+                .unwrap()
+                .into_iter()
+                .map(|f| {
+                    let size = env.size_of(&Path::new("db").join(&f)).unwrap();
+                    (f, size)
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let files = list_files();
+
+        // The database is still locked by db.
+        let ro = DB::open_read_only("db", opt.clone()).unwrap();
+        assert_eq!(None, ro.get(b"abc"));
+        assert_eq!(Some(b"2".to_vec()), ro.get(b"abd"));
+        assert_eq!(Some(b"3".to_vec()), ro.get(b"abe"));
+        let mut iter = ro.new_iter().unwrap();
+        assert_eq!(Some((b"abd".to_vec(), b"2".to_vec())), iter.next());
+        assert_eq!(Some((b"abe".to_vec(), b"3".to_vec())), iter.next());
+        assert_eq!(None, iter.next());
+
+        // Later writes aren't visible.
+        db.put(b"abf", b"4").unwrap();
+        assert_eq!(None, ro.get(b"abf"));
+
+        assert_eq!(
+            StatusCode::NotSupported,
+            ro.put(b"abg", b"5").err().unwrap().code
+        );
+        assert_eq!(StatusCode::NotSupported, ro.flush().err().unwrap().code);
+        assert_eq!(
+            StatusCode::NotSupported,
+            ro.compact_range(b"a", b"z").err().unwrap().code
+        );
+        assert_eq!(
+            StatusCode::NotSupported,
+            ro.create_column_family("cf", opt.clone())
+                .err()
+                .unwrap()
+                .code
+        );
+        ro.wait_for_compactions().unwrap();
+        drop(ro);
+
+        // Apart from the last write to the log file, nothing has changed.
+        db.flush().unwrap();
+        let log = files
+            .iter()
+            .position(|&(ref f, _)| f.to_str().unwrap().ends_with(".log"))
+            .unwrap();
+        let mut now = list_files();
+        assert!(now[log].1 > files[log].1);
+        now[log].1 = files[log].1;
+        assert_eq!(files, now);
+    }
+
+    #[test]
+    fn test_db_impl_secondary() {
+        let opt = options::for_test();
+        let primary = DB::open("db", opt.clone()).unwrap();
+        primary.put(b"abc", b"1").unwrap();
+        primary.put(b"abd", b"2").unwrap();
+        primary.flush().unwrap();
+
+        let secondary = DB::open_as_secondary("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"1".to_vec()), secondary.get(b"abc"));
+        assert_eq!(
+            StatusCode::NotSupported,
+            secondary.put(b"abe", b"3").err().unwrap().code
+        );
+        assert_eq!(
+            StatusCode::NotSupported,
+            primary.try_catch_up_with_primary().err().unwrap().code
+        );
+
+        // New log records.
+        primary.put(b"abe", b"3").unwrap();
+        primary.delete(b"abc").unwrap();
+        primary.flush().unwrap();
+        assert_eq!(None, secondary.get(b"abe"));
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"3".to_vec()), secondary.get(b"abe"));
+        assert_eq!(None, secondary.get(b"abc"));
+
+        // New manifest edits: the memtable is written to a table, and a new log file is started.
+        primary.compact_range(b"a", b"z").unwrap();
+        primary.put(b"abf", b"4").unwrap();
+        primary.flush().unwrap();
+        let cf = primary.create_column_family("cf", opt.clone()).unwrap();
+        primary.put_cf(&cf, b"abg", b"5").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        // Nothing changed since then.
+        secondary.try_catch_up_with_primary().unwrap();
+
+        let check = |secondary: &DB| {
+            let mut iter = secondary.new_iter().unwrap();
+            assert_eq!(Some((b"abd".to_vec(), b"2".to_vec())), iter.next());
+            assert_eq!(Some((b"abe".to_vec(), b"3".to_vec())), iter.next());
+            assert_eq!(Some((b"abf".to_vec(), b"4".to_vec())), iter.next());
+            assert_eq!(None, iter.next());
+            let cf = secondary.column_family("cf").unwrap();
+            assert_eq!(Some(b"5".to_vec()), secondary.get_cf(&cf, b"abg").unwrap());
+        };
+        check(&secondary);
+
+        // The reopened primary writes a new manifest.
+        drop(primary);
+        let mut opt = opt;
+        opt.reuse_manifest = false;
+        let primary = DB::open("db", opt).unwrap();
+        primary.put(b"abh", b"6").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"6".to_vec()), secondary.get(b"abh"));
+        primary.delete(b"abh").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        check(&secondary);
+    }
+
+    #[test]
+    fn test_db_impl_secondary_concurrent_readers() {
+        let mut opt = options::for_test();
+        opt.merge_operator = Some(append_operator());
+        let primary = DB::open("db", opt.clone()).unwrap();
+        primary.put(b"abc", b"0").unwrap();
+        primary.flush().unwrap();
+        // Readers may use a sequence number from before a catch-up; the snapshot keeps compactions
+        // from dropping the entries visible at it.
+        let _ss = primary.get_snapshot();
+        let secondary = DB::open_as_secondary("db", opt.clone()).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let secondary = secondary.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    // Every operand must be seen once, whether it is in a table or a memtable.
+                    let val = secondary.get(b"abc").unwrap();
+                    let operands: Vec<&[u8]> = val.split(|&b| b == b',').collect();
+                    for (i, op) in operands.iter().enumerate() {
+                        assert_eq!(i.to_string().as_bytes(), *op);
+                    }
+                }
+            })
+        };
+
+        for i in 1..50 {
+            // The iterator keeps the primary from deleting the tables the secondary still uses.
+            let iter = primary.new_iter().unwrap();
+            primary.merge(b"abc", i.to_string().as_bytes()).unwrap();
+            primary.flush().unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+            // The memtable is written to a table, and a new log file is started.
+            primary.compact_range(b"a", b"z").unwrap();
+            secondary.try_catch_up_with_primary().unwrap();
+            drop(iter);
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+    }
+
     #[test]
     fn test_db_impl_build_table() {
         let mut opt = options::for_test();
         opt.block_size = 128;
         let mt = build_memtable();
 
-        let f = build_table("db", &opt, mt.iter(), 123).unwrap();
+        let f = build_table("db", &opt, mt.iter(), &[], 123).unwrap();
         let path = Path::new("db/000123.ldb");
 
         assert_eq!(
@@ -1451,7 +3974,7 @@ mod tests {
 
     #[test]
     fn test_db_impl_get_from_table_with_snapshot() {
-        let mut db = build_db().0;
+        let db = build_db().0;
 
         assert_eq!(30, db.vset.borrow().last_seq);
 
@@ -1469,19 +3992,37 @@ mod tests {
             "123".as_bytes(),
             db.get("xyz".as_bytes()).unwrap().as_slice()
         );
-        assert!(db.get_internal(31, "xyy".as_bytes()).unwrap().is_some());
-        assert!(db.get_internal(32, "xyy".as_bytes()).unwrap().is_some());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 31, "xyy".as_bytes())
+            .unwrap()
+            .is_some());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 32, "xyy".as_bytes())
+            .unwrap()
+            .is_some());
 
-        assert!(db.get_internal(31, "xyz".as_bytes()).unwrap().is_none());
-        assert!(db.get_internal(32, "xyz".as_bytes()).unwrap().is_some());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 31, "xyz".as_bytes())
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 32, "xyz".as_bytes())
+            .unwrap()
+            .is_some());
 
         // table get
         assert_eq!(
             "val2".as_bytes(),
             db.get("eab".as_bytes()).unwrap().as_slice()
         );
-        assert!(db.get_internal(3, "eab".as_bytes()).unwrap().is_none());
-        assert!(db.get_internal(32, "eab".as_bytes()).unwrap().is_some());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 3, "eab".as_bytes())
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_internal(&ReadOptions::default(), 0, 32, "eab".as_bytes())
+            .unwrap()
+            .is_some());
 
         {
             let ss = db.get_snapshot();
@@ -1501,9 +4042,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_db_impl_get_updates_since() {
+        let mut opt = options::for_test();
+        opt.retain_log_files = true;
+        let db = DB::open("db", opt.clone()).unwrap();
+        let seqs = |db: &DB, seq| -> Result<Vec<(SequenceNumber, u32)>> {
+            let mut it = db.get_updates_since(seq)?;
+            let seqs = it.by_ref().map(|(s, b)| (s, b.count())).collect();
+            it.status()?;
+            Ok(seqs)
+        };
+
+        db.put(b"abc", b"1").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"abd", b"2");
+        wb.delete(b"abc");
+        db.write(wb, false).unwrap();
+        db.delete(b"abd").unwrap();
+        assert_eq!(vec![(1, 1), (2, 2), (4, 1)], seqs(&db, 1).unwrap());
+        assert_eq!(vec![(2, 2), (4, 1)], seqs(&db, 3).unwrap());
+        assert!(seqs(&db, 5).unwrap().is_empty());
+
+        let mut it = db.get_updates_since(4).unwrap();
+        let (seq, batch) = it.next().unwrap();
+        assert_eq!(4, seq);
+        assert_eq!(
+            vec![(0, ValueType::TypeDeletion, b"abd".to_vec())],
+            batch
+                .iter()
+                .map(|(cf, t, k, _)| (cf, t, k.to_vec()))
+                .collect::<Vec<_>>()
+        );
+        // Batches written after the iterator's creation are not returned.
+        db.put(b"abe", b"3").unwrap();
+        assert!(it.next().is_none());
+
+        // Log files are kept after flushing the memtable, until their updates are acknowledged.
+        db.compact_range(b"a", b"z").unwrap();
+        db.put(b"abf", b"4").unwrap();
+        assert_eq!(
+            vec![(1, 1), (2, 2), (4, 1), (5, 1), (6, 1)],
+            seqs(&db, 1).unwrap()
+        );
+        db.acknowledge_updates(3).unwrap();
+        assert_eq!(4, seqs(&db, 2).unwrap().len());
+        db.acknowledge_updates(5).unwrap();
+        assert_eq!(StatusCode::NotFound, seqs(&db, 2).unwrap_err().code);
+        assert_eq!(vec![(6, 1)], seqs(&db, 6).unwrap());
+
+        // Retained log files are not replayed when reopening the database.
+        drop(db);
+        let db = DB::open("db", opt).unwrap();
+        assert_eq!(6, db.vset.borrow().last_seq);
+        assert_eq!(Some(b"4".to_vec()), db.get(b"abf"));
+        assert_eq!(vec![(6, 1)], seqs(&db, 6).unwrap());
+
+        // Without retention, flushed log files are deleted.
+        let db = DB::open("db2", options::for_test()).unwrap();
+        db.put(b"abc", b"1").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        db.put(b"abd", b"2").unwrap();
+        assert_eq!(StatusCode::NotFound, seqs(&db, 1).unwrap_err().code);
+        assert_eq!(vec![(2, 1)], seqs(&db, 2).unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_multi_get() {
+        let db = build_db().0;
+        db.put(b"xyz", b"123").unwrap();
+        db.delete(b"aab").unwrap();
+        let ss = db.get_snapshot();
+        db.put(b"cab", b"new").unwrap();
+
+        // Unsorted, with duplicates and keys from memtable and tables.
+        let keys: &[&[u8]] = &[
+            b"xyz", b"cab", b"aaa", b"gca", b"aab", b"iba", b"cab", b"abc", b"eab",
+        ];
+        let results = db.multi_get(keys);
+        assert_eq!(keys.len(), results.len());
+        for (k, r) in keys.iter().zip(results.into_iter()) {
+            assert_eq!(db.get(k), r.unwrap());
+        }
+        assert_eq!(
+            Some(b"new".to_vec()),
+            db.multi_get(&[b"cab"])[0].clone().unwrap()
+        );
+
+        // All keys are read at the snapshot.
+        let ro = ReadOptions {
+            snapshot: Some(ss.clone()),
+            ..ReadOptions::default()
+        };
+        for (k, r) in keys.iter().zip(db.multi_get_opt(&ro, keys).into_iter()) {
+            assert_eq!(db.get_at(&ss, k).unwrap(), r.unwrap());
+        }
+        assert!(db.multi_get(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_db_impl_get_opt() {
+        let db = build_db().0;
+
+        db.put(b"xyy", b"123").unwrap();
+        let ss = db.get_snapshot();
+        db.put(b"xyy", b"456").unwrap();
+
+        let ro = ReadOptions {
+            snapshot: Some(ss),
+            ..ReadOptions::default()
+        };
+        assert_eq!(Some(b"123".to_vec()), db.get_opt(&ro, b"xyy").unwrap());
+        assert_eq!(
+            Some(b"456".to_vec()),
+            db.get_opt(&ReadOptions::default(), b"xyy").unwrap()
+        );
+
+        let ro = ReadOptions {
+            verify_checksums: false,
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        assert_eq!(Some(b"val2".to_vec()), db.get_opt(&ro, b"eab").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_get_corrupted_table() {
+        let (db, opt) = build_db();
+        // Make sure that the table isn't read from the cache.
+        db.cache.borrow_mut().evict(5).ok();
+        corrupt_file(opt.env.as_ref().as_ref(), Path::new("db/000005.ldb"), 10);
+
+        let e = db.get_opt(&ReadOptions::default(), b"eab").unwrap_err();
+        assert_eq!(StatusCode::Corruption, e.code);
+        assert!(e.err.contains("table file 5"));
+        assert!(db.get_at(&db.get_snapshot(), b"eab").is_err());
+        // The simplified interface can't distinguish errors from missing entries.
+        assert_eq!(None, db.get(b"eab"));
+    }
+
+    #[test]
+    fn test_db_impl_write_opt() {
+        let opt;
+        {
+            let db = build_db().0;
+            opt = db.opt.clone();
+
+            let mut batch = WriteBatch::new();
+            batch.put(b"xx1", b"111");
+            db.write_opt(
+                &WriteOptions {
+                    sync: true,
+                    disable_wal: false,
+                },
+                batch,
+            )
+            .unwrap();
+
+            let mut batch = WriteBatch::new();
+            batch.put(b"xx2", b"222");
+            db.write_opt(
+                &WriteOptions {
+                    sync: false,
+                    disable_wal: true,
+                },
+                batch,
+            )
+            .unwrap();
+
+            assert_eq!(Some(b"111".to_vec()), db.get(b"xx1"));
+            assert_eq!(Some(b"222".to_vec()), db.get(b"xx2"));
+        }
+
+        {
+            // Writes that bypassed the log are lost without a flush.
+            let db = DB::open("db", opt).unwrap();
+            assert_eq!(Some(b"111".to_vec()), db.get(b"xx1"));
+            assert_eq!(None, db.get(b"xx2"));
+        }
+    }
+
     #[test]
     fn test_db_impl_delete() {
-        let mut db = build_db().0;
+        let db = build_db().0;
 
         db.put(b"xyy", b"123").unwrap();
         db.put(b"xyz", b"123").unwrap();
@@ -1522,9 +4243,10 @@ mod tests {
 
     #[test]
     fn test_db_impl_compact_single_file() {
-        let mut db = build_db().0;
-        set_file_to_compact(&mut db, 4);
-        db.maybe_do_compaction().unwrap();
+        let db = build_db().0;
+        set_file_to_compact(&db, 4);
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
 
         let env = &db.opt.env;
         let name = &db.name;
@@ -1536,17 +4258,20 @@ mod tests {
 
     #[test]
     fn test_db_impl_compaction_trivial_move() {
-        let mut db = DB::open("db", options::for_test()).unwrap();
+        let db = DB::open("db", options::for_test()).unwrap();
 
         db.put("abc".as_bytes(), "xyz".as_bytes()).unwrap();
         db.put("ab3".as_bytes(), "xyz".as_bytes()).unwrap();
         db.put("ab0".as_bytes(), "xyz".as_bytes()).unwrap();
         db.put("abz".as_bytes(), "xyz".as_bytes()).unwrap();
-        assert_eq!(4, db.mem.len());
-        let mut imm = MemTable::new(db.opt.cmp.clone());
-        mem::swap(&mut imm, &mut db.mem);
-        db.imm = Some(imm);
-        db.compact_memtable().unwrap();
+        assert_eq!(4, db.mem.borrow()[&0].len());
+        let imm = mem::replace(
+            &mut *db.mem.borrow_mut(),
+            DB::new_memtables(&db.vset.borrow(), None),
+        );
+        let log_num = db.writer.borrow().log_num.unwrap();
+        *db.imm.borrow_mut() = Some((imm, log_num));
+        db.compact_memtable(&mut db.compactor.borrow_mut()).unwrap();
 
         println!(
             "children after: {:?}",
@@ -1561,7 +4286,8 @@ mod tests {
             v.file_to_compact_lvl = 2;
         }
 
-        db.maybe_do_compaction().unwrap();
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
 
         {
             let v = db.current();
@@ -1574,14 +4300,18 @@ mod tests {
     fn test_db_impl_memtable_compaction() {
         let mut opt = options::for_test();
         opt.write_buffer_size = 25;
-        let mut db = DB::new("db", opt);
+        let db = DB::new("db", opt);
 
         // Fill up memtable.
-        db.mem = build_memtable();
+        db.mem.borrow_mut().insert(0, build_memtable());
 
-        // Trigger memtable compaction.
-        db.make_room_for_write(true).unwrap();
-        assert_eq!(0, db.mem.len());
+        // Trigger memtable compaction. There is no background thread, so we have to write the
+        // immutable memtable ourselves.
+        db.make_room_for_write(&mut db.writer.borrow_mut(), true)
+            .unwrap();
+        assert_eq!(0, db.mem.borrow()[&0].len());
+        assert!(db.imm.borrow().is_some());
+        db.compact_memtable(&mut db.compactor.borrow_mut()).unwrap();
         assert!(db.opt.env.exists(Path::new("db/000002.log")).unwrap());
         assert!(db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
         assert_eq!(351, db.opt.env.size_of(Path::new("db/000003.ldb")).unwrap());
@@ -1591,14 +4321,242 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_db_impl_compaction_keeps_iterated_files() {
+        let db = build_db().0;
+        let mut iter = db.new_iter().unwrap();
+        {
+            let v = db.current();
+            v.borrow_mut().compaction_score = Some(2.0);
+            v.borrow_mut().compaction_level = Some(1);
+        }
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
+        assert_eq!(0, db.current().borrow().files[1].len());
+
+        // The iterator still reads the compacted files.
+        assert!(db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut iter).collect();
+        assert!(entries.contains(&(b"cab".to_vec(), b"val2".to_vec())));
+        assert!(entries.contains(&(b"dab".to_vec(), b"val2".to_vec())));
+
+        drop(iter);
+        db.delete_obsolete_files().unwrap();
+        assert!(!db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_checkpoint() {
+        let (db, opt) = build_db();
+        let env = opt.env.clone();
+        db.put(b"xxx", b"111").unwrap();
+        db.delete(b"aaa").unwrap();
+        let blobs = db.create_column_family("blobs", opt.clone()).unwrap();
+        db.put_cf(&blobs, b"abc", b"def").unwrap();
+
+        db.checkpoint("cp").unwrap();
+        assert_eq!(
+            StatusCode::AlreadyExists,
+            db.checkpoint("cp").unwrap_err().code
+        );
+        db.put(b"yyy", b"222").unwrap();
+
+        // Memtables have been flushed, so no log files are needed.
+        let children = env.children(Path::new("cp")).unwrap();
+        for name in &children {
+            let typ = parse_file_name(name).unwrap().1;
+            assert!(
+                typ == FileType::Table || typ == FileType::Descriptor || typ == FileType::Current
+            );
+        }
+        assert!(children.contains(&PathBuf::from("CURRENT")));
+
+        let cp = DB::open("cp", opt.clone()).unwrap();
+        assert_eq!(Some(b"111".to_vec()), cp.get(b"xxx"));
+        assert_eq!(None, cp.get(b"aaa"));
+        assert_eq!(Some(b"val2".to_vec()), cp.get(b"cab"));
+        assert_eq!(Some(b"val3".to_vec()), cp.get(b"fab"));
+        assert_eq!(None, cp.get(b"yyy"));
+        let blobs = cp.column_family("blobs").unwrap();
+        assert_eq!(Some(b"def".to_vec()), cp.get_cf(&blobs, b"abc").unwrap());
+
+        // The database itself is unaffected.
+        assert_eq!(Some(b"222".to_vec()), db.get(b"yyy"));
+        assert_eq!(Some(b"111".to_vec()), db.get(b"xxx"));
+    }
+
+    #[test]
+    fn test_db_impl_checkpoint_read_only() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        {
+            let db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"abc", b"111").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            db.put(b"abd", b"222").unwrap();
+            db.flush().unwrap();
+        }
+        let list = || {
+            let mut files = env.children(Path::new("db")).unwrap();
+            files.sort();
+            files
+                .into_iter()
+                .map(|f| {
+                    let size = env.size_of(&Path::new("db").join(&f)).unwrap();
+                    (f, size)
+                })
+                .collect::<Vec<_>>()
+        };
+        let before = list();
+
+        let db = DB::open_read_only("db", opt.clone()).unwrap();
+        db.checkpoint("cp").unwrap();
+        assert_eq!(before, list());
+        drop(db);
+
+        // The unflushed entry was carried over in the copied log file.
+        let cp = DB::open("cp", opt.clone()).unwrap();
+        assert_eq!(Some(b"111".to_vec()), cp.get(b"abc"));
+        assert_eq!(Some(b"222".to_vec()), cp.get(b"abd"));
+    }
+
+    #[test]
+    fn test_db_impl_ingest_external_file_failure() {
+        let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+        let mut opt = options::for_test();
+        opt.env = Arc::new(Box::new(env.clone()));
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"aaa", b"mem").unwrap();
+        let mut w = SstFileWriter::create(opt.clone(), "ext.sst").unwrap();
+        w.put(b"bbb", b"ext").unwrap();
+        w.finish().unwrap();
+
+        // Installing the file fails when the CURRENT file is replaced.
+        let last_seq = db.vset.borrow().last_seq;
+        env.fail_nth_rename(1);
+        assert!(db.ingest_external_file(&["ext.sst"]).is_err());
+        assert_eq!(last_seq, db.vset.borrow().last_seq);
+        assert_eq!(None, db.get(b"bbb"));
+
+        db.ingest_external_file(&["ext.sst"]).unwrap();
+        assert_eq!(last_seq + 1, db.vset.borrow().last_seq);
+        assert_eq!(Some(b"ext".to_vec()), db.get(b"bbb"));
+    }
+
+    #[test]
+    fn test_db_impl_ingest_external_file() {
+        let (db, opt) = build_db();
+        db.put(b"caa", b"mem").unwrap();
+        let ss = db.get_snapshot();
+
+        let write = |name: &str, entries: &[(&[u8], Option<&[u8]>)]| {
+            let mut w = SstFileWriter::create(opt.clone(), name).unwrap();
+            for &(k, v) in entries {
+                match v {
+                    Some(v) => w.put(k, v).unwrap(),
+                    None => w.delete(k).unwrap(),
+                }
+            }
+            w.finish().unwrap();
+        };
+        // Overlaps the memtable and level 1.
+        write(
+            "ext1.sst",
+            &[(b"caa", Some(b"ext")), (b"cab", Some(b"ext"))],
+        );
+        // Overlaps level 3.
+        write("ext2.sst", &[(b"hba", None)]);
+        // Doesn't overlap anything.
+        write(
+            "ext3.sst",
+            &[(b"jaa", Some(b"ext")), (b"jab", Some(b"ext"))],
+        );
+        write("ext4.sst", &[(b"jab", Some(b"ext"))]);
+
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.ingest_external_file(&["ext3.sst", "ext4.sst"])
+                .unwrap_err()
+                .code
+        );
+        let last_seq = db.vset.borrow().last_seq;
+        db.ingest_external_file(&["ext3.sst", "ext2.sst", "ext1.sst"])
+            .unwrap();
+        assert_eq!(last_seq + 1, db.vset.borrow().last_seq);
+        assert_eq!(0, db.mem.borrow()[&0].len());
+        assert!(db.opt.env.exists(Path::new("ext1.sst")).unwrap());
+
+        // ext1.sst went to level 0, from where it may already have been compacted.
+        db.wait_for_compactions().unwrap();
+        {
+            let v = db.current();
+            let v = v.borrow();
+            let level_of = |key: &[u8]| {
+                (0..NUM_LEVELS).find(|&l| {
+                    v.files[l]
+                        .iter()
+                        .any(|f| parse_internal_key(&f.borrow().smallest).2 == key)
+                })
+            };
+            assert_eq!(Some(2), level_of(b"hba"));
+            assert_eq!(Some(NUM_LEVELS - 1), level_of(b"jaa"));
+        }
+
+        let check = |db: &DB| {
+            assert_eq!(Some(b"ext".to_vec()), db.get(b"caa"));
+            assert_eq!(Some(b"ext".to_vec()), db.get(b"cab"));
+            assert_eq!(None, db.get(b"hba"));
+            assert_eq!(Some(b"ext".to_vec()), db.get(b"jab"));
+            assert_eq!(Some(b"val1".to_vec()), db.get(b"haa"));
+        };
+        check(&db);
+        assert_eq!(Some(b"mem".to_vec()), db.get_at(&ss, b"caa").unwrap());
+        assert_eq!(Some(b"val2".to_vec()), db.get_at(&ss, b"cab").unwrap());
+        assert_eq!(Some(b"val2".to_vec()), db.get_at(&ss, b"hba").unwrap());
+        drop(ss);
+
+        // Memtable entries not overlapping the ingested files are not flushed.
+        db.put(b"xxx", b"mem").unwrap();
+        write("ext5.sst", &[(b"kaa", Some(b"ext"))]);
+        db.ingest_external_file(&["ext5.sst"]).unwrap();
+        assert_eq!(1, db.mem.borrow()[&0].len());
+        assert_eq!(Some(b"ext".to_vec()), db.get(b"kaa"));
+
+        drop(db);
+        let db = DB::open("db", opt.clone()).unwrap();
+        check(&db);
+        assert_eq!(Some(b"mem".to_vec()), db.get(b"xxx"));
+    }
+
+    #[test]
+    fn test_db_impl_pause_deletions() {
+        let db = build_db().0;
+        *db.deletions_paused.borrow_mut() += 1;
+        {
+            let v = db.current();
+            v.borrow_mut().compaction_score = Some(2.0);
+            v.borrow_mut().compaction_level = Some(1);
+        }
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
+        assert!(db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
+
+        *db.deletions_paused.borrow_mut() -= 1;
+        db.delete_obsolete_files().unwrap();
+        assert!(!db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
+    }
+
     #[test]
     fn test_db_impl_compaction() {
-        let mut db = build_db().0;
-        let v = db.current();
-        v.borrow_mut().compaction_score = Some(2.0);
-        v.borrow_mut().compaction_level = Some(1);
+        let db = build_db().0;
+        {
+            let v = db.current();
+            v.borrow_mut().compaction_score = Some(2.0);
+            v.borrow_mut().compaction_level = Some(1);
+        }
 
-        db.maybe_do_compaction().unwrap();
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
 
         assert!(!db.opt.env.exists(Path::new("db/000003.ldb")).unwrap());
         assert!(db.opt.env.exists(Path::new("db/000013.ldb")).unwrap());
@@ -1610,6 +4568,338 @@ mod tests {
         assert_eq!(2, v.borrow().files[2].len());
     }
 
+    #[test]
+    fn test_db_impl_delete_range() {
+        let (db, opt) = build_db();
+        db.put(b"bbb", b"val6").unwrap();
+        let ss = db.get_snapshot();
+        // Covers entries in the memtable and in tables on all levels.
+        db.delete_range(b"ab", b"fab").unwrap();
+        db.put(b"cab", b"val7").unwrap();
+
+        let check = |db: &DB, ss: Option<&Snapshot>| {
+            assert_eq!(Some(b"val1".to_vec()), db.get(b"aaa"));
+            assert_eq!(None, db.get(b"aba"));
+            assert_eq!(None, db.get(b"bbb"));
+            assert_eq!(Some(b"val7".to_vec()), db.get(b"cab"));
+            assert_eq!(None, db.get(b"eab"));
+            assert_eq!(Some(b"val3".to_vec()), db.get(b"fab"));
+            if let Some(ss) = ss {
+                assert_eq!(Some(b"val6".to_vec()), db.get_at(ss, b"bbb").unwrap());
+                assert_eq!(Some(b"val2".to_vec()), db.get_at(ss, b"eab").unwrap());
+            }
+
+            let mut iter = db.new_iter().unwrap();
+            let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter)
+                .map(|(k, _)| k)
+                .take(6)
+                .collect();
+            let expected: &[&[u8]] = &[b"aaa", b"aab", b"aax", b"cab", b"fab", b"fba"];
+            assert_eq!(expected.to_vec(), keys);
+        };
+        check(&db, Some(&ss));
+
+        // Tombstones are recovered from the log, and written to a table.
+        drop(db);
+        let db = DB::open("db", opt.clone()).unwrap();
+        check(&db, None);
+        let tombstones = db.current().borrow().range_tombstones().unwrap();
+        assert_eq!(1, tombstones.len());
+
+        // Tombstones visible to all snapshots are dropped at the bottommost level, together with
+        // the entries they cover. Others are kept, as are the entries they cover.
+        let ss = db.get_snapshot();
+        db.delete_range(b"h", b"hb").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        check(&db, None);
+        assert_eq!(Some(b"val1".to_vec()), db.get_at(&ss, b"haa").unwrap());
+        assert_eq!(None, db.get(b"haa"));
+        let tombstones = db.current().borrow().range_tombstones().unwrap();
+        assert_eq!(vec![RangeTombstone::new(b"h", b"hb", 34)], tombstones);
+
+        // Afterwards, they are dropped at the bottommost level.
+        drop(ss);
+        db.compact_range(b"a", b"z").unwrap();
+        check(&db, None);
+        assert!(db.current().borrow().range_tombstones().unwrap().is_empty());
+        assert_eq!(None, db.get(b"haa"));
+    }
+
+    #[test]
+    fn test_db_impl_prefix_upper_bound() {
+        assert_eq!(Some(b"ab".to_vec()), prefix_upper_bound(b"aa"));
+        assert_eq!(Some(b"b".to_vec()), prefix_upper_bound(b"a\xff\xff"));
+        assert_eq!(None, prefix_upper_bound(b"\xff"));
+        assert_eq!(None, prefix_upper_bound(b""));
+    }
+
+    #[test]
+    fn test_db_impl_ttl() {
+        let db = build_db().0;
+        db.put_with_ttl(b"aaa", b"new", Duration::from_secs(3600))
+            .unwrap();
+        db.put_with_ttl(b"cab", b"short", Duration::from_millis(1))
+            .unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put_with_expiry(b"eab", b"expired", 1);
+        wb.put_with_expiry(b"zzz", b"expired", 1);
+        db.write(wb, false).unwrap();
+        thread::sleep(Duration::from_millis(5));
+
+        let check = |db: &DB| {
+            assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+            assert_eq!(None, db.get(b"cab"));
+            assert_eq!(None, db.get(b"eab"));
+            assert_eq!(None, db.get(b"zzz"));
+            assert_eq!(Some(b"val3".to_vec()), db.get(b"fab"));
+
+            let entries: Vec<(Vec<u8>, Vec<u8>)> =
+                LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).collect();
+            assert_eq!((b"aaa".to_vec(), b"new".to_vec()), entries[0]);
+            assert!(!entries
+                .iter()
+                .any(|e| e.0 == b"cab".to_vec() || e.0 == b"eab".to_vec()));
+            assert_eq!(b"iba".to_vec(), entries.last().unwrap().0);
+        };
+        check(&db);
+
+        // Compactions remove the expired entries and the entries hidden by them.
+        db.compact_range(b"a", b"zzz").unwrap();
+        check(&db);
+        let current = db.current();
+        for mut it in current.borrow().new_iters(&ReadOptions::default()).unwrap() {
+            for (k, _) in LdbIteratorIter::wrap(&mut it) {
+                let ukey = parse_internal_key(&k).2;
+                assert!(ukey != b"cab" && ukey != b"eab" && ukey != b"zzz");
+            }
+        }
+    }
+
+    struct TestFilter;
+
+    impl CompactionFilter for TestFilter {
+        fn name(&self) -> &'static str {
+            "TestFilter"
+        }
+        fn filter(&self, _: usize, key: &[u8], value: &[u8]) -> Decision {
+            if key.starts_with(b"d") {
+                Decision::Remove
+            } else if value == b"val2" {
+                Decision::ChangeValue(b"new".to_vec())
+            } else {
+                Decision::Keep
+            }
+        }
+    }
+
+    #[test]
+    fn test_db_impl_compaction_filter() {
+        let (db, mut opt) = build_db();
+        drop(db);
+        opt.compaction_filter = Some(Arc::new(Box::new(TestFilter)));
+        let db = DB::open("db", opt.clone()).unwrap();
+
+        let ss = db.get_snapshot();
+        db.put(b"dzz", b"val1").unwrap();
+        db.put(b"zzz", b"val2").unwrap();
+
+        // Entries visible to the snapshot are left alone.
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(None, db.get(b"dzz"));
+        assert_eq!(Some(b"new".to_vec()), db.get(b"zzz"));
+        assert_eq!(Some(b"val1".to_vec()), db.get(b"daa"));
+        assert_eq!(Some(b"val2".to_vec()), db.get(b"cab"));
+        assert_eq!(Some(b"val2".to_vec()), db.get_at(&ss, b"eab").unwrap());
+
+        drop(ss);
+        db.compact_range(b"a", b"z").unwrap();
+        for k in [&b"daa"[..], b"dab", b"dba", b"dzz"].iter() {
+            assert_eq!(None, db.get(k));
+        }
+        assert_eq!(Some(b"new".to_vec()), db.get(b"cab"));
+        assert_eq!(Some(b"new".to_vec()), db.get(b"eab"));
+        assert_eq!(Some(b"val3".to_vec()), db.get(b"fab"));
+        assert_eq!(Some(b"val1".to_vec()), db.get(b"haa"));
+
+        // Removing a value doesn't uncover an older one that is kept for a snapshot, even if no
+        // older entries exist below the compaction.
+        let mut opt = options::for_test();
+        opt.compaction_filter = Some(Arc::new(Box::new(TestFilter)));
+        let db = DB::open("db", opt).unwrap();
+        db.put(b"dkk", b"v1").unwrap();
+        let ss = db.get_snapshot();
+        db.put(b"dkk", b"v2").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(None, db.get(b"dkk"));
+        assert_eq!(Some(b"v1".to_vec()), db.get_at(&ss, b"dkk").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_merge() {
+        let (db, mut opt) = build_db();
+        assert_eq!(
+            StatusCode::NotSupported,
+            db.merge(b"aab", b"m").unwrap_err().code
+        );
+        drop(db);
+        opt.merge_operator = Some(append_operator());
+        let db = DB::open("db", opt.clone()).unwrap();
+
+        db.merge(b"aab", b"m").unwrap();
+        db.merge(b"fba", b"m1").unwrap();
+        let ss = db.get_snapshot();
+        db.merge(b"fba", b"m2").unwrap();
+        db.delete(b"xyz").unwrap();
+        db.merge(b"xyz", b"m3").unwrap();
+        db.merge(b"zzz", b"m4").unwrap();
+
+        let check = |db: &DB| {
+            assert_eq!(Some(b"val2,m".to_vec()), db.get(b"aab"));
+            assert_eq!(Some(b"val3,m1,m2".to_vec()), db.get(b"fba"));
+            assert_eq!(Some(b"m3".to_vec()), db.get(b"xyz"));
+            assert_eq!(Some(b"m4".to_vec()), db.get(b"zzz"));
+        };
+        check(&db);
+        assert_eq!(Some(b"val3,m1".to_vec()), db.get_at(&ss, b"fba").unwrap());
+
+        // Operands are merged with values in tables. While a snapshot may observe them, they are
+        // kept as they are.
+        db.make_room_for_write(&mut db.writer.borrow_mut(), true)
+            .unwrap();
+        {
+            // The background thread may have written the memtable already.
+            let mut c = db.compactor.borrow_mut();
+            if db.imm.borrow().is_some() {
+                db.compact_memtable(&mut c).unwrap();
+            }
+        }
+        check(&db);
+        assert_eq!(Some(b"val3,m1".to_vec()), db.get_at(&ss, b"fba").unwrap());
+        drop(ss);
+
+        // Compacting level 0 into level 1 combines the operands for fba, whose value is in
+        // level 2.
+        let num = db.current().borrow().files[0][0].borrow().num;
+        set_file_to_compact(&db, num);
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
+        check(&db);
+        let mut operands = vec![];
+        let lkey = LookupKey::new(b"fba", MAX_SEQUENCE_NUMBER);
+        db.current()
+            .borrow()
+            .get(
+                &ReadOptions::default(),
+                lkey.internal_key(),
+                0,
+                &mut operands,
+            )
+            .unwrap();
+        assert_eq!(vec![b"m1,m2".to_vec()], operands);
+
+        // A full compaction leaves only values, which can be read without a merge operator.
+        db.compact_range(b"a", b"zzz").unwrap();
+        drop(db);
+        opt.merge_operator = None;
+        let db = DB::open("db", opt.clone()).unwrap();
+        check(&db);
+        assert_eq!(
+            StatusCode::NotSupported,
+            db.merge(b"aab", b"m").unwrap_err().code
+        );
+    }
+
+    #[test]
+    fn test_db_impl_column_families() {
+        let opt = options::for_test();
+        let db = DB::open("db", opt.clone()).unwrap();
+        let mut cfopt = options::for_test();
+        cfopt.merge_operator = Some(append_operator());
+        let blobs = db.create_column_family("blobs", cfopt.clone()).unwrap();
+        assert_eq!(1, blobs.id());
+        assert_eq!(
+            StatusCode::AlreadyExists,
+            db.create_column_family("blobs", opt.clone())
+                .unwrap_err()
+                .code
+        );
+        assert_eq!(Some(blobs.clone()), db.column_family("blobs"));
+        assert_eq!(None, db.column_family("audit"));
+
+        // Families are separate keyspaces with their own options.
+        db.put(b"abc", b"def").unwrap();
+        db.put_cf(&blobs, b"abc", b"xyz").unwrap();
+        db.merge_cf(&blobs, b"abc", b"m").unwrap();
+        assert_eq!(
+            StatusCode::NotSupported,
+            db.merge(b"abc", b"m").unwrap_err().code
+        );
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+        assert_eq!(Some(b"xyz,m".to_vec()), db.get_cf(&blobs, b"abc").unwrap());
+
+        // A batch spanning several families.
+        let mut wb = WriteBatch::new();
+        wb.delete(b"abc");
+        wb.put_cf(&blobs, b"bcd", b"efg");
+        db.write(wb, false).unwrap();
+        assert_eq!(None, db.get(b"abc"));
+        let mut iter = db.new_iter_cf(&blobs).unwrap();
+        assert_eq!(
+            vec![
+                (b"abc".to_vec(), b"xyz,m".to_vec()),
+                (b"bcd".to_vec(), b"efg".to_vec()),
+            ],
+            LdbIteratorIter::wrap(&mut iter).collect::<Vec<_>>()
+        );
+
+        // The family's entries are written to tables in its own levels.
+        db.compact_range_cf(&blobs, b"a", b"z").unwrap();
+        let v = db.vset.borrow().current_cf(blobs.id()).unwrap();
+        let files: usize = (0..NUM_LEVELS).map(|l| v.borrow().num_level_files(l)).sum();
+        assert_eq!(1, files);
+        assert_eq!(0, db.mem.borrow()[&blobs.id()].len());
+        db.put_cf(&blobs, b"cde", b"fgh").unwrap();
+
+        // Families and their entries are recovered from the manifest and the log.
+        drop(iter);
+        drop(db);
+        let db = DB::open_with_column_families("db", opt.clone(), vec![("blobs", cfopt)]).unwrap();
+        assert_eq!(
+            vec![ColumnFamilyHandle::default(), blobs.clone()],
+            db.column_families()
+        );
+        assert_eq!(Some(b"xyz,m".to_vec()), db.get_cf(&blobs, b"abc").unwrap());
+        assert_eq!(Some(b"fgh".to_vec()), db.get_cf(&blobs, b"cde").unwrap());
+        assert_eq!(None, db.get(b"abc"));
+
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.drop_column_family(&ColumnFamilyHandle::default())
+                .unwrap_err()
+                .code
+        );
+        db.drop_column_family(&blobs).unwrap();
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.get_cf(&blobs, b"abc").unwrap_err().code
+        );
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.put_cf(&blobs, b"abc", b"def").unwrap_err().code
+        );
+        drop(db);
+
+        let db = DB::open("db", opt).unwrap();
+        assert_eq!(vec![ColumnFamilyHandle::default()], db.column_families());
+        assert_eq!(
+            2,
+            db.create_column_family("audit", options::for_test())
+                .unwrap()
+                .id()
+        );
+    }
+
     #[test]
     fn test_db_impl_compaction_trivial() {
         let (mut v, opt) = make_version();
@@ -1618,11 +4908,12 @@ mod tests {
         v.file_to_compact = Some(to_compact);
         v.file_to_compact_lvl = 2;
 
-        let mut db = DB::new("db", opt.clone());
+        let db = DB::new("db", opt.clone());
         db.vset.borrow_mut().add_version(v);
         db.vset.borrow_mut().next_file_num = 10;
 
-        db.maybe_do_compaction().unwrap();
+        db.maybe_do_compaction(&mut db.compactor.borrow_mut())
+            .unwrap();
 
         assert!(opt.env.exists(Path::new("db/000006.ldb")).unwrap());
         assert!(!opt.env.exists(Path::new("db/000010.ldb")).unwrap());
@@ -1646,7 +4937,11 @@ mod tests {
         let mut fmd = FileMetaData::default();
         fmd.num = 1;
 
-        let mut cs = CompactionState::new(Compaction::new(&options::for_test(), 2, None), 12);
+        let mut cs = CompactionState::new(
+            Compaction::new(&options::for_test(), 2, None),
+            12,
+            options::for_test(),
+        );
         cs.outputs = vec![fmd];
         cs.cleanup(&env, name);
 
@@ -1657,7 +4952,7 @@ mod tests {
     fn test_db_impl_open_close_reopen() {
         let opt;
         {
-            let mut db = build_db().0;
+            let db = build_db().0;
             opt = db.opt.clone();
             db.put(b"xx1", b"111").unwrap();
             db.put(b"xx2", b"112").unwrap();
@@ -1668,12 +4963,12 @@ mod tests {
         }
 
         {
-            let mut db = DB::open("db", opt.clone()).unwrap();
+            let db = DB::open("db", opt.clone()).unwrap();
             db.delete(b"xx5").unwrap();
         }
 
         {
-            let mut db = DB::open("db", opt.clone()).unwrap();
+            let db = DB::open("db", opt.clone()).unwrap();
 
             assert_eq!(None, db.get(b"xx5"));
 
@@ -1690,7 +4985,7 @@ mod tests {
         }
 
         {
-            let mut db = DB::open("db", opt).unwrap();
+            let db = DB::open("db", opt).unwrap();
 
             let ss = db.get_snapshot();
             assert_eq!(Some(b"113".to_vec()), db.get_at(&ss, b"xx3").unwrap());
@@ -1698,4 +4993,395 @@ mod tests {
             assert_eq!(None, db.get_at(&ss, b"xx2").unwrap());
         }
     }
+
+    #[test]
+    fn test_db_impl_get_property() {
+        let db = build_db().0;
+
+        assert_eq!(None, db.get_property("leveldb.unknown"));
+        assert_eq!(None, db.get_property("num-files-at-level0"));
+        assert_eq!(None, db.get_property("leveldb.num-files-at-levelx"));
+        assert_eq!(
+            None,
+            db.get_property(&format!("leveldb.num-files-at-level{}", NUM_LEVELS))
+        );
+        let current = db.current();
+        for l in 0..NUM_LEVELS {
+            assert_eq!(
+                Some(current.borrow().num_level_files(l).to_string()),
+                db.get_property(&format!("leveldb.num-files-at-level{}", l))
+            );
+        }
+
+        let sstables = db.get_property("leveldb.sstables").unwrap();
+        assert!(sstables.contains("--- level 1 ---\n"));
+        assert!(sstables.contains(" 9:200[\"iaa\" @ 1 .. \"iba\" @ 2]"));
+
+        let usage = |db: &DB| {
+            db.get_property("leveldb.approximate-memory-usage")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        let before = usage(&db);
+        db.put(b"xxx", &[0; 1000]).unwrap();
+        assert!(usage(&db) >= before + 1000);
+        // Reading from a table populates the block cache.
+        let before = usage(&db);
+        assert!(db.get(b"gaa").is_some());
+        assert!(usage(&db) > before);
+
+        db.compact_range(b"aaa", b"zzz").unwrap();
+        let stats = db.get_property("leveldb.stats").unwrap();
+        let lines: Vec<&str> = stats.lines().collect();
+        assert_eq!(
+            "Level  Files Size(MB) Time(sec) Read(MB) Write(MB)",
+            lines[1]
+        );
+        // Level 0 is empty, but has compaction statistics.
+        assert!(lines[3].starts_with("  0        0"));
+    }
+
+    #[test]
+    fn test_db_impl_statistics() {
+        let mut opt = options::for_test();
+        let stats = Arc::new(Statistics::new());
+        opt.statistics = Some(stats.clone());
+        let db = DB::open("db", opt).unwrap();
+        let delta = |t: Ticker, before: usize| stats.ticker(t) - before;
+
+        db.put(b"abc", b"1").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"abe", b"2");
+        db.write(wb, true).unwrap();
+        db.flush().unwrap();
+        assert_eq!(2, stats.ticker(Ticker::WalSyncs));
+        assert!(stats.ticker(Ticker::BytesWritten) > 0);
+
+        assert_eq!(Some(b"1".to_vec()), db.get(b"abc"));
+        assert_eq!(1, stats.ticker(Ticker::MemtableHit));
+        assert_eq!(0, stats.ticker(Ticker::MemtableMiss));
+
+        db.compact_range(b"a", b"z").unwrap();
+        assert!(stats.ticker(Ticker::CompactionBytesWritten) > 0);
+
+        // The first read from the table fills the block cache.
+        assert_eq!(Some(b"1".to_vec()), db.get(b"abc"));
+        let (hits, misses) = (
+            stats.ticker(Ticker::BlockCacheHit),
+            stats.ticker(Ticker::BlockCacheMiss),
+        );
+        let (useful, useless) = (
+            stats.ticker(Ticker::FilterUseful),
+            stats.ticker(Ticker::FilterUseless),
+        );
+        assert_eq!(Some(b"1".to_vec()), db.get(b"abc"));
+        assert_eq!(1, delta(Ticker::BlockCacheHit, hits));
+        assert_eq!(0, delta(Ticker::BlockCacheMiss, misses));
+        assert_eq!(1, delta(Ticker::FilterUseless, useless));
+        // The filter rules out the block for a missing key.
+        assert_eq!(None, db.get(b"abd"));
+        assert_eq!(1, delta(Ticker::FilterUseful, useful));
+        assert_eq!(1, delta(Ticker::BlockCacheHit, hits));
+        assert_eq!(3, stats.ticker(Ticker::MemtableMiss));
+        assert!(stats.ticker(Ticker::BytesRead) > 0);
+
+        let mut iter = db.new_iter().unwrap();
+        iter.seek(b"abd");
+        assert_eq!(
+            Some((b"abe".to_vec(), b"2".to_vec())),
+            current_key_val(&iter)
+        );
+
+        assert_eq!(4, stats.histogram(Histogram::GetMicros).count());
+        assert_eq!(2, stats.histogram(Histogram::WriteMicros).count());
+        assert_eq!(1, stats.histogram(Histogram::SeekMicros).count());
+        let dump = db.get_property("leveldb.statistics").unwrap();
+        assert_eq!(stats.to_string(), dump);
+        assert!(dump.contains("leveldb.wal.syncs COUNT : 2\n"));
+        assert!(dump.contains("leveldb.db.seek.micros P50 : "));
+    }
+
+    #[test]
+    fn test_db_impl_get_approximate_sizes() {
+        let db = build_db().0;
+        let ranges = &[
+            Range {
+                start: b"aaa",
+                limit: b"zzz",
+            },
+            Range {
+                start: b"aaa",
+                limit: b"daa",
+            },
+            Range {
+                start: b"daa",
+                limit: b"zzz",
+            },
+            Range {
+                start: b"x",
+                limit: b"y",
+            },
+            // Empty range.
+            Range {
+                start: b"zzz",
+                limit: b"aaa",
+            },
+        ];
+
+        let sizes = db.get_approximate_sizes(ranges);
+        let total = db
+            .current()
+            .borrow()
+            .approximate_offset(LookupKey::new(b"zzz", MAX_SEQUENCE_NUMBER).internal_key())
+            as u64;
+        assert!(total > 0);
+        assert_eq!(total, sizes[0]);
+        assert!(sizes[1] > 0 && sizes[2] > 0);
+        assert_eq!(sizes[0], sizes[1] + sizes[2]);
+        assert_eq!(0, sizes[3]);
+        assert_eq!(0, sizes[4]);
+
+        db.put(b"xyz", &[0; 1000]).unwrap();
+        assert_eq!(sizes, db.get_approximate_sizes(ranges));
+        let with_mem = db.get_approximate_sizes_with_memtables(ranges);
+        assert!(with_mem[0] > sizes[0] + 1000);
+        assert!(with_mem[3] > 1000);
+        assert_eq!(sizes[1], with_mem[1]);
+        assert_eq!(0, with_mem[4]);
+    }
+
+    #[test]
+    fn test_db_impl_destroy() {
+        let opt = options::for_test();
+        {
+            let db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"abc", b"def").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            db.put(b"abd", b"def").unwrap();
+
+            // The database can't be destroyed while it is open.
+            assert_eq!(
+                StatusCode::LockError,
+                DB::destroy("db", opt.clone()).unwrap_err().code
+            );
+        }
+        opt.env
+            .open_writable_file(Path::new("db/otherfile"))
+            .unwrap();
+
+        DB::destroy("db", opt.clone()).unwrap();
+        // Only files not belonging to the database are left.
+        assert_eq!(
+            vec![Path::new("otherfile").to_owned()],
+            opt.env.children(Path::new("db")).unwrap()
+        );
+
+        let mut opt2 = opt.clone();
+        opt2.create_if_missing = false;
+        assert!(DB::open("db", opt2).is_err());
+
+        // Destroying a nonexistent database is not an error.
+        DB::destroy("nonexistent", opt).unwrap();
+    }
+
+    #[test]
+    fn test_db_impl_repair() {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        opt.reuse_manifest = false;
+        {
+            let db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"aaa", b"111").unwrap();
+            db.put(b"bbb", b"222").unwrap();
+            db.delete(b"aaa").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            // This write is only in the log.
+            db.put(b"ccc", b"333").unwrap();
+            db.flush().unwrap();
+        }
+
+        // Lose the MANIFEST and CURRENT files.
+        opt.create_if_missing = false;
+        for file in opt.env.children(Path::new("db")).unwrap() {
+            match parse_file_name(&file) {
+                Ok((_, FileType::Descriptor)) | Ok((_, FileType::Current)) => {
+                    opt.env.delete(&Path::new("db").join(file)).unwrap()
+                }
+                _ => {}
+            }
+        }
+        assert!(DB::open("db", opt.clone()).is_err());
+
+        DB::repair("db", opt.clone()).unwrap();
+
+        let db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(None, db.get(b"aaa"));
+        assert_eq!(Some(b"222".to_vec()), db.get(b"bbb"));
+        assert_eq!(Some(b"333".to_vec()), db.get(b"ccc"));
+        // Sequence numbers are recovered, so new writes shadow old ones.
+        db.put(b"bbb", b"444").unwrap();
+        assert_eq!(Some(b"444".to_vec()), db.get(b"bbb"));
+    }
+
+    #[test]
+    fn test_db_impl_repair_column_families() {
+        let create = || {
+            let mut opt = options::for_test();
+            opt.reuse_logs = false;
+            opt.reuse_manifest = false;
+            let db = DB::open("db", opt.clone()).unwrap();
+            let blobs = db.create_column_family("blobs", opt.clone()).unwrap();
+            db.put(b"aaa", b"111").unwrap();
+            db.put_cf(&blobs, b"aaa", b"blob1").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            // These writes are only in the log.
+            db.put(b"bbb", b"222").unwrap();
+            db.put_cf(&blobs, b"bbb", b"blob2").unwrap();
+            db.flush().unwrap();
+            opt.create_if_missing = false;
+            opt
+        };
+        let delete = |opt: &Options, typ: FileType| {
+            for file in opt.env.children(Path::new("db")).unwrap() {
+                if parse_file_name(&file)
+                    .map(|(_, t)| t == typ)
+                    .unwrap_or(false)
+                {
+                    opt.env.delete(&Path::new("db").join(file)).unwrap();
+                }
+            }
+        };
+
+        // The families and their tables are recovered from the manifest.
+        let opt = create();
+        delete(&opt, FileType::Current);
+        DB::repair("db", opt.clone()).unwrap();
+        let db = DB::open("db", opt.clone()).unwrap();
+        let blobs = db.column_family("blobs").unwrap();
+        assert_eq!(Some(b"111".to_vec()), db.get(b"aaa"));
+        assert_eq!(Some(b"222".to_vec()), db.get(b"bbb"));
+        assert_eq!(Some(b"blob1".to_vec()), db.get_cf(&blobs, b"aaa").unwrap());
+        assert_eq!(Some(b"blob2".to_vec()), db.get_cf(&blobs, b"bbb").unwrap());
+
+        // Without the manifest, tables can't be assigned to families, and the log contains
+        // entries of another family: only the default family's log entries are recovered, and
+        // nothing is merged into it.
+        let opt = create();
+        delete(&opt, FileType::Current);
+        delete(&opt, FileType::Descriptor);
+        DB::repair("db", opt.clone()).unwrap();
+        let db = DB::open("db", opt.clone()).unwrap();
+        assert!(db.column_family("blobs").is_none());
+        assert_eq!(None, db.get(b"aaa"));
+        assert_eq!(Some(b"222".to_vec()), db.get(b"bbb"));
+        let lost = opt.env.children(Path::new("db/lost")).unwrap();
+        let count = |typ| {
+            lost.iter()
+                .filter(|f| parse_file_name(f).map(|(_, t)| t == typ).unwrap_or(false))
+                .count()
+        };
+        assert_eq!(1, count(FileType::Log));
+        assert_eq!(2, count(FileType::Table));
+    }
+
+    #[test]
+    fn test_db_impl_repair_corrupted_table() {
+        let (db, opt) = build_db();
+        drop(db);
+
+        // Corrupt the footer of one table, and the MANIFEST.
+        let size = opt.env.size_of(Path::new("db/000005.ldb")).unwrap();
+        corrupt_file(
+            opt.env.as_ref().as_ref(),
+            Path::new("db/000005.ldb"),
+            size - 1,
+        );
+        let current = read_current_file(&opt.env, Path::new("db")).unwrap();
+        let manifest = Path::new("db").join(current.trim_end());
+        corrupt_file(opt.env.as_ref().as_ref(), &manifest, 10);
+        assert!(DB::open("db", opt.clone()).is_err());
+
+        DB::repair("db", opt.clone()).unwrap();
+        assert!(!opt.env.exists(Path::new("db/000005.ldb")).unwrap());
+        assert!(opt.env.exists(Path::new("db/lost/000005.ldb")).unwrap());
+
+        let db = DB::open("db", opt.clone()).unwrap();
+        // From the lost table.
+        assert_eq!(None, db.get(b"eab"));
+        // From other tables.
+        assert_eq!(Some(b"val2".to_vec()), db.get(b"cab"));
+        assert_eq!(Some(b"val1".to_vec()), db.get(b"aaa"));
+        assert_eq!(None, db.get(b"gca"));
+    }
+
+    #[test]
+    fn test_db_impl_background_compaction() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1 << 10;
+        opt.l0_slowdown_writes_trigger = 2;
+        opt.l0_stop_writes_trigger = 3;
+        let db = DB::open("db", opt).unwrap();
+
+        for i in 0..2000 {
+            let k = format!("key{:05}", i);
+            db.put(k.as_bytes(), b"some value that takes up some space")
+                .unwrap();
+            // Writers are stopped until the background thread catches up.
+            assert!(db.current().borrow().num_level_files(0) <= 3);
+        }
+        db.wait_for_compactions().unwrap();
+
+        assert!(db.imm.borrow().is_none());
+        assert!(!db.needs_compaction());
+        let v = db.current();
+        assert!(v.borrow().num_level_files(0) < 3);
+        assert!((1..NUM_LEVELS).any(|l| v.borrow().num_level_files(l) > 0));
+        for i in 0..2000 {
+            let k = format!("key{:05}", i);
+            assert!(db.get(k.as_bytes()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_db_impl_concurrent_access() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DB>();
+
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1 << 10;
+        let db = DB::open("db", opt).unwrap();
+
+        let mut threads = vec![];
+        for t in 0..4 {
+            let db = db.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..200 {
+                    let k = format!("key{}-{:03}", t, i);
+                    db.put(k.as_bytes(), k.as_bytes()).unwrap();
+                    assert_eq!(
+                        Some(k.into_bytes()),
+                        db.get(format!("key{}-{:03}", t, i).as_bytes())
+                    );
+                }
+            }));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        for t in 0..4 {
+            for i in 0..200 {
+                let k = format!("key{}-{:03}", t, i);
+                assert_eq!(Some(k.clone().into_bytes()), db.get(k.as_bytes()));
+            }
+        }
+        assert_eq!(
+            800,
+            LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).count()
+        );
+        // The small write buffer forces memtable compactions while the threads are writing.
+        db.wait_for_compactions().unwrap();
+        assert!(db.current().borrow().files.iter().any(|l| !l.is_empty()));
+    }
 }
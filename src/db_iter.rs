@@ -2,15 +2,21 @@
 use std::prelude::v1::*;
 
 use cmp::Cmp;
-use key_types::{parse_internal_key, truncate_to_userkey, LookupKey, ValueType};
+use env::Env;
+use error::{Result, Status};
+use key_types::{parse_internal_key, resolve_ttl, truncate_to_userkey, LookupKey, ValueType};
+use merge_operator::{self, BoxedMergeOperator};
 use merging_iter::MergingIter;
+use range_tombstone::{max_covering_seq, RangeTombstone};
 use snapshot::Snapshot;
-use types::{Direction, LdbIterator, Shared};
+use statistics::{Histogram, Statistics};
+use types::{Direction, LdbIterator, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER};
+use version::Version;
 use version_set::VersionSet;
 
 use std::cmp::Ordering;
 use std::mem;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use rand;
 
@@ -19,44 +25,121 @@ const READ_BYTES_PERIOD: isize = 1048576;
 /// DBIterator is an iterator over the contents of a database.
 pub struct DBIterator {
     // A user comparator.
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
     vset: Shared<VersionSet>,
+    // The column family that is iterated over.
+    cf: u32,
+    // The version whose files are read by iter. Holding onto it prevents the files from being
+    // deleted once they have been compacted.
+    _version: Shared<Version>,
     iter: MergingIter,
+    // Range tombstones visible in the snapshot. Entries covered by them are skipped.
+    tombstones: Vec<RangeTombstone>,
     // By holding onto a snapshot, we make sure that the iterator iterates over the state at the
     // point of its creation.
     ss: Snapshot,
+    // Entries outside of [lower_bound; upper_bound) are not returned.
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+    merge_operator: Option<BoxedMergeOperator>,
+    // Values with an expiry time before this are treated as deleted.
+    now: u64,
+    env: Arc<Box<dyn Env>>,
+    statistics: Option<Arc<Statistics>>,
     dir: Direction,
     byte_count: isize,
 
     valid: bool,
+    // Set if the current entry is the result of merging operands on a forward-moving iterator.
+    // In that case, the current entry is stored in savedkey/savedval and self.iter is positioned
+    // after the merged entries.
+    merged: bool,
+    // The first error encountered while merging operands.
+    status: Option<Status>,
     // temporarily stored user key.
     savedkey: Vec<u8>,
     // buffer for reading internal keys
     keybuf: Vec<u8>,
     savedval: Vec<u8>,
     valbuf: Vec<u8>,
+    // Merge operands of the entry being assembled, newest first.
+    operands: Vec<Vec<u8>>,
 }
 
 impl DBIterator {
     pub fn new(
-        cmp: Rc<Box<dyn Cmp>>,
+        cmp: Arc<Box<dyn Cmp>>,
         vset: Shared<VersionSet>,
+        cf: u32,
+        version: Shared<Version>,
         iter: MergingIter,
+        mut tombstones: Vec<RangeTombstone>,
         ss: Snapshot,
+        lower_bound: Option<Vec<u8>>,
+        upper_bound: Option<Vec<u8>>,
+        merge_operator: Option<BoxedMergeOperator>,
+        env: Arc<Box<dyn Env>>,
+        statistics: Option<Arc<Statistics>>,
     ) -> DBIterator {
+        tombstones.retain(|t| t.seq <= ss.sequence());
         DBIterator {
             cmp: cmp,
             vset: vset,
+            cf: cf,
+            _version: version,
             iter: iter,
+            tombstones: tombstones,
             ss: ss,
+            lower_bound: lower_bound,
+            upper_bound: upper_bound,
+            merge_operator: merge_operator,
+            now: env.micros(),
+            env: env,
+            statistics: statistics,
             dir: Direction::Forward,
             byte_count: random_period(),
 
             valid: false,
+            merged: false,
+            status: None,
             savedkey: vec![],
             keybuf: vec![],
             savedval: vec![],
             valbuf: vec![],
+            operands: vec![],
+        }
+    }
+
+    /// status returns the first error encountered by this iterator, e.g. because a merge operand
+    /// was found without a merge operator being configured. The iterator becomes invalid when an
+    /// error occurs.
+    pub fn status(&self) -> Result<()> {
+        match self.status {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// seek_internal implements seek(), which additionally measures its latency.
+    fn seek_internal(&mut self, to: &[u8]) {
+        self.dir = Direction::Forward;
+        self.merged = false;
+        self.savedkey.clear();
+        self.savedval.clear();
+        let to = match self.lower_bound {
+            Some(ref lower) if self.cmp.cmp(to, lower) == Ordering::Less => lower.clone(),
+            _ => to.to_vec(),
+        };
+        self.savedkey
+            .extend_from_slice(LookupKey::new(&to, self.ss.sequence()).internal_key());
+        self.iter.seek(&self.savedkey);
+        if self.iter.valid() {
+            self.find_next_user_entry(
+                // skipping=
+                false,
+            );
+        } else {
+            self.valid = false;
         }
     }
 
@@ -65,35 +148,124 @@ impl DBIterator {
     fn record_read_sample<'a>(&mut self, len: usize) {
         self.byte_count -= len as isize;
         if self.byte_count < 0 {
-            let v = self.vset.borrow().current();
-            v.borrow_mut().record_read_sample(&self.keybuf);
+            if let Some(v) = self.vset.borrow().current_cf(self.cf) {
+                v.borrow_mut().record_read_sample(&self.keybuf);
+            }
             while self.byte_count < 0 {
                 self.byte_count += random_period();
             }
         }
     }
 
+    /// is_covered returns whether an entry is deleted by a newer range tombstone.
+    fn is_covered(&self, ukey: &[u8], seq: SequenceNumber) -> bool {
+        !self.tombstones.is_empty()
+            && max_covering_seq(
+                self.tombstones.iter(),
+                self.cmp.as_ref().as_ref(),
+                ukey,
+                self.ss.sequence(),
+            ) > seq
+    }
+
+    /// merge_operands applies self.operands to `base` and stores the result in self.savedval. On
+    /// failure, the iterator is invalidated and the error is kept for status().
+    fn merge_operands(&mut self, base: Option<Vec<u8>>) -> bool {
+        let r = merge_operator::full_merge(
+            self.merge_operator.as_ref(),
+            &self.savedkey,
+            base.as_ref().map(|b| b.as_slice()),
+            &self.operands,
+        );
+        self.operands.clear();
+        match r {
+            Ok(v) => {
+                self.savedval = v;
+                true
+            }
+            Err(e) => {
+                self.status = Some(e);
+                self.valid = false;
+                self.savedkey.clear();
+                self.savedval.clear();
+                false
+            }
+        }
+    }
+
+    /// merge_forward, on a forward-moving iterator positioned at a merge operand, collects the
+    /// operands and the base value of the current user key and stores the merged entry in
+    /// savedkey/savedval. self.iter is left after the last entry that was consumed.
+    fn merge_forward(&mut self) -> bool {
+        self.savedkey.clear();
+        self.savedkey
+            .extend_from_slice(parse_internal_key(&self.keybuf).2);
+        self.operands.clear();
+        self.operands.push(mem::replace(&mut self.savedval, vec![]));
+        let mut base = None;
+
+        loop {
+            self.iter.advance();
+            if !self.iter.valid() {
+                break;
+            }
+            self.iter.current(&mut self.keybuf, &mut self.valbuf);
+            let len = self.keybuf.len() + self.valbuf.len();
+            self.record_read_sample(len);
+            let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            if self.cmp.cmp(ukey, &self.savedkey) != Ordering::Equal {
+                break;
+            }
+            if self.is_covered(ukey, seq) {
+                break;
+            }
+            match resolve_ttl(typ, &mut self.valbuf, self.now) {
+                ValueType::TypeMerge => self.operands.push(self.valbuf.clone()),
+                ValueType::TypeValue => {
+                    base = Some(self.valbuf.clone());
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        self.merged = true;
+        self.valid = self.merge_operands(base);
+        self.valid
+    }
+
     /// find_next_user_entry skips to the next user entry after the one saved in self.savedkey.
     fn find_next_user_entry(&mut self, mut skipping: bool) -> bool {
         assert!(self.iter.valid());
         assert!(self.dir == Direction::Forward);
+        self.merged = false;
 
         while self.iter.valid() {
             self.iter.current(&mut self.keybuf, &mut self.savedval);
             let len = self.keybuf.len() + self.savedval.len();
             self.record_read_sample(len);
             let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            let typ = resolve_ttl(typ, &mut self.savedval, self.now);
+
+            if let Some(ref upper) = self.upper_bound {
+                if self.cmp.cmp(ukey, upper) != Ordering::Less {
+                    break;
+                }
+            }
 
             // Skip keys with a sequence number after our snapshot.
             if seq <= self.ss.sequence() {
-                if typ == ValueType::TypeDeletion {
+                let is_entry = typ == ValueType::TypeValue || typ == ValueType::TypeMerge;
+                if typ == ValueType::TypeDeletion || (is_entry && self.is_covered(ukey, seq)) {
                     // Mark current (deleted) key to be skipped.
                     self.savedkey.clear();
                     self.savedkey.extend_from_slice(ukey);
                     skipping = true;
-                } else if typ == ValueType::TypeValue {
+                } else if is_entry {
                     if skipping && self.cmp.cmp(ukey, &self.savedkey) <= Ordering::Equal {
                         // Entry hidden, because it's smaller than the key to be skipped.
+                    } else if typ == ValueType::TypeMerge {
+                        return self.merge_forward();
                     } else {
                         self.valid = true;
                         self.savedkey.clear();
@@ -114,6 +286,11 @@ impl DBIterator {
     fn find_prev_user_entry(&mut self) -> bool {
         assert!(self.dir == Direction::Reverse);
         let mut value_type = ValueType::TypeDeletion;
+        // Whether savedval holds a value that merge operands found later (i.e. newer ones) are
+        // applied to.
+        let mut has_base = false;
+        // Merge operands are collected oldest first here.
+        self.operands.clear();
 
         // The iterator should be already set to the previous entry if this is a direction change
         // (i.e. first prev() call after advance()). savedkey is set to the key of that entry.
@@ -132,6 +309,12 @@ impl DBIterator {
             self.record_read_sample(len);
             let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
 
+            if let Some(ref lower) = self.lower_bound {
+                if self.cmp.cmp(ukey, lower) == Ordering::Less {
+                    break;
+                }
+            }
+
             if seq > 0 && seq <= self.ss.sequence() {
                 if value_type != ValueType::TypeDeletion
                     && self.cmp.cmp(ukey, &self.savedkey) == Ordering::Less
@@ -139,15 +322,26 @@ impl DBIterator {
                     // We found a non-deleted entry for a previous key (in the previous iteration)
                     break;
                 }
-                value_type = typ;
+                value_type = resolve_ttl(typ, &mut self.valbuf, self.now);
+                if value_type != ValueType::TypeDeletion && self.is_covered(ukey, seq) {
+                    value_type = ValueType::TypeDeletion;
+                }
                 if value_type == ValueType::TypeDeletion {
                     self.savedkey.clear();
                     self.savedval.clear();
+                    self.operands.clear();
+                    has_base = false;
                 } else {
                     self.savedkey.clear();
                     self.savedkey.extend_from_slice(ukey);
 
-                    mem::swap(&mut self.savedval, &mut self.valbuf);
+                    if value_type == ValueType::TypeMerge {
+                        self.operands.push(self.valbuf.clone());
+                    } else {
+                        mem::swap(&mut self.savedval, &mut self.valbuf);
+                        self.operands.clear();
+                        has_base = true;
+                    }
                 }
             }
             self.iter.prev();
@@ -158,6 +352,14 @@ impl DBIterator {
             self.savedkey.clear();
             self.savedval.clear();
             self.dir = Direction::Forward;
+        } else if value_type == ValueType::TypeMerge {
+            self.operands.reverse();
+            let base = if has_base {
+                Some(mem::replace(&mut self.savedval, vec![]))
+            } else {
+                None
+            };
+            self.valid = self.merge_operands(base);
         } else {
             self.valid = true;
         }
@@ -167,6 +369,9 @@ impl DBIterator {
 
 impl LdbIterator for DBIterator {
     fn advance(&mut self) -> bool {
+        if self.status.is_some() {
+            return false;
+        }
         if !self.valid() {
             self.seek_to_first();
             return self.valid();
@@ -184,6 +389,15 @@ impl LdbIterator for DBIterator {
                 self.savedkey.clear();
                 return false;
             }
+        } else if self.merged {
+            // The merged entry's key is in savedkey, and self.iter has already been moved past
+            // the merged entries.
+            if !self.iter.valid() {
+                self.valid = false;
+                self.merged = false;
+                self.savedkey.clear();
+                return false;
+            }
         } else {
             // Save current user key.
             assert!(self.iter.current(&mut self.savedkey, &mut self.savedval));
@@ -198,9 +412,12 @@ impl LdbIterator for DBIterator {
         if !self.valid() {
             return false;
         }
-        // If direction is forward, savedkey and savedval are not used.
-        if self.dir == Direction::Forward {
+        // If direction is forward, savedkey and savedval are not used unless the entry has been
+        // merged.
+        if self.dir == Direction::Forward && !self.merged {
             self.iter.current(key, val);
+            // Strips the expiry time from values with a TTL.
+            resolve_ttl(parse_internal_key(key).0, val, self.now);
             truncate_to_userkey(key);
             true
         } else {
@@ -221,8 +438,15 @@ impl LdbIterator for DBIterator {
             // find_prev_user_entry() wants savedkey to be the key of the entry that is supposed to
             // be left in savedkey/savedval, which is why we have to go to the previous entry before
             // calling it.
-            self.iter.current(&mut self.savedkey, &mut self.savedval);
-            truncate_to_userkey(&mut self.savedkey);
+            if self.merged {
+                // self.iter has been moved past the merged entries; go back to the first one.
+                self.merged = false;
+                let first = LookupKey::new(&self.savedkey, MAX_SEQUENCE_NUMBER);
+                self.iter.seek(first.internal_key());
+            } else {
+                self.iter.current(&mut self.savedkey, &mut self.savedval);
+                truncate_to_userkey(&mut self.savedkey);
+            }
             loop {
                 self.iter.prev();
                 if !self.iter.valid() {
@@ -246,23 +470,23 @@ impl LdbIterator for DBIterator {
         self.valid
     }
     fn seek(&mut self, to: &[u8]) {
-        self.dir = Direction::Forward;
-        self.savedkey.clear();
-        self.savedval.clear();
-        self.savedkey
-            .extend_from_slice(LookupKey::new(to, self.ss.sequence()).internal_key());
-        self.iter.seek(&self.savedkey);
-        if self.iter.valid() {
-            self.find_next_user_entry(
-                // skipping=
-                false,
-            );
-        } else {
-            self.valid = false;
+        if self.statistics.is_none() {
+            return self.seek_internal(to);
+        }
+        let start = self.env.micros();
+        self.seek_internal(to);
+        let elapsed = self.env.micros().saturating_sub(start);
+        if let Some(ref stats) = self.statistics {
+            stats.measure(Histogram::SeekMicros, elapsed);
         }
     }
     fn seek_to_first(&mut self) {
+        if let Some(lower) = self.lower_bound.clone() {
+            self.seek(&lower);
+            return;
+        }
         self.dir = Direction::Forward;
+        self.merged = false;
         self.savedval.clear();
         self.iter.seek_to_first();
         if self.iter.valid() {
@@ -277,6 +501,7 @@ impl LdbIterator for DBIterator {
     fn reset(&mut self) {
         self.iter.reset();
         self.valid = false;
+        self.merged = false;
         self.savedkey.clear();
         self.savedval.clear();
         self.keybuf.clear();
@@ -292,8 +517,12 @@ mod tests {
     use super::*;
     use db_impl::testutil::*;
     use db_impl::DB;
+    use error::StatusCode;
+    use merge_operator::testutil::append_operator;
+    use options::{self, ReadOptions};
     use test_util::LdbIteratorIter;
     use types::{current_key_val, Direction};
+    use write_batch::WriteBatch;
 
     use std::collections::HashMap;
     use std::collections::HashSet;
@@ -301,7 +530,7 @@ mod tests {
 
     #[test]
     fn db_iter_basic_test() {
-        let mut db = build_db().0;
+        let db = build_db().0;
         let mut iter = db.new_iter().unwrap();
 
         // keys and values come from make_version(); they are each the latest entry.
@@ -320,7 +549,7 @@ mod tests {
 
     #[test]
     fn db_iter_reset() {
-        let mut db = build_db().0;
+        let db = build_db().0;
         let mut iter = db.new_iter().unwrap();
 
         assert!(iter.advance());
@@ -333,7 +562,7 @@ mod tests {
 
     #[test]
     fn db_iter_test_fwd_backwd() {
-        let mut db = build_db().0;
+        let db = build_db().0;
         let mut iter = db.new_iter().unwrap();
 
         // keys and values come from make_version(); they are each the latest entry.
@@ -384,7 +613,7 @@ mod tests {
 
     #[test]
     fn db_iter_test_seek() {
-        let mut db = build_db().0;
+        let db = build_db().0;
         let mut iter = db.new_iter().unwrap();
 
         // gca is the deleted entry.
@@ -414,9 +643,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn db_iter_bounds() {
+        let db = build_db().0;
+        let ro = ReadOptions {
+            iterate_lower_bound: Some(b"aab".to_vec()),
+            iterate_upper_bound: Some(b"bba".to_vec()),
+            ..ReadOptions::default()
+        };
+        let mut iter = db.new_iter_opt(&ro).unwrap();
+
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![
+                b"aab".to_vec(),
+                b"aax".to_vec(),
+                b"aba".to_vec(),
+                b"bab".to_vec(),
+            ],
+            keys
+        );
+
+        // Seeking below the lower bound positions the iterator at the lower bound.
+        iter.seek(b"aaa");
+        assert_eq!(
+            (b"aab".to_vec(), b"val2".to_vec()),
+            current_key_val(&iter).unwrap()
+        );
+        // Moving backwards stops at the lower bound.
+        iter.prev();
+        assert!(!iter.valid());
+
+        iter.seek(b"bba");
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn db_iter_prefix() {
+        let db = build_db().0;
+        let keys = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            let mut iter = db.new_prefix_iter(prefix).unwrap();
+            LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect()
+        };
+        assert_eq!(
+            vec![b"aaa".to_vec(), b"aab".to_vec(), b"aax".to_vec()],
+            keys(b"aa")
+        );
+        assert_eq!(vec![b"cab".to_vec()], keys(b"ca"));
+        assert_eq!(vec![b"eab".to_vec()], keys(b"eab"));
+        assert!(keys(b"ab0").is_empty());
+        // The empty prefix covers all keys.
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(LdbIteratorIter::wrap(&mut iter).count(), keys(b"").len());
+    }
+
     #[test]
     fn db_iter_deleted_entry_not_returned() {
-        let mut db = build_db().0;
+        let db = build_db().0;
         let mut iter = db.new_iter().unwrap();
         let must_not_appear = b"gca";
 
@@ -425,9 +708,110 @@ mod tests {
         }
     }
 
+    #[test]
+    fn db_iter_range_deletion() {
+        let db = build_db().0;
+        let ss = db.get_snapshot();
+        db.delete_range(b"aab", b"bba").unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        let keys: &[&[u8]] = &[b"aaa", b"bba", b"cab", b"cba"];
+        let vals: &[&[u8]] = &[b"val1", b"val5", b"val2", b"val3"];
+        for (k, v) in keys.iter().zip(vals.iter()) {
+            assert!(iter.advance());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+
+        // Older snapshots are not affected.
+        let mut iter = db.new_iter_at(ss).unwrap();
+        iter.seek(b"aab");
+        assert_eq!(b"aab".to_vec(), current_key_val(&iter).unwrap().0);
+
+        // Backwards iteration skips covered entries, too.
+        let db = DB::open("rangedel", options::for_test()).unwrap();
+        for k in &[b"a", b"b", b"c", b"d"] {
+            db.put(*k, *k).unwrap();
+        }
+        db.delete_range(b"b", b"d").unwrap();
+        db.put(b"c", b"cc").unwrap();
+        let mut iter = db.new_iter().unwrap();
+        let expected: &[(&[u8], &[u8])] = &[(b"a", b"a"), (b"c", b"cc"), (b"d", b"d")];
+        for &(k, v) in expected {
+            assert!(iter.advance());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+        for &(k, v) in expected.iter().rev().skip(1) {
+            iter.prev();
+            assert!(iter.valid());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+    }
+
+    #[test]
+    fn db_iter_merge() {
+        let mut opt = options::for_test();
+        opt.merge_operator = Some(append_operator());
+        let db = DB::open("merge", opt).unwrap();
+        db.put(b"a", b"a").unwrap();
+        db.merge(b"b", b"b1").unwrap();
+        db.put(b"c", b"c").unwrap();
+        db.merge(b"c", b"c1").unwrap();
+        db.merge(b"c", b"c2").unwrap();
+        db.delete(b"d").unwrap();
+        db.merge(b"d", b"d1").unwrap();
+        db.put(b"e", b"e").unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        let expected: &[(&[u8], &[u8])] = &[
+            (b"a", b"a"),
+            (b"b", b"b1"),
+            (b"c", b"c,c1,c2"),
+            (b"d", b"d1"),
+            (b"e", b"e"),
+        ];
+        for &(k, v) in expected {
+            assert!(iter.advance());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+        assert!(!iter.advance());
+
+        iter.seek(b"c");
+        assert_eq!(
+            (b"c".to_vec(), b"c,c1,c2".to_vec()),
+            current_key_val(&iter).unwrap()
+        );
+        for &(k, v) in expected.iter().rev().skip(3) {
+            iter.prev();
+            assert!(iter.valid());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+
+        // Backwards iteration from the end merges, too.
+        iter.seek(b"e");
+        for &(k, v) in expected.iter().rev().skip(1) {
+            iter.prev();
+            assert!(iter.valid());
+            assert_eq!((k.to_vec(), v.to_vec()), current_key_val(&iter).unwrap());
+        }
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn db_iter_merge_without_operator() {
+        let db = build_db().0;
+        let mut wb = WriteBatch::new();
+        wb.merge(b"aaa", b"x");
+        db.write(wb, false).unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        assert!(!iter.advance());
+        assert_eq!(StatusCode::NotSupported, iter.status().unwrap_err().code);
+        assert!(!iter.advance());
+    }
+
     #[test]
     fn db_iter_deleted_entry_not_returned_memtable() {
-        let mut db = build_db().0;
+        let db = build_db().0;
 
         db.put(b"xyz", b"123").unwrap();
         db.delete(b"xyz").unwrap();
@@ -444,7 +828,7 @@ mod tests {
     fn db_iter_repeated_open_close() {
         let opt;
         {
-            let (mut db, opt_) = build_db();
+            let (db, opt_) = build_db();
             opt = opt_;
 
             db.put(b"xx1", b"111").unwrap();
@@ -455,12 +839,12 @@ mod tests {
         }
 
         {
-            let mut db = DB::open("db", opt.clone()).unwrap();
+            let db = DB::open("db", opt.clone()).unwrap();
             db.put(b"xx4", b"222").unwrap();
         }
 
         {
-            let mut db = DB::open("db", opt).unwrap();
+            let db = DB::open("db", opt).unwrap();
 
             let ss = db.get_snapshot();
             // xx5 should not be visible.
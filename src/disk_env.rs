@@ -1,7 +1,7 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
-use env::{path_to_str, Env, FileLock, Logger, RandomAccess};
+use env::{path_to_str, Env, FileLock, Logger, RandomAccess, WritableFile};
 use env_common::micros;
 use error::{err, Result, Status, StatusCode};
 
@@ -66,6 +66,51 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
     s
 }
 
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        /// ProtectedWritableFile is a protected file opened for writing. Its path is kept in order
+        /// to sync the underlying file.
+        struct ProtectedWritableFile {
+            file: protected_fs::ProtectedFile,
+            path: PathBuf,
+        }
+
+        impl Write for ProtectedWritableFile {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.file.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.file.flush()
+            }
+        }
+
+        impl ProtectedWritableFile {
+            /// flush_to_disk writes the cache to the underlying file, and returns that file to be
+            /// synced.
+            fn flush_to_disk(&mut self) -> Result<fs::File> {
+                self.file
+                    .flush()
+                    .map_err(|e| map_err_with_name("sync (flush)", &self.path, e))?;
+                fs::File::open(&self.path)
+                    .map_err(|e| map_err_with_name("sync (open)", &self.path, e))
+            }
+        }
+
+        // Protected files keep an encrypted cache inside the enclave. Flushing only hands it to
+        // the untrusted file, which then has to be synced like any other file.
+        impl WritableFile for ProtectedWritableFile {
+            fn sync(&mut self) -> Result<()> {
+                let f = self.flush_to_disk()?;
+                f.sync_all().map_err(|e| map_err_with_name("sync", &self.path, e))
+            }
+            fn sync_data(&mut self) -> Result<()> {
+                let f = self.flush_to_disk()?;
+                f.sync_data().map_err(|e| map_err_with_name("sync", &self.path, e))
+            }
+        }
+    }
+}
+
 // Note: We're using Ok(f()?) in several locations below in order to benefit from the automatic
 // error conversion using std::convert::From.
 impl Env for PosixDiskEnv {
@@ -111,16 +156,17 @@ impl Env for PosixDiskEnv {
             }
         }
     }
-    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
         cfg_if! {
             if #[cfg(feature = "mesalock_sgx")]  {
-                Ok(Box::new(
-                    protected_fs::OpenOptions::default()
+                Ok(Box::new(ProtectedWritableFile {
+                    file: protected_fs::OpenOptions::default()
                         .write(true)
                         .append(false)
                         .open_ex(p, &self.key)
                         .map_err(|e| map_err_with_name("open_sgx (write)", p, e))?,
-                ))
+                    path: p.to_owned(),
+                }))
             }
             else {
                 Ok(Box::new(
@@ -134,15 +180,16 @@ impl Env for PosixDiskEnv {
             }
         }
     }
-    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
         cfg_if! {
             if #[cfg(feature = "mesalock_sgx")] {
-                Ok(Box::new(
-                    protected_fs::OpenOptions::default()
+                Ok(Box::new(ProtectedWritableFile {
+                    file: protected_fs::OpenOptions::default()
                         .append(true)
                         .open_ex(p, &self.key)
                         .map_err(|e| map_err_with_name("open_sgx (append_sgx)", p, e))?,
-                ))
+                    path: p.to_owned(),
+                }))
             } else {
                 Ok(Box::new(
                     fs::OpenOptions::new()
@@ -221,6 +268,11 @@ impl Env for PosixDiskEnv {
             }
         }
     }
+    fn sync_dir(&self, p: &Path) -> Result<()> {
+        let f = fs::File::open(p).map_err(|e| map_err_with_name("sync_dir", p, e))?;
+        f.sync_all()
+            .map_err(|e| map_err_with_name("sync_dir", p, e))
+    }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         let mut locks = self.locks.lock().unwrap();
@@ -325,8 +377,11 @@ mod tests {
                 // write
                 let mut f = env.open_writable_file(name).unwrap();
                 let _ = f.write("123xyz".as_bytes());
+                assert!(f.sync_data().is_ok());
+                assert!(f.sync().is_ok());
             }
             assert_eq!(6, env.size_of(name).unwrap_or(0));
+            assert!(env.sync_dir(Path::new(".")).is_ok());
 
             // rename
             let newname = Path::new("testfile2.xyz");
@@ -3,9 +3,9 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
-use error::Result;
+use error::{err, Result, StatusCode};
 
-use std::io::prelude::*;
+use std::io::{self, prelude::*, BufWriter};
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 
@@ -19,7 +19,7 @@ cfg_if! {
     }
 }
 
-pub trait RandomAccess {
+pub trait RandomAccess: Send + Sync {
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
 }
 
@@ -39,15 +39,58 @@ cfg_if! {
     }
 }
 
+/// A WritableFile is a file opened for writing. Data written to it may be buffered by the Env or
+/// the operating system; it is only guaranteed to survive a crash after `sync()` has returned.
+pub trait WritableFile: Write + Send + Sync {
+    /// sync writes all data and metadata of the file to stable storage.
+    fn sync(&mut self) -> Result<()>;
+    /// sync_data writes all data of the file to stable storage, but not necessarily metadata
+    /// like the modification time that isn't needed to read the data back.
+    fn sync_data(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+
+impl WritableFile for File {
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.sync_all()?)
+    }
+    fn sync_data(&mut self) -> Result<()> {
+        Ok(File::sync_data(self)?)
+    }
+}
+
+impl<W: WritableFile + ?Sized> WritableFile for Box<W> {
+    fn sync(&mut self) -> Result<()> {
+        (**self).sync()
+    }
+    fn sync_data(&mut self) -> Result<()> {
+        (**self).sync_data()
+    }
+}
+
+/// Buffered data is written to the file before syncing it.
+impl<W: WritableFile> WritableFile for BufWriter<W> {
+    fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
+    fn sync_data(&mut self) -> Result<()> {
+        self.flush()?;
+        self.get_mut().sync_data()
+    }
+}
+
 pub struct FileLock {
     pub id: String,
 }
 
-pub trait Env {
+/// Implementations must be thread-safe, as a database may be shared between threads.
+pub trait Env: Send + Sync {
     fn open_sequential_file(&self, &Path) -> Result<Box<dyn Read>>;
     fn open_random_access_file(&self, &Path) -> Result<Box<dyn RandomAccess>>;
-    fn open_writable_file(&self, &Path) -> Result<Box<dyn Write>>;
-    fn open_appendable_file(&self, &Path) -> Result<Box<dyn Write>>;
+    fn open_writable_file(&self, &Path) -> Result<Box<dyn WritableFile>>;
+    fn open_appendable_file(&self, &Path) -> Result<Box<dyn WritableFile>>;
 
     fn exists(&self, &Path) -> Result<bool>;
     fn children(&self, &Path) -> Result<Vec<PathBuf>>;
@@ -57,6 +100,9 @@ pub trait Env {
     fn mkdir(&self, &Path) -> Result<()>;
     fn rmdir(&self, &Path) -> Result<()>;
     fn rename(&self, &Path, &Path) -> Result<()>;
+    /// sync_dir makes sure that the creation, deletion and renaming of files in a directory
+    /// survive a crash.
+    fn sync_dir(&self, &Path) -> Result<()>;
 
     fn lock(&self, &Path) -> Result<FileLock>;
     fn unlock(&self, l: FileLock) -> Result<()>;
@@ -67,11 +113,11 @@ pub trait Env {
 }
 
 pub struct Logger {
-    dst: Box<dyn Write>,
+    dst: Box<dyn Write + Send + Sync>,
 }
 
 impl Logger {
-    pub fn new(w: Box<dyn Write>) -> Logger {
+    pub fn new(w: Box<dyn Write + Send + Sync>) -> Logger {
         Logger { dst: w }
     }
 
@@ -81,6 +127,29 @@ impl Logger {
     }
 }
 
+/// copy_file copies the first `size` bytes of `src` to a new file `dst` in a possibly different
+/// Env. The data is read and written through the Envs, so that e.g. protected files are
+/// re-encrypted for their new location.
+pub fn copy_file(
+    src_env: &dyn Env,
+    src: &Path,
+    dst_env: &dyn Env,
+    dst: &Path,
+    size: usize,
+) -> Result<()> {
+    let r = src_env.open_sequential_file(src)?;
+    let mut w = dst_env.open_writable_file(dst)?;
+    let copied = io::copy(&mut r.take(size as u64), &mut w)?;
+    w.sync()?;
+    if copied < size as u64 {
+        return err(
+            StatusCode::IOError,
+            &format!("{} is shorter than {} bytes", src.display(), size),
+        );
+    }
+    Ok(())
+}
+
 pub fn path_to_string(p: &Path) -> String {
     p.to_str().map(String::from).unwrap()
 }
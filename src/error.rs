@@ -18,6 +18,7 @@ pub enum StatusCode {
     OK,
 
     AlreadyExists,
+    Busy,
     Corruption,
     CompressionError,
     IOError,
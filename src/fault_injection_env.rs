@@ -0,0 +1,550 @@
+//! A FaultInjectionEnv wraps another Env in order to test how a database copes with crashes and
+//! I/O errors. It keeps track of which data has been synced, and can simulate a crash by dropping
+//! everything else.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use env::{Env, FileLock, Logger, RandomAccess, WritableFile};
+use error::{err, Result, StatusCode};
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        use std::sync::{Arc, SgxMutex as Mutex};
+    } else {
+        use std::sync::{Arc, Mutex};
+    }
+}
+
+/// The write position of a file, and the position it had when it was last synced.
+struct FileState {
+    pos: usize,
+    synced: usize,
+}
+
+struct State {
+    files: HashMap<PathBuf, FileState>,
+    // Files created since their directory was last synced.
+    new_files: HashSet<PathBuf>,
+    // Countdowns to the next injected error.
+    write_error: Option<usize>,
+    read_error: Option<usize>,
+    rename_error: Option<usize>,
+    no_space: bool,
+}
+
+/// countdown decrements an error countdown, and returns true if the error is due.
+fn countdown(c: &mut Option<usize>) -> bool {
+    match *c {
+        Some(1) => {
+            *c = None;
+            true
+        }
+        Some(n) => {
+            *c = Some(n - 1);
+            false
+        }
+        None => false,
+    }
+}
+
+fn injected_error(op: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("injected {} error", op))
+}
+
+/// FaultInjectionEnv passes all operations on to another Env, while tracking the data written to
+/// files that hasn't been synced yet. Calling `simulate_crash()` leaves the files in the state they
+/// could be in after a crash. It can also be told to fail single reads, writes and renames.
+///
+/// The model follows LevelDB's fault injection tests: syncing a file makes both its contents and
+/// its directory entry durable, while files created since the last `sync_dir()` of their
+/// directory that have never been synced are lost. Renames and deletions are never undone.
+///
+/// Clones share their state, so that one clone can be used as `Options::env` while another one
+/// controls the faults.
+#[derive(Clone)]
+pub struct FaultInjectionEnv {
+    inner: Arc<Box<dyn Env>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjectionEnv {
+    pub fn new(inner: Box<dyn Env>) -> FaultInjectionEnv {
+        FaultInjectionEnv {
+            inner: Arc::new(inner),
+            state: Arc::new(Mutex::new(State {
+                files: HashMap::new(),
+                new_files: HashSet::new(),
+                write_error: None,
+                read_error: None,
+                rename_error: None,
+                no_space: false,
+            })),
+        }
+    }
+
+    /// fail_nth_write makes the n-th write to any file from now on fail, with n = 1 being the next
+    /// write.
+    pub fn fail_nth_write(&self, n: usize) {
+        assert!(n > 0);
+        self.state.lock().unwrap().write_error = Some(n);
+    }
+
+    /// fail_nth_read makes the n-th read from any file from now on fail.
+    pub fn fail_nth_read(&self, n: usize) {
+        assert!(n > 0);
+        self.state.lock().unwrap().read_error = Some(n);
+    }
+
+    /// fail_nth_rename makes the n-th rename from now on fail.
+    pub fn fail_nth_rename(&self, n: usize) {
+        assert!(n > 0);
+        self.state.lock().unwrap().rename_error = Some(n);
+    }
+
+    /// set_no_space simulates a full disk: While set, all writes fail like they would with
+    /// ENOSPC.
+    pub fn set_no_space(&self, no_space: bool) {
+        self.state.lock().unwrap().no_space = no_space;
+    }
+
+    /// drop_unsynced_data truncates all files to the size they had when they were last synced.
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for (path, f) in state.files.iter_mut() {
+            if f.synced < f.pos {
+                truncate(self.inner.as_ref().as_ref(), path, f.synced)?;
+                f.pos = f.synced;
+            }
+        }
+        Ok(())
+    }
+
+    /// delete_unsynced_files deletes all files that have been created since their directory was
+    /// last synced, and that haven't been synced themselves.
+    pub fn delete_unsynced_files(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let new_files: Vec<PathBuf> = state.new_files.drain().collect();
+        for path in new_files {
+            state.files.remove(&path);
+            if self.inner.exists(&path)? {
+                self.inner.delete(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// simulate_crash drops all data that isn't durable yet. It should only be called once the
+    /// database using this Env has been closed, as the files mustn't be written concurrently.
+    pub fn simulate_crash(&self) -> Result<()> {
+        self.delete_unsynced_files()?;
+        self.drop_unsynced_data()
+    }
+
+    fn track_file(&self, p: &Path, size: usize, new: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(
+            p.to_owned(),
+            FileState {
+                pos: size,
+                synced: size,
+            },
+        );
+        if new {
+            state.new_files.insert(p.to_owned());
+        }
+    }
+
+    fn writable_file(&self, p: &Path, f: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        Box::new(FaultInjectionFile {
+            path: p.to_owned(),
+            file: f,
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// truncate shortens a file to `len` bytes, using only the operations provided by every Env.
+fn truncate(env: &dyn Env, p: &Path, len: usize) -> Result<()> {
+    let mut contents = vec![];
+    env.open_sequential_file(p)?
+        .take(len as u64)
+        .read_to_end(&mut contents)?;
+    let mut f = env.open_writable_file(p)?;
+    f.write_all(&contents)?;
+    f.sync()
+}
+
+impl Env for FaultInjectionEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        Ok(Box::new(FaultInjectionReader {
+            r: self.inner.open_sequential_file(p)?,
+            state: self.state.clone(),
+        }))
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        Ok(Box::new(FaultInjectionReader {
+            r: self.inner.open_random_access_file(p)?,
+            state: self.state.clone(),
+        }))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        let f = self.inner.open_writable_file(p)?;
+        self.track_file(p, 0, true);
+        Ok(self.writable_file(p, f))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        let exists = self.inner.exists(p)?;
+        let f = self.inner.open_appendable_file(p)?;
+        if !self.state.lock().unwrap().files.contains_key(p) {
+            let size = if exists { self.inner.size_of(p)? } else { 0 };
+            self.track_file(p, size, !exists);
+        }
+        Ok(self.writable_file(p, f))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.inner.size_of(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.inner.delete(p)?;
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(p);
+        state.new_files.remove(p);
+        Ok(())
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        if countdown(&mut self.state.lock().unwrap().rename_error) {
+            return err(StatusCode::IOError, "injected rename error");
+        }
+        self.inner.rename(old, new)?;
+        let mut state = self.state.lock().unwrap();
+        match state.files.remove(old) {
+            Some(f) => state.files.insert(new.to_owned(), f),
+            None => state.files.remove(new),
+        };
+        if state.new_files.remove(old) {
+            state.new_files.insert(new.to_owned());
+        }
+        Ok(())
+    }
+    fn sync_dir(&self, p: &Path) -> Result<()> {
+        self.inner.sync_dir(p)?;
+        self.state
+            .lock()
+            .unwrap()
+            .new_files
+            .retain(|f| f.parent() != Some(p));
+        Ok(())
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.inner.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+}
+
+struct FaultInjectionFile {
+    path: PathBuf,
+    file: Box<dyn WritableFile>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Write for FaultInjectionFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.no_space {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "No space left on device",
+                ));
+            }
+            if countdown(&mut state.write_error) {
+                return Err(injected_error("write"));
+            }
+        }
+        let n = self.file.write(buf)?;
+        if let Some(f) = self.state.lock().unwrap().files.get_mut(&self.path) {
+            f.pos += n;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WritableFile for FaultInjectionFile {
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync()?;
+        let mut state = self.state.lock().unwrap();
+        if let Some(f) = state.files.get_mut(&self.path) {
+            f.synced = f.pos;
+        }
+        state.new_files.remove(&self.path);
+        Ok(())
+    }
+}
+
+struct FaultInjectionReader<R> {
+    r: R,
+    state: Arc<Mutex<State>>,
+}
+
+impl<R: Read> Read for FaultInjectionReader<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if countdown(&mut self.state.lock().unwrap().read_error) {
+            return Err(injected_error("read"));
+        }
+        self.r.read(dst)
+    }
+}
+
+impl RandomAccess for FaultInjectionReader<Box<dyn RandomAccess>> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        if countdown(&mut self.state.lock().unwrap().read_error) {
+            return err(StatusCode::IOError, "injected read error");
+        }
+        self.r.read_at(off, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db_impl::DB;
+    use mem_env::MemEnv;
+    use options::{self, Options, ReadOptions};
+    use types::LdbIterator;
+    use write_batch::WriteBatch;
+
+    fn fault_env() -> FaultInjectionEnv {
+        FaultInjectionEnv::new(Box::new(MemEnv::new()))
+    }
+
+    fn options_with(env: &FaultInjectionEnv) -> Options {
+        let mut opt = options::for_test();
+        opt.env = Arc::new(Box::new(env.clone()));
+        opt
+    }
+
+    fn read_file(env: &dyn Env, p: &Path) -> String {
+        let mut s = String::new();
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    }
+
+    #[test]
+    fn test_fault_injection_env_simulate_crash() {
+        let env = fault_env();
+        let (a, b, c) = (Path::new("/d/a"), Path::new("/d/b"), Path::new("/d/c"));
+
+        {
+            let mut f = env.open_writable_file(a).unwrap();
+            f.write_all(b"synced").unwrap();
+            f.sync().unwrap();
+            f.write_all(b" lost").unwrap();
+        }
+        {
+            // Written, but never synced: its directory entry survives, but not its contents.
+            let mut f = env.open_writable_file(c).unwrap();
+            f.write_all(b"lost").unwrap();
+        }
+        env.sync_dir(Path::new("/d")).unwrap();
+        env.open_writable_file(b)
+            .unwrap()
+            .write_all(b"lost")
+            .unwrap();
+        env.open_appendable_file(a)
+            .unwrap()
+            .write_all(b" appended")
+            .unwrap();
+        assert_eq!("synced lost appended", read_file(&env, a));
+
+        env.simulate_crash().unwrap();
+        assert_eq!("synced", read_file(&env, a));
+        assert!(!env.exists(b).unwrap());
+        assert_eq!(0, env.size_of(c).unwrap());
+
+        // Crashing again doesn't change anything.
+        env.simulate_crash().unwrap();
+        assert_eq!("synced", read_file(&env, a));
+    }
+
+    #[test]
+    fn test_fault_injection_env_errors() {
+        let env = fault_env();
+        let (a, b) = (Path::new("/d/a"), Path::new("/d/b"));
+
+        let mut f = env.open_writable_file(a).unwrap();
+        env.fail_nth_write(2);
+        f.write_all(b"abc").unwrap();
+        assert!(f.write_all(b"def").is_err());
+        f.write_all(b"ghi").unwrap();
+        assert_eq!("abcghi", read_file(&env, a));
+
+        env.set_no_space(true);
+        let e = f.write_all(b"jkl").unwrap_err();
+        assert!(e.to_string().contains("No space left"));
+        env.set_no_space(false);
+        f.write_all(b"jkl").unwrap();
+
+        env.fail_nth_read(1);
+        let mut s = String::new();
+        let mut r = env.open_sequential_file(a).unwrap();
+        assert!(r.read_to_string(&mut s).is_err());
+        assert_eq!("abcghijkl", read_file(&env, a));
+
+        let r = env.open_random_access_file(a).unwrap();
+        let mut buf = [0; 3];
+        env.fail_nth_read(2);
+        assert_eq!(3, r.read_at(3, &mut buf).unwrap());
+        assert_eq!(
+            StatusCode::IOError,
+            r.read_at(3, &mut buf).unwrap_err().code
+        );
+        assert_eq!(b"ghi", &buf);
+
+        env.fail_nth_rename(1);
+        assert!(env.rename(a, b).is_err());
+        assert!(env.exists(a).unwrap());
+        env.rename(a, b).unwrap();
+        assert!(!env.exists(a).unwrap());
+        assert_eq!("abcghijkl", read_file(&env, b));
+    }
+
+    fn value_of(key: &[u8]) -> Vec<u8> {
+        let mut v = key.to_vec();
+        v.extend_from_slice(&[b'v'; 64]);
+        v
+    }
+
+    fn check_db(db: &DB, synced: &[Vec<u8>]) {
+        for k in synced {
+            assert_eq!(Some(value_of(k)), db.get(k));
+        }
+        // Unsynced writes may have been lost, but mustn't have been corrupted.
+        let mut iter = db.new_iter().unwrap();
+        while let Some((k, v)) = iter.next() {
+            assert_eq!(value_of(&k), v);
+        }
+    }
+
+    #[test]
+    fn test_fault_injection_env_db_crash_recovery() {
+        let env = fault_env();
+        let mut opt = options_with(&env);
+        opt.write_buffer_size = 8 * 1024;
+
+        let mut synced = vec![];
+        for round in 0..5 {
+            let db = DB::open("db", opt.clone()).unwrap();
+            check_db(&db, &synced);
+
+            // Start failing at some point in later rounds, possibly in the background thread.
+            if round >= 3 {
+                env.fail_nth_write(50 * round);
+            }
+            for i in 0..400 {
+                let k = format!("key{}-{:03}", round, i).into_bytes();
+                let mut b = WriteBatch::new();
+                b.put(&k, &value_of(&k));
+                let sync = i % 7 == 0;
+                if db.write(b, sync).is_err() {
+                    break;
+                }
+                if sync {
+                    synced.push(k);
+                }
+            }
+            drop(db);
+            env.simulate_crash().unwrap();
+        }
+
+        let db = DB::open("db", opt).unwrap();
+        check_db(&db, &synced);
+    }
+
+    #[test]
+    fn test_fault_injection_env_db_errors() {
+        let env = fault_env();
+        let opt = options_with(&env);
+
+        // Installing the first manifest fails.
+        env.fail_nth_rename(1);
+        assert!(DB::open("db", opt.clone()).is_err());
+
+        let mut keys = vec![];
+        for round in 0..2 {
+            let db = DB::open("db", opt.clone()).unwrap();
+            check_db(&db, &keys);
+            for i in 0..10 {
+                let k = format!("key{}-{}", round, i).into_bytes();
+                let mut b = WriteBatch::new();
+                b.put(&k, &value_of(&k));
+                db.write(b, true).unwrap();
+                keys.push(k);
+            }
+
+            let mut b = WriteBatch::new();
+            b.put(b"failed", b"write");
+            if round == 0 {
+                env.set_no_space(true);
+                assert_eq!(StatusCode::IOError, db.write(b, true).unwrap_err().code);
+                env.set_no_space(false);
+            } else {
+                env.fail_nth_write(1);
+                assert!(db.write(b, true).is_err());
+            }
+            assert_eq!(None, db.get(b"failed"));
+
+            // No more writes are accepted after a failed write to the log.
+            let mut b = WriteBatch::new();
+            b.put(b"later", b"write");
+            assert!(db.write(b, true).is_err());
+
+            drop(db);
+            env.simulate_crash().unwrap();
+        }
+
+        let db = DB::open("db", opt).unwrap();
+        check_db(&db, &keys);
+        assert_eq!(None, db.get(b"failed"));
+
+        // Reads from table files fail once.
+        db.compact_range(b"a", b"z").unwrap();
+        env.fail_nth_read(1);
+        assert!(db.get_opt(&ReadOptions::default(), &keys[0]).is_err());
+        assert_eq!(Some(value_of(&keys[0])), db.get(&keys[0]));
+    }
+}
@@ -3,8 +3,9 @@ use std::prelude::v1::*;
 
 use block::BlockContents;
 use filter::BoxedFilterPolicy;
+use statistics::{self, Statistics, Ticker};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use integer_encoding::FixedInt;
 
@@ -110,18 +111,19 @@ impl FilterBlockBuilder {
 #[derive(Clone)]
 pub struct FilterBlockReader {
     policy: BoxedFilterPolicy,
-    block: Rc<BlockContents>,
+    block: Arc<BlockContents>,
 
     offsets_offset: usize,
     filter_base_lg2: u32,
+    statistics: Option<Arc<Statistics>>,
 }
 
 impl FilterBlockReader {
     pub fn new_owned(pol: BoxedFilterPolicy, data: Vec<u8>) -> FilterBlockReader {
-        FilterBlockReader::new(pol, Rc::new(data))
+        FilterBlockReader::new(pol, Arc::new(data))
     }
 
-    pub fn new(pol: BoxedFilterPolicy, data: Rc<Vec<u8>>) -> FilterBlockReader {
+    pub fn new(pol: BoxedFilterPolicy, data: Arc<Vec<u8>>) -> FilterBlockReader {
         assert!(data.len() >= 5);
 
         let fbase = data[data.len() - 1] as u32;
@@ -132,9 +134,15 @@ impl FilterBlockReader {
             block: data,
             filter_base_lg2: fbase,
             offsets_offset: offset,
+            statistics: None,
         }
     }
 
+    /// set_statistics makes key_may_match() count how often the filter rules out a block.
+    pub fn set_statistics(&mut self, stats: Option<Arc<Statistics>>) {
+        self.statistics = stats;
+    }
+
     /// Returns number of filters
     pub fn num(&self) -> u32 {
         ((self.block.len() - self.offsets_offset - 5) / 4) as u32
@@ -159,8 +167,16 @@ impl FilterBlockReader {
         assert!(filter_begin < filter_end);
         assert!(filter_end <= self.offsets_offset);
 
-        self.policy
-            .key_may_match(key, &self.block[filter_begin..filter_end])
+        let may_match = self
+            .policy
+            .key_may_match(key, &self.block[filter_begin..filter_end]);
+        let ticker = if may_match {
+            Ticker::FilterUseless
+        } else {
+            Ticker::FilterUseful
+        };
+        statistics::record(&self.statistics, ticker, 1);
+        may_match
     }
 }
 
@@ -188,7 +204,7 @@ mod tests {
 
     fn produce_filter_block() -> Vec<u8> {
         let keys = get_keys();
-        let mut bld = FilterBlockBuilder::new(Rc::new(Box::new(BloomPolicy::new(32))));
+        let mut bld = FilterBlockBuilder::new(Arc::new(Box::new(BloomPolicy::new(32))));
 
         bld.start_block(0);
 
@@ -225,7 +241,7 @@ mod tests {
     #[test]
     fn test_filter_block_build_read() {
         let result = produce_filter_block();
-        let reader = FilterBlockReader::new_owned(Rc::new(Box::new(BloomPolicy::new(32))), result);
+        let reader = FilterBlockReader::new_owned(Arc::new(Box::new(BloomPolicy::new(32))), result);
 
         assert_eq!(
             reader.offset_of(get_filter_index(5121, FILTER_BASE_LOG2)),
@@ -2,7 +2,7 @@
 use std::prelude::v1::*;
 
 use cmp::Cmp;
-use types::SequenceNumber;
+use types::{SequenceNumber, MAX_SEQUENCE_NUMBER};
 
 use std::cmp::Ordering;
 use std::io::Write;
@@ -19,6 +19,15 @@ use integer_encoding::{FixedInt, FixedIntWriter, VarInt, VarIntWriter};
 pub enum ValueType {
     TypeDeletion = 0,
     TypeValue = 1,
+    /// A merge operand, to be combined with older entries by the configured MergeOperator.
+    TypeMerge = 2,
+    /// A value that expires at a given time. The expiry time (in microseconds, as returned by
+    /// `Env::micros()`) is appended to the value as fixed64. Expired values behave like
+    /// deletions.
+    TypeValueWithTTL = 3,
+    /// Marks a range of user keys as deleted. Range deletions are not stored as ordinary entries
+    /// in memtables or tables, see the range_tombstone module.
+    TypeRangeDeletion = 0xF,
 }
 
 /// A MemtableKey consists of the following elements: [keylen, key, tag, (vallen, value)] where
@@ -97,6 +106,9 @@ pub fn parse_tag(tag: u64) -> (ValueType, u64) {
     match typ {
         0 => (ValueType::TypeDeletion, seq),
         1 => (ValueType::TypeValue, seq),
+        2 => (ValueType::TypeMerge, seq),
+        3 => (ValueType::TypeValueWithTTL, seq),
+        0xF => (ValueType::TypeRangeDeletion, seq),
         _ => (ValueType::TypeValue, seq),
     }
 }
@@ -216,10 +228,86 @@ pub fn truncate_to_userkey(ikey: &mut Vec<u8>) {
     ikey.truncate(len - 8);
 }
 
+/// internal_bound converts a user key used as iterator bound into an InternalKey that bounds the
+/// same entries: an entry's user key is smaller than `ukey` iff its InternalKey is smaller than
+/// the returned key.
+pub fn internal_bound<'a>(ukey: UserKey<'a>) -> Vec<u8> {
+    LookupKey::new(ukey, MAX_SEQUENCE_NUMBER)
+        .internal_key()
+        .to_vec()
+}
+
+const EXPIRY_LEN: usize = 8;
+
+/// append_expiry encodes a value with an expiry time, as stored in entries of type
+/// TypeValueWithTTL.
+pub fn append_expiry(val: &[u8], expiry: u64) -> Vec<u8> {
+    let mut v = Vec::with_capacity(val.len() + EXPIRY_LEN);
+    v.extend_from_slice(val);
+    v.write_fixedint(expiry).expect("error writing to vec");
+    v
+}
+
+/// split_expiry splits the value of a TypeValueWithTTL entry into the actual value and the
+/// expiry time.
+pub fn split_expiry<'a>(val: &'a [u8]) -> (&'a [u8], u64) {
+    if val.len() < EXPIRY_LEN {
+        // Malformed; treat as expired.
+        return (&val[0..0], 0);
+    }
+    let split = val.len() - EXPIRY_LEN;
+    (&val[..split], u64::decode_fixed(&val[split..]))
+}
+
+/// resolve_ttl turns a value with an expiry time into an ordinary value (truncating `val` to the
+/// actual value), or into a deletion if it has expired at time `now`. Other entries are returned
+/// unchanged.
+pub fn resolve_ttl(typ: ValueType, val: &mut Vec<u8>, now: u64) -> ValueType {
+    if typ != ValueType::TypeValueWithTTL {
+        return typ;
+    }
+    let (len, expiry) = {
+        let (v, expiry) = split_expiry(val);
+        (v.len(), expiry)
+    };
+    if expiry <= now {
+        val.clear();
+        ValueType::TypeDeletion
+    } else {
+        val.truncate(len);
+        ValueType::TypeValue
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_key_types_ttl() {
+        let v = append_expiry(b"abc", 1000);
+        assert_eq!((&b"abc"[..], 1000), split_expiry(&v));
+
+        let mut val = v.clone();
+        assert_eq!(
+            ValueType::TypeValue,
+            resolve_ttl(ValueType::TypeValueWithTTL, &mut val, 999)
+        );
+        assert_eq!(b"abc".to_vec(), val);
+        let mut val = v.clone();
+        assert_eq!(
+            ValueType::TypeDeletion,
+            resolve_ttl(ValueType::TypeValueWithTTL, &mut val, 1000)
+        );
+        assert!(val.is_empty());
+        let mut val = v.clone();
+        assert_eq!(
+            ValueType::TypeValue,
+            resolve_ttl(ValueType::TypeValue, &mut val, 2000)
+        );
+        assert_eq!(v, val);
+    }
+
     #[test]
     fn test_memtable_lookupkey() {
         use integer_encoding::VarInt;
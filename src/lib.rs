@@ -6,7 +6,7 @@
 //! use rusty_leveldb::{DB, DBIterator, LdbIterator, Options};
 //!
 //! let opt = rusty_leveldb::in_memory();
-//! let mut db = DB::open("mydatabase", opt).unwrap();
+//! let db = DB::open("mydatabase", opt).unwrap();
 //!
 //! db.put(b"Hello", b"World").unwrap();
 //! assert_eq!(b"World", db.get(b"Hello").unwrap().as_slice());
@@ -51,15 +51,19 @@ extern crate snap;
 #[macro_use]
 extern crate time_test;
 
+mod backup;
 mod block;
 mod block_builder;
 mod blockhandle;
 mod cache;
 mod cmp;
+mod column_family;
+mod compaction_filter;
 mod disk_env;
 mod env;
 mod env_common;
 mod error;
+mod fault_injection_env;
 mod filter;
 mod filter_block;
 #[macro_use]
@@ -68,33 +72,51 @@ mod key_types;
 mod log;
 mod mem_env;
 mod memtable;
+mod merge_operator;
 mod merging_iter;
 mod options;
+mod range_tombstone;
+mod secondary;
 mod skipmap;
 mod snapshot;
+mod statistics;
+mod sst_file_writer;
 mod table_block;
 mod table_builder;
 mod table_cache;
 mod table_reader;
 mod test_util;
+mod transaction;
 mod types;
+mod update_iter;
 mod version;
 mod version_edit;
 mod version_set;
 mod write_batch;
+mod write_batch_with_index;
 
 mod db_impl;
 mod db_iter;
 
+pub use backup::{BackupEngine, BackupID, BackupInfo};
 pub use cmp::{Cmp, DefaultCmp};
+pub use column_family::ColumnFamilyHandle;
+pub use compaction_filter::{BoxedCompactionFilter, CompactionFilter, Decision};
 pub use db_impl::DB;
 pub use db_iter::DBIterator;
 pub use disk_env::PosixDiskEnv;
-pub use env::Env;
+pub use env::{Env, WritableFile};
 pub use error::{Result, Status, StatusCode};
+pub use fault_injection_env::FaultInjectionEnv;
 pub use filter::{BloomPolicy, FilterPolicy};
 pub use mem_env::MemEnv;
-pub use options::{in_memory, CompressionType, Options};
+pub use merge_operator::{BoxedMergeOperator, MergeOperator};
+pub use options::{in_memory, CompressionType, Options, ReadOptions, WriteOptions};
 pub use skipmap::SkipMap;
-pub use types::LdbIterator;
+pub use sst_file_writer::{ExternalFileInfo, SstFileWriter};
+pub use statistics::{Histogram, HistogramData, Statistics, Ticker};
+pub use transaction::Transaction;
+pub use types::{LdbIterator, Range};
+pub use update_iter::UpdateIterator;
 pub use write_batch::WriteBatch;
+pub use write_batch_with_index::{WriteBatchWithIndex, WriteBatchWithIndexIter};
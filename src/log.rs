@@ -5,6 +5,7 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use env::WritableFile;
 use error::{err, Result, StatusCode};
 
 use std::io::{Read, Write};
@@ -118,6 +119,13 @@ impl<W: Write> LogWriter<W> {
     }
 }
 
+impl<W: WritableFile> LogWriter<W> {
+    /// sync writes all records added so far to stable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        self.dst.sync()
+    }
+}
+
 pub struct LogReader<R: Read> {
     // TODO: Wrap src in a buffer to enhance read performance.
     src: R,
@@ -140,6 +148,14 @@ impl<R: Read> LogReader<R> {
         }
     }
 
+    /// new_with_off opens a reader for a source positioned at some offset of a log file, which
+    /// must be the start of a record. The file must have the default block size.
+    pub fn new_with_off(src: R, chksum: bool, off: usize) -> LogReader<R> {
+        let mut r = LogReader::new(src, chksum);
+        r.blk_off = off % BLOCK_SIZE;
+        r
+    }
+
     /// EOF is signalled by Ok(0)
     pub fn read(&mut self, dst: &mut Vec<u8>) -> Result<usize> {
         let mut checksum: u32;
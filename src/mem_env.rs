@@ -2,7 +2,7 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
-use env::{path_to_str, path_to_string, Env, FileLock, Logger, RandomAccess};
+use env::{path_to_str, path_to_string, Env, FileLock, Logger, RandomAccess, WritableFile};
 use env_common::micros;
 use error::{err, Result, StatusCode};
 
@@ -118,6 +118,13 @@ impl Write for MemFileWriter {
     }
 }
 
+/// Written data is immediately visible to readers; there is nothing to sync.
+impl WritableFile for MemFileWriter {
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
 impl RandomAccess for MemFile {
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
         let guard = self.0.lock().unwrap();
@@ -167,7 +174,7 @@ impl MemFS {
         }
     }
     /// Open a file for writing.
-    fn open_w(&self, p: &Path, append: bool, truncate: bool) -> Result<Box<dyn Write>> {
+    fn open_w(&self, p: &Path, append: bool, truncate: bool) -> Result<Box<dyn WritableFile>> {
         let f = self.open(p, true)?;
         if truncate {
             f.0.lock().unwrap().clear();
@@ -299,10 +306,10 @@ impl Env for MemEnv {
             .open(p, false)
             .map(|m| Box::new(m) as Box<dyn RandomAccess>)
     }
-    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
         self.0.open_w(p, true, true)
     }
-    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
         self.0.open_w(p, true, false)
     }
 
@@ -336,6 +343,9 @@ impl Env for MemEnv {
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
         self.0.rename_(old, new)
     }
+    fn sync_dir(&self, _: &Path) -> Result<()> {
+        Ok(())
+    }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         self.0.lock_(p)
@@ -580,8 +590,9 @@ mod tests {
         let me = MemEnv::new();
         let (p1, p2, p3) = (Path::new("/a/b"), Path::new("/a/c"), Path::new("/a/d"));
         let nonexist = Path::new("/x/y");
-        me.open_writable_file(p2).unwrap();
-        me.open_appendable_file(p3).unwrap();
+        me.open_writable_file(p2).unwrap().sync().unwrap();
+        me.open_appendable_file(p3).unwrap().sync_data().unwrap();
+        me.sync_dir(Path::new("/a")).unwrap();
         me.open_sequential_file(p2).unwrap();
         me.open_random_access_file(p3).unwrap();
 
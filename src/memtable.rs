@@ -2,69 +2,146 @@
 use std::prelude::v1::*;
 
 use cmp::{Cmp, MemtableKeyCmp};
-use key_types::{build_memtable_key, parse_internal_key, parse_memtable_key, ValueType};
+use key_types::split_expiry;
+use key_types::ValueType;
+use key_types::{build_memtable_key, parse_internal_key, parse_memtable_key, parse_tag};
 use key_types::{LookupKey, UserKey};
+use range_tombstone::{max_covering_seq, RangeTombstone};
 use skipmap::{SkipMap, SkipMapIter};
-use types::{current_key_val, LdbIterator, SequenceNumber};
+use types::{current_key_val, share, LdbIterator, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use integer_encoding::FixedInt;
 
 /// Provides Insert/Get/Iterate, based on the SkipMap implementation.
 /// MemTable uses MemtableKeys internally, that is, it stores key and value in the [Skipmap] key.
+/// Range tombstones are kept separately from the map.
+/// Clones of a MemTable refer to the same underlying map.
+#[derive(Clone)]
 pub struct MemTable {
     map: SkipMap,
+    ucmp: Arc<Box<dyn Cmp>>,
+    tombstones: Shared<Vec<RangeTombstone>>,
 }
 
 impl MemTable {
     /// Returns a new MemTable.
     /// This wraps opt.cmp inside a MemtableKey-specific comparator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
-        MemTable::new_raw(Rc::new(Box::new(MemtableKeyCmp(cmp))))
-    }
-
-    /// Doesn't wrap the comparator in a MemtableKeyCmp.
-    fn new_raw(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
+    pub fn new(cmp: Arc<Box<dyn Cmp>>) -> MemTable {
         MemTable {
-            map: SkipMap::new(cmp),
+            map: SkipMap::new(Arc::new(Box::new(MemtableKeyCmp(cmp.clone())))),
+            ucmp: cmp,
+            tombstones: share(vec![]),
         }
     }
 
+    /// len returns the number of entries, including range tombstones.
     pub fn len(&self) -> usize {
-        self.map.len()
+        self.map.len() + self.tombstones.borrow().len()
     }
 
     pub fn approx_mem_usage(&self) -> usize {
         self.map.approx_memory()
+            + self
+                .tombstones
+                .borrow()
+                .iter()
+                .map(|t| t.start.len() + t.end.len() + 8)
+                .sum::<usize>()
     }
 
+    /// add inserts an entry. For range deletions, key and value are the start and end of the
+    /// deleted range.
     pub fn add<'a>(&mut self, seq: SequenceNumber, t: ValueType, key: UserKey<'a>, value: &[u8]) {
+        if t == ValueType::TypeRangeDeletion {
+            self.tombstones
+                .borrow_mut()
+                .push(RangeTombstone::new(key, value, seq));
+            return;
+        }
         self.map
             .insert(build_memtable_key(key, value, t, seq), Vec::new())
     }
 
+    /// range_tombstones returns a copy of the range tombstones in this memtable.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.tombstones.borrow().clone()
+    }
+
     /// get returns the value for the given entry and whether the entry is marked as deleted. This
-    /// is to distinguish between not-found and found-deleted.
+    /// is to distinguish between not-found and found-deleted. An entry is also marked as deleted
+    /// if it is covered by a newer range tombstone, or if its value has expired at time `now`.
+    ///
+    /// Merge operands found before the entry's value or deletion are appended to `operands`,
+    /// newest first. If neither is found, the lookup has to be continued in older data.
     #[allow(unused_variables)]
-    pub fn get(&self, key: &LookupKey) -> (Option<Vec<u8>>, bool) {
+    pub fn get(
+        &self,
+        key: &LookupKey,
+        now: u64,
+        operands: &mut Vec<Vec<u8>>,
+    ) -> (Option<Vec<u8>>, bool) {
+        let (_, snapshot, _) = parse_internal_key(key.internal_key());
+        let tomb_seq = max_covering_seq(
+            self.tombstones.borrow().iter(),
+            self.ucmp.as_ref().as_ref(),
+            key.user_key(),
+            snapshot,
+        );
+
         let mut iter = self.map.iter();
         iter.seek(key.memtable_key());
 
-        if let Some((foundkey, _)) = current_key_val(&iter) {
+        while let Some((foundkey, _)) = current_key_val(&iter) {
             let (fkeylen, fkeyoff, tag, vallen, valoff) = parse_memtable_key(&foundkey);
 
             // Compare user key -- if equal, proceed
             // We only care about user key equality here
-            if key.user_key() == &foundkey[fkeyoff..fkeyoff + fkeylen] {
-                if tag & 0xff == ValueType::TypeValue as u64 {
-                    return (Some(foundkey[valoff..valoff + vallen].to_vec()), false);
-                } else {
-                    return (None, true);
+            if key.user_key() != &foundkey[fkeyoff..fkeyoff + fkeylen] {
+                break;
+            }
+            if tag >> 8 < tomb_seq {
+                return (None, true);
+            }
+            let val = &foundkey[valoff..valoff + vallen];
+            match parse_tag(tag).0 {
+                ValueType::TypeValue => return (Some(val.to_vec()), false),
+                ValueType::TypeValueWithTTL => {
+                    let (val, expiry) = split_expiry(val);
+                    if expiry <= now {
+                        return (None, true);
+                    }
+                    return (Some(val.to_vec()), false);
                 }
+                ValueType::TypeMerge => operands.push(val.to_vec()),
+                _ => return (None, true),
+            }
+            iter.advance();
+        }
+        (None, tomb_seq > 0)
+    }
+
+    /// latest_sequence returns the sequence number of the newest entry for a key, or of the newest
+    /// range tombstone covering it. It returns 0 if there is no such entry.
+    pub fn latest_sequence<'a>(&self, key: UserKey<'a>) -> SequenceNumber {
+        let mut latest = max_covering_seq(
+            self.tombstones.borrow().iter(),
+            self.ucmp.as_ref().as_ref(),
+            key,
+            MAX_SEQUENCE_NUMBER,
+        );
+
+        let lkey = LookupKey::new(key, MAX_SEQUENCE_NUMBER);
+        let mut iter = self.map.iter();
+        iter.seek(lkey.memtable_key());
+        if let Some((foundkey, _)) = current_key_val(&iter) {
+            let (fkeylen, fkeyoff, tag, _, _) = parse_memtable_key(&foundkey);
+            if key == &foundkey[fkeyoff..fkeyoff + fkeylen] && tag >> 8 > latest {
+                latest = tag >> 8;
             }
         }
-        (None, false)
+        latest
     }
 
     pub fn iter(&self) -> MemtableIterator {
@@ -103,10 +180,11 @@ impl LdbIterator for MemtableIterator {
             if self.skipmapiter.current(&mut key, &mut val) {
                 let (_, _, tag, _, _) = parse_memtable_key(&key);
 
-                if tag & 0xff == ValueType::TypeValue as u64 {
-                    return true;
-                } else {
-                    continue;
+                match parse_tag(tag).0 {
+                    ValueType::TypeValue | ValueType::TypeValueWithTTL | ValueType::TypeMerge => {
+                        return true
+                    }
+                    _ => continue,
                 }
             } else {
                 return false;
@@ -219,44 +297,173 @@ mod tests {
         let mt = get_memtable();
 
         // Smaller sequence number doesn't find entry
-        if let Some(v) = mt.get(&LookupKey::new("abc".as_bytes(), 110)).0 {
+        if let Some(v) = mt
+            .get(&LookupKey::new("abc".as_bytes(), 110), 0, &mut vec![])
+            .0
+        {
             println!("{:?}", v);
             panic!("found");
         }
 
-        if let Some(v) = mt.get(&LookupKey::new("abf".as_bytes(), 110)).0 {
+        if let Some(v) = mt
+            .get(&LookupKey::new("abf".as_bytes(), 110), 0, &mut vec![])
+            .0
+        {
             println!("{:?}", v);
             panic!("found");
         }
 
         // Bigger sequence number falls back to next smaller
-        if let Some(v) = mt.get(&LookupKey::new("abc".as_bytes(), 116)).0 {
+        if let Some(v) = mt
+            .get(&LookupKey::new("abc".as_bytes(), 116), 0, &mut vec![])
+            .0
+        {
             assert_eq!(v, "122".as_bytes());
         } else {
             panic!("not found");
         }
 
         // Exact match works
-        if let (Some(v), deleted) = mt.get(&LookupKey::new("abc".as_bytes(), 120)) {
+        if let (Some(v), deleted) = mt.get(&LookupKey::new("abc".as_bytes(), 120), 0, &mut vec![]) {
             assert_eq!(v, "123".as_bytes());
             assert!(!deleted);
         } else {
             panic!("not found");
         }
 
-        if let (None, deleted) = mt.get(&LookupKey::new("abe".as_bytes(), 122)) {
+        if let (None, deleted) = mt.get(&LookupKey::new("abe".as_bytes(), 122), 0, &mut vec![]) {
             assert!(deleted);
         } else {
             panic!("found deleted");
         }
 
-        if let Some(v) = mt.get(&LookupKey::new("abf".as_bytes(), 129)).0 {
+        if let Some(v) = mt
+            .get(&LookupKey::new("abf".as_bytes(), 129), 0, &mut vec![])
+            .0
+        {
             assert_eq!(v, "126".as_bytes());
         } else {
             panic!("not found");
         }
     }
 
+    #[test]
+    fn test_memtable_range_deletion() {
+        let mut mt = get_memtable();
+        let len = mt.len();
+        mt.add(124, ValueType::TypeRangeDeletion, b"abc", b"abe");
+        mt.add(125, ValueType::TypeValue, b"abd", b"127");
+        assert_eq!(len + 2, mt.len());
+        assert_eq!(
+            vec![RangeTombstone::new(b"abc", b"abe", 124)],
+            mt.range_tombstones()
+        );
+
+        // Entries older than the tombstone are deleted.
+        assert_eq!(
+            (None, true),
+            mt.get(&LookupKey::new(b"abc", 124), 0, &mut vec![])
+        );
+        assert_eq!(
+            (None, true),
+            mt.get(&LookupKey::new(b"abd", 124), 0, &mut vec![])
+        );
+        // Snapshots older than the tombstone still see the entries.
+        assert_eq!(
+            (Some(b"123".to_vec()), false),
+            mt.get(&LookupKey::new(b"abc", 123), 0, &mut vec![])
+        );
+        // Newer entries are not affected, and neither is the end of the range.
+        assert_eq!(
+            (Some(b"127".to_vec()), false),
+            mt.get(&LookupKey::new(b"abd", 125), 0, &mut vec![])
+        );
+        assert_eq!(
+            (Some(b"126".to_vec()), false),
+            mt.get(&LookupKey::new(b"abf", 125), 0, &mut vec![])
+        );
+        // Keys without entries are reported as deleted, too.
+        assert_eq!(
+            (None, true),
+            mt.get(&LookupKey::new(b"abcd", 125), 0, &mut vec![])
+        );
+        assert_eq!(
+            (None, false),
+            mt.get(&LookupKey::new(b"abcd", 123), 0, &mut vec![])
+        );
+    }
+
+    #[test]
+    fn test_memtable_latest_sequence() {
+        let mut mt = get_memtable();
+        assert_eq!(120, mt.latest_sequence(b"abc"));
+        assert_eq!(122, mt.latest_sequence(b"abe"));
+        assert_eq!(0, mt.latest_sequence(b"abcd"));
+        assert_eq!(0, mt.latest_sequence(b"zzz"));
+
+        mt.add(124, ValueType::TypeRangeDeletion, b"abc", b"abe");
+        mt.add(125, ValueType::TypeValue, b"abd", b"127");
+        assert_eq!(124, mt.latest_sequence(b"abc"));
+        assert_eq!(124, mt.latest_sequence(b"abcd"));
+        assert_eq!(125, mt.latest_sequence(b"abd"));
+        assert_eq!(122, mt.latest_sequence(b"abe"));
+    }
+
+    #[test]
+    fn test_memtable_merge() {
+        let mut mt = get_memtable();
+        mt.add(124, ValueType::TypeMerge, b"abc", b"m1");
+        mt.add(125, ValueType::TypeMerge, b"abc", b"m2");
+        mt.add(126, ValueType::TypeMerge, b"xyz", b"m3");
+
+        // Operands are collected newest first, until a value is found.
+        let mut operands = vec![];
+        assert_eq!(
+            (Some(b"123".to_vec()), false),
+            mt.get(&LookupKey::new(b"abc", 130), 0, &mut operands)
+        );
+        assert_eq!(vec![b"m2".to_vec(), b"m1".to_vec()], operands);
+
+        let mut operands = vec![];
+        assert_eq!(
+            (Some(b"123".to_vec()), false),
+            mt.get(&LookupKey::new(b"abc", 124), 0, &mut operands)
+        );
+        assert_eq!(vec![b"m1".to_vec()], operands);
+
+        // Without a value, the lookup has to continue.
+        let mut operands = vec![];
+        assert_eq!(
+            (None, false),
+            mt.get(&LookupKey::new(b"xyz", 130), 0, &mut operands)
+        );
+        assert_eq!(vec![b"m3".to_vec()], operands);
+    }
+
+    #[test]
+    fn test_memtable_ttl() {
+        let mut mt = MemTable::new(options::for_test().cmp);
+        mt.add(1, ValueType::TypeValue, b"abc", b"old");
+        mt.add(
+            2,
+            ValueType::TypeValueWithTTL,
+            b"abc",
+            &append_expiry(b"new", 100),
+        );
+        assert_eq!(
+            (Some(b"new".to_vec()), false),
+            mt.get(&LookupKey::new(b"abc", 5), 99, &mut vec![])
+        );
+        assert_eq!(
+            (None, true),
+            mt.get(&LookupKey::new(b"abc", 5), 100, &mut vec![])
+        );
+        assert_eq!(
+            (Some(b"old".to_vec()), false),
+            mt.get(&LookupKey::new(b"abc", 1), 100, &mut vec![])
+        );
+    }
+
     #[test]
     fn test_memtable_iterator_init() {
         let mt = get_memtable();
@@ -0,0 +1,95 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use error::{err, Result, StatusCode};
+
+use std::sync::Arc;
+
+/// A MergeOperator combines the value of a key with merge operands written using `DB::merge()`.
+/// This allows read-modify-write updates (e.g. counters, or appending to lists) without reading
+/// the current value first.
+pub trait MergeOperator: Send + Sync {
+    /// Returns a string identifying this operator.
+    fn name(&self) -> &'static str;
+    /// Applies the operands (ordered from oldest to newest) to the existing value, which is None
+    /// if the key doesn't exist or has been deleted.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8>;
+    /// Combines two consecutive operands into a single one, if possible. `older` has been
+    /// written before `newer`. This is used to shorten chains of operands during compactions.
+    fn partial_merge(&self, _key: &[u8], _older: &[u8], _newer: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A boxed and refcounted merge operator, as stored in `Options`.
+pub type BoxedMergeOperator = Arc<Box<dyn MergeOperator>>;
+
+/// full_merge applies operands ordered from newest to oldest (the order in which they are found
+/// during lookups) to an existing value. It fails if there is no merge operator.
+pub fn full_merge(
+    op: Option<&BoxedMergeOperator>,
+    key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &[Vec<u8>],
+) -> Result<Vec<u8>> {
+    match op {
+        Some(op) => {
+            let operands: Vec<&[u8]> = operands.iter().rev().map(|o| o.as_slice()).collect();
+            Ok(op.full_merge(key, existing, &operands))
+        }
+        None => err(
+            StatusCode::NotSupported,
+            "found merge operand, but no merge operator is configured",
+        ),
+    }
+}
+
+#[cfg(test)]
+pub mod testutil {
+    use super::*;
+
+    /// AppendOperator joins all operands with the existing value, separated by commas.
+    pub struct AppendOperator;
+
+    impl MergeOperator for AppendOperator {
+        fn name(&self) -> &'static str {
+            "AppendOperator"
+        }
+        fn full_merge(&self, _: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+            let mut parts: Vec<&[u8]> = existing.into_iter().collect();
+            parts.extend_from_slice(operands);
+            parts.join(&b","[..])
+        }
+        fn partial_merge(&self, _: &[u8], older: &[u8], newer: &[u8]) -> Option<Vec<u8>> {
+            Some([older, newer].join(&b","[..]))
+        }
+    }
+
+    pub fn append_operator() -> BoxedMergeOperator {
+        Arc::new(Box::new(AppendOperator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testutil::*;
+    use super::*;
+
+    #[test]
+    fn test_merge_operator_full_merge() {
+        let op = append_operator();
+        let operands = vec![b"c".to_vec(), b"b".to_vec()];
+        assert_eq!(
+            b"a,b,c".to_vec(),
+            full_merge(Some(&op), b"k", Some(b"a"), &operands).unwrap()
+        );
+        assert_eq!(
+            b"b,c".to_vec(),
+            full_merge(Some(&op), b"k", None, &operands).unwrap()
+        );
+        assert_eq!(
+            StatusCode::NotSupported,
+            full_merge(None, b"k", None, &operands).unwrap_err().code
+        );
+    }
+}
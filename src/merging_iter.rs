@@ -5,7 +5,7 @@ use cmp::Cmp;
 use types::{current_key_val, Direction, LdbIterator};
 
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 // Warning: This module is kinda messy. The original implementation is
 // not that much better though :-)
@@ -23,22 +23,61 @@ pub struct MergingIter {
     iters: Vec<Box<dyn LdbIterator>>,
     current: Option<usize>,
     direction: Direction,
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
+    // Entries outside of [lower_bound; upper_bound) are not returned.
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
 }
 
 impl MergingIter {
     /// Construct a new merging iterator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>, iters: Vec<Box<dyn LdbIterator>>) -> MergingIter {
+    pub fn new(cmp: Arc<Box<dyn Cmp>>, iters: Vec<Box<dyn LdbIterator>>) -> MergingIter {
         let mi = MergingIter {
             iters: iters,
             current: None,
             direction: Direction::Forward,
             cmp: cmp,
+            lower_bound: None,
+            upper_bound: None,
         };
         mi
     }
 
+    /// set_bounds restricts the iterator to entries within [lower; upper), as ordered by the
+    /// iterator's comparator. The bounds should also be applied to the merged iterators, so that
+    /// they can avoid reading out-of-bounds data.
+    pub fn set_bounds(&mut self, lower: Option<Vec<u8>>, upper: Option<Vec<u8>>) {
+        self.lower_bound = lower;
+        self.upper_bound = upper;
+    }
+
+    /// in_bounds returns whether the current entry is within the bounds.
+    fn in_bounds(&self) -> bool {
+        if self.lower_bound.is_none() && self.upper_bound.is_none() {
+            return true;
+        }
+        let (mut key, mut val) = (vec![], vec![]);
+        if !self.current(&mut key, &mut val) {
+            return true;
+        }
+        if let Some(ref lower) = self.lower_bound {
+            if self.cmp.cmp(&key, lower) == Ordering::Less {
+                return false;
+            }
+        }
+        if let Some(ref upper) = self.upper_bound {
+            if self.cmp.cmp(&key, upper) != Ordering::Less {
+                return false;
+            }
+        }
+        true
+    }
+
     fn init(&mut self) {
+        if let Some(lower) = self.lower_bound.clone() {
+            self.seek(&lower);
+            return;
+        }
         for i in 0..self.iters.len() {
             self.iters[i].reset();
             self.iters[i].advance();
@@ -87,8 +126,17 @@ impl MergingIter {
                                 if self.iters[i].valid() {
                                     self.iters[i].prev();
                                 } else {
-                                    // seek to last.
-                                    while self.iters[i].advance() {}
+                                    // seek to last. Iterators become invalid when advancing
+                                    // past their end, so the last key has to be sought again.
+                                    let mut last = None;
+                                    self.iters[i].reset();
+                                    while self.iters[i].advance() {
+                                        self.iters[i].current(&mut keybuf, &mut valbuf);
+                                        last = Some(keybuf.clone());
+                                    }
+                                    if let Some(last) = last {
+                                        self.iters[i].seek(&last);
+                                    }
                                 }
                             }
                         }
@@ -136,6 +184,9 @@ impl MergingIter {
         }
 
         self.current = Some(next_ix);
+        if !self.in_bounds() {
+            self.current = None;
+        }
     }
 }
 
@@ -163,8 +214,13 @@ impl LdbIterator for MergingIter {
         }
     }
     fn seek(&mut self, key: &[u8]) {
+        let key = match self.lower_bound {
+            Some(ref lower) if self.cmp.cmp(key, lower) == Ordering::Less => lower.clone(),
+            _ => key.to_vec(),
+        };
+        self.direction = Direction::Forward;
         for i in 0..self.iters.len() {
-            self.iters[i].seek(key);
+            self.iters[i].seek(&key);
         }
         self.find_smallest();
     }
@@ -212,7 +268,7 @@ mod tests {
         let iter = skm.iter();
         let mut iter2 = skm.iter();
 
-        let mut miter = MergingIter::new(Rc::new(Box::new(DefaultCmp)), vec![Box::new(iter)]);
+        let mut miter = MergingIter::new(Arc::new(Box::new(DefaultCmp)), vec![Box::new(iter)]);
 
         loop {
             if let Some((k, v)) = miter.next() {
@@ -235,7 +291,7 @@ mod tests {
         let iter2 = skm.iter();
 
         let mut miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
 
@@ -255,7 +311,7 @@ mod tests {
 
     #[test]
     fn test_merging_zero() {
-        let mut miter = MergingIter::new(Rc::new(Box::new(DefaultCmp)), vec![]);
+        let mut miter = MergingIter::new(Arc::new(Box::new(DefaultCmp)), vec![]);
         assert_eq!(0, LdbIteratorIter::wrap(&mut miter).count());
     }
 
@@ -265,7 +321,7 @@ mod tests {
         let iter = TestLdbIter::new(vec![(b("aba"), val), (b("abc"), val)]);
         let iter2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
         let miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
         test_iterator_properties(miter);
@@ -278,7 +334,7 @@ mod tests {
         let iter2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
 
         let mut miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
 
@@ -313,6 +369,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_merging_bounds() {
+        let val = "def".as_bytes();
+        let it1 = TestLdbIter::new(vec![(b("aba"), val), (b("abc"), val), (b("abe"), val)]);
+        let it2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
+
+        let mut iter = MergingIter::new(
+            Arc::new(Box::new(DefaultCmp)),
+            vec![Box::new(it1), Box::new(it2)],
+        );
+        iter.set_bounds(Some(b("abb").to_vec()), Some(b("abe").to_vec()));
+
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(vec![b("abb"), b("abc"), b("abd")], keys);
+
+        iter.seek(b("aba"));
+        assert_eq!(
+            Some(b("abb").to_vec()),
+            current_key_val(&iter).map(|(k, _)| k)
+        );
+        assert!(!iter.prev());
+        iter.seek(b("abe"));
+        assert!(!iter.valid());
+    }
+
     fn b(s: &'static str) -> &'static [u8] {
         s.as_bytes()
     }
@@ -326,7 +407,7 @@ mod tests {
         let expected = vec![b("aba"), b("abb"), b("abc"), b("abd"), b("abe")];
 
         let mut iter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(it1), Box::new(it2)],
         );
 
@@ -345,7 +426,7 @@ mod tests {
         let it2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
 
         let mut iter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(it1), Box::new(it2)],
         );
 
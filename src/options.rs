@@ -4,15 +4,19 @@ use std::prelude::v1::*;
 use block::Block;
 use cache::Cache;
 use cmp::{Cmp, DefaultCmp};
+use compaction_filter;
 use disk_env;
 
 use env::Env;
 use filter;
 use infolog::{self, Logger};
 use mem_env::MemEnv;
+use merge_operator;
+use snapshot::Snapshot;
+use statistics::Statistics;
 use types::{share, Shared};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 cfg_if! {
     if #[cfg(feature = "mesalock_sgx")] {
@@ -51,8 +55,8 @@ pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
 /// Note: Compression is not yet implemented.
 #[derive(Clone)]
 pub struct Options {
-    pub cmp: Rc<Box<dyn Cmp>>,
-    pub env: Rc<Box<dyn Env>>,
+    pub cmp: Arc<Box<dyn Cmp>>,
+    pub env: Arc<Box<dyn Env>>,
     pub log: Option<Shared<Logger>>,
     pub create_if_missing: bool,
     pub error_if_exists: bool,
@@ -67,6 +71,22 @@ pub struct Options {
     pub reuse_logs: bool,
     pub reuse_manifest: bool,
     pub filter_policy: filter::BoxedFilterPolicy,
+    /// Writes are delayed by 1ms each once level 0 has this many files.
+    pub l0_slowdown_writes_trigger: usize,
+    /// Writes are stopped until a background compaction has finished once level 0 has this many
+    /// files.
+    pub l0_stop_writes_trigger: usize,
+    /// Required for using `DB::merge()`. It must not be changed between runs of a database that
+    /// contains merge operands.
+    pub merge_operator: Option<merge_operator::BoxedMergeOperator>,
+    /// Called for entries being compacted; it can drop entries or replace their values.
+    pub compaction_filter: Option<compaction_filter::BoxedCompactionFilter>,
+    /// Keep log files until the updates in them have been acknowledged using
+    /// `DB::acknowledge_updates()`, so that `DB::get_updates_since()` can still read them.
+    pub retain_log_files: bool,
+    /// Collects counters and latencies of the database's operations if set. It applies to all
+    /// column families.
+    pub statistics: Option<Arc<Statistics>>,
 }
 
 
@@ -75,8 +95,8 @@ cfg_if! {
         impl Options {
             pub fn new_disk_db_with(key: DBPersistKey) -> Options {
                 Options {
-                    cmp: Rc::new(Box::new(DefaultCmp)),
-                    env: Rc::new(Box::new(disk_env::PosixDiskEnv::new_with(key))),
+                    cmp: Arc::new(Box::new(DefaultCmp)),
+                    env: Arc::new(Box::new(disk_env::PosixDiskEnv::new_with(key))),
                     log: None,
                     create_if_missing: true,
                     error_if_exists: false,
@@ -91,14 +111,20 @@ cfg_if! {
                     reuse_logs: true,
                     reuse_manifest: true,
                     compression_type: CompressionType::CompressionNone,
-                    filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    l0_slowdown_writes_trigger: 8,
+                    l0_stop_writes_trigger: 12,
+                    merge_operator: None,
+                    compaction_filter: None,
+                    retain_log_files: false,
+                    statistics: None,
                 }
             }
 
             pub fn new_mem_db() -> Options {
                 Options {
-                    cmp: Rc::new(Box::new(DefaultCmp)),
-                    env: Rc::new(Box::new(MemEnv::new())),
+                    cmp: Arc::new(Box::new(DefaultCmp)),
+                    env: Arc::new(Box::new(MemEnv::new())),
                     log: None,
                     create_if_missing: true,
                     error_if_exists: false,
@@ -113,7 +139,13 @@ cfg_if! {
                     reuse_logs: true,
                     reuse_manifest: true,
                     compression_type: CompressionType::CompressionNone,
-                    filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    l0_slowdown_writes_trigger: 8,
+                    l0_stop_writes_trigger: 12,
+                    merge_operator: None,
+                    compaction_filter: None,
+                    retain_log_files: false,
+                    statistics: None,
                 }
             }
         }
@@ -121,8 +153,8 @@ cfg_if! {
         impl Default for Options {
             fn default() -> Options {
                 Options {
-                    cmp: Rc::new(Box::new(DefaultCmp)),
-                    env: Rc::new(Box::new(MemEnv::new()));
+                    cmp: Arc::new(Box::new(DefaultCmp)),
+                    env: Arc::new(Box::new(MemEnv::new()));
                     log: None,
                     create_if_missing: true,
                     error_if_exists: false,
@@ -137,13 +169,59 @@ cfg_if! {
                     reuse_logs: true,
                     reuse_manifest: true,
                     compression_type: CompressionType::CompressionNone,
-                    filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+                    l0_slowdown_writes_trigger: 8,
+                    l0_stop_writes_trigger: 12,
+                    merge_operator: None,
+                    compaction_filter: None,
+                    retain_log_files: false,
+                    statistics: None,
                 }
             }
         }
     }
 }
 
+/// ReadOptions control the behavior of a single read operation, like `DB::get_opt()` or
+/// `DB::new_iter_opt()`.
+#[derive(Clone)]
+pub struct ReadOptions {
+    /// Read the state of the database at the time the snapshot was taken. If unset, the current
+    /// state is read.
+    pub snapshot: Option<Snapshot>,
+    /// Verify the checksums of all blocks read from table files.
+    pub verify_checksums: bool,
+    /// Insert blocks read from table files into the block cache. Bulk scans may want to disable
+    /// this in order to not evict other, frequently used blocks.
+    pub fill_cache: bool,
+    /// Iterators don't return entries with keys smaller than this bound.
+    pub iterate_lower_bound: Option<Vec<u8>>,
+    /// Iterators don't return entries with keys greater than or equal to this bound.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            snapshot: None,
+            verify_checksums: true,
+            fill_cache: true,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+        }
+    }
+}
+
+/// WriteOptions control the behavior of a single write operation, like `DB::write_opt()`.
+#[derive(Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Flush the log to disk before returning.
+    pub sync: bool,
+    /// Don't write the update to the log. The update is lost on close or crash unless it has
+    /// been written to a table file before, e.g. by a memtable compaction.
+    pub disable_wal: bool,
+}
+
 /// Returns Options that will cause a database to exist purely in-memory instead of being stored on
 /// disk. This is useful for testing or ephemeral databases.
 pub fn in_memory() -> Options {
@@ -152,7 +230,7 @@ pub fn in_memory() -> Options {
             Options::new_mem_db()
         } else {
             let mut opt = Options::default();
-            opt.env = Rc::new(Box::new(MemEnv::new()));
+            opt.env = Arc::new(Box::new(MemEnv::new()));
             opt
         }
     }
@@ -164,7 +242,7 @@ pub fn for_test() -> Options {
             let mut o = Options::new_mem_db();
         } else {
             let mut o = Options::default();
-            o.env = Rc::new(Box::new(MemEnv::new()));
+            o.env = Arc::new(Box::new(MemEnv::new()));
         }
     }
 
@@ -0,0 +1,168 @@
+//! Range tombstones mark all keys in a user key range [start; end) as deleted for every entry
+//! older than the tombstone. They are stored next to the point entries in memtables, and in a
+//! dedicated meta block in table files.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use cmp::{Cmp, InternalKeyCmp};
+use key_types::{parse_internal_key, LookupKey, UserKey, ValueType};
+use types::{FileMetaData, SequenceNumber, MAX_SEQUENCE_NUMBER};
+
+use std::cmp::Ordering;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], seq: SequenceNumber) -> RangeTombstone {
+        RangeTombstone {
+            start: start.to_vec(),
+            end: end.to_vec(),
+            seq: seq,
+        }
+    }
+
+    /// decode reconstructs a tombstone from an entry of a range deletion block, which has the
+    /// format (InternalKey(start, seq, TypeRangeDeletion), end).
+    pub fn decode(key: &[u8], val: &[u8]) -> RangeTombstone {
+        let (_, seq, start) = parse_internal_key(key);
+        RangeTombstone::new(start, val, seq)
+    }
+
+    /// encode returns the (key, value) pair under which this tombstone is stored in a range
+    /// deletion block.
+    pub fn encode(&self) -> (Vec<u8>, Vec<u8>) {
+        (self.smallest_key(), self.end.clone())
+    }
+
+    /// smallest_key returns the internal key at which this tombstone starts.
+    pub fn smallest_key(&self) -> Vec<u8> {
+        LookupKey::new_full(&self.start, self.seq, ValueType::TypeRangeDeletion)
+            .internal_key()
+            .to_vec()
+    }
+
+    /// largest_key returns an internal key that is ordered before all entries for the user key
+    /// `end`, which is not covered by this tombstone.
+    pub fn largest_key(&self) -> Vec<u8> {
+        LookupKey::new_full(&self.end, MAX_SEQUENCE_NUMBER, ValueType::TypeRangeDeletion)
+            .internal_key()
+            .to_vec()
+    }
+
+    /// covers returns whether the user key lies within this tombstone's range.
+    pub fn covers<'a>(&self, cmp: &dyn Cmp, ukey: UserKey<'a>) -> bool {
+        cmp.cmp(&self.start, ukey) <= Ordering::Equal && cmp.cmp(ukey, &self.end) == Ordering::Less
+    }
+
+    /// truncate returns the part of this tombstone that lies within [lower; upper), or None if
+    /// that part is empty. A bound of None means unbounded.
+    pub fn truncate(
+        &self,
+        cmp: &dyn Cmp,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Option<RangeTombstone> {
+        let mut t = self.clone();
+        if let Some(lower) = lower {
+            if cmp.cmp(&t.start, lower) == Ordering::Less {
+                t.start = lower.to_vec();
+            }
+        }
+        if let Some(upper) = upper {
+            if cmp.cmp(&t.end, upper) == Ordering::Greater {
+                t.end = upper.to_vec();
+            }
+        }
+        if cmp.cmp(&t.start, &t.end) == Ordering::Less {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+/// max_covering_seq returns the highest sequence number not newer than `snapshot` of any
+/// tombstone covering the user key, or 0 if there is no such tombstone.
+pub fn max_covering_seq<'a, 'b, I: Iterator<Item = &'b RangeTombstone>>(
+    tombstones: I,
+    cmp: &dyn Cmp,
+    ukey: UserKey<'a>,
+    snapshot: SequenceNumber,
+) -> SequenceNumber {
+    tombstones
+        .filter(|t| t.seq <= snapshot && t.covers(cmp, ukey))
+        .map(|t| t.seq)
+        .max()
+        .unwrap_or(0)
+}
+
+/// extend_file_bounds widens the key range of a table file's metadata so that it includes the
+/// given tombstone.
+pub fn extend_file_bounds(icmp: &InternalKeyCmp, f: &mut FileMetaData, t: &RangeTombstone) {
+    let (smallest, largest) = (t.smallest_key(), t.largest_key());
+    if f.smallest.is_empty() || icmp.cmp(&smallest, &f.smallest) == Ordering::Less {
+        f.smallest = smallest;
+    }
+    if f.largest.is_empty() || icmp.cmp(&largest, &f.largest) == Ordering::Greater {
+        f.largest = largest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::DefaultCmp;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_range_tombstone_covers_truncate() {
+        let cmp = DefaultCmp;
+        let t = RangeTombstone::new(b"b", b"d", 5);
+        assert!(!t.covers(&cmp, b"a"));
+        assert!(t.covers(&cmp, b"b"));
+        assert!(t.covers(&cmp, b"cxx"));
+        assert!(!t.covers(&cmp, b"d"));
+
+        assert_eq!(Some(t.clone()), t.truncate(&cmp, None, None));
+        assert_eq!(Some(t.clone()), t.truncate(&cmp, Some(b"a"), Some(b"e")));
+        assert_eq!(
+            Some(RangeTombstone::new(b"c", b"cc", 5)),
+            t.truncate(&cmp, Some(b"c"), Some(b"cc"))
+        );
+        assert_eq!(None, t.truncate(&cmp, Some(b"d"), None));
+        assert_eq!(None, t.truncate(&cmp, None, Some(b"b")));
+
+        let ts = vec![t, RangeTombstone::new(b"c", b"e", 7)];
+        assert_eq!(0, max_covering_seq(ts.iter(), &cmp, b"a", 10));
+        assert_eq!(5, max_covering_seq(ts.iter(), &cmp, b"b", 10));
+        assert_eq!(7, max_covering_seq(ts.iter(), &cmp, b"c", 10));
+        assert_eq!(5, max_covering_seq(ts.iter(), &cmp, b"c", 6));
+        assert_eq!(0, max_covering_seq(ts.iter(), &cmp, b"c", 4));
+    }
+
+    #[test]
+    fn test_range_tombstone_encode_bounds() {
+        let t = RangeTombstone::new(b"b", b"d", 5);
+        let (k, v) = t.encode();
+        assert_eq!(t, RangeTombstone::decode(&k, &v));
+
+        let icmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
+        let mut f = FileMetaData::default();
+        f.smallest = LookupKey::new(b"c", 3).internal_key().to_vec();
+        f.largest = LookupKey::new(b"c", 2).internal_key().to_vec();
+        extend_file_bounds(&icmp, &mut f, &t);
+        assert_eq!(t.smallest_key(), f.smallest);
+        assert_eq!(t.largest_key(), f.largest);
+        // d@any is not part of the file.
+        assert_eq!(
+            Ordering::Less,
+            icmp.cmp(&f.largest, LookupKey::new(b"d", 100).internal_key())
+        );
+    }
+}
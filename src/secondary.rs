@@ -0,0 +1,111 @@
+//! A secondary instance follows the manifest and log files written by the primary instance of a
+//! database, without writing to the database directory itself. See `DB::open_as_secondary()`.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use env::Env;
+use error::Result;
+use log::LogReader;
+use types::FileNum;
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// SecondaryState records how far a secondary instance has read the files of the primary.
+pub struct SecondaryState {
+    // The manifest being followed, and the offset after the last edit that has been applied.
+    pub manifest: Option<PathBuf>,
+    pub manifest_off: usize,
+    // The log numbers of the column families when the memtables were last rebuilt.
+    pub log_nums: Vec<(u32, FileNum)>,
+    // The log files read into the memtables, and the offset after the last record read.
+    pub logs: BTreeMap<FileNum, usize>,
+}
+
+impl SecondaryState {
+    pub fn new() -> SecondaryState {
+        SecondaryState {
+            manifest: None,
+            manifest_off: 0,
+            log_nums: vec![],
+            logs: BTreeMap::new(),
+        }
+    }
+}
+
+/// read_new_records returns the complete records found after offset `off` of a log file, which
+/// may still be written to, and the offset after the last of them. `off` must be the start of a
+/// record; a record that is still being written is left for the next call.
+pub fn read_new_records(env: &dyn Env, p: &Path, off: usize) -> Result<(Vec<Vec<u8>>, usize)> {
+    let size = env.size_of(p)?;
+    if size <= off {
+        return Ok((vec![], off));
+    }
+    let mut buf = vec![0; size - off];
+    let n = env.open_random_access_file(p)?.read_at(off, &mut buf)?;
+    buf.truncate(n);
+
+    let mut src = Cursor::new(&buf[..]);
+    let mut records = vec![];
+    let mut end = off;
+    loop {
+        let pos = off + src.position() as usize;
+        let mut record = vec![];
+        match LogReader::new_with_off(&mut src, true, pos).read(&mut record) {
+            Ok(len) if len > 0 => {
+                records.push(record);
+                end = off + src.position() as usize;
+            }
+            // Either the end of the file, or a record that hasn't been written completely.
+            _ => break,
+        }
+    }
+    Ok((records, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::LogWriter;
+    use mem_env::MemEnv;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_secondary_read_new_records() {
+        let env = MemEnv::new();
+        let p = Path::new("/log");
+        let records: Vec<Vec<u8>> = vec![
+            b"first".to_vec(),
+            vec![b'x'; 40000],
+            b"third".to_vec(),
+            vec![b'y'; 70000],
+        ];
+        let mut encoded = vec![];
+        let mut ends = vec![];
+        for r in records.iter() {
+            let len = encoded.len();
+            LogWriter::new_with_off(&mut encoded, len)
+                .add_record(r)
+                .unwrap();
+            ends.push(encoded.len());
+        }
+
+        // Write the log in several steps, ending in the middle of records.
+        let mut f = env.open_writable_file(p).unwrap();
+        let mut off = 0;
+        let mut read = vec![];
+        for &split in [ends[0] + 3, ends[1] - 10, ends[2], encoded.len()].iter() {
+            let written = env.size_of(p).unwrap();
+            f.write_all(&encoded[written..split]).unwrap();
+            let (new, end) = read_new_records(&env, p, off).unwrap();
+            assert!(ends.contains(&end));
+            read.extend(new);
+            off = end;
+        }
+        assert_eq!(records, read);
+        assert_eq!(encoded.len(), off);
+        assert!(read_new_records(&env, p, off).unwrap().0.is_empty());
+    }
+}
@@ -4,12 +4,11 @@ use std::prelude::v1::*;
 use cmp::{Cmp, MemtableKeyCmp};
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
-use types::LdbIterator;
+use types::{share, LdbIterator, Shared};
 
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem::{replace, size_of};
-use std::rc::Rc;
+use std::sync::Arc;
 
 const MAX_HEIGHT: usize = 12;
 const BRANCHING_FACTOR: u32 = 4;
@@ -33,26 +32,33 @@ struct InnerSkipMap {
     len: usize,
     // approximation of memory used.
     approx_mem: usize,
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
 }
 
+// The raw pointers in `Node` only point into the map's own nodes, and all accesses (including
+// those from `SkipMapIter`) go through the lock of the `Shared` wrapping the map.
+unsafe impl Send for InnerSkipMap {}
+unsafe impl Sync for InnerSkipMap {}
+
+/// A SkipMap can be cloned cheaply; clones refer to the same map.
+#[derive(Clone)]
 pub struct SkipMap {
-    map: Rc<RefCell<InnerSkipMap>>,
+    map: Shared<InnerSkipMap>,
 }
 
 impl SkipMap {
     /// Returns a SkipMap that wraps the comparator inside a MemtableKeyCmp.
-    pub fn new_memtable_map(cmp: Rc<Box<dyn Cmp>>) -> SkipMap {
-        SkipMap::new(Rc::new(Box::new(MemtableKeyCmp(cmp))))
+    pub fn new_memtable_map(cmp: Arc<Box<dyn Cmp>>) -> SkipMap {
+        SkipMap::new(Arc::new(Box::new(MemtableKeyCmp(cmp))))
     }
 
     /// Returns a SkipMap that uses the specified comparator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>) -> SkipMap {
+    pub fn new(cmp: Arc<Box<dyn Cmp>>) -> SkipMap {
         let mut s = Vec::new();
         s.resize(MAX_HEIGHT, None);
 
         SkipMap {
-            map: Rc::new(RefCell::new(InnerSkipMap {
+            map: share(InnerSkipMap {
                 head: Box::new(Node {
                     skips: s,
                     next: None,
@@ -63,7 +69,7 @@ impl SkipMap {
                 len: 0,
                 approx_mem: size_of::<Self>() + MAX_HEIGHT * size_of::<Option<*mut Node>>(),
                 cmp: cmp,
-            })),
+            }),
         }
     }
 
@@ -291,22 +297,24 @@ impl InnerSkipMap {
 }
 
 pub struct SkipMapIter {
-    map: Rc<RefCell<InnerSkipMap>>,
+    map: Shared<InnerSkipMap>,
     current: *const Node,
 }
 
 impl LdbIterator for SkipMapIter {
     fn advance(&mut self) -> bool {
         // we first go to the next element, then return that -- in order to skip the head node
-        let r = unsafe {
-            (*self.current)
-                .next
-                .as_ref()
-                .map(|next| {
+        let r = {
+            // Hold the lock while following the link, as it may be modified by a concurrent
+            // insert.
+            let _map = self.map.borrow();
+            match unsafe { (*self.current).next.as_ref() } {
+                Some(next) => {
                     self.current = next.as_ref() as *const Node;
                     true
-                })
-                .unwrap_or(false)
+                }
+                None => false,
+            }
         };
         if !r {
             self.reset();
@@ -478,7 +486,7 @@ pub mod tests {
     #[test]
     fn test_empty_skipmap_find_memtable_cmp() {
         // Regression test: Make sure comparator isn't called with empty key.
-        let cmp: Rc<Box<dyn Cmp>> = Rc::new(Box::new(MemtableKeyCmp(options::for_test().cmp)));
+        let cmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(MemtableKeyCmp(options::for_test().cmp)));
         let skm = SkipMap::new(cmp);
 
         let mut it = skm.iter();
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use types::{share, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Opaque snapshot handle; Represents index to SnapshotList.map
 type SnapshotHandle = u64;
@@ -23,7 +23,7 @@ impl Drop for InnerSnapshot {
 
 #[derive(Clone)]
 pub struct Snapshot {
-    inner: Rc<InnerSnapshot>,
+    inner: Arc<InnerSnapshot>,
 }
 
 impl Snapshot {
@@ -36,9 +36,9 @@ impl Snapshot {
 struct InnerSnapshotList {
     map: HashMap<SnapshotHandle, SequenceNumber>,
     newest: SnapshotHandle,
-    oldest: SnapshotHandle,
 }
 
+#[derive(Clone)]
 pub struct SnapshotList {
     inner: Shared<InnerSnapshotList>,
 }
@@ -49,12 +49,11 @@ impl SnapshotList {
             inner: share(InnerSnapshotList {
                 map: HashMap::new(),
                 newest: 0,
-                oldest: 0,
             }),
         }
     }
 
-    pub fn new_snapshot(&mut self, seq: SequenceNumber) -> Snapshot {
+    pub fn new_snapshot(&self, seq: SequenceNumber) -> Snapshot {
         let inner = self.inner.clone();
         let mut sl = self.inner.borrow_mut();
 
@@ -62,12 +61,8 @@ impl SnapshotList {
         let newest = sl.newest;
         sl.map.insert(newest, seq);
 
-        if sl.oldest == 0 {
-            sl.oldest = sl.newest;
-        }
-
         Snapshot {
-            inner: Rc::new(InnerSnapshot {
+            inner: Arc::new(InnerSnapshot {
                 id: sl.newest,
                 seq: seq,
                 sl: inner,
@@ -85,7 +80,7 @@ impl SnapshotList {
             .iter()
             .fold(
                 MAX_SEQUENCE_NUMBER,
-                |s, (seq, _)| if *seq < s { *seq } else { s },
+                |s, (_, seq)| if *seq < s { *seq } else { s },
             );
         if oldest == MAX_SEQUENCE_NUMBER {
             0
@@ -101,11 +96,11 @@ impl SnapshotList {
             .borrow()
             .map
             .iter()
-            .fold(0, |s, (seq, _)| if *seq > s { *seq } else { s })
+            .fold(0, |s, (_, seq)| if *seq > s { *seq } else { s })
     }
 
     pub fn empty(&self) -> bool {
-        self.inner.borrow().oldest == 0
+        self.inner.borrow().map.is_empty()
     }
 }
 
@@ -122,7 +117,7 @@ mod tests {
     #[allow(unused_variables)]
     #[test]
     fn test_snapshot_list() {
-        let mut l = SnapshotList::new();
+        let l = SnapshotList::new();
 
         {
             assert!(l.empty());
@@ -146,5 +141,16 @@ mod tests {
             assert_eq!(l.oldest(), 1);
         }
         assert_eq!(l.oldest(), 0);
+        assert!(l.empty());
+
+        // Sequence numbers are independent of the order in which snapshots are taken.
+        let a = l.new_snapshot(20);
+        let b = l.new_snapshot(10);
+        assert_eq!(l.oldest(), 10);
+        assert_eq!(l.newest(), 20);
+        drop(b);
+        assert_eq!(l.oldest(), 20);
+        drop(a);
+        assert!(l.empty());
     }
 }
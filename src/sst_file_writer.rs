@@ -0,0 +1,263 @@
+//! An SstFileWriter creates table files outside of a database, which can then be added to a
+//! database using `DB::ingest_external_file()`. This is much faster than writing the entries
+//! with `put()`, as they bypass the log and the memtable and aren't compacted repeatedly.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use env::WritableFile;
+use error::{err, Result, StatusCode};
+use key_types::{parse_internal_key, LookupKey, ValueType};
+use options::{Options, ReadOptions};
+use table_builder::TableBuilder;
+use table_cache::table_file_name;
+use table_reader::Table;
+use types::{current_key_val, FileMetaData, FileNum, LdbIterator, SequenceNumber};
+
+use std::cmp::Ordering;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// ExternalFileInfo describes a table file written by an SstFileWriter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalFileInfo {
+    pub path: PathBuf,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    pub num_entries: usize,
+    pub size: usize,
+}
+
+/// SstFileWriter writes entries, which must be added in strictly increasing key order, to a new
+/// table file. All entries have the sequence number 0; a database ingesting the file assigns it a
+/// sequence number that is newer than all entries it already contains.
+///
+/// The options (comparator, filter policy, compression) must match those of the database (or the
+/// column family) the file will be ingested into.
+pub struct SstFileWriter {
+    opt: Options,
+    path: PathBuf,
+    builder: TableBuilder<BufWriter<Box<dyn WritableFile>>>,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+}
+
+impl SstFileWriter {
+    /// create opens a new table file at `path`, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(opt: Options, path: P) -> Result<SstFileWriter> {
+        let path = path.as_ref().to_path_buf();
+        let f = BufWriter::new(opt.env.open_writable_file(&path)?);
+        Ok(SstFileWriter {
+            builder: TableBuilder::new(opt.clone(), f),
+            opt: opt,
+            path: path,
+            smallest: vec![],
+            largest: vec![],
+        })
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.add(key, val, ValueType::TypeValue)
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.add(key, &[], ValueType::TypeDeletion)
+    }
+
+    /// merge adds a merge operand, which is applied to the value of key found in the database
+    /// when the file is ingested.
+    pub fn merge(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.add(key, val, ValueType::TypeMerge)
+    }
+
+    /// entries returns the number of entries added so far.
+    pub fn entries(&self) -> usize {
+        self.builder.entries()
+    }
+
+    fn add(&mut self, key: &[u8], val: &[u8], t: ValueType) -> Result<()> {
+        if self.entries() > 0 && self.opt.cmp.cmp(&self.largest, key) != Ordering::Less {
+            return err(
+                StatusCode::InvalidArgument,
+                "keys must be added in strictly increasing order",
+            );
+        }
+        self.builder
+            .add(LookupKey::new_full(key, 0, t).internal_key(), val)?;
+        if self.builder.entries() == 1 {
+            self.smallest = key.to_vec();
+        }
+        self.largest = key.to_vec();
+        Ok(())
+    }
+
+    /// finish writes the remaining blocks and the table's footer. Empty files are not allowed.
+    pub fn finish(self) -> Result<ExternalFileInfo> {
+        let builder = self.builder;
+        let num_entries = builder.entries();
+        if num_entries == 0 {
+            drop(builder);
+            let _ = self.opt.env.delete(&self.path);
+            return err(
+                StatusCode::InvalidArgument,
+                "can't create an empty table file",
+            );
+        }
+        builder.finish_and_sync()?;
+        Ok(ExternalFileInfo {
+            size: self.opt.env.size_of(&self.path)?,
+            path: self.path,
+            smallest: self.smallest,
+            largest: self.largest,
+            num_entries: num_entries,
+        })
+    }
+}
+
+/// ExternalFile is a table file that is about to be ingested into a database.
+pub(crate) struct ExternalFile {
+    pub path: PathBuf,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    table: Table,
+}
+
+impl ExternalFile {
+    /// open opens the table file at `path` and reads its smallest and largest user key.
+    pub fn open(opt: &Options, path: &Path) -> Result<ExternalFile> {
+        let size = opt.env.size_of(path)?;
+        let file = opt.env.open_random_access_file(path)?;
+        let table = Table::new(opt.clone(), Arc::new(file), size)?;
+
+        let mut it = table.iter();
+        it.advance();
+        let smallest = current_key_val(&it);
+        it.seek_to_last();
+        let largest = current_key_val(&it);
+        match (smallest, largest) {
+            (Some((smallest, _)), Some((largest, _))) => Ok(ExternalFile {
+                path: path.to_path_buf(),
+                smallest: parse_internal_key(&smallest).2.to_vec(),
+                largest: parse_internal_key(&largest).2.to_vec(),
+                table: table,
+            }),
+            _ => err(
+                StatusCode::InvalidArgument,
+                &format!("external file {} is empty", path.display()),
+            ),
+        }
+    }
+
+    /// copy_to writes the entries of this file to the table file `num` of the database at
+    /// `dbname`, assigning them the sequence number `seq`.
+    pub fn copy_to(
+        &self,
+        dbname: &Path,
+        opt: &Options,
+        num: FileNum,
+        seq: SequenceNumber,
+    ) -> Result<FileMetaData> {
+        let filename = table_file_name(dbname, num);
+        let mut md = FileMetaData::default();
+        md.num = num;
+
+        let r = (|| -> Result<()> {
+            let f = BufWriter::new(opt.env.open_writable_file(&filename)?);
+            let mut builder = TableBuilder::new(opt.clone(), f);
+            let mut ro = ReadOptions::default();
+            ro.fill_cache = false;
+            let mut it = self.table.iter_opt(&ro);
+            let (mut k, mut v) = (vec![], vec![]);
+            let mut last: Option<Vec<u8>> = None;
+            while it.advance() {
+                it.current(&mut k, &mut v);
+                let (typ, _, ukey) = parse_internal_key(&k);
+                if let Some(ref last) = last {
+                    if opt.cmp.cmp(last, ukey) != Ordering::Less {
+                        return err(
+                            StatusCode::InvalidArgument,
+                            &format!(
+                                "keys in external file {} are not strictly increasing",
+                                self.path.display()
+                            ),
+                        );
+                    }
+                }
+                let ikey = LookupKey::new_full(ukey, seq, typ);
+                builder.add(ikey.internal_key(), &v)?;
+                if md.smallest.is_empty() {
+                    md.smallest = ikey.internal_key().to_vec();
+                }
+                md.largest = ikey.internal_key().to_vec();
+                last = Some(ukey.to_vec());
+            }
+            builder.finish_and_sync()?;
+            md.size = opt.env.size_of(&filename)?;
+            Ok(())
+        })();
+
+        if let Err(e) = r {
+            let _ = opt.env.delete(&filename);
+            return Err(e);
+        }
+        Ok(md)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options;
+
+    #[test]
+    fn test_sst_file_writer() {
+        let opt = options::for_test();
+        let path = Path::new("/ext/000001.sst");
+        let mut w = SstFileWriter::create(opt.clone(), path).unwrap();
+        w.put(b"abc", b"1").unwrap();
+        w.delete(b"abd").unwrap();
+        w.put(b"xyz", b"2").unwrap();
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            w.put(b"xyz", b"3").unwrap_err().code
+        );
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            w.put(b"abc", b"3").unwrap_err().code
+        );
+        assert_eq!(3, w.entries());
+
+        let info = w.finish().unwrap();
+        assert_eq!(b"abc".to_vec(), info.smallest);
+        assert_eq!(b"xyz".to_vec(), info.largest);
+        assert_eq!(3, info.num_entries);
+        assert_eq!(opt.env.size_of(path).unwrap(), info.size);
+
+        let file = opt.env.open_random_access_file(path).unwrap();
+        let t = Table::new(opt, Arc::new(file), info.size).unwrap();
+        let mut it = t.iter();
+        let mut entries = vec![];
+        while let Some((k, v)) = it.next() {
+            let (typ, seq, ukey) = parse_internal_key(&k);
+            entries.push((ukey.to_vec(), v, typ, seq));
+        }
+        assert_eq!(
+            vec![
+                (b"abc".to_vec(), b"1".to_vec(), ValueType::TypeValue, 0),
+                (b"abd".to_vec(), vec![], ValueType::TypeDeletion, 0),
+                (b"xyz".to_vec(), b"2".to_vec(), ValueType::TypeValue, 0),
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_sst_file_writer_empty() {
+        let opt = options::for_test();
+        let path = Path::new("/ext/000001.sst");
+        let w = SstFileWriter::create(opt.clone(), path).unwrap();
+        assert_eq!(StatusCode::InvalidArgument, w.finish().unwrap_err().code);
+        assert!(!opt.env.exists(path).unwrap());
+    }
+}
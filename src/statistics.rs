@@ -0,0 +1,491 @@
+//! Statistics collect counters ("tickers") and latency histograms about the operations of a
+//! database, e.g. how often the block cache is hit. They are enabled by setting
+//! `Options::statistics`.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use env::{Env, FileLock, Logger, RandomAccess, WritableFile};
+use error::Result;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        use std::sync::{Arc, SgxMutex as Mutex};
+    } else {
+        use std::sync::{Arc, Mutex};
+    }
+}
+
+/// A Ticker is a counter of events or bytes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Ticker {
+    /// Blocks found in the block cache.
+    BlockCacheHit,
+    /// Blocks that had to be read from a table file.
+    BlockCacheMiss,
+    /// Lookups where the filter ruled out a block, so that it didn't have to be read.
+    FilterUseful,
+    /// Lookups where the filter couldn't rule out a block.
+    FilterUseless,
+    /// Bytes read from files through the database's Env.
+    BytesRead,
+    /// Bytes written to files through the database's Env.
+    BytesWritten,
+    /// Lookups answered by a memtable.
+    MemtableHit,
+    /// Lookups that had to continue in the table files.
+    MemtableMiss,
+    /// Bytes of table files read by compactions.
+    CompactionBytesRead,
+    /// Bytes of table files written by compactions and memtable flushes.
+    CompactionBytesWritten,
+    /// Syncs of the write-ahead log.
+    WalSyncs,
+}
+
+const TICKERS: [Ticker; 11] = [
+    Ticker::BlockCacheHit,
+    Ticker::BlockCacheMiss,
+    Ticker::FilterUseful,
+    Ticker::FilterUseless,
+    Ticker::BytesRead,
+    Ticker::BytesWritten,
+    Ticker::MemtableHit,
+    Ticker::MemtableMiss,
+    Ticker::CompactionBytesRead,
+    Ticker::CompactionBytesWritten,
+    Ticker::WalSyncs,
+];
+
+impl Ticker {
+    /// name returns the name of the ticker used in the text dump of Statistics.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Ticker::BlockCacheHit => "leveldb.block.cache.hit",
+            Ticker::BlockCacheMiss => "leveldb.block.cache.miss",
+            Ticker::FilterUseful => "leveldb.filter.useful",
+            Ticker::FilterUseless => "leveldb.filter.useless",
+            Ticker::BytesRead => "leveldb.bytes.read",
+            Ticker::BytesWritten => "leveldb.bytes.written",
+            Ticker::MemtableHit => "leveldb.memtable.hit",
+            Ticker::MemtableMiss => "leveldb.memtable.miss",
+            Ticker::CompactionBytesRead => "leveldb.compaction.bytes.read",
+            Ticker::CompactionBytesWritten => "leveldb.compaction.bytes.written",
+            Ticker::WalSyncs => "leveldb.wal.syncs",
+        }
+    }
+}
+
+/// A Histogram records the distribution of the latencies of an operation, in microseconds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Histogram {
+    /// Latency of reads of single keys, e.g. `DB::get()`.
+    GetMicros,
+    /// Latency of writes, i.e. `DB::put()`, `DB::delete()` and `DB::write()`.
+    WriteMicros,
+    /// Latency of seeks of `DBIterator`s.
+    SeekMicros,
+}
+
+const HISTOGRAMS: [Histogram; 3] = [
+    Histogram::GetMicros,
+    Histogram::WriteMicros,
+    Histogram::SeekMicros,
+];
+
+impl Histogram {
+    /// name returns the name of the histogram used in the text dump of Statistics.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Histogram::GetMicros => "leveldb.db.get.micros",
+            Histogram::WriteMicros => "leveldb.db.write.micros",
+            Histogram::SeekMicros => "leveldb.db.seek.micros",
+        }
+    }
+}
+
+// Bucket 0 holds the value 0, bucket i > 0 the values in [2^(i-1), 2^i).
+const NUM_BUCKETS: usize = 65;
+
+fn bucket_of(value: u64) -> usize {
+    64 - value.leading_zeros() as usize
+}
+
+/// bucket_bounds returns the smallest and the largest value of a bucket.
+fn bucket_bounds(bucket: usize) -> (u64, u64) {
+    match bucket {
+        0 => (0, 0),
+        64 => (1 << 63, u64::max_value()),
+        _ => (1 << (bucket - 1), (1 << bucket) - 1),
+    }
+}
+
+/// HistogramData is a snapshot of the values recorded for a histogram.
+#[derive(Clone)]
+pub struct HistogramData {
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+    buckets: [u64; NUM_BUCKETS],
+}
+
+impl HistogramData {
+    fn new() -> HistogramData {
+        HistogramData {
+            count: 0,
+            sum: 0,
+            min: 0,
+            max: 0,
+            buckets: [0; NUM_BUCKETS],
+        }
+    }
+
+    fn add(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.buckets[bucket_of(value)] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// percentile returns an estimate of the value below which `p` percent of the recorded
+    /// values lie. Values are only recorded in buckets of powers of two, so the result is
+    /// interpolated within the bucket containing the percentile.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+        let threshold = self.count as f64 * p / 100.;
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            if (seen + n) as f64 >= threshold {
+                let (lo, hi) = bucket_bounds(bucket);
+                let (lo, hi) = (lo.max(self.min) as f64, hi.min(self.max) as f64);
+                let pos = ((threshold - seen as f64) / n as f64).max(0.);
+                return lo + (hi - lo) * pos;
+            }
+            seen += n;
+        }
+        self.max as f64
+    }
+}
+
+impl fmt::Display for HistogramData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "P50 : {:.1} P95 : {:.1} P99 : {:.1} P100 : {} COUNT : {} SUM : {}",
+            self.percentile(50.),
+            self.percentile(95.),
+            self.percentile(99.),
+            self.max,
+            self.count,
+            self.sum
+        )
+    }
+}
+
+/// Statistics counts events in a database and records the latencies of its operations. A single
+/// Statistics object can be shared by several databases by setting it in all their Options; its
+/// text dump is returned by `format!("{}", stats)` or the `leveldb.statistics` property of
+/// `DB::get_property()`.
+///
+/// Recording is cheap, but not free: Tickers are atomic counters, while histograms are protected
+/// by a mutex, and timing an operation calls `Env::micros()` twice.
+pub struct Statistics {
+    tickers: Vec<AtomicUsize>,
+    histograms: Mutex<Vec<HistogramData>>,
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics {
+            tickers: TICKERS.iter().map(|_| AtomicUsize::new(0)).collect(),
+            histograms: Mutex::new(HISTOGRAMS.iter().map(|_| HistogramData::new()).collect()),
+        }
+    }
+
+    /// record adds `n` to a ticker.
+    pub fn record(&self, t: Ticker, n: usize) {
+        self.tickers[t as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// ticker returns the current value of a ticker.
+    pub fn ticker(&self, t: Ticker) -> usize {
+        self.tickers[t as usize].load(Ordering::Relaxed)
+    }
+
+    /// measure records a value, usually a latency in microseconds, in a histogram.
+    pub fn measure(&self, h: Histogram, value: u64) {
+        self.histograms.lock().unwrap()[h as usize].add(value);
+    }
+
+    /// histogram returns the values recorded for a histogram so far.
+    pub fn histogram(&self, h: Histogram) -> HistogramData {
+        self.histograms.lock().unwrap()[h as usize].clone()
+    }
+
+    /// reset sets all tickers to zero and clears all histograms.
+    pub fn reset(&self) {
+        for t in self.tickers.iter() {
+            t.store(0, Ordering::Relaxed);
+        }
+        for h in self.histograms.lock().unwrap().iter_mut() {
+            *h = HistogramData::new();
+        }
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &t in TICKERS.iter() {
+            writeln!(f, "{} COUNT : {}", t.name(), self.ticker(t))?;
+        }
+        for &h in HISTOGRAMS.iter() {
+            writeln!(f, "{} {}", h.name(), self.histogram(h))?;
+        }
+        Ok(())
+    }
+}
+
+/// record adds `n` to a ticker if statistics are enabled.
+pub fn record(stats: &Option<Arc<Statistics>>, t: Ticker, n: usize) {
+    if let Some(ref stats) = *stats {
+        stats.record(t, n);
+    }
+}
+
+/// StatisticsEnv passes all operations on to another Env, and counts the bytes read from and
+/// written to files in a Statistics object.
+pub struct StatisticsEnv {
+    inner: Arc<Box<dyn Env>>,
+    stats: Arc<Statistics>,
+}
+
+impl StatisticsEnv {
+    pub fn new(inner: Arc<Box<dyn Env>>, stats: Arc<Statistics>) -> StatisticsEnv {
+        StatisticsEnv {
+            inner: inner,
+            stats: stats,
+        }
+    }
+
+    fn counting<T>(&self, inner: T) -> Counting<T> {
+        Counting {
+            inner: inner,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl Env for StatisticsEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        Ok(Box::new(self.counting(self.inner.open_sequential_file(p)?)))
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        Ok(Box::new(
+            self.counting(self.inner.open_random_access_file(p)?),
+        ))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(self.counting(self.inner.open_writable_file(p)?)))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(self.counting(self.inner.open_appendable_file(p)?)))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.inner.size_of(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.inner.delete(p)
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.inner.rename(old, new)
+    }
+    fn sync_dir(&self, p: &Path) -> Result<()> {
+        self.inner.sync_dir(p)
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.inner.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+}
+
+/// Counting wraps a file opened by StatisticsEnv.
+struct Counting<T> {
+    inner: T,
+    stats: Arc<Statistics>,
+}
+
+impl Read for Counting<Box<dyn Read>> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(dst)?;
+        self.stats.record(Ticker::BytesRead, n);
+        Ok(n)
+    }
+}
+
+impl RandomAccess for Counting<Box<dyn RandomAccess>> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read_at(off, dst)?;
+        self.stats.record(Ticker::BytesRead, n);
+        Ok(n)
+    }
+}
+
+impl Write for Counting<Box<dyn WritableFile>> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.stats.record(Ticker::BytesWritten, n);
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WritableFile for Counting<Box<dyn WritableFile>> {
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mem_env::MemEnv;
+
+    #[test]
+    fn test_statistics_tickers() {
+        let stats = Statistics::new();
+        stats.record(Ticker::BlockCacheHit, 1);
+        stats.record(Ticker::BlockCacheHit, 2);
+        stats.record(Ticker::WalSyncs, 1);
+        assert_eq!(3, stats.ticker(Ticker::BlockCacheHit));
+        assert_eq!(1, stats.ticker(Ticker::WalSyncs));
+        assert_eq!(0, stats.ticker(Ticker::BlockCacheMiss));
+
+        let dump = format!("{}", stats);
+        assert!(dump.contains("leveldb.block.cache.hit COUNT : 3\n"));
+        assert!(dump.contains("leveldb.block.cache.miss COUNT : 0\n"));
+        assert!(dump.contains("leveldb.db.get.micros P50 : 0.0"));
+
+        stats.reset();
+        assert_eq!(0, stats.ticker(Ticker::BlockCacheHit));
+    }
+
+    #[test]
+    fn test_statistics_histogram() {
+        let stats = Statistics::new();
+        for v in 1..101 {
+            stats.measure(Histogram::GetMicros, v);
+        }
+        stats.measure(Histogram::SeekMicros, 0);
+
+        let h = stats.histogram(Histogram::GetMicros);
+        assert_eq!(100, h.count());
+        assert_eq!(5050, h.sum());
+        assert_eq!(1, h.min());
+        assert_eq!(100, h.max());
+        assert_eq!(50.5, h.average());
+        // 50 is in the bucket [32; 63], 99 and 100 in [64; 127].
+        let p50 = h.percentile(50.);
+        assert!(32. <= p50 && p50 <= 63., "{}", p50);
+        let p99 = h.percentile(99.);
+        assert!(64. <= p99 && p99 <= 100., "{}", p99);
+        assert_eq!(100., h.percentile(100.));
+
+        let h = stats.histogram(Histogram::SeekMicros);
+        assert_eq!((1, 0, 0), (h.count(), h.min(), h.max()));
+        assert_eq!(0., h.percentile(50.));
+        assert_eq!(0, stats.histogram(Histogram::WriteMicros).count());
+
+        stats.reset();
+        assert_eq!(0, stats.histogram(Histogram::GetMicros).count());
+    }
+
+    #[test]
+    fn test_statistics_env() {
+        let stats = Arc::new(Statistics::new());
+        let env = StatisticsEnv::new(Arc::new(Box::new(MemEnv::new())), stats.clone());
+        let p = Path::new("/file");
+
+        let mut f = env.open_writable_file(p).unwrap();
+        f.write_all(b"hello world").unwrap();
+        f.sync().unwrap();
+        assert_eq!(11, stats.ticker(Ticker::BytesWritten));
+
+        let mut buf = vec![];
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        let mut dst = [0; 5];
+        env.open_random_access_file(p)
+            .unwrap()
+            .read_at(6, &mut dst)
+            .unwrap();
+        assert_eq!(b"world", &dst);
+        assert_eq!(16, stats.ticker(Ticker::BytesRead));
+    }
+}
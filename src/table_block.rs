@@ -4,11 +4,11 @@ use std::prelude::v1::*;
 use block::Block;
 use blockhandle::BlockHandle;
 use env::RandomAccess;
-use error::{err, Result, StatusCode};
+use error::{err, Result, Status, StatusCode};
 use filter;
 use filter_block::FilterBlockReader;
 use log::unmask_crc;
-use options::{self, CompressionType, Options};
+use options::{self, CompressionType, Options, ReadOptions};
 use table_builder;
 
 use crc::crc32::{self, Hasher32};
@@ -37,11 +37,28 @@ pub fn read_filter_block(
     Ok(FilterBlockReader::new_owned(policy, buf))
 }
 
+/// map_err_with_block annotates an error with the offset of the block that was being read.
+fn map_err_with_block(mut s: Status, location: &BlockHandle) -> Status {
+    s.err = format!("{} (block at offset {})", s.err, location.offset());
+    s
+}
+
 /// Reads a table block from a random-access source.
 /// A table block consists of [bytes..., compress (1B), checksum (4B)]; the handle only refers to
-/// the location and length of [bytes...].
+/// the location and length of [bytes...]. The checksum is only verified if requested by `ro`.
+/// Errors are annotated with the offset of the block.
 pub fn read_table_block(
     opt: Options,
+    ro: &ReadOptions,
+    f: &dyn RandomAccess,
+    location: &BlockHandle,
+) -> Result<Block> {
+    read_table_block_unannotated(opt, ro, f, location).map_err(|e| map_err_with_block(e, location))
+}
+
+fn read_table_block_unannotated(
+    opt: Options,
+    ro: &ReadOptions,
     f: &dyn RandomAccess,
     location: &BlockHandle,
 ) -> Result<Block> {
@@ -64,14 +81,10 @@ pub fn read_table_block(
         )
     )?;
 
-    if !verify_table_block(&buf, compress[0], unmask_crc(u32::decode_fixed(&cksum))) {
-        return err(
-            StatusCode::Corruption,
-            &format!(
-                "checksum verification failed for block at {}",
-                location.offset()
-            ),
-        );
+    if ro.verify_checksums
+        && !verify_table_block(&buf, compress[0], unmask_crc(u32::decode_fixed(&cksum)))
+    {
+        return err(StatusCode::Corruption, "checksum verification failed");
     }
 
     if let Some(ctype) = options::int_to_compressiontype(compress[0] as u32) {
@@ -4,17 +4,19 @@ use std::prelude::v1::*;
 use block::BlockContents;
 use block_builder::BlockBuilder;
 use blockhandle::BlockHandle;
-use cmp::InternalKeyCmp;
+use cmp::{DefaultCmp, InternalKeyCmp};
+use env::WritableFile;
 use error::Result;
 use filter::{InternalFilterPolicy, NoFilterPolicy};
 use filter_block::FilterBlockBuilder;
 use key_types::InternalKey;
 use log::mask_crc;
 use options::{CompressionType, Options};
+use range_tombstone::RangeTombstone;
 
 use std::cmp::Ordering;
 use std::io::Write;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crc::crc32;
 use crc::Hasher32;
@@ -29,6 +31,9 @@ pub const MAGIC_FOOTER_ENCODED: [u8; 8] = [0x57, 0xfb, 0x80, 0x8b, 0x24, 0x75, 0
 pub const TABLE_BLOCK_COMPRESS_LEN: usize = 1;
 pub const TABLE_BLOCK_CKSUM_LEN: usize = 4;
 
+/// Name of the metaindex entry pointing to the block containing a table's range tombstones.
+pub const RANGE_DEL_BLOCK_NAME: &str = "rangedel";
+
 /// Footer is a helper for encoding/decoding a table footer.
 #[derive(Debug, Clone)]
 pub struct Footer {
@@ -78,7 +83,9 @@ impl Footer {
 /// A table consists of DATA BLOCKs, META BLOCKs, a METAINDEX BLOCK, an INDEX BLOCK and a FOOTER.
 ///
 /// DATA BLOCKs, META BLOCKs, INDEX BLOCK and METAINDEX BLOCK are built using the code in
-/// the `block` module.
+/// the `block` module. The META BLOCKs are the filter block and the range deletion block, which
+/// contains the table's range tombstones. The METAINDEX BLOCK maps their names to their locations,
+/// and is always ordered bytewise.
 ///
 /// The FOOTER consists of a BlockHandle that points to the metaindex block, another pointing to
 /// the index block, padding to fill up to 40 B and at the end the 8B magic number
@@ -95,11 +102,12 @@ pub struct TableBuilder<Dst: Write> {
     data_block: Option<BlockBuilder>,
     index_block: Option<BlockBuilder>,
     filter_block: Option<FilterBlockBuilder>,
+    range_tombstones: Vec<RangeTombstone>,
 }
 
 impl<Dst: Write> TableBuilder<Dst> {
     pub fn new_no_filter(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
-        opt.filter_policy = Rc::new(Box::new(NoFilterPolicy::new()));
+        opt.filter_policy = Arc::new(Box::new(NoFilterPolicy::new()));
         TableBuilder::new(opt, dst)
     }
 }
@@ -111,8 +119,8 @@ impl<Dst: Write> TableBuilder<Dst> {
     /// The comparator in opt will be wrapped in a InternalKeyCmp, and the filter policy
     /// in an InternalFilterPolicy.
     pub fn new(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
-        opt.filter_policy = Rc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        opt.filter_policy = Arc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
         TableBuilder::new_raw(opt, dst)
     }
 
@@ -127,6 +135,7 @@ impl<Dst: Write> TableBuilder<Dst> {
             data_block: Some(BlockBuilder::new(opt.clone())),
             filter_block: Some(FilterBlockBuilder::new(opt.filter_policy.clone())),
             index_block: Some(BlockBuilder::new(opt)),
+            range_tombstones: vec![],
         }
     }
 
@@ -171,6 +180,12 @@ impl<Dst: Write> TableBuilder<Dst> {
         Ok(())
     }
 
+    /// Add a range tombstone to the table. Tombstones may be added in any order, and are stored
+    /// in a separate meta block.
+    pub fn add_range_tombstone(&mut self, t: &RangeTombstone) {
+        self.range_tombstones.push(t.clone());
+    }
+
     /// Writes an index entry for the current data_block where `next_key` is the first key of the
     /// next block.
     /// Calls write_block() for writing the block to disk.
@@ -224,7 +239,12 @@ impl<Dst: Write> TableBuilder<Dst> {
         Ok(handle)
     }
 
+    /// finish writes the remaining blocks and the footer, returning the size of the table.
     pub fn finish(mut self) -> Result<usize> {
+        self.finish_blocks()
+    }
+
+    fn finish_blocks(&mut self) -> Result<usize> {
         assert!(self.data_block.is_some());
         let ctype = self.opt.compression_type;
 
@@ -239,7 +259,9 @@ impl<Dst: Write> TableBuilder<Dst> {
         }
 
         // Create metaindex block
-        let mut meta_ix_block = BlockBuilder::new(self.opt.clone());
+        let mut meta_opt = self.opt.clone();
+        meta_opt.cmp = Arc::new(Box::new(DefaultCmp));
+        let mut meta_ix_block = BlockBuilder::new(meta_opt);
 
         if self.filter_block.is_some() {
             // if there's a filter block, write the filter block and add it to the metaindex block.
@@ -254,6 +276,23 @@ impl<Dst: Write> TableBuilder<Dst> {
             meta_ix_block.add(filter_key.as_bytes(), &handle_enc[0..enc_len]);
         }
 
+        if !self.range_tombstones.is_empty() {
+            let mut tombstones: Vec<(Vec<u8>, Vec<u8>)> =
+                self.range_tombstones.iter().map(|t| t.encode()).collect();
+            let cmp = self.opt.cmp.clone();
+            tombstones.sort_by(|a, b| cmp.cmp(&a.0, &b.0));
+            tombstones.dedup_by(|a, b| a.0 == b.0);
+
+            let mut rblock = BlockBuilder::new(self.opt.clone());
+            for (k, v) in tombstones.iter() {
+                rblock.add(k, v);
+            }
+            let rblock_handle = self.write_block(rblock.finish(), ctype)?;
+            let mut handle_enc = [0 as u8; 16];
+            let enc_len = rblock_handle.encode_to(&mut handle_enc);
+            meta_ix_block.add(RANGE_DEL_BLOCK_NAME.as_bytes(), &handle_enc[0..enc_len]);
+        }
+
         // write metaindex block
         let meta_ix = meta_ix_block.finish();
         let meta_ix_handle = self.write_block(meta_ix, ctype)?;
@@ -273,6 +312,15 @@ impl<Dst: Write> TableBuilder<Dst> {
     }
 }
 
+impl<Dst: WritableFile> TableBuilder<Dst> {
+    /// finish_and_sync works like finish(), and syncs the table file afterwards.
+    pub fn finish_and_sync(mut self) -> Result<usize> {
+        let size = self.finish_blocks()?;
+        self.dst.sync()?;
+        Ok(size)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -7,7 +7,7 @@ use std::prelude::v1::*;
 use cache::{self, Cache};
 use error::{err, Result, StatusCode};
 use key_types::InternalKey;
-use options::Options;
+use options::{Options, ReadOptions};
 use table_reader::Table;
 use types::FileNum;
 
@@ -15,7 +15,7 @@ use integer_encoding::FixedIntWriter;
 
 use std::convert::AsRef;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub fn table_file_name<P: AsRef<Path>>(name: P, num: FileNum) -> PathBuf {
     assert!(num > 0);
@@ -48,11 +48,12 @@ impl TableCache {
 
     pub fn get<'a>(
         &mut self,
+        ro: &ReadOptions,
         file_num: FileNum,
         key: InternalKey<'a>,
     ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
         let tbl = self.get_table(file_num)?;
-        tbl.get(key)
+        tbl.get(ro, key)
     }
 
     /// Return a table from cache, or open the backing file, then cache and return it.
@@ -72,7 +73,7 @@ impl TableCache {
         if file_size == 0 {
             return err(StatusCode::InvalidData, "file is empty");
         }
-        let file = Rc::new(self.opts.env.open_random_access_file(&path)?);
+        let file = Arc::new(self.opts.env.open_random_access_file(&path)?);
         // No SSTable file name compatibility.
         let table = Table::new(self.opts.clone(), file, file_size)?;
         self.cache.insert(&filenum_to_key(file_num), table.clone());
@@ -139,7 +140,7 @@ mod tests {
         // Tests that a table can be written to a MemFS file, read back by the table cache and
         // parsed/iterated by the table reader.
         let mut opt = options::for_test();
-        opt.env = Rc::new(Box::new(MemEnv::new()));
+        opt.env = Arc::new(Box::new(MemEnv::new()));
         let dbname = Path::new("testdb1");
         let tablename = table_file_name(dbname, 123);
         let tblpath = Path::new(&tablename);
@@ -4,32 +4,40 @@ use std::prelude::v1::*;
 use block::{Block, BlockIter};
 use blockhandle::BlockHandle;
 use cache;
-use cmp::InternalKeyCmp;
+use cmp::{DefaultCmp, InternalKeyCmp};
 use env::RandomAccess;
-use error::Result;
+use error::{err, Result, StatusCode};
 use filter;
 use filter_block::FilterBlockReader;
 use key_types::InternalKey;
-use options::Options;
+use options::{Options, ReadOptions};
+use range_tombstone::RangeTombstone;
+use statistics::{self, Ticker};
 use table_block;
 use table_builder::{self, Footer};
 use types::{current_key_val, LdbIterator};
 
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use integer_encoding::FixedIntWriter;
 
 /// Reads the table footer.
 fn read_footer(f: &dyn RandomAccess, size: usize) -> Result<Footer> {
+    if size < table_builder::FULL_FOOTER_LENGTH {
+        return err(StatusCode::Corruption, "file is too short to be a table");
+    }
     let mut buf = vec![0; table_builder::FULL_FOOTER_LENGTH];
     f.read_at(size - table_builder::FULL_FOOTER_LENGTH, &mut buf)?;
+    if buf[table_builder::FOOTER_LENGTH..] != table_builder::MAGIC_FOOTER_ENCODED {
+        return err(StatusCode::Corruption, "bad magic number in table footer");
+    }
     Ok(Footer::decode(&buf))
 }
 
 #[derive(Clone)]
 pub struct Table {
-    file: Rc<Box<dyn RandomAccess>>,
+    file: Arc<Box<dyn RandomAccess>>,
     file_size: usize,
     cache_id: cache::CacheID,
 
@@ -38,25 +46,33 @@ pub struct Table {
     footer: Footer,
     indexblock: Block,
     filters: Option<FilterBlockReader>,
+    range_tombstones: Arc<Vec<RangeTombstone>>,
 }
 
 impl Table {
     /// Creates a new table reader operating on unformatted keys (i.e., UserKey).
-    fn new_raw(opt: Options, file: Rc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
+    fn new_raw(opt: Options, file: Arc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
         let footer = read_footer(file.as_ref().as_ref(), size)?;
         let indexblock = table_block::read_table_block(
             opt.clone(),
+            &ReadOptions::default(),
             file.as_ref().as_ref(),
             &footer.index
         )?;
+        // The metaindex block is ordered bytewise.
+        let mut meta_opt = opt.clone();
+        meta_opt.cmp = Arc::new(Box::new(DefaultCmp));
         let metaindexblock = table_block::read_table_block(
-            opt.clone(),
+            meta_opt,
+            &ReadOptions::default(),
             file.as_ref().as_ref(),
             &footer.meta_index
         )?;
 
         let filter_block_reader =
             Table::read_filter_block(&metaindexblock, file.as_ref().as_ref(), &opt)?;
+        let range_tombstones =
+            Table::read_range_tombstones(&metaindexblock, file.as_ref().as_ref(), &opt)?;
         let cache_id = opt.block_cache.borrow_mut().new_cache_id();
 
         Ok(Table {
@@ -67,6 +83,7 @@ impl Table {
             footer: footer,
             filters: filter_block_reader,
             indexblock: indexblock,
+            range_tombstones: Arc::new(range_tombstones),
         })
     }
 
@@ -83,25 +100,60 @@ impl Table {
         let mut metaindexiter = metaix.iter();
         metaindexiter.seek(&filter_name);
 
-        if let Some((_key, val)) = current_key_val(&metaindexiter) {
+        if let Some((key, val)) = current_key_val(&metaindexiter) {
+            if key != filter_name {
+                return Ok(None);
+            }
             let filter_block_location = BlockHandle::decode(&val).0;
             if filter_block_location.size() > 0 {
-                return Ok(Some(table_block::read_filter_block(
+                let mut reader = table_block::read_filter_block(
                     file,
                     &filter_block_location,
                     options.filter_policy.clone(),
-                )?));
+                )?;
+                reader.set_statistics(options.statistics.clone());
+                return Ok(Some(reader));
             }
         }
         Ok(None)
     }
 
+    fn read_range_tombstones(
+        metaix: &Block,
+        file: &dyn RandomAccess,
+        options: &Options,
+    ) -> Result<Vec<RangeTombstone>> {
+        let mut metaindexiter = metaix.iter();
+        metaindexiter.seek(table_builder::RANGE_DEL_BLOCK_NAME.as_bytes());
+
+        let mut tombstones = vec![];
+        if let Some((key, val)) = current_key_val(&metaindexiter) {
+            if key != table_builder::RANGE_DEL_BLOCK_NAME.as_bytes() {
+                return Ok(tombstones);
+            }
+            let location = BlockHandle::decode(&val).0;
+            let block = table_block::read_table_block(
+                options.clone(),
+                &ReadOptions::default(),
+                file,
+                &location
+            )?;
+            let mut iter = block.iter();
+            let (mut k, mut v) = (vec![], vec![]);
+            while iter.advance() {
+                iter.current(&mut k, &mut v);
+                tombstones.push(RangeTombstone::decode(&k, &v));
+            }
+        }
+        Ok(tombstones)
+    }
+
     /// Creates a new table reader operating on internal keys (i.e., InternalKey). This means that
     /// a different comparator (internal_key_cmp) and a different filter policy
     /// (InternalFilterPolicy) are used.
-    pub fn new(mut opt: Options, file: Rc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
-        opt.filter_policy = Rc::new(Box::new(filter::InternalFilterPolicy::new(
+    pub fn new(mut opt: Options, file: Arc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        opt.filter_policy = Arc::new(Box::new(filter::InternalFilterPolicy::new(
             opt.filter_policy,
         )));
         Table::new_raw(opt, file, size)
@@ -121,25 +173,30 @@ impl Table {
     }
 
     /// Read a block from the current table at `location`, and cache it in the options' block
-    /// cache.
-    fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+    /// cache (unless `ro.fill_cache` is false).
+    fn read_block(&self, ro: &ReadOptions, location: &BlockHandle) -> Result<Block> {
         let cachekey = self.block_cache_handle(location.offset());
         if let Some(block) = self.opt.block_cache.borrow_mut().get(&cachekey) {
+            statistics::record(&self.opt.statistics, Ticker::BlockCacheHit, 1);
             return Ok(block.clone());
         }
+        statistics::record(&self.opt.statistics, Ticker::BlockCacheMiss, 1);
 
-        // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
+        // Two times as_ref(): First time to get a ref from Arc<>, then one from Box<>.
         let b = table_block::read_table_block(
             self.opt.clone(),
+            ro,
             self.file.as_ref().as_ref(),
             location
         )?;
 
-        // insert a cheap copy (Rc).
-        self.opt
-            .block_cache
-            .borrow_mut()
-            .insert(&cachekey, b.clone());
+        if ro.fill_cache {
+            // insert a cheap copy (Arc).
+            self.opt
+                .block_cache
+                .borrow_mut()
+                .insert(&cachekey, b.clone());
+        }
 
         Ok(b)
     }
@@ -158,13 +215,49 @@ impl Table {
         return self.footer.meta_index.offset();
     }
 
+    /// Returns the range tombstones stored in this table.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Reads all data blocks from the file, bypassing the block cache, and verifies their
+    /// checksums.
+    pub fn verify(&self) -> Result<()> {
+        let ro = ReadOptions {
+            verify_checksums: true,
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        let mut iter = self.indexblock.iter();
+        let (mut key, mut val) = (vec![], vec![]);
+        while iter.advance() {
+            iter.current(&mut key, &mut val);
+            let location = BlockHandle::decode(&val).0;
+            table_block::read_table_block(
+                self.opt.clone(),
+                &ro,
+                self.file.as_ref().as_ref(),
+                &location
+            )?;
+        }
+        Ok(())
+    }
+
     /// Iterators read from the file; thus only one iterator can be borrowed (mutably) per scope
     pub fn iter(&self) -> TableIterator {
+        self.iter_opt(&ReadOptions::default())
+    }
+
+    /// Returns an iterator reading blocks as specified by `ro`.
+    pub fn iter_opt(&self, ro: &ReadOptions) -> TableIterator {
         let iter = TableIterator {
             current_block: None,
             current_block_off: 0,
             index_block: self.indexblock.iter(),
             table: self.clone(),
+            ro: ro.clone(),
+            lower_bound: None,
+            upper_bound: None,
         };
         iter
     }
@@ -180,7 +273,11 @@ impl Table {
     /// This is done this way because some key types, like internal keys, will not result in an
     /// exact match; it depends on other comparators than the one that the table reader knows
     /// whether a match is acceptable.
-    pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    pub fn get<'a>(
+        &self,
+        ro: &ReadOptions,
+        key: InternalKey<'a>,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
         let mut index_iter = self.indexblock.iter();
         index_iter.seek(key);
 
@@ -205,7 +302,7 @@ impl Table {
         }
 
         // Read block (potentially from cache)
-        let tb = self.read_block(&handle)?;
+        let tb = self.read_block(ro, &handle)?;
         let mut iter = tb.iter();
 
         // Go to entry and check if it's the wanted entry.
@@ -232,9 +329,113 @@ pub struct TableIterator {
     current_block: Option<BlockIter>,
     current_block_off: usize,
     index_block: BlockIter,
+    ro: ReadOptions,
+    // Entries outside of [lower_bound; upper_bound) are not returned. The bounds are keys in the
+    // table's format (i.e. InternalKeys for tables of a database).
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
 }
 
 impl TableIterator {
+    /// set_bounds restricts the iterator to entries within [lower; upper), given as keys in the
+    /// table's format. Blocks outside of the bounds are not read.
+    pub fn set_bounds(&mut self, lower: Option<Vec<u8>>, upper: Option<Vec<u8>>) {
+        self.lower_bound = lower;
+        self.upper_bound = upper;
+    }
+
+    /// seek_to_last positions the iterator at the last entry of the table that is within the
+    /// bounds.
+    pub fn seek_to_last(&mut self) {
+        if let Some(upper) = self.upper_bound.clone() {
+            // Position at the first entry not below the upper bound, then step back.
+            if self.seek_unbounded(&upper) {
+                if self.valid() {
+                    self.prev();
+                } else {
+                    // All entries in this block are below the bound, and the following blocks
+                    // only contain entries above it.
+                    self.current_block.as_mut().unwrap().seek_to_last();
+                    self.check_lower_bound();
+                }
+                return;
+            }
+        }
+        self.index_block.seek_to_last();
+        if let Some((_, handle)) = current_key_val(&self.index_block) {
+            if self.load_block(&handle).is_ok() {
+                self.current_block.as_mut().unwrap().seek_to_last();
+                self.check_lower_bound();
+                return;
+            }
+        }
+        self.reset();
+    }
+
+    // Positions the iterator at the first entry at or after `to`, regardless of the bounds.
+    fn seek_unbounded(&mut self, to: &[u8]) -> bool {
+        // first seek in index block, rewind by one entry (so we get the next smaller index entry),
+        // then set current_block and seek there
+        self.index_block.seek(to);
+
+        // It's possible that this is a seek past-last; reset in that case.
+        if let Some((past_block, handle)) = current_key_val(&self.index_block) {
+            if self.table.opt.cmp.cmp(to, &past_block) <= Ordering::Equal {
+                // ok, found right block: continue
+                if let Ok(()) = self.load_block(&handle) {
+                    // current_block is always set if load_block() returned Ok.
+                    self.current_block.as_mut().unwrap().seek(to);
+                    return true;
+                }
+            }
+        }
+        // Reached in case of failure.
+        self.reset();
+        false
+    }
+
+    // Returns whether `key` is at or after the upper bound.
+    fn after_upper_bound(&self, key: &[u8]) -> bool {
+        match self.upper_bound {
+            Some(ref upper) => self.table.opt.cmp.cmp(key, upper) != Ordering::Less,
+            None => false,
+        }
+    }
+
+    // Returns whether `key` is before the lower bound.
+    fn before_lower_bound(&self, key: &[u8]) -> bool {
+        match self.lower_bound {
+            Some(ref lower) => self.table.opt.cmp.cmp(key, lower) == Ordering::Less,
+            None => false,
+        }
+    }
+
+    // Resets the iterator if the current entry is at or after the upper bound.
+    fn check_upper_bound(&mut self) -> bool {
+        if self.upper_bound.is_some() {
+            if let Some((key, _)) = current_key_val(self) {
+                if self.after_upper_bound(&key) {
+                    self.reset();
+                    return false;
+                }
+            }
+        }
+        self.valid()
+    }
+
+    // Resets the iterator if the current entry is before the lower bound.
+    fn check_lower_bound(&mut self) -> bool {
+        if self.lower_bound.is_some() {
+            if let Some((key, _)) = current_key_val(self) {
+                if self.before_lower_bound(&key) {
+                    self.reset();
+                    return false;
+                }
+            }
+        }
+        self.valid()
+    }
+
     // Skips to the entry referenced by the next entry in the index block.
     // This is called once a block has run out of entries.
     // Err means corruption or I/O error; Ok(true) means a new block was loaded; Ok(false) means
@@ -250,7 +451,7 @@ impl TableIterator {
     // Load the block at `handle` into `self.current_block`
     fn load_block(&mut self, handle: &[u8]) -> Result<()> {
         let (new_block_handle, _) = BlockHandle::decode(handle);
-        let block = self.table.read_block(&new_block_handle)?;
+        let block = self.table.read_block(&self.ro, &new_block_handle)?;
 
         self.current_block = Some(block.iter());
         self.current_block_off = new_block_handle.offset();
@@ -263,6 +464,10 @@ impl LdbIterator for TableIterator {
     fn advance(&mut self) -> bool {
         // Uninitialized case.
         if self.current_block.is_none() {
+            if let Some(lower) = self.lower_bound.clone() {
+                self.seek(&lower);
+                return self.valid();
+            }
             match self.skip_to_next_entry() {
                 Ok(true) => return self.advance(),
                 Ok(false) => {
@@ -275,13 +480,23 @@ impl LdbIterator for TableIterator {
         }
 
         // Initialized case -- does the current block have more entries?
-        if let Some(ref mut cb) = self.current_block {
-            if cb.advance() {
-                return true;
-            }
+        let advanced = match self.current_block {
+            Some(ref mut cb) => cb.advance(),
+            None => false,
+        };
+        if advanced {
+            return self.check_upper_bound();
         }
 
-        // If the current block is exhausted, try loading the next block.
+        // If the current block is exhausted, try loading the next block -- unless the current
+        // block's index entry, which is not smaller than any key in the current block and smaller
+        // than all keys in the next block, shows that the next block is out of bounds.
+        if let Some((block_limit, _)) = current_key_val(&self.index_block) {
+            if self.after_upper_bound(&block_limit) {
+                self.reset();
+                return false;
+            }
+        }
         self.current_block = None;
         match self.skip_to_next_entry() {
             Ok(true) => self.advance(),
@@ -297,39 +512,40 @@ impl LdbIterator for TableIterator {
     // A call to valid() after seeking is necessary to ensure that the seek worked (e.g., no error
     // while reading from disk)
     fn seek(&mut self, to: &[u8]) {
-        // first seek in index block, rewind by one entry (so we get the next smaller index entry),
-        // then set current_block and seek there
-        self.index_block.seek(to);
-
-        // It's possible that this is a seek past-last; reset in that case.
-        if let Some((past_block, handle)) = current_key_val(&self.index_block) {
-            if self.table.opt.cmp.cmp(to, &past_block) <= Ordering::Equal {
-                // ok, found right block: continue
-                if let Ok(()) = self.load_block(&handle) {
-                    // current_block is always set if load_block() returned Ok.
-                    self.current_block.as_mut().unwrap().seek(to);
-                    return;
-                }
-            }
+        let to = match self.lower_bound {
+            Some(ref lower) if self.before_lower_bound(to) => lower.clone(),
+            _ => to.to_vec(),
+        };
+        if self.after_upper_bound(&to) {
+            self.reset();
+            return;
+        }
+        if self.seek_unbounded(&to) {
+            self.check_upper_bound();
         }
-        // Reached in case of failure.
-        self.reset();
     }
 
     fn prev(&mut self) -> bool {
         // happy path: current block contains previous entry
-        if let Some(ref mut cb) = self.current_block {
-            if cb.prev() {
-                return true;
-            }
+        let moved = match self.current_block {
+            Some(ref mut cb) => cb.prev(),
+            None => false,
+        };
+        if moved {
+            return self.check_lower_bound();
         }
 
         // Go back one block and look for the last entry in the previous block
         if self.index_block.prev() {
-            if let Some((_, handle)) = current_key_val(&self.index_block) {
+            if let Some((block_limit, handle)) = current_key_val(&self.index_block) {
+                // All keys in the previous block are at most as large as its index entry.
+                if self.before_lower_bound(&block_limit) {
+                    self.reset();
+                    return false;
+                }
                 if self.load_block(&handle).is_ok() {
                     self.current_block.as_mut().unwrap().seek_to_last();
-                    self.current_block.as_ref().unwrap().valid()
+                    self.check_lower_bound()
                 } else {
                     self.reset();
                     false
@@ -364,6 +580,7 @@ impl LdbIterator for TableIterator {
 
 #[cfg(test)]
 mod tests {
+    use error::StatusCode;
     use filter::BloomPolicy;
     use key_types::LookupKey;
     use options::{self, CompressionType};
@@ -418,7 +635,7 @@ mod tests {
         let mut opt = options::for_test();
         opt.block_restart_interval = 1;
         opt.block_size = 32;
-        opt.filter_policy = Rc::new(Box::new(BloomPolicy::new(4)));
+        opt.filter_policy = Arc::new(Box::new(BloomPolicy::new(4)));
 
         let mut i = 1 as u64;
         let data: Vec<(Vec<u8>, &'static str)> = build_data()
@@ -445,8 +662,8 @@ mod tests {
         (d, size)
     }
 
-    fn wrap_buffer(src: Vec<u8>) -> Rc<Box<dyn RandomAccess>> {
-        Rc::new(Box::new(src))
+    fn wrap_buffer(src: Vec<u8>) -> Arc<Box<dyn RandomAccess>> {
+        Arc::new(Box::new(src))
     }
 
     #[test]
@@ -489,6 +706,30 @@ mod tests {
         assert_eq!(opt.block_cache.borrow().count(), 2);
     }
 
+    #[test]
+    fn test_table_no_fill_cache() {
+        let (src, size) = build_table(build_data());
+        let mut opt = options::for_test();
+        opt.block_size = 32;
+
+        let table = Table::new_raw(opt.clone(), wrap_buffer(src), size).unwrap();
+        let ro = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+
+        {
+            let mut iter = table.iter_opt(&ro);
+            let iter = LdbIteratorIter::wrap(&mut iter);
+            assert_eq!(iter.count(), build_data().len());
+        }
+        assert!(table.get(&ro, b"bcd").unwrap().is_some());
+        assert_eq!(opt.block_cache.borrow().count(), 0);
+
+        assert!(table.get(&ReadOptions::default(), b"bcd").unwrap().is_some());
+        assert_eq!(opt.block_cache.borrow().count(), 1);
+    }
+
     #[test]
     fn test_table_iterator_fwd_bwd() {
         let (src, size) = build_table(build_data());
@@ -540,6 +781,40 @@ mod tests {
         assert_eq!(j, 6);
     }
 
+    #[test]
+    fn test_table_iterator_bounds() {
+        let (src, size) = build_table(build_data());
+        let opt = options::for_test();
+        let table = Table::new_raw(opt.clone(), wrap_buffer(src), size).unwrap();
+
+        let mut iter = table.iter();
+        iter.set_bounds(Some(b"abd".to_vec()), Some(b"xyz".to_vec()));
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(vec![b"abd".to_vec(), b"bcd".to_vec(), b"bsr".to_vec()], keys);
+        // The last block has not been read.
+        assert_eq!(2, opt.block_cache.borrow().count());
+
+        iter.seek(b"a");
+        assert_eq!(b"abd".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(!iter.prev());
+        iter.seek(b"xzz");
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        assert_eq!(b"bsr".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(iter.prev());
+        assert_eq!(b"bcd".to_vec(), current_key_val(&iter).unwrap().0);
+
+        // The next block is not read if its index entry shows that it is out of bounds.
+        let opt = options::for_test();
+        let (src, size) = build_table(build_data());
+        let table = Table::new_raw(opt.clone(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+        iter.set_bounds(None, Some(b"bcz".to_vec()));
+        assert_eq!(3, LdbIteratorIter::wrap(&mut iter).count());
+        assert_eq!(1, opt.block_cache.borrow().count());
+    }
+
     #[test]
     fn test_table_iterator_filter() {
         let (src, size) = build_table(build_data());
@@ -670,21 +945,21 @@ mod tests {
         let mut _iter = table.iter();
         // Test that all of the table's entries are reachable via get()
         for (k, v) in LdbIteratorIter::wrap(&mut _iter) {
-            let r = table2.get(&k);
+            let r = table2.get(&ReadOptions::default(), &k);
             assert_eq!(Ok(Some((k, v))), r);
         }
 
         assert_eq!(table.opt.block_cache.borrow().count(), 3);
 
         // test that filters work and don't return anything at all.
-        assert!(table.get(b"aaa").unwrap().is_none());
-        assert!(table.get(b"aaaa").unwrap().is_none());
-        assert!(table.get(b"aa").unwrap().is_none());
-        assert!(table.get(b"abcd").unwrap().is_none());
-        assert!(table.get(b"abb").unwrap().is_none());
-        assert!(table.get(b"zzy").unwrap().is_none());
-        assert!(table.get(b"zz1").unwrap().is_none());
-        assert!(table.get("zz{".as_bytes()).unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"aaa").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"aaaa").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"aa").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"abcd").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"abb").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"zzy").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), b"zz1").unwrap().is_none());
+        assert!(table.get(&ReadOptions::default(), "zz{".as_bytes()).unwrap().is_none());
     }
 
     // This test verifies that the table and filters work with internal keys. This means:
@@ -705,11 +980,14 @@ mod tests {
         let mut _iter = table.iter();
         for (ref k, ref v) in LdbIteratorIter::wrap(&mut _iter) {
             assert_eq!(k.len(), 3 + 8);
-            assert_eq!((k.to_vec(), v.to_vec()), table.get(k).unwrap().unwrap());
+            assert_eq!(
+                (k.to_vec(), v.to_vec()),
+                table.get(&ReadOptions::default(), k).unwrap().unwrap()
+            );
         }
 
         assert!(table
-            .get(LookupKey::new(b"abc", 1000).internal_key())
+            .get(&ReadOptions::default(), LookupKey::new(b"abc", 1000).internal_key())
             .unwrap()
             .is_some());
 
@@ -728,6 +1006,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_table_range_tombstones() {
+        let mut opt = options::for_test();
+        opt.filter_policy = Arc::new(Box::new(BloomPolicy::new(4)));
+        let tombstones = vec![
+            RangeTombstone::new(b"b", b"d", 3),
+            RangeTombstone::new(b"a", b"c", 4),
+            RangeTombstone::new(b"b", b"x", 5),
+        ];
+
+        let mut d = Vec::with_capacity(512);
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            b.add(LookupKey::new(b"abc", 1).internal_key(), b"def")
+                .unwrap();
+            for t in tombstones.iter().chain(tombstones.iter()) {
+                b.add_range_tombstone(t);
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(opt.clone(), wrap_buffer(d), size).unwrap();
+        assert!(table.filters.is_some());
+        assert_eq!(
+            vec![
+                tombstones[1].clone(),
+                tombstones[2].clone(),
+                tombstones[0].clone()
+            ],
+            table.range_tombstones().to_vec()
+        );
+        assert!(table
+            .get(&ReadOptions::default(), LookupKey::new(b"abc", 1).internal_key())
+            .unwrap()
+            .is_some());
+
+        // A table may consist of nothing but range tombstones.
+        let mut d = Vec::with_capacity(512);
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            b.add_range_tombstone(&tombstones[0]);
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        assert_eq!(&tombstones[0..1], table.range_tombstones());
+        assert_eq!(0, LdbIteratorIter::wrap(&mut table.iter()).count());
+    }
+
     #[test]
     fn test_table_reader_checksum() {
         let (mut src, size) = build_table(build_data());
@@ -736,6 +1063,13 @@ mod tests {
 
         let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
 
+        // get() reports the corrupted block.
+        let e = table
+            .get(&ReadOptions::default(), build_data()[0].0.as_bytes())
+            .unwrap_err();
+        assert_eq!(StatusCode::Corruption, e.code);
+        assert!(e.err.contains("block at offset 0"));
+
         assert!(table.filters.is_some());
         assert_eq!(table.filters.as_ref().unwrap().num(), 1);
 
@@ -759,4 +1093,36 @@ mod tests {
             panic!("Should have hit 5th record in table!");
         }
     }
+
+    #[test]
+    fn test_table_reader_verify() {
+        let (mut src, size) = build_table(build_data());
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src.clone()), size).unwrap();
+        table.verify().unwrap();
+
+        src[10] += 1;
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+        // Iterators skip the corrupted block, while verify() reports it.
+        assert_eq!(4, LdbIteratorIter::wrap(&mut table.iter()).count());
+        let e = table.verify().unwrap_err();
+        assert_eq!(StatusCode::Corruption, e.code);
+    }
+
+    #[test]
+    fn test_table_reader_no_verify_checksums() {
+        let (mut src, size) = build_table(build_data());
+
+        src[10] += 1;
+
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+        let ro = ReadOptions {
+            verify_checksums: false,
+            ..ReadOptions::default()
+        };
+
+        // The corrupted first block is not skipped anymore.
+        let mut _iter = table.iter_opt(&ro);
+        let iter = LdbIteratorIter::wrap(&mut _iter);
+        assert_eq!(iter.count(), build_data().len());
+    }
 }
@@ -2,9 +2,21 @@
 use std::prelude::v1::*;
 
 use cmp::{Cmp, DefaultCmp};
+use env::{Env, FileLock, Logger, RandomAccess, WritableFile};
+use error::Result;
 use types::{current_key_val, LdbIterator};
 
 use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        use std::sync::{Arc, SgxMutex as Mutex};
+    } else {
+        use std::sync::{Arc, Mutex};
+    }
+}
 
 /// TestLdbIter is an LdbIterator over a vector, to be used for testing purposes.
 pub struct TestLdbIter<'a> {
@@ -166,3 +178,143 @@ mod tests {
         test_iterator_properties(TestLdbIter::new(v));
     }
 }
+
+/// corrupt_file flips the bits of the byte at `offset` in the file at `p`.
+pub fn corrupt_file(env: &dyn Env, p: &Path, offset: usize) {
+    let size = env.size_of(p).unwrap();
+    let mut buf = vec![0; size];
+    env.open_random_access_file(p)
+        .unwrap()
+        .read_at(0, &mut buf)
+        .unwrap();
+    buf[offset] ^= 0xff;
+    env.open_writable_file(p).unwrap().write_all(&buf).unwrap();
+}
+
+#[derive(Default)]
+struct Syncs {
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+/// SyncCountingEnv passes all operations on to another Env, and records which files and
+/// directories have been synced. Clones share the record.
+#[derive(Clone)]
+pub struct SyncCountingEnv {
+    inner: Arc<Box<dyn Env>>,
+    syncs: Arc<Mutex<Syncs>>,
+}
+
+impl SyncCountingEnv {
+    pub fn new(inner: Box<dyn Env>) -> SyncCountingEnv {
+        SyncCountingEnv {
+            inner: Arc::new(inner),
+            syncs: Arc::new(Mutex::new(Syncs::default())),
+        }
+    }
+
+    /// synced_files returns the files synced so far, once per call of sync().
+    pub fn synced_files(&self) -> Vec<PathBuf> {
+        self.syncs.lock().unwrap().files.clone()
+    }
+
+    /// synced_dirs returns the directories synced so far, once per call of sync_dir().
+    pub fn synced_dirs(&self) -> Vec<PathBuf> {
+        self.syncs.lock().unwrap().dirs.clone()
+    }
+
+    pub fn clear(&self) {
+        *self.syncs.lock().unwrap() = Syncs::default();
+    }
+
+    fn writable_file(&self, p: &Path, f: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        Box::new(SyncCountingFile {
+            path: p.to_owned(),
+            file: f,
+            syncs: self.syncs.clone(),
+        })
+    }
+}
+
+impl Env for SyncCountingEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        self.inner.open_sequential_file(p)
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        self.inner.open_random_access_file(p)
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        let f = self.inner.open_writable_file(p)?;
+        Ok(self.writable_file(p, f))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn WritableFile>> {
+        let f = self.inner.open_appendable_file(p)?;
+        Ok(self.writable_file(p, f))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.inner.size_of(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.inner.delete(p)
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.inner.rename(old, new)
+    }
+    fn sync_dir(&self, p: &Path) -> Result<()> {
+        self.inner.sync_dir(p)?;
+        self.syncs.lock().unwrap().dirs.push(p.to_owned());
+        Ok(())
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.inner.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+}
+
+struct SyncCountingFile {
+    path: PathBuf,
+    file: Box<dyn WritableFile>,
+    syncs: Arc<Mutex<Syncs>>,
+}
+
+impl Write for SyncCountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WritableFile for SyncCountingFile {
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync()?;
+        self.syncs.lock().unwrap().files.push(self.path.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Optimistic transactions buffer their writes and remember the keys they have read or written.
+//! Nothing is locked while a transaction is running; instead, `commit()` checks whether any of
+//! those keys has been modified since the transaction's snapshot was taken, and fails with
+//! `StatusCode::Busy` if so. The application may then retry the transaction.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use db_impl::DB;
+use error::Result;
+use options::WriteOptions;
+use snapshot::Snapshot;
+use write_batch::WriteBatch;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A Transaction is obtained from `DB::begin_transaction()`. Dropping it without calling
+/// `commit()` discards its writes.
+pub struct Transaction<'a> {
+    db: &'a DB,
+    // Held until the transaction ends, so that compactions keep all entries written after it.
+    snapshot: Snapshot,
+    batch: WriteBatch,
+    // Buffered writes, so that reads observe them. A value of None marks a deletion.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    // Keys that must not have been modified since the snapshot was taken.
+    tracked: BTreeSet<Vec<u8>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(db: &'a DB, snapshot: Snapshot) -> Transaction<'a> {
+        Transaction {
+            db: db,
+            snapshot: snapshot,
+            batch: WriteBatch::new(),
+            writes: BTreeMap::new(),
+            tracked: BTreeSet::new(),
+        }
+    }
+
+    /// snapshot returns the snapshot that reads of this transaction observe.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// get reads the value of a key as written by this transaction, or else as of the
+    /// transaction's snapshot. The key is tracked for conflicts.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tracked.insert(key.to_vec());
+        if let Some(v) = self.writes.get(key) {
+            return Ok(v.clone());
+        }
+        self.db.get_at(&self.snapshot, key)
+    }
+
+    /// put buffers a write of key, which is tracked for conflicts.
+    pub fn put(&mut self, key: &[u8], val: &[u8]) {
+        self.tracked.insert(key.to_vec());
+        self.writes.insert(key.to_vec(), Some(val.to_vec()));
+        self.batch.put(key, val);
+    }
+
+    /// delete buffers a deletion of key, which is tracked for conflicts.
+    pub fn delete(&mut self, key: &[u8]) {
+        self.tracked.insert(key.to_vec());
+        self.writes.insert(key.to_vec(), None);
+        self.batch.delete(key);
+    }
+
+    /// commit atomically applies the writes of this transaction, unless one of the keys it has
+    /// read or written has been modified after its snapshot; in that case, an error with
+    /// StatusCode::Busy is returned and nothing is written.
+    pub fn commit(self) -> Result<()> {
+        self.commit_opt(&WriteOptions::default())
+    }
+
+    /// commit_opt works like commit(), using the supplied WriteOptions.
+    pub fn commit_opt(self, wo: &WriteOptions) -> Result<()> {
+        self.db.write_if_unchanged(
+            wo,
+            self.tracked.iter(),
+            self.snapshot.sequence(),
+            self.batch,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::StatusCode;
+    use options;
+
+    #[test]
+    fn test_transaction_read_own_writes() {
+        let db = DB::open("db", options::for_test()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.put(b"bcd", b"efg").unwrap();
+
+        let mut txn = db.begin_transaction();
+        assert_eq!(Some(b"def".to_vec()), txn.get(b"abc").unwrap());
+        txn.put(b"abc", b"xyz");
+        txn.delete(b"bcd");
+        txn.put(b"cde", b"fgh");
+        assert_eq!(Some(b"xyz".to_vec()), txn.get(b"abc").unwrap());
+        assert_eq!(None, txn.get(b"bcd").unwrap());
+
+        // Writes are invisible until the transaction is committed.
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+        assert_eq!(None, db.get(b"cde"));
+        txn.commit().unwrap();
+        assert_eq!(Some(b"xyz".to_vec()), db.get(b"abc"));
+        assert_eq!(None, db.get(b"bcd"));
+        assert_eq!(Some(b"fgh".to_vec()), db.get(b"cde"));
+
+        // Dropped transactions don't write anything.
+        let mut txn = db.begin_transaction();
+        txn.put(b"abc", b"123");
+        drop(txn);
+        assert_eq!(Some(b"xyz".to_vec()), db.get(b"abc"));
+    }
+
+    #[test]
+    fn test_transaction_conflicts() {
+        let db = DB::open("db", options::for_test()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.put(b"bcd", b"efg").unwrap();
+
+        // Read-write conflict.
+        let mut txn = db.begin_transaction();
+        txn.get(b"abc").unwrap();
+        txn.put(b"bcd", b"xyz");
+        db.put(b"abc", b"123").unwrap();
+        assert_eq!(StatusCode::Busy, txn.commit().unwrap_err().code);
+        assert_eq!(Some(b"efg".to_vec()), db.get(b"bcd"));
+
+        // Write-write conflict, also detected if the entry has been written to a table.
+        let mut txn = db.begin_transaction();
+        txn.put(b"bcd", b"xyz");
+        db.delete(b"bcd").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(StatusCode::Busy, txn.commit().unwrap_err().code);
+
+        // Range deletions conflict with the keys they cover.
+        let mut txn = db.begin_transaction();
+        txn.get(b"abc").unwrap();
+        db.delete_range(b"aaa", b"abd").unwrap();
+        assert_eq!(StatusCode::Busy, txn.commit().unwrap_err().code);
+        let mut txn = db.begin_transaction();
+        txn.get(b"cde").unwrap();
+        db.delete_range(b"c", b"d").unwrap();
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(StatusCode::Busy, txn.commit().unwrap_err().code);
+
+        // Writes to other keys don't conflict.
+        let mut txn = db.begin_transaction();
+        txn.get(b"abc").unwrap();
+        txn.put(b"bcd", b"xyz");
+        db.put(b"abd", b"456").unwrap();
+        db.delete_range(b"c", b"d").unwrap();
+        txn.commit().unwrap();
+        assert_eq!(None, db.get(b"abc"));
+        assert_eq!(Some(b"xyz".to_vec()), db.get(b"bcd"));
+    }
+}
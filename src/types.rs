@@ -4,9 +4,17 @@ use std::prelude::v1::*;
 
 use error::{err, Result, StatusCode};
 
-use std::cell::RefCell;
+use std::fmt;
 use std::path::Path;
-use std::rc::Rc;
+
+cfg_if! {
+    if #[cfg(feature = "mesalock_sgx")] {
+        use std::sync::{Arc, SgxRwLock as RwLock, Weak};
+        use std::sync::{SgxRwLockReadGuard as RwLockReadGuard, SgxRwLockWriteGuard as RwLockWriteGuard};
+    } else {
+        use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+    }
+}
 
 pub const NUM_LEVELS: usize = 7;
 
@@ -15,11 +23,55 @@ pub type SequenceNumber = u64;
 
 pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
 
-/// A shared thingy with interior mutability.
-pub type Shared<T> = Rc<RefCell<T>>;
+/// A shared thingy with interior mutability. It can be sent to and shared between threads;
+/// `borrow()` acquires a read lock and `borrow_mut()` a write lock on the contained value. Like
+/// with a `RefCell`, a thread must not call `borrow_mut()` while it still holds a borrow of the
+/// same value.
+pub struct Shared<T>(Arc<RwLock<T>>);
+
+impl<T> Shared<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap()
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap()
+    }
+
+    pub fn downgrade(&self) -> WeakShared<T> {
+        WeakShared(Arc::downgrade(&self.0))
+    }
+}
+
+/// A WeakShared refers to a Shared value without keeping it alive.
+pub struct WeakShared<T>(Weak<RwLock<T>>);
+
+impl<T> WeakShared<T> {
+    pub fn upgrade(&self) -> Option<Shared<T>> {
+        self.0.upgrade().map(Shared)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T> Clone for WeakShared<T> {
+    fn clone(&self) -> WeakShared<T> {
+        WeakShared(self.0.clone())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
-pub fn share<T>(t: T) -> Rc<RefCell<T>> {
-    Rc::new(RefCell::new(t))
+pub fn share<T>(t: T) -> Shared<T> {
+    Shared(Arc::new(RwLock::new(t)))
 }
 
 #[derive(PartialEq)]
@@ -28,7 +80,7 @@ pub enum Direction {
     Reverse,
 }
 
-/// Denotes a key range
+/// Denotes a key range [start; limit).
 pub struct Range<'a> {
     pub start: &'a [u8],
     pub limit: &'a [u8],
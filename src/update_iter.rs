@@ -0,0 +1,144 @@
+//! An UpdateIterator replays the write batches recorded in a database's log files, starting at a
+//! given sequence number. This can be used to follow the changes made to a database, e.g. in
+//! order to replicate them.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use env::Env;
+use error::{Result, Status};
+use log::LogReader;
+use types::SequenceNumber;
+use write_batch::WriteBatch;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// UpdateIterator yields the write batches written after a sequence number, together with the
+/// sequence number of their first entry, as returned by `DB::get_updates_since()`.
+///
+/// The iterator only returns batches written before its creation. Batches written with
+/// `WriteOptions::disable_wal` aren't recorded in the log and are thus never returned.
+pub struct UpdateIterator {
+    env: Arc<Box<dyn Env>>,
+    // Log files that haven't been read yet, in the order they were written.
+    logs: Vec<PathBuf>,
+    reader: Option<LogReader<Box<dyn Read>>>,
+    // Batches ending before this sequence number are skipped.
+    start: SequenceNumber,
+    // The last sequence number written when the iterator was created.
+    last: SequenceNumber,
+    done: bool,
+    // The first error encountered while reading the logs.
+    status: Option<Status>,
+    scratch: Vec<u8>,
+}
+
+impl UpdateIterator {
+    pub(crate) fn new(
+        env: Arc<Box<dyn Env>>,
+        mut logs: Vec<PathBuf>,
+        start: SequenceNumber,
+        last: SequenceNumber,
+    ) -> UpdateIterator {
+        logs.reverse();
+        UpdateIterator {
+            env: env,
+            logs: logs,
+            reader: None,
+            start: start,
+            last: last,
+            done: start > last,
+            status: None,
+            scratch: vec![],
+        }
+    }
+
+    /// status returns the first error encountered by this iterator, e.g. because a log file was
+    /// corrupted or has been deleted. The iterator ends when an error occurs.
+    pub fn status(&self) -> Result<()> {
+        match self.status {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// read_batch reads the next batch from the log files.
+    fn read_batch(&mut self) -> Result<Option<WriteBatch>> {
+        loop {
+            if self.reader.is_none() {
+                match self.logs.pop() {
+                    Some(log) => {
+                        let f = self.env.open_sequential_file(&log)?;
+                        self.reader = Some(LogReader::new(f, true));
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let len = self.reader.as_mut().unwrap().read(&mut self.scratch)?;
+            if len == 0 {
+                self.reader = None;
+                continue;
+            }
+            if len < 12 {
+                // Like during recovery, records too short to be a batch are skipped.
+                continue;
+            }
+            let mut batch = WriteBatch::new();
+            batch.set_contents(&self.scratch);
+            return Ok(Some(batch));
+        }
+    }
+}
+
+impl Iterator for UpdateIterator {
+    type Item = (SequenceNumber, WriteBatch);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_batch() {
+                Ok(Some(batch)) => {
+                    let seq = batch.sequence();
+                    let end = seq + batch.count() as u64;
+                    if end <= self.start {
+                        continue;
+                    }
+                    if end > self.last {
+                        // Later batches may not have been written completely.
+                        self.done = true;
+                        if seq > self.last {
+                            break;
+                        }
+                    }
+                    return Some((seq, batch));
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.status = Some(e);
+                    self.done = true;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// first_sequence returns the sequence number of the first batch in a log file, or None if the
+/// file doesn't contain a complete batch.
+pub fn first_sequence(env: &dyn Env, log: &Path) -> Option<SequenceNumber> {
+    let f = env.open_sequential_file(log).ok()?;
+    let mut reader = LogReader::new(f, true);
+    let mut scratch = vec![];
+    loop {
+        match reader.read(&mut scratch) {
+            Ok(len) if len >= 12 => {
+                let mut batch = WriteBatch::new();
+                batch.set_contents(&scratch);
+                return Some(batch.sequence());
+            }
+            Ok(len) if len > 0 => continue,
+            _ => return None,
+        }
+    }
+}
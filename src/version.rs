@@ -2,15 +2,21 @@
 use std::prelude::v1::*;
 
 use cmp::{Cmp, InternalKeyCmp};
-use error::Result;
-use key_types::{parse_internal_key, InternalKey, LookupKey, UserKey, ValueType};
+use error::{Result, Status};
+use key_types::{
+    internal_bound, parse_internal_key, resolve_ttl, InternalKey, LookupKey, UserKey, ValueType,
+};
+use options::ReadOptions;
+use range_tombstone::{max_covering_seq, RangeTombstone};
 use table_cache::TableCache;
-use table_reader::TableIterator;
-use types::{FileMetaData, FileNum, LdbIterator, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS};
+use table_reader::{Table, TableIterator};
+use types::{
+    FileMetaData, FileNum, LdbIterator, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS,
+};
 
 use std::cmp::Ordering;
 use std::default::Default;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// FileMetaHandle is a reference-counted FileMetaData object with interior mutability. This is
 /// necessary to provide a shared metadata container that can be modified while referenced by e.g.
@@ -18,14 +24,43 @@ use std::rc::Rc;
 pub type FileMetaHandle = Shared<FileMetaData>;
 
 /// Contains statistics about seeks occurred in a file.
+#[derive(Debug)]
 pub struct GetStats {
     file: Option<FileMetaHandle>,
     level: usize,
 }
 
+/// KeyLookup is the state of the lookup of a single key by `Version::multi_get()`.
+pub struct KeyLookup {
+    pub key: LookupKey,
+    /// Merge operands found so far, newest first.
+    pub operands: Vec<Vec<u8>>,
+    /// Set once the lookup has ended, with the value found (None if the key has been deleted).
+    pub result: Option<Result<Option<Vec<u8>>>>,
+    max_tombstone: SequenceNumber,
+    last_read: Option<(FileMetaHandle, usize)>,
+    stats: GetStats,
+}
+
+impl KeyLookup {
+    pub fn new(key: LookupKey) -> KeyLookup {
+        KeyLookup {
+            key: key,
+            operands: vec![],
+            result: None,
+            max_tombstone: 0,
+            last_read: None,
+            stats: GetStats {
+                file: None,
+                level: 0,
+            },
+        }
+    }
+}
+
 pub struct Version {
     table_cache: Shared<TableCache>,
-    user_cmp: Rc<Box<dyn Cmp>>,
+    user_cmp: Arc<Box<dyn Cmp>>,
     pub files: [Vec<FileMetaHandle>; NUM_LEVELS],
 
     pub file_to_compact: Option<FileMetaHandle>,
@@ -35,7 +70,7 @@ pub struct Version {
 }
 
 impl Version {
-    pub fn new(cache: Shared<TableCache>, ucmp: Rc<Box<dyn Cmp>>) -> Version {
+    pub fn new(cache: Shared<TableCache>, ucmp: Arc<Box<dyn Cmp>>) -> Version {
         Version {
             table_cache: cache,
             user_cmp: ucmp,
@@ -58,12 +93,23 @@ impl Version {
     }
 
     /// get returns the value for the specified key using the persistent tables contained in this
-    /// Version.
+    /// Version. Merge operands found before the value are appended to `operands`, newest first;
+    /// they have to be applied to the returned value, which is None if the key doesn't exist or
+    /// has been deleted. Values that have expired at time `now` count as deleted.
     #[allow(unused_assignments)]
-    pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, GetStats)>> {
+    pub fn get<'a>(
+        &self,
+        ro: &ReadOptions,
+        key: InternalKey<'a>,
+        now: u64,
+        operands: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<(Vec<u8>, GetStats)>> {
         let levels = self.get_overlapping(key);
         let ikey = key;
-        let ukey = parse_internal_key(ikey).2;
+        let (_, snapshot, ukey) = parse_internal_key(ikey);
+        // The highest sequence number of a range tombstone covering ukey in the files consulted
+        // so far. Entries older than that are deleted.
+        let mut max_tombstone = 0;
 
         let mut stats = GetStats {
             file: None,
@@ -82,27 +128,274 @@ impl Version {
                 last_read_level = level;
                 last_read = Some(f.clone());
 
-                // We receive both key and value from the table. Because we're using InternalKey
-                // keys, we now need to check whether the found entry's user key is equal to the
-                // one we're looking for (get() just returns the next-bigger key).
-                if let Ok(Some((k, v))) = self.table_cache.borrow_mut().get(f.borrow().num, ikey) {
-                    // We don't need to check the sequence number; get() will not return an entry
-                    // with a higher sequence number than the one in the supplied key.
-                    let (typ, _, foundkey) = parse_internal_key(&k);
-                    if typ == ValueType::TypeValue
-                        && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
-                    {
-                        return Ok(Some((v, stats)));
-                    } else if typ == ValueType::TypeDeletion {
-                        // Skip looking once we have found a deletion.
-                        return Ok(None);
-                    }
+                // Only hold the table cache's lock while retrieving the table, not while reading
+                // from it.
+                // Errors are returned to the caller instead of being treated as a missing entry;
+                // otherwise a corrupted or unreadable table would look like a missing key.
+                let num = f.borrow().num;
+                let tbl = self.table_cache.borrow_mut().get_table(num);
+                let r = tbl
+                    .and_then(|t| {
+                        let tombstone = max_covering_seq(
+                            t.range_tombstones().iter(),
+                            self.user_cmp.as_ref().as_ref(),
+                            ukey,
+                            snapshot,
+                        );
+                        if tombstone > max_tombstone {
+                            max_tombstone = tombstone;
+                        }
+                        self.get_from_table(ro, &t, ukey, snapshot, max_tombstone, now, operands)
+                    })
+                    .map_err(|e| table_error(e, num));
+                match r? {
+                    Some(Some(v)) => return Ok(Some((v, stats))),
+                    // Skip looking once we have found a deletion.
+                    Some(None) => return Ok(None),
+                    None => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// get_from_table looks up a user key in a single table, collecting merge operands like
+    /// get(). It returns Some(value) if the lookup ends with a value, Some(None) if it ends with
+    /// a deletion (including entries older than `max_tombstone`), and None if the lookup
+    /// has to continue in older tables.
+    fn get_from_table<'a>(
+        &self,
+        ro: &ReadOptions,
+        t: &Table,
+        ukey: UserKey<'a>,
+        snapshot: SequenceNumber,
+        max_tombstone: SequenceNumber,
+        now: u64,
+        operands: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<Option<Vec<u8>>>> {
+        let mut lkey = LookupKey::new(ukey, snapshot);
+        // We receive both key and value from the table. Because we're using InternalKey keys, we
+        // now need to check whether the found entry's user key is equal to the one we're looking
+        // for (get() just returns the next-bigger key).
+        while let Some((k, mut v)) = t.get(ro, lkey.internal_key())? {
+            // We don't need to check the sequence number; get() will not return an entry with a
+            // higher sequence number than the one in the supplied key.
+            let (typ, seq, foundkey) = parse_internal_key(&k);
+            if self.user_cmp.cmp(foundkey, ukey) != Ordering::Equal {
+                break;
+            }
+            if seq < max_tombstone {
+                // Covered by a newer range deletion.
+                return Ok(Some(None));
+            }
+            match resolve_ttl(typ, &mut v, now) {
+                ValueType::TypeValue => return Ok(Some(Some(v))),
+                ValueType::TypeMerge if seq > 0 => {
+                    operands.push(v);
+                    // Continue with the next older entry.
+                    lkey = LookupKey::new(ukey, seq - 1);
+                }
+                ValueType::TypeMerge => {
+                    operands.push(v);
+                    break;
                 }
+                _ => return Ok(Some(None)),
             }
         }
         Ok(None)
     }
 
+    /// latest_sequence returns the sequence number of the newest entry for a user key in this
+    /// Version's tables, or of the newest range tombstone covering it. It returns 0 if there is no
+    /// such entry. Only tables overlapping the key are read.
+    pub fn latest_sequence<'a>(&self, ukey: UserKey<'a>) -> Result<SequenceNumber> {
+        let lkey = LookupKey::new(ukey, MAX_SEQUENCE_NUMBER);
+        for files in self.get_overlapping(lkey.internal_key()).iter() {
+            for f in files {
+                let num = f.borrow().num;
+                let tbl = self.table_cache.borrow_mut().get_table(num);
+                let latest = tbl
+                    .and_then(|t| {
+                        let mut latest = max_covering_seq(
+                            t.range_tombstones().iter(),
+                            self.user_cmp.as_ref().as_ref(),
+                            ukey,
+                            MAX_SEQUENCE_NUMBER,
+                        );
+                        if let Some((k, _)) = t.get(&ReadOptions::default(), lkey.internal_key())? {
+                            let (_, seq, foundkey) = parse_internal_key(&k);
+                            if self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal && seq > latest
+                            {
+                                latest = seq;
+                            }
+                        }
+                        Ok(latest)
+                    })
+                    .map_err(|e| table_error(e, num))?;
+                // Files are visited from the newest to the oldest, so the first one mentioning
+                // the key has the newest entry.
+                if latest > 0 {
+                    return Ok(latest);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// multi_get looks up several keys like get(), which must be sorted by user key. Each table
+    /// is retrieved from the table cache once for all keys that have to be looked up in it.
+    /// Lookups that haven't ended yet are continued; lookups ending with a value return the
+    /// statistics that get() would have returned for them.
+    pub fn multi_get(
+        &self,
+        ro: &ReadOptions,
+        now: u64,
+        lookups: &mut [KeyLookup],
+    ) -> Vec<GetStats> {
+        // Level-0 files may overlap each other; they are consulted from newest to oldest.
+        let mut level0 = self.files[0].clone();
+        level0.sort_by(|a, b| b.borrow().num.cmp(&a.borrow().num));
+        for f in &level0 {
+            let group: Vec<usize> = {
+                let f = f.borrow();
+                let (fsmallest, flargest) = (
+                    parse_internal_key(&f.smallest).2,
+                    parse_internal_key(&f.largest).2,
+                );
+                (0..lookups.len())
+                    .filter(|&i| {
+                        let ukey = lookups[i].key.user_key();
+                        lookups[i].result.is_none()
+                            && self.user_cmp.cmp(ukey, fsmallest) >= Ordering::Equal
+                            && self.user_cmp.cmp(ukey, flargest) <= Ordering::Equal
+                    })
+                    .collect()
+            };
+            self.multi_get_from_file(ro, f, 0, now, lookups, &group);
+        }
+
+        let icmp = InternalKeyCmp(self.user_cmp.clone());
+        for level in 1..NUM_LEVELS {
+            let files = &self.files[level];
+            // As the keys are sorted, the keys found in the same file are adjacent.
+            let mut group = vec![];
+            let mut group_file = None;
+            for i in 0..lookups.len() {
+                if lookups[i].result.is_some() {
+                    continue;
+                }
+                let ix = match find_file(&icmp, files, lookups[i].key.internal_key()) {
+                    Some(ix) => ix,
+                    None => continue,
+                };
+                let fsmallest = files[ix].borrow().smallest.clone();
+                if self
+                    .user_cmp
+                    .cmp(lookups[i].key.user_key(), parse_internal_key(&fsmallest).2)
+                    == Ordering::Less
+                {
+                    continue;
+                }
+                if group_file != Some(ix) {
+                    if let Some(g) = group_file {
+                        self.multi_get_from_file(ro, &files[g], level, now, lookups, &group);
+                    }
+                    group.clear();
+                    group_file = Some(ix);
+                }
+                group.push(i);
+            }
+            if let Some(g) = group_file {
+                self.multi_get_from_file(ro, &files[g], level, now, lookups, &group);
+            }
+        }
+
+        lookups
+            .iter_mut()
+            .filter(|l| match l.result {
+                Some(Ok(Some(_))) => true,
+                _ => false,
+            })
+            .map(|l| GetStats {
+                file: l.stats.file.take(),
+                level: l.stats.level,
+            })
+            .collect()
+    }
+
+    /// multi_get_from_file continues the lookups with the indices in `group` in table file `f`.
+    fn multi_get_from_file(
+        &self,
+        ro: &ReadOptions,
+        f: &FileMetaHandle,
+        level: usize,
+        now: u64,
+        lookups: &mut [KeyLookup],
+        group: &[usize],
+    ) {
+        if group.is_empty() {
+            return;
+        }
+        let num = f.borrow().num;
+        let tbl = match self.table_cache.borrow_mut().get_table(num) {
+            Ok(tbl) => tbl,
+            Err(e) => {
+                for &i in group {
+                    lookups[i].result = Some(Err(table_error(e.clone(), num)));
+                }
+                return;
+            }
+        };
+        for &i in group {
+            let l = &mut lookups[i];
+            if let Some((last, last_level)) = l.last_read.take() {
+                if last_level == level && l.stats.file.is_none() {
+                    l.stats.file = Some(last);
+                    l.stats.level = last_level;
+                }
+            }
+            l.last_read = Some((f.clone(), level));
+
+            let (_, snapshot, ukey) = parse_internal_key(l.key.internal_key());
+            let tombstone = max_covering_seq(
+                tbl.range_tombstones().iter(),
+                self.user_cmp.as_ref().as_ref(),
+                ukey,
+                snapshot,
+            );
+            if tombstone > l.max_tombstone {
+                l.max_tombstone = tombstone;
+            }
+            let r = self.get_from_table(
+                ro,
+                &tbl,
+                ukey,
+                snapshot,
+                l.max_tombstone,
+                now,
+                &mut l.operands,
+            );
+            match r {
+                Ok(Some(v)) => l.result = Some(Ok(v)),
+                Ok(None) => {}
+                Err(e) => l.result = Some(Err(table_error(e, num))),
+            }
+        }
+    }
+
+    /// range_tombstones returns the range tombstones from all tables in this Version.
+    pub fn range_tombstones(&self) -> Result<Vec<RangeTombstone>> {
+        let mut tombstones = vec![];
+        for level in 0..NUM_LEVELS {
+            for f in &self.files[level] {
+                let num = f.borrow().num;
+                let tbl = self.table_cache.borrow_mut().get_table(num)?;
+                tombstones.extend_from_slice(tbl.range_tombstones());
+            }
+        }
+        Ok(tombstones)
+    }
+
     /// get_overlapping returns the files overlapping key in each level.
     fn get_overlapping<'a>(&self, key: InternalKey<'a>) -> [Vec<FileMetaHandle>; NUM_LEVELS] {
         let mut levels: [Vec<FileMetaHandle>; NUM_LEVELS] = Default::default();
@@ -141,6 +434,33 @@ impl Version {
         levels
     }
 
+    /// approximate_offset returns the approximate offset of `key` within the database's table
+    /// files, i.e. the approximate number of bytes stored in tables before `key`.
+    pub fn approximate_offset<'a>(&self, key: InternalKey<'a>) -> usize {
+        let icmp = InternalKeyCmp(self.user_cmp.clone());
+        let mut offset = 0;
+        for level in 0..NUM_LEVELS {
+            for f in &self.files[level] {
+                let f = f.borrow();
+                if icmp.cmp(&f.largest, key) <= Ordering::Equal {
+                    offset += f.size;
+                } else if icmp.cmp(&f.smallest, key) == Ordering::Greater {
+                    // In higher levels, files are sorted; we don't need to search further.
+                    if level > 0 {
+                        break;
+                    }
+                } else {
+                    // Only hold the table cache's lock while retrieving the table.
+                    let tbl = self.table_cache.borrow_mut().get_table(f.num);
+                    if let Ok(tbl) = tbl {
+                        offset += tbl.approx_offset_of(key);
+                    }
+                }
+            }
+        }
+        offset
+    }
+
     /// level_summary returns a summary of the distribution of tables and bytes in this version.
     pub fn level_summary(&self) -> String {
         let mut acc = String::with_capacity(256);
@@ -165,6 +485,30 @@ impl Version {
         acc
     }
 
+    /// sstables_summary describes every table file of this version, one per line and grouped by
+    /// level, with number, size, and key range.
+    pub fn sstables_summary(&self) -> String {
+        let mut acc = String::with_capacity(1024);
+        for level in 0..NUM_LEVELS {
+            acc.push_str(&format!("--- level {} ---\n", level));
+            for f in &self.files[level] {
+                let f = f.borrow();
+                let (_, sseq, skey) = parse_internal_key(&f.smallest);
+                let (_, lseq, lkey) = parse_internal_key(&f.largest);
+                acc.push_str(&format!(
+                    " {}:{}[{:?} @ {} .. {:?} @ {}]\n",
+                    f.num,
+                    f.size,
+                    String::from_utf8_lossy(skey),
+                    sseq,
+                    String::from_utf8_lossy(lkey),
+                    lseq
+                ));
+            }
+        }
+        acc
+    }
+
     pub fn pick_memtable_output_level<'a, 'b>(&self, min: UserKey<'a>, max: UserKey<'b>) -> usize {
         let mut level = 0;
         if !self.overlap_in_level(0, min, max) {
@@ -195,6 +539,19 @@ impl Version {
         return level;
     }
 
+    /// pick_ingestion_level returns the level an ingested file with the range [min; max] is added
+    /// to. Its entries are newer than all existing ones, so it goes to the deepest level for which
+    /// neither that level nor any level above it contains overlapping files.
+    pub fn pick_ingestion_level<'a, 'b>(&self, min: UserKey<'a>, max: UserKey<'b>) -> usize {
+        let mut level = 0;
+        if !self.overlap_in_level(0, min, max) {
+            while level + 1 < NUM_LEVELS && !self.overlap_in_level(level + 1, min, max) {
+                level += 1;
+            }
+        }
+        level
+    }
+
     /// record_read_sample returns true if there is a new file to be compacted. It counts the
     /// number of files overlapping a key, and which level contains the first overlap.
     #[allow(unused_assignments)]
@@ -353,8 +710,9 @@ impl Version {
 
     /// new_concat_iter returns an iterator that iterates over the files in a level. Note that this
     /// only really makes sense for levels > 0.
-    fn new_concat_iter(&self, level: usize) -> VersionIter {
+    fn new_concat_iter(&self, ro: &ReadOptions, level: usize) -> VersionIter {
         new_version_iter(
+            ro,
             self.files[level].clone(),
             self.table_cache.clone(),
             self.user_cmp.clone(),
@@ -363,20 +721,23 @@ impl Version {
 
     /// new_iters returns a set of iterators that can be merged to yield all entries in this
     /// version.
-    pub fn new_iters(&self) -> Result<Vec<Box<dyn LdbIterator>>> {
+    pub fn new_iters(&self, ro: &ReadOptions) -> Result<Vec<Box<dyn LdbIterator>>> {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
+        let lower = ro.iterate_lower_bound.as_ref().map(|b| internal_bound(b));
+        let upper = ro.iterate_upper_bound.as_ref().map(|b| internal_bound(b));
         for f in &self.files[0] {
-            iters.push(Box::new(
-                self.table_cache
-                    .borrow_mut()
-                    .get_table(f.borrow().num)?
-                    .iter(),
-            ));
+            let mut iter = self
+                .table_cache
+                .borrow_mut()
+                .get_table(f.borrow().num)?
+                .iter_opt(ro);
+            iter.set_bounds(lower.clone(), upper.clone());
+            iters.push(Box::new(iter));
         }
 
         for l in 1..NUM_LEVELS {
             if !self.files[l].is_empty() {
-                iters.push(Box::new(self.new_concat_iter(l)));
+                iters.push(Box::new(self.new_concat_iter(ro, l)));
             }
         }
 
@@ -387,14 +748,18 @@ impl Version {
 /// new_version_iter returns an iterator over the entries in the specified ordered list of table
 /// files.
 pub fn new_version_iter(
+    ro: &ReadOptions,
     files: Vec<FileMetaHandle>,
     cache: Shared<TableCache>,
-    ucmp: Rc<Box<dyn Cmp>>,
+    ucmp: Arc<Box<dyn Cmp>>,
 ) -> VersionIter {
     VersionIter {
         files: files,
         cache: cache,
         cmp: InternalKeyCmp(ucmp),
+        ro: ro.clone(),
+        lower_bound: ro.iterate_lower_bound.as_ref().map(|b| internal_bound(b)),
+        upper_bound: ro.iterate_upper_bound.as_ref().map(|b| internal_bound(b)),
         current: None,
         current_ix: 0,
     }
@@ -410,11 +775,29 @@ pub struct VersionIter {
     files: Vec<FileMetaHandle>,
     cache: Shared<TableCache>,
     cmp: InternalKeyCmp,
+    ro: ReadOptions,
+    // The iterator's bounds as InternalKeys. Tables outside of them are not opened.
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
 
     current: Option<TableIterator>,
     current_ix: usize,
 }
 
+impl VersionIter {
+    /// table_iter returns an iterator over the table files[ix], restricted to the bounds.
+    fn table_iter(&self, ix: usize) -> Option<TableIterator> {
+        let num = self.files[ix].borrow().num;
+        if let Ok(tbl) = self.cache.borrow_mut().get_table(num) {
+            let mut iter = tbl.iter_opt(&self.ro);
+            iter.set_bounds(self.lower_bound.clone(), self.upper_bound.clone());
+            Some(iter)
+        } else {
+            None
+        }
+    }
+}
+
 impl LdbIterator for VersionIter {
     fn advance(&mut self) -> bool {
         assert!(!self.files.is_empty());
@@ -429,17 +812,27 @@ impl LdbIterator for VersionIter {
 
             // Load next table if current table is exhausted and we have more tables to go through.
             self.current_ix += 1;
+        } else if let Some(lower) = self.lower_bound.clone() {
+            self.seek(&lower);
+            return self.valid();
+        }
+
+        if let Some(ref upper) = self.upper_bound {
+            if self
+                .cmp
+                .cmp(&self.files[self.current_ix].borrow().smallest, upper)
+                != Ordering::Less
+            {
+                // The next table is entirely out of bounds.
+                self.reset();
+                return false;
+            }
         }
 
         // Initialize iterator or load next table.
-        if let Ok(tbl) = self
-            .cache
-            .borrow_mut()
-            .get_table(self.files[self.current_ix].borrow().num)
-        {
-            self.current = Some(tbl.iter());
-        } else {
-            return false;
+        match self.table_iter(self.current_ix) {
+            Some(iter) => self.current = Some(iter),
+            None => return false,
         }
         self.advance()
     }
@@ -451,14 +844,13 @@ impl LdbIterator for VersionIter {
         }
     }
     fn seek(&mut self, key: &[u8]) {
-        if let Some(ix) = find_file(&self.cmp, &self.files, key) {
-            if let Ok(tbl) = self
-                .cache
-                .borrow_mut()
-                .get_table(self.files[ix].borrow().num)
-            {
-                let mut iter = tbl.iter();
-                iter.seek(key);
+        let key = match self.lower_bound {
+            Some(ref lower) if self.cmp.cmp(key, lower) == Ordering::Less => lower.clone(),
+            _ => key.to_vec(),
+        };
+        if let Some(ix) = find_file(&self.cmp, &self.files, &key) {
+            if let Some(mut iter) = self.table_iter(ix) {
+                iter.seek(&key);
                 if iter.valid() {
                     self.current_ix = ix;
                     self.current = Some(iter);
@@ -476,21 +868,36 @@ impl LdbIterator for VersionIter {
         self.current.as_ref().map(|t| t.valid()).unwrap_or(false)
     }
     fn prev(&mut self) -> bool {
-        if let Some(ref mut t) = self.current {
-            if t.prev() {
-                return true;
-            } else if self.current_ix > 0 {
-                let f = &self.files[self.current_ix - 1];
-                // Find previous table, seek to last entry.
-                if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
-                    let mut iter = tbl.iter();
-                    iter.seek(&f.borrow().largest);
-                    // The saved largest key must be in the table.
-                    assert!(iter.valid());
-                    self.current_ix -= 1;
-                    *t = iter;
-                    return true;
+        let moved = match self.current {
+            Some(ref mut t) => t.prev(),
+            None => return false,
+        };
+        if moved {
+            return true;
+        }
+        // Find previous table containing entries, seek to last entry. (A table's largest key
+        // may belong to a range tombstone, so it's not necessarily an entry.)
+        while self.current_ix > 0 {
+            self.current_ix -= 1;
+            if let Some(ref lower) = self.lower_bound {
+                if self
+                    .cmp
+                    .cmp(&self.files[self.current_ix].borrow().largest, lower)
+                    == Ordering::Less
+                {
+                    // The previous tables are entirely out of bounds.
+                    break;
+                }
+            }
+            match self.table_iter(self.current_ix) {
+                Some(mut iter) => {
+                    iter.seek_to_last();
+                    if iter.valid() {
+                        self.current = Some(iter);
+                        return true;
+                    }
                 }
+                None => break,
             }
         }
         self.reset();
@@ -517,6 +924,12 @@ fn key_is_before_file<'a>(cmp: &InternalKeyCmp, key: UserKey<'a>, f: &FileMetaHa
     !key.is_empty() && cmp.cmp_inner(key, usmallest) == Ordering::Less
 }
 
+/// table_error adds the number of the table file an error occurred in to its message.
+fn table_error(mut e: Status, num: FileNum) -> Status {
+    e.err = format!("{} (table file {})", e.err, num);
+    e
+}
+
 /// find_file returns the index of the file in files that potentially contains the internal key
 /// key. files must not overlap and be ordered ascendingly. If no file can contain the key, None is
 /// returned.
@@ -711,7 +1124,7 @@ pub mod testutil {
         let t9 = write_table(&env, f9, 1, 9);
 
         let cache = TableCache::new("db", opts.clone(), 100);
-        let mut v = Version::new(share(cache), Rc::new(Box::new(DefaultCmp)));
+        let mut v = Version::new(share(cache), Arc::new(Box::new(DefaultCmp)));
         v.files[0] = vec![t1, t2];
         v.files[1] = vec![t3, t4, t5];
         v.files[2] = vec![t6, t7];
@@ -726,10 +1139,13 @@ mod tests {
     use super::*;
 
     use cmp::DefaultCmp;
-    use error::Result;
+    use error::{Result, StatusCode};
     use merging_iter::MergingIter;
     use options;
-    use test_util::{test_iterator_properties, LdbIteratorIter};
+    use test_util::{corrupt_file, test_iterator_properties, LdbIteratorIter};
+    use types::current_key_val;
+
+    use std::path::Path;
 
     #[test]
     fn test_version_concat_iter() {
@@ -737,16 +1153,50 @@ mod tests {
 
         let expected_entries = vec![0, 9, 8, 4];
         for l in 1..4 {
-            let mut iter = v.new_concat_iter(l);
+            let mut iter = v.new_concat_iter(&ReadOptions::default(), l);
             let iter = LdbIteratorIter::wrap(&mut iter);
             assert_eq!(iter.count(), expected_entries[l]);
         }
     }
 
+    #[test]
+    fn test_version_concat_iter_bounds() {
+        let v = make_version().0;
+        let ro = ReadOptions {
+            iterate_lower_bound: Some(b"cb".to_vec()),
+            iterate_upper_bound: Some(b"eab".to_vec()),
+            ..ReadOptions::default()
+        };
+        let mut iter = v.new_concat_iter(&ro, 1);
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter)
+            .map(|(k, _)| parse_internal_key(&k).2.to_vec())
+            .collect();
+        let expected: &[&[u8]] = &[b"cba", b"daa", b"dab", b"dba", b"eaa"];
+        assert_eq!(expected.to_vec(), keys);
+
+        // Moving backwards crosses tables, and stops at the lower bound.
+        iter.seek(LookupKey::new(b"daa", MAX_SEQUENCE_NUMBER).internal_key());
+        assert!(iter.prev());
+        assert_eq!(
+            b"cba",
+            parse_internal_key(&current_key_val(&iter).unwrap().0).2
+        );
+        assert!(!iter.prev());
+
+        // Seeks are limited to the bounds.
+        iter.seek(LookupKey::new(b"aaa", MAX_SEQUENCE_NUMBER).internal_key());
+        assert_eq!(
+            b"cba",
+            parse_internal_key(&current_key_val(&iter).unwrap().0).2
+        );
+        iter.seek(LookupKey::new(b"eab", MAX_SEQUENCE_NUMBER).internal_key());
+        assert!(!iter.valid());
+    }
+
     #[test]
     fn test_version_concat_iter_properties() {
         let v = make_version().0;
-        let iter = v.new_concat_iter(3);
+        let iter = v.new_concat_iter(&ReadOptions::default(), 3);
         test_iterator_properties(iter);
     }
 
@@ -759,16 +1209,16 @@ mod tests {
     #[test]
     fn test_version_all_iters() {
         let v = make_version().0;
-        let iters = v.new_iters().unwrap();
+        let iters = v.new_iters(&ReadOptions::default()).unwrap();
         let mut opt = options::for_test();
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(Rc::new(Box::new(DefaultCmp)))));
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(Arc::new(Box::new(DefaultCmp)))));
 
         let mut miter = MergingIter::new(opt.cmp.clone(), iters);
         assert_eq!(LdbIteratorIter::wrap(&mut miter).count(), 30);
 
         // Check that all elements are in order.
         let init = LookupKey::new("000".as_bytes(), MAX_SEQUENCE_NUMBER);
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
         LdbIteratorIter::wrap(&mut miter).fold(init.internal_key().to_vec(), |b, (k, _)| {
             assert!(cmp.cmp(&b, &k) == Ordering::Less);
             k
@@ -782,6 +1232,10 @@ mod tests {
                         bytes ([(3, 218), (4, 216), (5, 217)]); level 2: 2 files, 468 bytes ([(6, \
                         218), (7, 250)]); level 3: 2 files, 400 bytes ([(8, 200), (9, 200)]); ";
         assert_eq!(expected, &v.level_summary());
+
+        let summary = v.sstables_summary();
+        assert!(summary.starts_with("--- level 0 ---\n 1:232[\"aaa\" @ 22 .. \"aba\" @ 25]\n"));
+        assert!(summary.contains("--- level 3 ---\n 8:200[\"haa\" @ 3 .. \"hba\" @ 4]\n"));
     }
 
     #[test]
@@ -807,7 +1261,12 @@ mod tests {
         ];
 
         for ref c in cases {
-            match v.get(LookupKey::new(c.0, c.1).internal_key()) {
+            match v.get(
+                &ReadOptions::default(),
+                LookupKey::new(c.0, c.1).internal_key(),
+                0,
+                &mut vec![],
+            ) {
                 Ok(Some((val, _))) => assert_eq!(c.2.as_ref().unwrap().as_ref().unwrap(), &val),
                 Ok(None) => assert!(c.2.as_ref().unwrap().as_ref().is_none()),
                 Err(_) => assert!(c.2.is_err()),
@@ -815,6 +1274,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_version_latest_sequence() {
+        let v = make_version().0;
+        let cases: &[(&[u8], u64)] = &[
+            // Level 0, found in the newer of two overlapping tables.
+            (b"aaa", 22),
+            (b"aac", 26),
+            // Level 1, shadowing level 2.
+            (b"cab", 20),
+            (b"fba", 12),
+            (b"gca", 8),
+            (b"iba", 2),
+            // Within a table's range, but not in it.
+            (b"dac", 0),
+            (b"zzz", 0),
+        ];
+        for &(key, seq) in cases {
+            assert_eq!(seq, v.latest_sequence(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_version_multi_get() {
+        let v = make_version().0;
+        let ro = ReadOptions::default();
+        let keys: &[&[u8]] = &[
+            b"aaa", b"aab", b"aac", b"aba", b"bab", b"cab", b"daa", b"dac", b"eab", b"fab", b"gca",
+            b"gca", b"hba", b"zzz",
+        ];
+        for &seq in &[25, 100] {
+            let mut lookups: Vec<KeyLookup> = keys
+                .iter()
+                .map(|k| KeyLookup::new(LookupKey::new(k, seq)))
+                .collect();
+            let stats = v.multi_get(&ro, 0, &mut lookups);
+            let mut found = 0;
+            for (k, l) in keys.iter().zip(lookups.iter()) {
+                let expected = v
+                    .get(&ro, LookupKey::new(k, seq).internal_key(), 0, &mut vec![])
+                    .unwrap()
+                    .map(|(val, _)| val);
+                if expected.is_some() {
+                    found += 1;
+                }
+                let result = l.result.as_ref().map(|r| r.as_ref().unwrap().clone());
+                assert_eq!(expected, result.unwrap_or(None));
+            }
+            assert_eq!(found, stats.len());
+        }
+    }
+
+    #[test]
+    fn test_version_get_errors() {
+        let (v, opt) = make_version();
+        let ro = ReadOptions::default();
+
+        // A corrupted data block is reported, not treated as a missing entry.
+        corrupt_file(opt.env.as_ref().as_ref(), Path::new("db/000005.ldb"), 10);
+        let e = v
+            .get(
+                &ro,
+                LookupKey::new(b"eab", 100).internal_key(),
+                0,
+                &mut vec![],
+            )
+            .unwrap_err();
+        assert_eq!(StatusCode::Corruption, e.code);
+        assert!(e.err.contains("block at offset 0"));
+        assert!(e.err.contains("table file 5"));
+
+        // Without checksum verification, the lookup doesn't fail.
+        let ro_noverify = ReadOptions {
+            verify_checksums: false,
+            ..ReadOptions::default()
+        };
+        assert!(v
+            .get(
+                &ro_noverify,
+                LookupKey::new(b"eab", 100).internal_key(),
+                0,
+                &mut vec![]
+            )
+            .is_ok());
+
+        // So is a missing table file.
+        opt.env.delete(Path::new("db/000008.ldb")).unwrap();
+        let e = v
+            .get(
+                &ro,
+                LookupKey::new(b"haa", 100).internal_key(),
+                0,
+                &mut vec![],
+            )
+            .unwrap_err();
+        assert!(e.err.contains("table file 8"));
+
+        // Other tables are unaffected.
+        assert_eq!(
+            b"val1".to_vec(),
+            v.get(
+                &ro,
+                LookupKey::new(b"daa", 100).internal_key(),
+                0,
+                &mut vec![]
+            )
+            .unwrap()
+            .unwrap()
+            .0
+        );
+    }
+
     #[test]
     fn test_version_get_overlapping_basic() {
         let v = make_version().0;
@@ -871,6 +1441,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_version_pick_ingestion_level() {
+        let v = make_version().0;
+
+        for c in [
+            ("000".as_bytes(), "abc".as_bytes(), 0),
+            ("gab".as_bytes(), "hhh".as_bytes(), 1),
+            ("000".as_bytes(), "111".as_bytes(), NUM_LEVELS - 1),
+            ("hab".as_bytes(), "hzz".as_bytes(), 2),
+            ("jjj".as_bytes(), "kkk".as_bytes(), NUM_LEVELS - 1),
+        ]
+        .iter()
+        {
+            assert_eq!(c.2, v.pick_ingestion_level(c.0, c.1));
+        }
+    }
+
     #[test]
     fn test_version_overlapping_inputs() {
         let v = make_version().0;
@@ -934,7 +1521,7 @@ mod tests {
     fn test_version_key_ordering() {
         time_test!();
         let fmh = new_file(1, &[1, 0, 0], 0, &[2, 0, 0], 1);
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
 
         // Keys before file.
         for k in &[&[0][..], &[1], &[1, 0], &[0, 9, 9, 9]] {
@@ -973,7 +1560,7 @@ mod tests {
             new_file(2, &[2, 5, 0], 0, &[4, 0, 0], 1),
             new_file(3, &[3, 5, 1], 0, &[5, 0, 0], 1),
         ];
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
 
         assert!(some_file_overlaps_range(
             &cmp,
@@ -26,6 +26,12 @@ enum EditTag {
     DeletedFile = 6,
     NewFile = 7,
     PrevLogNumber = 9, // sic!
+
+    // Column family tags, as used by RocksDB.
+    ColumnFamily = 200,
+    ColumnFamilyAdd = 201,
+    ColumnFamilyDrop = 202,
+    MaxColumnFamily = 203,
 }
 
 fn tag_to_enum(t: u32) -> Option<EditTag> {
@@ -38,6 +44,10 @@ fn tag_to_enum(t: u32) -> Option<EditTag> {
         6 => Some(EditTag::DeletedFile),
         7 => Some(EditTag::NewFile),
         9 => Some(EditTag::PrevLogNumber),
+        200 => Some(EditTag::ColumnFamily),
+        201 => Some(EditTag::ColumnFamilyAdd),
+        202 => Some(EditTag::ColumnFamilyDrop),
+        203 => Some(EditTag::MaxColumnFamily),
         _ => None,
     }
 }
@@ -61,7 +71,16 @@ fn read_length_prefixed<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
 }
 
 /// Manages changes to the set of managed SSTables and logfiles.
+///
+/// An edit applies to a single column family: file changes, compaction pointers, the comparator
+/// and the log number refer to `column_family`, while the other fields are database-wide.
 pub struct VersionEdit {
+    pub column_family: u32,
+    // Set if this edit creates the column family, with the name of the new family.
+    pub add_column_family: Option<String>,
+    pub drop_column_family: bool,
+    pub max_column_family: Option<u32>,
+
     comparator: Option<String>,
     pub log_number: Option<FileNum>,
     pub prev_log_number: Option<FileNum>,
@@ -76,6 +95,10 @@ pub struct VersionEdit {
 impl VersionEdit {
     pub fn new() -> VersionEdit {
         VersionEdit {
+            column_family: 0,
+            add_column_family: None,
+            drop_column_family: false,
+            max_column_family: None,
             comparator: None,
             log_number: None,
             prev_log_number: None,
@@ -99,10 +122,32 @@ impl VersionEdit {
         self.deleted.insert((level, file_num));
     }
 
+    pub fn set_column_family(&mut self, cf: u32) {
+        self.column_family = cf
+    }
+
+    /// add_column_family marks this edit as creating its column family.
+    pub fn add_column_family(&mut self, name: &str) {
+        self.add_column_family = Some(name.to_string())
+    }
+
+    /// drop_column_family marks this edit as dropping its column family.
+    pub fn drop_column_family(&mut self) {
+        self.drop_column_family = true
+    }
+
+    pub fn set_max_column_family(&mut self, cf: u32) {
+        self.max_column_family = Some(cf)
+    }
+
     pub fn set_comparator_name(&mut self, name: &str) {
         self.comparator = Some(name.to_string())
     }
 
+    pub fn comparator_name(&self) -> Option<&str> {
+        self.comparator.as_ref().map(|c| c.as_str())
+    }
+
     pub fn set_log_num(&mut self, num: u64) {
         self.log_number = Some(num)
     }
@@ -130,6 +175,28 @@ impl VersionEdit {
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(256);
 
+        // Edits of the default column family don't carry the column family tags, and can be read
+        // by the original.
+        if self.column_family != 0 {
+            buf.write_varint(EditTag::ColumnFamily as u32).unwrap();
+            buf.write_varint(self.column_family).unwrap();
+        }
+
+        if let Some(ref name) = self.add_column_family {
+            buf.write_varint(EditTag::ColumnFamilyAdd as u32).unwrap();
+            buf.write_varint(name.len()).unwrap();
+            buf.write(name.as_bytes()).unwrap();
+        }
+
+        if self.drop_column_family {
+            buf.write_varint(EditTag::ColumnFamilyDrop as u32).unwrap();
+        }
+
+        if let Some(maxcf) = self.max_column_family {
+            buf.write_varint(EditTag::MaxColumnFamily as u32).unwrap();
+            buf.write_varint(maxcf).unwrap();
+        }
+
         if let Some(ref cmp) = self.comparator {
             // swallow errors, because it's a pure in-memory write
             buf.write_varint(EditTag::Comparator as u32).unwrap();
@@ -193,6 +260,35 @@ impl VersionEdit {
         while let Ok(tag) = reader.read_varint::<u32>() {
             if let Some(tag) = tag_to_enum(tag) {
                 match tag {
+                    EditTag::ColumnFamily => {
+                        if let Ok(cf) = reader.read_varint() {
+                            ve.column_family = cf;
+                        } else {
+                            return err(StatusCode::IOError, "Couldn't read column family");
+                        }
+                    }
+
+                    EditTag::ColumnFamilyAdd => {
+                        let buf = read_length_prefixed(&mut reader)?;
+                        if let Ok(name) = String::from_utf8(buf) {
+                            ve.add_column_family = Some(name);
+                        } else {
+                            return err(StatusCode::Corruption, "Bad column family name encoding");
+                        }
+                    }
+
+                    EditTag::ColumnFamilyDrop => {
+                        ve.drop_column_family = true;
+                    }
+
+                    EditTag::MaxColumnFamily => {
+                        if let Ok(cf) = reader.read_varint() {
+                            ve.max_column_family = Some(cf);
+                        } else {
+                            return err(StatusCode::IOError, "Couldn't read max column family");
+                        }
+                    }
+
                     EditTag::Comparator => {
                         let buf = read_length_prefixed(&mut reader)?;
                         if let Ok(c) = String::from_utf8(buf) {
@@ -374,5 +470,32 @@ mod tests {
         );
         assert_eq!(decoded.deleted.len(), 1);
         assert!(decoded.deleted.contains(&(1, 132)));
+        assert_eq!(decoded.column_family, 0);
+        assert_eq!(decoded.add_column_family, None);
+        assert!(!decoded.drop_column_family);
+        assert_eq!(decoded.max_column_family, None);
+    }
+
+    #[test]
+    fn test_version_edit_column_families() {
+        let mut ve = VersionEdit::new();
+        ve.set_column_family(3);
+        ve.add_column_family("blobs");
+        ve.set_max_column_family(3);
+        ve.set_log_num(12);
+
+        let decoded = VersionEdit::decode_from(&ve.encode()).unwrap();
+        assert_eq!(decoded.column_family, 3);
+        assert_eq!(decoded.add_column_family.as_ref().unwrap(), "blobs");
+        assert!(!decoded.drop_column_family);
+        assert_eq!(decoded.max_column_family, Some(3));
+        assert_eq!(decoded.log_number, Some(12));
+
+        let mut ve = VersionEdit::new();
+        ve.set_column_family(3);
+        ve.drop_column_family();
+        let decoded = VersionEdit::decode_from(&ve.encode()).unwrap();
+        assert_eq!(decoded.column_family, 3);
+        assert!(decoded.drop_column_family);
     }
 }
@@ -2,24 +2,28 @@
 use std::prelude::v1::*;
 
 use cmp::{Cmp, InternalKeyCmp};
-use env::Env;
+use column_family::{DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
+use env::{Env, WritableFile};
 use error::{err, Result, Status, StatusCode};
 use key_types::{parse_internal_key, InternalKey, UserKey};
 use log::{LogReader, LogWriter};
 use merging_iter::MergingIter;
-use options::Options;
+use options::{Options, ReadOptions};
+use range_tombstone::RangeTombstone;
 use table_cache::TableCache;
 use types::{
-    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, NUM_LEVELS,
+    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, WeakShared,
+    NUM_LEVELS,
 };
 use version::{new_version_iter, total_size, FileMetaHandle, Version};
 use version_edit::VersionEdit;
 
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Write;
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use std::os::unix::ffi::OsStrExt;
 
@@ -28,7 +32,7 @@ pub struct Compaction {
     max_file_size: usize,
     input_version: Option<Shared<Version>>,
     level_ixs: [usize; NUM_LEVELS],
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
     icmp: InternalKeyCmp,
 
     manual: bool,
@@ -84,6 +88,11 @@ impl Compaction {
         self.inputs[parent].len()
     }
 
+    /// column_family returns the id of the column family this compaction belongs to.
+    pub fn column_family(&self) -> u32 {
+        self.edit.column_family
+    }
+
     pub fn edit(&mut self) -> &mut VersionEdit {
         &mut self.edit
     }
@@ -125,6 +134,19 @@ impl Compaction {
         true
     }
 
+    /// is_base_level_for_range checks whether any key in [start; end] may exist in levels higher
+    /// than this compaction's level plus 2.
+    pub fn is_base_level_for_range<'a>(&self, start: UserKey<'a>, end: UserKey<'a>) -> bool {
+        assert!(self.input_version.is_some());
+        let inp_version = self.input_version.as_ref().unwrap().borrow();
+        for level in self.level + 2..NUM_LEVELS {
+            if inp_version.overlap_in_level(level, start, end) {
+                return false;
+            }
+        }
+        true
+    }
+
     pub fn is_trivial_move(&self) -> bool {
         if self.manual {
             return false;
@@ -167,129 +189,64 @@ impl Compaction {
     }
 }
 
-/// VersionSet managed the various versions that are live within a database. A single version
-/// contains references to the files on disk as they were at a certain point.
-pub struct VersionSet {
-    dbname: PathBuf,
+/// ColumnFamilyData contains the versions and compaction state of a single column family.
+#[derive(Clone)]
+struct ColumnFamilyData {
+    id: u32,
+    name: String,
     opt: Options,
     cmp: InternalKeyCmp,
     cache: Shared<TableCache>,
 
-    pub next_file_num: u64,
-    pub manifest_num: u64,
-    pub last_seq: u64,
-    pub log_num: u64,
-    pub prev_log_num: u64,
+    // Log files older than this one don't contain entries for this column family anymore.
+    log_num: FileNum,
 
     current: Option<Shared<Version>>,
     compaction_ptrs: [Vec<u8>; NUM_LEVELS],
-
-    descriptor_log: Option<LogWriter<Box<dyn Write>>>,
 }
 
-impl VersionSet {
-    // Note: opt.cmp should not contain an InternalKeyCmp at this point, but instead the default or
-    // user-supplied one.
-    pub fn new<P: AsRef<Path>>(db: P, opt: Options, cache: Shared<TableCache>) -> VersionSet {
+impl ColumnFamilyData {
+    fn new(id: u32, name: &str, opt: Options, cache: Shared<TableCache>) -> ColumnFamilyData {
         let v = share(Version::new(cache.clone(), opt.cmp.clone()));
-        VersionSet {
-            dbname: db.as_ref().to_owned(),
+        ColumnFamilyData {
+            id: id,
+            name: name.to_string(),
             cmp: InternalKeyCmp(opt.cmp.clone()),
             opt: opt,
             cache: cache,
-
-            next_file_num: 2,
-            manifest_num: 0,
-            last_seq: 0,
             log_num: 0,
-            prev_log_num: 0,
-
             current: Some(v),
             compaction_ptrs: Default::default(),
-            descriptor_log: None,
         }
     }
 
-    pub fn current_summary(&self) -> String {
-        self.current.as_ref().unwrap().borrow().level_summary()
-    }
-
-    /// live_files returns the files that are currently active.
-    pub fn live_files(&self) -> HashSet<FileNum> {
-        let mut files = HashSet::new();
-        if let Some(ref version) = self.current {
-            for level in 0..NUM_LEVELS {
-                for file in &version.borrow().files[level] {
-                    files.insert(file.borrow().num);
-                }
-            }
-        }
-        files
-    }
-
     /// current returns a reference to the current version. It panics if there is no current
     /// version.
-    pub fn current(&self) -> Shared<Version> {
+    fn current(&self) -> Shared<Version> {
         assert!(self.current.is_some());
         self.current.as_ref().unwrap().clone()
     }
 
-    pub fn add_version(&mut self, v: Version) {
-        self.current = Some(share(v));
-    }
-
-    pub fn new_file_number(&mut self) -> FileNum {
-        self.next_file_num += 1;
-        self.next_file_num - 1
-    }
-
-    pub fn reuse_file_number(&mut self, n: FileNum) {
-        if n == self.next_file_num - 1 {
-            self.next_file_num = n;
-        }
-    }
-
-    pub fn mark_file_number_used(&mut self, n: FileNum) {
-        if self.next_file_num <= n {
-            self.next_file_num = n + 1;
-        }
+    fn new_compaction(&self, level: usize) -> Compaction {
+        let mut c = Compaction::new(&self.opt, level, self.current.clone());
+        c.edit.set_column_family(self.id);
+        c
     }
 
     /// needs_compaction returns true if a compaction makes sense at this point.
-    pub fn needs_compaction(&self) -> bool {
+    fn needs_compaction(&self) -> bool {
         assert!(self.current.is_some());
         let v = self.current.as_ref().unwrap();
         let v = v.borrow();
         v.compaction_score.unwrap_or(0.0) >= 1.0 || v.file_to_compact.is_some()
     }
 
-    fn approximate_offset<'a>(&self, v: &Shared<Version>, key: InternalKey<'a>) -> usize {
-        let mut offset = 0;
-        for level in 0..NUM_LEVELS {
-            for f in &v.borrow().files[level] {
-                if self.opt.cmp.cmp(&f.borrow().largest, key) <= Ordering::Equal {
-                    offset += f.borrow().size;
-                } else if self.opt.cmp.cmp(&f.borrow().smallest, key) == Ordering::Greater {
-                    // In higher levels, files are sorted; we don't need to search further.
-                    if level > 0 {
-                        break;
-                    }
-                } else {
-                    if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
-                        offset += tbl.approx_offset_of(key);
-                    }
-                }
-            }
-        }
-        offset
-    }
-
-    pub fn pick_compaction(&mut self) -> Option<Compaction> {
+    fn pick_compaction(&mut self) -> Option<Compaction> {
         assert!(self.current.is_some());
         let current = self.current();
         let current = current.borrow();
 
-        let mut c = Compaction::new(&self.opt, 0, self.current.clone());
+        let mut c = self.new_compaction(0);
         let level;
 
         // Size compaction?
@@ -335,7 +292,7 @@ impl VersionSet {
         Some(c)
     }
 
-    pub fn compact_range<'a, 'b>(
+    fn compact_range<'a, 'b>(
         &mut self,
         level: usize,
         from: InternalKey<'a>,
@@ -363,7 +320,7 @@ impl VersionSet {
             }
         }
 
-        let mut c = Compaction::new(&self.opt, level, self.current.clone());
+        let mut c = self.new_compaction(level);
         c.inputs[0] = inputs;
         c.manual = true;
         self.setup_other_inputs(&mut c);
@@ -444,7 +401,8 @@ impl VersionSet {
 
         log!(
             self.opt.log,
-            "Compacting @{} {:?} .. {:?}",
+            "Compacting {}@{} {:?} .. {:?}",
+            self.name,
             level,
             smallest,
             largest
@@ -454,12 +412,15 @@ impl VersionSet {
         self.compaction_ptrs[level] = largest;
     }
 
-    /// write_snapshot writes the current version, with all files, to the manifest.
-    fn write_snapshot(&mut self) -> Result<usize> {
-        assert!(self.descriptor_log.is_some());
-
+    /// snapshot returns an edit recreating the current state of this column family.
+    fn snapshot(&self) -> VersionEdit {
         let mut edit = VersionEdit::new();
+        edit.set_column_family(self.id);
+        if self.id != DEFAULT_COLUMN_FAMILY_ID {
+            edit.add_column_family(&self.name);
+        }
         edit.set_comparator_name(self.opt.cmp.id());
+        edit.set_log_num(self.log_num);
 
         // Save compaction pointers.
         for level in 0..NUM_LEVELS {
@@ -476,37 +437,392 @@ impl VersionSet {
                 edit.add_file(level, f.borrow().clone());
             }
         }
-        self.descriptor_log
-            .as_mut()
+        edit
+    }
+}
+
+/// VersionSet managed the various versions that are live within a database. A single version
+/// contains references to the files on disk as they were at a certain point.
+///
+/// Every column family has its own sequence of versions; file numbers, sequence numbers and the
+/// manifest are shared by all families.
+pub struct VersionSet {
+    dbname: PathBuf,
+    opt: Options,
+
+    families: BTreeMap<u32, ColumnFamilyData>,
+    // Versions that have been replaced, but may still be used by iterators. Their files must not
+    // be deleted until they are released.
+    old_versions: Vec<WeakShared<Version>>,
+    // Options to use for column families when they are created or recovered, by name.
+    family_options: HashMap<String, Options>,
+    max_column_family: u32,
+
+    pub next_file_num: u64,
+    pub manifest_num: u64,
+    pub last_seq: u64,
+    // The smallest log number of all column families. Older log files can be deleted.
+    pub log_num: u64,
+    pub prev_log_num: u64,
+
+    descriptor_log: Option<LogWriter<Box<dyn WritableFile>>>,
+}
+
+impl VersionSet {
+    // Note: opt.cmp should not contain an InternalKeyCmp at this point, but instead the default or
+    // user-supplied one. `opt` and `cache` are used by the default column family.
+    pub fn new<P: AsRef<Path>>(db: P, opt: Options, cache: Shared<TableCache>) -> VersionSet {
+        let mut families = BTreeMap::new();
+        families.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            ColumnFamilyData::new(
+                DEFAULT_COLUMN_FAMILY_ID,
+                DEFAULT_COLUMN_FAMILY_NAME,
+                opt.clone(),
+                cache,
+            ),
+        );
+        VersionSet {
+            dbname: db.as_ref().to_owned(),
+            opt: opt,
+
+            families: families,
+            old_versions: vec![],
+            family_options: HashMap::new(),
+            max_column_family: DEFAULT_COLUMN_FAMILY_ID,
+
+            next_file_num: 2,
+            manifest_num: 0,
+            last_seq: 0,
+            log_num: 0,
+            prev_log_num: 0,
+
+            descriptor_log: None,
+        }
+    }
+
+    /// duplicate returns a copy of the version set that shares its versions and table caches,
+    /// but not its manifest writer. A secondary instance applies new manifest edits to a copy, so
+    /// that the result can be installed at the same time as the matching memtables.
+    pub fn duplicate(&self) -> VersionSet {
+        VersionSet {
+            dbname: self.dbname.clone(),
+            opt: self.opt.clone(),
+
+            families: self.families.clone(),
+            old_versions: self.old_versions.clone(),
+            family_options: self.family_options.clone(),
+            max_column_family: self.max_column_family,
+
+            next_file_num: self.next_file_num,
+            manifest_num: self.manifest_num,
+            last_seq: self.last_seq,
+            log_num: self.log_num,
+            prev_log_num: self.prev_log_num,
+
+            descriptor_log: None,
+        }
+    }
+
+    fn default_family(&self) -> &ColumnFamilyData {
+        &self.families[&DEFAULT_COLUMN_FAMILY_ID]
+    }
+
+    pub fn current_summary(&self) -> String {
+        self.default_family()
+            .current
+            .as_ref()
             .unwrap()
-            .add_record(&edit.encode())
+            .borrow()
+            .level_summary()
+    }
+
+    /// live_files returns the files that are currently active in any column family.
+    pub fn live_files(&self) -> HashSet<FileNum> {
+        let mut versions = self.current_versions();
+        versions.extend(self.old_versions.iter().filter_map(|v| v.upgrade()));
+        files_of(versions)
+    }
+
+    /// current_files returns the files of the current versions of all column families, leaving
+    /// out those only used by replaced versions.
+    pub fn current_files(&self) -> HashSet<FileNum> {
+        files_of(self.current_versions())
+    }
+
+    fn current_versions(&self) -> Vec<Shared<Version>> {
+        self.families
+            .values()
+            .filter_map(|f| f.current.clone())
+            .collect()
+    }
+
+    /// max_level0_files returns the largest number of level-0 files in any column family.
+    pub fn max_level0_files(&self) -> usize {
+        self.families
+            .values()
+            .map(|f| f.current().borrow().num_level_files(0))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// current returns a reference to the current version of the default column family. It
+    /// panics if there is no current version.
+    pub fn current(&self) -> Shared<Version> {
+        self.default_family().current()
+    }
+
+    /// current_cf returns the current version of a column family, or None if the family doesn't
+    /// exist.
+    pub fn current_cf(&self, cf: u32) -> Option<Shared<Version>> {
+        self.families.get(&cf).map(|f| f.current())
+    }
+
+    /// add_version installs v as the current version of the default column family.
+    pub fn add_version(&mut self, v: Version) {
+        self.install_version(DEFAULT_COLUMN_FAMILY_ID, Some(v));
+    }
+
+    /// install_version replaces the current version of a column family; a version of None drops
+    /// the family. The replaced version is remembered for as long as it is in use.
+    fn install_version(&mut self, cf: u32, v: Option<Version>) {
+        let old = match v {
+            Some(v) => mem::replace(
+                &mut self.families.get_mut(&cf).unwrap().current,
+                Some(share(v)),
+            ),
+            None => self.families.remove(&cf).and_then(|f| f.current),
+        };
+        self.old_versions.retain(|v| v.upgrade().is_some());
+        if let Some(old) = old {
+            self.old_versions.push(old.downgrade());
+        }
+    }
+
+    /// column_families returns the ids and names of all column families.
+    pub fn column_families(&self) -> Vec<(u32, String)> {
+        self.families
+            .values()
+            .map(|f| (f.id, f.name.clone()))
+            .collect()
+    }
+
+    /// column_family_id returns the id of the column family with the given name.
+    pub fn column_family_id(&self, name: &str) -> Option<u32> {
+        self.families
+            .values()
+            .find(|f| f.name == name)
+            .map(|f| f.id)
+    }
+
+    pub fn column_family_options(&self, cf: u32) -> Option<Options> {
+        self.families.get(&cf).map(|f| f.opt.clone())
+    }
+
+    pub fn column_family_log_num(&self, cf: u32) -> Option<FileNum> {
+        self.families.get(&cf).map(|f| f.log_num)
+    }
+
+    pub fn table_cache(&self, cf: u32) -> Option<Shared<TableCache>> {
+        self.families.get(&cf).map(|f| f.cache.clone())
+    }
+
+    /// evict_table removes a table from the table caches of all column families.
+    pub fn evict_table(&self, num: FileNum) {
+        for family in self.families.values() {
+            let _ = family.cache.borrow_mut().evict(num);
+        }
+    }
+
+    /// set_column_family_options sets the options used by the column family `name` once it is
+    /// created or recovered. Families without options use the database's options.
+    /// Database-wide settings (like env, log and statistics) are always taken from the database's
+    /// options.
+    pub fn set_column_family_options(&mut self, name: &str, opt: Options) {
+        self.family_options.insert(name.to_string(), opt);
+    }
+
+    fn new_column_family(&self, id: u32, name: &str) -> ColumnFamilyData {
+        let mut opt = self
+            .family_options
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| self.opt.clone());
+        opt.env = self.opt.env.clone();
+        opt.log = self.opt.log.clone();
+        opt.statistics = self.opt.statistics.clone();
+        let cache = share(TableCache::new(
+            &self.dbname,
+            opt.clone(),
+            opt.max_open_files - 10,
+        ));
+        ColumnFamilyData::new(id, name, opt, cache)
+    }
+
+    /// create_column_family adds a new, empty column family using `opt` and records it in the
+    /// manifest. Its entries are written to log files starting with `log_num`. It returns the new
+    /// family's id.
+    pub fn create_column_family(
+        &mut self,
+        name: &str,
+        opt: Options,
+        log_num: FileNum,
+    ) -> Result<u32> {
+        if self.column_family_id(name).is_some() {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("column family {} already exists", name),
+            );
+        }
+        self.set_column_family_options(name, opt);
+        let id = self.max_column_family + 1;
+        let mut edit = VersionEdit::new();
+        edit.set_column_family(id);
+        edit.add_column_family(name);
+        edit.set_log_num(log_num);
+        self.log_and_apply(edit)?;
+        Ok(id)
+    }
+
+    /// drop_column_family removes a column family and records this in the manifest. Its files
+    /// are not live anymore afterwards.
+    pub fn drop_column_family(&mut self, cf: u32) -> Result<()> {
+        if cf == DEFAULT_COLUMN_FAMILY_ID {
+            return err(
+                StatusCode::InvalidArgument,
+                "the default column family can't be dropped",
+            );
+        }
+        let mut edit = VersionEdit::new();
+        edit.set_column_family(cf);
+        edit.drop_column_family();
+        self.log_and_apply(edit)
+    }
+
+    pub fn new_file_number(&mut self) -> FileNum {
+        self.next_file_num += 1;
+        self.next_file_num - 1
+    }
+
+    pub fn reuse_file_number(&mut self, n: FileNum) {
+        if n == self.next_file_num - 1 {
+            self.next_file_num = n;
+        }
     }
 
-    /// log_and_apply merges the given edit with the current state and generates a new version. It
-    /// writes the VersionEdit to the manifest.
+    pub fn mark_file_number_used(&mut self, n: FileNum) {
+        if self.next_file_num <= n {
+            self.next_file_num = n + 1;
+        }
+    }
+
+    /// needs_compaction returns true if a compaction of any column family makes sense at this
+    /// point.
+    pub fn needs_compaction(&self) -> bool {
+        self.families.values().any(|f| f.needs_compaction())
+    }
+
+    /// pick_compaction returns a compaction for the first column family needing one.
+    pub fn pick_compaction(&mut self) -> Option<Compaction> {
+        self.families
+            .values_mut()
+            .filter(|f| f.needs_compaction())
+            .filter_map(|f| f.pick_compaction())
+            .next()
+    }
+
+    /// compact_range returns a compaction of the files in `level` of column family `cf`
+    /// overlapping [from; to], if there are any.
+    pub fn compact_range<'a, 'b>(
+        &mut self,
+        cf: u32,
+        level: usize,
+        from: InternalKey<'a>,
+        to: InternalKey<'b>,
+    ) -> Option<Compaction> {
+        self.families
+            .get_mut(&cf)
+            .and_then(|f| f.compact_range(level, from, to))
+    }
+
+    /// write_snapshot writes the current versions of all column families, with all files, to
+    /// the manifest.
+    fn write_snapshot(&mut self) -> Result<usize> {
+        assert!(self.descriptor_log.is_some());
+
+        let mut written = 0;
+        for family in self.families.values() {
+            let mut edit = family.snapshot();
+            if family.id == DEFAULT_COLUMN_FAMILY_ID {
+                edit.set_max_column_family(self.max_column_family);
+            }
+            written += self
+                .descriptor_log
+                .as_mut()
+                .unwrap()
+                .add_record(&edit.encode())?;
+        }
+        Ok(written)
+    }
+
+    /// log_and_apply merges the given edit with the current state of its column family and
+    /// generates a new version. It writes the VersionEdit to the manifest.
     pub fn log_and_apply(&mut self, mut edit: VersionEdit) -> Result<()> {
-        assert!(self.current.is_some());
+        let cf = edit.column_family;
+        // A new column family is only added once the edit creating it has been written.
+        let mut new_family = None;
+        if let Some(ref name) = edit.add_column_family {
+            assert!(!self.families.contains_key(&cf));
+            new_family = Some(self.new_column_family(cf, name));
+            if cf > self.max_column_family {
+                self.max_column_family = cf;
+            }
+        }
+        if let Some(ref f) = new_family {
+            edit.set_comparator_name(f.opt.cmp.id());
+            edit.set_max_column_family(self.max_column_family);
+        }
 
-        if edit.log_number.is_none() {
-            edit.set_log_num(self.log_num);
-        } else {
-            assert!(edit.log_number.unwrap() >= self.log_num);
-            assert!(edit.log_number.unwrap() < self.next_file_num);
+        let mut v = None;
+        {
+            let family = match new_family {
+                Some(ref mut f) => f,
+                None => match self.families.get_mut(&cf) {
+                    Some(f) => f,
+                    None => {
+                        return err(
+                            StatusCode::InvalidArgument,
+                            &format!("unknown column family {}", cf),
+                        )
+                    }
+                },
+            };
+
+            if edit.log_number.is_none() {
+                edit.set_log_num(family.log_num);
+            } else {
+                assert!(edit.log_number.unwrap() >= family.log_num);
+                assert!(edit.log_number.unwrap() < self.next_file_num);
+            }
+
+            if !edit.drop_column_family {
+                let mut version = Version::new(family.cache.clone(), family.opt.cmp.clone());
+                let mut builder = Builder::new();
+                builder.apply(&edit, &mut family.compaction_ptrs);
+                builder.save_to(&family.cmp, family.current.as_ref().unwrap(), &mut version);
+                v = Some(version);
+            }
         }
+        if let Some(ref mut v) = v {
+            self.finalize(v);
+        }
+
         if edit.prev_log_number.is_none() {
             edit.set_prev_log_num(self.prev_log_num);
         }
         edit.set_next_file(self.next_file_num);
         edit.set_last_seq(self.last_seq);
 
-        let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
-        {
-            let mut builder = Builder::new();
-            builder.apply(&edit, &mut self.compaction_ptrs);
-            builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
-        }
-        self.finalize(&mut v);
-
         if self.descriptor_log.is_none() {
             let descname = manifest_file_name(&self.dbname, self.manifest_num);
             edit.set_next_file(self.next_file_num);
@@ -519,18 +835,30 @@ impl VersionSet {
         let encoded = edit.encode();
         if let Some(ref mut lw) = self.descriptor_log {
             lw.add_record(&encoded)?;
-            lw.flush()?;
+            lw.sync()?;
         }
         set_current_file(&self.opt.env, &self.dbname, self.manifest_num)?;
 
-        self.add_version(v);
-        // log_number was set above.
-        self.log_num = edit.log_number.unwrap();
+        if let Some(f) = new_family {
+            self.families.insert(cf, f);
+        }
+        if v.is_some() {
+            // log_number was set above.
+            self.families.get_mut(&cf).unwrap().log_num = edit.log_number.unwrap();
+        }
+        self.install_version(cf, v);
+        self.update_log_num();
 
         // TODO: Roll back written files if something went wrong.
         Ok(())
     }
 
+    /// update_log_num sets self.log_num to the oldest log file still needed by any column
+    /// family.
+    fn update_log_num(&mut self) {
+        self.log_num = self.families.values().map(|f| f.log_num).min().unwrap_or(0);
+    }
+
     fn finalize(&self, v: &mut Version) {
         let mut best_lvl = None;
         let mut best_score = None;
@@ -563,15 +891,13 @@ impl VersionSet {
     /// recover recovers the state of a LevelDB instance from the files on disk. If recover()
     /// returns true, the a manifest needs to be written eventually (using log_and_apply()).
     pub fn recover(&mut self) -> Result<bool> {
-        assert!(self.current.is_some());
-
         let mut current = read_current_file(&self.opt.env, &self.dbname)?;
         let len = current.len();
         current.truncate(len - 1);
         let current = Path::new(&current);
 
         let descfilename = self.dbname.join(current);
-        let mut builder = Builder::new();
+        let mut edits = vec![];
         {
             let mut descfile = self
                 .opt
@@ -583,83 +909,185 @@ impl VersionSet {
                 true,
             );
 
-            let mut log_number = None;
-            let mut prev_log_number = None;
-            let mut next_file_number = None;
-            let mut last_seq = None;
-
             let mut buf = Vec::new();
             while let Ok(size) = logreader.read(&mut buf) {
                 if size == 0 {
                     break;
                 }
-                let edit = VersionEdit::decode_from(&buf)?;
-                builder.apply(&edit, &mut self.compaction_ptrs);
-                if let Some(ln) = edit.log_number {
-                    log_number = Some(ln);
-                }
-                if let Some(nfn) = edit.next_file_number {
-                    next_file_number = Some(nfn);
-                }
-                if let Some(ls) = edit.last_seq {
-                    last_seq = Some(ls);
-                }
-                if let Some(pln) = edit.prev_log_number {
-                    prev_log_number = Some(pln);
+                edits.push(VersionEdit::decode_from(&buf)?);
+            }
+        }
+        self.apply_manifest_edits(&edits, true)?;
+
+        self.manifest_num = self.next_file_num - 1;
+        log!(
+            self.opt.log,
+            "Recovered manifest with next_file={} manifest_num={} log_num={} prev_log_num={} \
+             last_seq={} column_families={}",
+            self.next_file_num,
+            self.manifest_num,
+            self.log_num,
+            self.prev_log_num,
+            self.last_seq,
+            self.families.len()
+        );
+
+        // A new manifest needs to be written only if we don't reuse the existing one.
+        Ok(!self.reuse_manifest(&descfilename, &current))
+    }
+
+    /// apply_manifest_edits applies edits read from a manifest, and installs new versions of the
+    /// column families. If `complete` is true, the edits make up a whole manifest, which has to
+    /// contain the log number, next file number and last sequence number. Otherwise, they
+    /// continue the edits applied before, like when a secondary instance follows a manifest.
+    pub fn apply_manifest_edits(&mut self, edits: &[VersionEdit], complete: bool) -> Result<()> {
+        if edits.is_empty() && !complete {
+            return Ok(());
+        }
+        let mut builders = BTreeMap::new();
+        for &cf in self.families.keys() {
+            builders.insert(cf, Builder::new());
+        }
+
+        let mut log_number = None;
+        let mut prev_log_number = None;
+        let mut next_file_number = None;
+        let mut last_seq = None;
+
+        for edit in edits {
+            self.apply_recovered_edit(edit, &mut builders)?;
+            if let Some(ln) = edit.log_number {
+                log_number = Some(ln);
+            }
+            if let Some(nfn) = edit.next_file_number {
+                next_file_number = Some(nfn);
+            }
+            if let Some(ls) = edit.last_seq {
+                last_seq = Some(ls);
+            }
+            if let Some(pln) = edit.prev_log_number {
+                prev_log_number = Some(pln);
+            }
+            if let Some(maxcf) = edit.max_column_family {
+                if maxcf > self.max_column_family {
+                    self.max_column_family = maxcf;
                 }
             }
+        }
 
-            if let Some(ln) = log_number {
-                self.log_num = ln;
+        if log_number.is_some() {
+            self.update_log_num();
+            let log_nums: Vec<FileNum> = self.families.values().map(|f| f.log_num).collect();
+            for ln in log_nums {
                 self.mark_file_number_used(ln);
-            } else {
+            }
+        } else if complete {
+            return err(
+                StatusCode::Corruption,
+                "no meta-lognumber entry in descriptor",
+            );
+        }
+        if let Some(nfn) = next_file_number {
+            self.next_file_num = nfn + 1;
+        } else if complete {
+            return err(
+                StatusCode::Corruption,
+                "no meta-next-file entry in descriptor",
+            );
+        }
+        if let Some(ls) = last_seq {
+            if ls > self.last_seq {
+                self.last_seq = ls;
+            }
+        } else if complete {
+            return err(
+                StatusCode::Corruption,
+                "no last-sequence entry in descriptor",
+            );
+        }
+        if let Some(pln) = prev_log_number {
+            self.prev_log_num = pln;
+            self.mark_file_number_used(pln);
+        } else if complete {
+            self.prev_log_num = 0;
+        }
+
+        for (cf, mut builder) in builders {
+            let mut v = {
+                let family = &self.families[&cf];
+                let mut v = Version::new(family.cache.clone(), family.opt.cmp.clone());
+                builder.save_to(&family.cmp, family.current.as_ref().unwrap(), &mut v);
+                v
+            };
+            self.finalize(&mut v);
+            self.install_version(cf, Some(v));
+        }
+        Ok(())
+    }
+
+    /// apply_recovered_edit applies an edit read from the manifest to the column family it
+    /// refers to, creating or dropping the family if requested.
+    fn apply_recovered_edit(
+        &mut self,
+        edit: &VersionEdit,
+        builders: &mut BTreeMap<u32, Builder>,
+    ) -> Result<()> {
+        let cf = edit.column_family;
+        if let Some(ref name) = edit.add_column_family {
+            if self.families.contains_key(&cf) {
                 return err(
                     StatusCode::Corruption,
-                    "no meta-lognumber entry in descriptor",
+                    &format!("column family {} created twice", cf),
                 );
             }
-            if let Some(nfn) = next_file_number {
-                self.next_file_num = nfn + 1;
-            } else {
+            let family = self.new_column_family(cf, name);
+            self.families.insert(cf, family);
+            builders.insert(cf, Builder::new());
+            if cf > self.max_column_family {
+                self.max_column_family = cf;
+            }
+        }
+
+        if edit.drop_column_family {
+            builders.remove(&cf);
+            if self.families.remove(&cf).is_none() {
                 return err(
                     StatusCode::Corruption,
-                    "no meta-next-file entry in descriptor",
+                    &format!("dropped unknown column family {}", cf),
                 );
             }
-            if let Some(ls) = last_seq {
-                self.last_seq = ls;
-            } else {
+            return Ok(());
+        }
+        let family = match self.families.get_mut(&cf) {
+            Some(f) => f,
+            None => {
                 return err(
                     StatusCode::Corruption,
-                    "no last-sequence entry in descriptor",
-                );
+                    &format!("edit for unknown column family {}", cf),
+                )
             }
-            if let Some(pln) = prev_log_number {
-                self.prev_log_num = pln;
-                self.mark_file_number_used(prev_log_number.unwrap());
-            } else {
-                self.prev_log_num = 0;
+        };
+        if let Some(name) = edit.comparator_name() {
+            if name != family.opt.cmp.id() {
+                return err(
+                    StatusCode::InvalidArgument,
+                    &format!(
+                        "comparator {} of column family {} doesn't match {}",
+                        family.opt.cmp.id(),
+                        family.name,
+                        name
+                    ),
+                );
             }
         }
-
-        let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
-        builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
-        self.finalize(&mut v);
-        self.add_version(v);
-        self.manifest_num = self.next_file_num - 1;
-        log!(
-            self.opt.log,
-            "Recovered manifest with next_file={} manifest_num={} log_num={} prev_log_num={} \
-             last_seq={}",
-            self.next_file_num,
-            self.manifest_num,
-            self.log_num,
-            self.prev_log_num,
-            self.last_seq
-        );
-
-        // A new manifest needs to be written only if we don't reuse the existing one.
-        Ok(!self.reuse_manifest(&descfilename, &current))
+        if let Some(ln) = edit.log_number {
+            family.log_num = ln;
+        }
+        builders
+            .get_mut(&cf)
+            .unwrap()
+            .apply(edit, &mut family.compaction_ptrs);
+        Ok(())
     }
 
     /// reuse_manifest checks whether the current manifest can be reused.
@@ -707,8 +1135,28 @@ impl VersionSet {
         false
     }
 
+    /// compaction_range_tombstones returns the range tombstones contained in the input files of a
+    /// compaction.
+    pub fn compaction_range_tombstones(&self, c: &Compaction) -> Result<Vec<RangeTombstone>> {
+        let family = &self.families[&c.column_family()];
+        let mut tombstones = vec![];
+        for i in 0..2 {
+            for f in &c.inputs[i] {
+                let tbl = family.cache.borrow_mut().get_table(f.borrow().num)?;
+                tombstones.extend_from_slice(tbl.range_tombstones());
+            }
+        }
+        Ok(tombstones)
+    }
+
     /// make_input_iterator returns an iterator over the inputs of a compaction.
     pub fn make_input_iterator(&self, c: &Compaction) -> Box<dyn LdbIterator> {
+        let family = &self.families[&c.column_family()];
+        // Compaction inputs are only read once, so don't pollute the block cache with them.
+        let ro = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
         let cap = if c.level == 0 { c.num_inputs(0) + 1 } else { 2 };
         let mut iters: Vec<Box<dyn LdbIterator>> = Vec::with_capacity(cap);
         for i in 0..2 {
@@ -719,9 +1167,9 @@ impl VersionSet {
                 // Add individual iterators for L0 tables.
                 for fi in 0..c.num_inputs(i) {
                     let f = &c.inputs[i][fi];
-                    let s = self.cache.borrow_mut().get_table(f.borrow().num);
+                    let s = family.cache.borrow_mut().get_table(f.borrow().num);
                     if let Ok(tbl) = s {
-                        iters.push(Box::new(tbl.iter()));
+                        iters.push(Box::new(tbl.iter_opt(&ro)));
                     } else {
                         log!(
                             self.opt.log,
@@ -734,14 +1182,15 @@ impl VersionSet {
             } else {
                 // Create concatenating iterator higher levels.
                 iters.push(Box::new(new_version_iter(
+                    &ro,
                     c.inputs[i].clone(),
-                    self.cache.clone(),
-                    self.opt.cmp.clone(),
+                    family.cache.clone(),
+                    family.opt.cmp.clone(),
                 )));
             }
         }
         assert!(iters.len() <= cap);
-        let cmp: Rc<Box<dyn Cmp>> = Rc::new(Box::new(self.cmp.clone()));
+        let cmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(family.cmp.clone()));
         Box::new(MergingIter::new(cmp, iters))
     }
 }
@@ -868,6 +1317,19 @@ fn current_file_name<P: AsRef<Path>>(dbname: P) -> PathBuf {
     dbname.as_ref().join("CURRENT").to_owned()
 }
 
+/// files_of returns the numbers of all files in `versions`.
+fn files_of(versions: Vec<Shared<Version>>) -> HashSet<FileNum> {
+    let mut files = HashSet::new();
+    for version in versions {
+        for level in 0..NUM_LEVELS {
+            for file in &version.borrow().files[level] {
+                files.insert(file.borrow().num);
+            }
+        }
+    }
+    files
+}
+
 pub fn read_current_file(env: &Box<dyn Env>, dbname: &Path) -> Result<String> {
     let mut current = String::new();
     let mut f = env.open_sequential_file(Path::new(&current_file_name(dbname)))?;
@@ -893,6 +1355,7 @@ pub fn set_current_file<P: AsRef<Path>>(
         let mut f = env.open_writable_file(Path::new(&tempfile))?;
         f.write(manifest_base.as_os_str().as_bytes())?;
         f.write("\n".as_bytes())?;
+        f.sync()?;
     }
     let currentfile = current_file_name(dbname);
     if let Err(e) = env.rename(Path::new(&tempfile), Path::new(&currentfile)) {
@@ -900,7 +1363,8 @@ pub fn set_current_file<P: AsRef<Path>>(
         let _ = env.delete(Path::new(&tempfile));
         return Err(Status::from(e));
     }
-    Ok(())
+    // Make the new CURRENT file (and the manifest it points to) durable.
+    env.sync_dir(dbname)
 }
 
 /// sort_files_by_smallest sorts the list of files by the smallest keys of the files.
@@ -1113,9 +1577,9 @@ mod tests {
             assert_eq!(10, vs.log_num);
             assert_eq!(21, vs.next_file_num);
             assert_eq!(30, vs.last_seq);
-            assert_eq!(0, vs.current.as_ref().unwrap().borrow().files[0].len());
-            assert_eq!(0, vs.current.as_ref().unwrap().borrow().files[1].len());
-            assert_eq!(35, vs.write_snapshot().unwrap());
+            assert_eq!(0, vs.current().borrow().files[0].len());
+            assert_eq!(0, vs.current().borrow().files[1].len());
+            assert_eq!(40, vs.write_snapshot().unwrap());
         }
 
         // Simulate compaction by adding a file.
@@ -1145,12 +1609,110 @@ mod tests {
 
             // The previous "compaction" should have added one file to the first level in the
             // current version.
-            assert_eq!(0, vs.current.as_ref().unwrap().borrow().files[0].len());
-            assert_eq!(1, vs.current.as_ref().unwrap().borrow().files[1].len());
-            assert_eq!(63, vs.write_snapshot().unwrap());
+            assert_eq!(0, vs.current().borrow().files[0].len());
+            assert_eq!(1, vs.current().borrow().files[1].len());
+            assert_eq!(68, vs.write_snapshot().unwrap());
         }
     }
 
+    #[test]
+    fn test_version_set_column_families() {
+        let (_, opt) = make_version();
+        let cache = share(TableCache::new("db", opt.clone(), 100));
+        let mut vs = VersionSet::new("db", opt.clone(), cache.clone());
+        {
+            let mut ve = VersionEdit::new();
+            ve.set_comparator_name("leveldb.BytewiseComparator");
+            ve.set_log_num(10);
+            ve.set_next_file(20);
+            ve.set_last_seq(30);
+
+            let manifest = manifest_file_name("db", 19);
+            let mffile = opt.env.open_writable_file(Path::new(&manifest)).unwrap();
+            let mut lw = LogWriter::new(mffile);
+            lw.add_record(&ve.encode()).unwrap();
+            lw.flush().unwrap();
+            set_current_file(&opt.env.as_ref(), "db", 19).unwrap();
+        }
+        vs.recover().unwrap();
+
+        assert_eq!(
+            1,
+            vs.create_column_family("blobs", opt.clone(), 12).unwrap()
+        );
+        assert_eq!(
+            StatusCode::AlreadyExists,
+            vs.create_column_family("blobs", opt.clone(), 12)
+                .unwrap_err()
+                .code
+        );
+        assert_eq!(Some(1), vs.column_family_id("blobs"));
+        assert_eq!(Some(12), vs.column_family_log_num(1));
+        // The default family still needs log 10.
+        assert_eq!(10, vs.log_num);
+
+        let mut fmd = FileMetaData::default();
+        fmd.num = 21;
+        fmd.size = 123;
+        fmd.smallest = LookupKey::new(b"abc", 777).internal_key().to_vec();
+        fmd.largest = LookupKey::new(b"def", 700).internal_key().to_vec();
+        let mut ve = VersionEdit::new();
+        ve.set_column_family(1);
+        ve.add_file(1, fmd);
+        vs.log_and_apply(ve).unwrap();
+        assert_eq!(1, vs.current_cf(1).unwrap().borrow().files[1].len());
+        assert_eq!(0, vs.current().borrow().files[1].len());
+        assert!(vs.live_files().contains(&21));
+
+        // Level-0 files are counted per family.
+        let mut fmd = FileMetaData::default();
+        fmd.num = 22;
+        fmd.size = 123;
+        fmd.smallest = LookupKey::new(b"abc", 780).internal_key().to_vec();
+        fmd.largest = LookupKey::new(b"def", 779).internal_key().to_vec();
+        let mut ve = VersionEdit::new();
+        ve.set_column_family(1);
+        ve.add_file(0, fmd);
+        vs.log_and_apply(ve).unwrap();
+        assert_eq!(0, vs.current().borrow().num_level_files(0));
+        assert_eq!(1, vs.max_level0_files());
+
+        assert_eq!(
+            2,
+            vs.create_column_family("index", opt.clone(), 12).unwrap()
+        );
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            vs.drop_column_family(0).unwrap_err().code
+        );
+        vs.drop_column_family(1).unwrap();
+        assert!(vs.current_cf(1).is_none());
+        assert!(!vs.live_files().contains(&21));
+        // Ids of dropped families are not reused.
+        assert_eq!(
+            3,
+            vs.create_column_family("blobs", opt.clone(), 12).unwrap()
+        );
+
+        // Recover the families from the manifest.
+        let mut vs2 = VersionSet::new("db", opt.clone(), cache);
+        vs2.recover().unwrap();
+        assert_eq!(
+            vec![
+                (0, "default".to_string()),
+                (2, "index".to_string()),
+                (3, "blobs".to_string())
+            ],
+            vs2.column_families()
+        );
+        assert_eq!(Some(12), vs2.column_family_log_num(3));
+        assert_eq!(10, vs2.log_num);
+        assert_eq!(
+            4,
+            vs2.create_column_family("audit", opt.clone(), 12).unwrap()
+        );
+    }
+
     #[test]
     fn test_version_set_utils() {
         let (v, opt) = make_version();
@@ -1212,7 +1774,7 @@ mod tests {
 
     /// iterator_properties tests that it contains len elements and that they are ordered in
     /// ascending order by cmp.
-    fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Rc<Box<dyn Cmp>>) {
+    fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Arc<Box<dyn Cmp>>) {
         let mut wr = LdbIteratorIter::wrap(&mut it);
         let first = wr.next().unwrap();
         let mut count = 1;
@@ -1227,7 +1789,11 @@ mod tests {
     #[test]
     fn test_version_set_compaction() {
         let (v, opt) = make_version();
-        let mut vs = VersionSet::new("db", opt.clone(), share(TableCache::new("db", opt, 100)));
+        let mut vs = VersionSet::new(
+            "db",
+            opt.clone(),
+            share(TableCache::new("db", opt.clone(), 100)),
+        );
         time_test!();
         vs.add_version(v);
 
@@ -1236,15 +1802,19 @@ mod tests {
             let v = vs.current();
             assert_eq!(
                 0,
-                vs.approximate_offset(&v, LookupKey::new("aaa".as_bytes(), 9000).internal_key())
+                v.borrow()
+                    .approximate_offset(LookupKey::new("aaa".as_bytes(), 9000).internal_key())
             );
             assert_eq!(
                 232,
-                vs.approximate_offset(&v, LookupKey::new("bab".as_bytes(), 9000).internal_key())
+                v.borrow()
+                    .approximate_offset(LookupKey::new("bab".as_bytes(), 9000).internal_key())
             );
+            // Table 5 ends with "fab" @ 13, which is ordered after the lookup key.
             assert_eq!(
-                1134,
-                vs.approximate_offset(&v, LookupKey::new("fab".as_bytes(), 9000).internal_key())
+                917,
+                v.borrow()
+                    .approximate_offset(LookupKey::new("fab".as_bytes(), 9000).internal_key())
             );
         }
         // The following tests reuse the same version set and verify that various compactions work
@@ -1255,7 +1825,7 @@ mod tests {
             let from = LookupKey::new("000".as_bytes(), 1000);
             let to = LookupKey::new("ab".as_bytes(), 1010);
             let c = vs
-                .compact_range(0, from.internal_key(), to.internal_key())
+                .compact_range(0, 0, from.internal_key(), to.internal_key())
                 .unwrap();
             assert_eq!(2, c.inputs[0].len());
             assert_eq!(1, c.inputs[1].len());
@@ -1265,7 +1835,7 @@ mod tests {
             let from = LookupKey::new("000".as_bytes(), 1000);
             let to = LookupKey::new("zzz".as_bytes(), 1010);
             let c = vs
-                .compact_range(0, from.internal_key(), to.internal_key())
+                .compact_range(0, 0, from.internal_key(), to.internal_key())
                 .unwrap();
             assert_eq!(2, c.inputs[0].len());
             assert_eq!(1, c.inputs[1].len());
@@ -1273,14 +1843,14 @@ mod tests {
             iterator_properties(
                 vs.make_input_iterator(&c),
                 12,
-                Rc::new(Box::new(vs.cmp.clone())),
+                Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             );
 
             // Expand input range on higher level.
             let from = LookupKey::new("dab".as_bytes(), 1000);
             let to = LookupKey::new("eab".as_bytes(), 1010);
             let c = vs
-                .compact_range(1, from.internal_key(), to.internal_key())
+                .compact_range(0, 1, from.internal_key(), to.internal_key())
                 .unwrap();
             assert_eq!(3, c.inputs[0].len());
             assert_eq!(1, c.inputs[1].len());
@@ -1288,14 +1858,14 @@ mod tests {
             iterator_properties(
                 vs.make_input_iterator(&c),
                 12,
-                Rc::new(Box::new(vs.cmp.clone())),
+                Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             );
 
             // is_trivial_move
             let from = LookupKey::new("fab".as_bytes(), 1000);
             let to = LookupKey::new("fba".as_bytes(), 1010);
             let mut c = vs
-                .compact_range(2, from.internal_key(), to.internal_key())
+                .compact_range(0, 2, from.internal_key(), to.internal_key())
                 .unwrap();
             // pretend it's not manual
             c.manual = false;
@@ -1306,7 +1876,7 @@ mod tests {
             let to = LookupKey::new("zzz".as_bytes(), 1010);
             let mid = LookupKey::new("abc".as_bytes(), 1010);
             let mut c = vs
-                .compact_range(0, from.internal_key(), to.internal_key())
+                .compact_range(0, 0, from.internal_key(), to.internal_key())
                 .unwrap();
             assert!(!c.should_stop_before(from.internal_key()));
             assert!(!c.should_stop_before(mid.internal_key()));
@@ -1316,7 +1886,7 @@ mod tests {
             let from = LookupKey::new("000".as_bytes(), 1000);
             let to = LookupKey::new("zzz".as_bytes(), 1010);
             let mut c = vs
-                .compact_range(0, from.internal_key(), to.internal_key())
+                .compact_range(0, 0, from.internal_key(), to.internal_key())
                 .unwrap();
             assert!(c.is_base_level_for("aaa".as_bytes()));
             assert!(!c.is_base_level_for("hac".as_bytes()));
@@ -1325,7 +1895,7 @@ mod tests {
             let from = LookupKey::new("000".as_bytes(), 1000);
             let to = LookupKey::new("zzz".as_bytes(), 1010);
             let mut c = vs
-                .compact_range(0, from.internal_key(), to.internal_key())
+                .compact_range(0, 0, from.internal_key(), to.internal_key())
                 .unwrap();
             for inp in &[(0, 0, 1), (0, 1, 2), (1, 0, 3)] {
                 let f = &c.inputs[inp.0][inp.1];
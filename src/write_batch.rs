@@ -1,22 +1,30 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use column_family::{ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_ID};
 use integer_encoding::{FixedInt, VarInt, VarIntWriter};
-use key_types::ValueType;
+use key_types::{append_expiry, parse_tag, ValueType};
 use memtable::MemTable;
 use types::SequenceNumber;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
 
 const SEQNUM_OFFSET: usize = 0;
 const COUNT_OFFSET: usize = 8;
 const HEADER_SIZE: usize = 12;
+// Set in the tag of entries belonging to a column family other than the default one.
+const COLUMN_FAMILY_FLAG: u8 = 0x80;
 
 /// A WriteBatch contains entries to be written to a MemTable (for example) in a compact form.
 ///
 /// The storage format is (with the respective length in bytes)
 ///
 /// [tag: 1, keylen: ~var, key: keylen, vallen: ~var, val: vallen]
+///
+/// Range deletions store the start of the range as key and its (exclusive) end as value. Entries
+/// of a column family other than the default one have the highest bit of their tag set, which is
+/// followed by the column family id (~var).
 pub struct WriteBatch {
     entries: Vec<u8>,
 }
@@ -36,26 +44,85 @@ impl WriteBatch {
     }
 
     /// Adds an entry to a WriteBatch, to be added to the database.
-    #[allow(unused_assignments)]
     pub fn put(&mut self, k: &[u8], v: &[u8]) {
-        self.entries.write(&[ValueType::TypeValue as u8]).unwrap();
-        self.entries.write_varint(k.len()).unwrap();
-        self.entries.write(k).unwrap();
-        self.entries.write_varint(v.len()).unwrap();
-        self.entries.write(v).unwrap();
+        self.add_entry(DEFAULT_COLUMN_FAMILY_ID, ValueType::TypeValue, k, Some(v))
+    }
 
-        let c = self.count();
-        self.set_count(c + 1);
+    /// Adds an entry to a column family.
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, k: &[u8], v: &[u8]) {
+        self.add_entry(cf.id(), ValueType::TypeValue, k, Some(v))
+    }
+
+    /// Adds an entry that expires at `expiry`, a time in microseconds as returned by
+    /// `Env::micros()`.
+    pub fn put_with_expiry(&mut self, k: &[u8], v: &[u8], expiry: u64) {
+        let v = append_expiry(v, expiry);
+        self.add_entry(
+            DEFAULT_COLUMN_FAMILY_ID,
+            ValueType::TypeValueWithTTL,
+            k,
+            Some(&v),
+        )
+    }
+
+    /// Adds an entry that expires at `expiry` to a column family.
+    pub fn put_cf_with_expiry(&mut self, cf: &ColumnFamilyHandle, k: &[u8], v: &[u8], expiry: u64) {
+        let v = append_expiry(v, expiry);
+        self.add_entry(cf.id(), ValueType::TypeValueWithTTL, k, Some(&v))
+    }
+
+    /// Adds a merge operand for an entry, to be combined with its value by the database's merge
+    /// operator.
+    pub fn merge(&mut self, k: &[u8], v: &[u8]) {
+        self.add_entry(DEFAULT_COLUMN_FAMILY_ID, ValueType::TypeMerge, k, Some(v))
+    }
+
+    /// Adds a merge operand for an entry of a column family, to be combined with its value by the
+    /// family's merge operator.
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, k: &[u8], v: &[u8]) {
+        self.add_entry(cf.id(), ValueType::TypeMerge, k, Some(v))
     }
 
     /// Marks an entry to be deleted from the database.
-    #[allow(unused_assignments)]
     pub fn delete(&mut self, k: &[u8]) {
-        self.entries
-            .write(&[ValueType::TypeDeletion as u8])
-            .unwrap();
+        self.add_entry(DEFAULT_COLUMN_FAMILY_ID, ValueType::TypeDeletion, k, None)
+    }
+
+    /// Marks an entry of a column family to be deleted.
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, k: &[u8]) {
+        self.add_entry(cf.id(), ValueType::TypeDeletion, k, None)
+    }
+
+    /// Marks all entries with keys in [start; end) to be deleted from the database.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.add_entry(
+            DEFAULT_COLUMN_FAMILY_ID,
+            ValueType::TypeRangeDeletion,
+            start,
+            Some(end),
+        )
+    }
+
+    /// Marks all entries of a column family with keys in [start; end) to be deleted.
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamilyHandle, start: &[u8], end: &[u8]) {
+        self.add_entry(cf.id(), ValueType::TypeRangeDeletion, start, Some(end))
+    }
+
+    fn add_entry(&mut self, cf: u32, typ: ValueType, k: &[u8], v: Option<&[u8]>) {
+        if cf == DEFAULT_COLUMN_FAMILY_ID {
+            self.entries.write(&[typ as u8]).unwrap();
+        } else {
+            self.entries
+                .write(&[typ as u8 | COLUMN_FAMILY_FLAG])
+                .unwrap();
+            self.entries.write_varint(cf).unwrap();
+        }
         self.entries.write_varint(k.len()).unwrap();
         self.entries.write(k).unwrap();
+        if let Some(v) = v {
+            self.entries.write_varint(v.len()).unwrap();
+            self.entries.write(v).unwrap();
+        }
 
         let c = self.count();
         self.set_count(c + 1);
@@ -94,19 +161,31 @@ impl WriteBatch {
         }
     }
 
-    pub fn insert_into_memtable(&self, mut seq: SequenceNumber, mt: &mut MemTable) {
-        for (k, v) in self.iter() {
-            match v {
-                Some(v_) => mt.add(seq, ValueType::TypeValue, k, v_),
-                None => mt.add(seq, ValueType::TypeDeletion, k, "".as_bytes()),
+    /// column_families returns the ids of the column families this batch writes to.
+    pub fn column_families(&self) -> BTreeSet<u32> {
+        self.iter().map(|(cf, _, _, _)| cf).collect()
+    }
+
+    /// insert_into_memtables adds the entries to the memtables of their column families, keyed
+    /// by column family id. Entries of column families without a memtable are skipped; they
+    /// still take up a sequence number.
+    pub fn insert_into_memtables(
+        &self,
+        mut seq: SequenceNumber,
+        mems: &mut BTreeMap<u32, MemTable>,
+    ) {
+        for (cf, typ, k, v) in self.iter() {
+            if let Some(mt) = mems.get_mut(&cf) {
+                mt.add(seq, typ, k, v.unwrap_or("".as_bytes()));
             }
             seq += 1;
         }
     }
 
-    pub fn encode(mut self, seq: SequenceNumber) -> Vec<u8> {
+    /// encode sets the sequence number of the batch's first entry, and returns its contents.
+    pub fn encode(&mut self, seq: SequenceNumber) -> &[u8] {
         self.set_sequence(seq);
-        self.entries
+        &self.entries
     }
 }
 
@@ -115,39 +194,51 @@ pub struct WriteBatchIter<'a> {
     ix: usize,
 }
 
-/// The iterator also plays the role of the decoder.
+/// The iterator also plays the role of the decoder. It yields the column family id, type, key and
+/// value of each entry; deletions don't have a value.
 impl<'a> Iterator for WriteBatchIter<'a> {
-    type Item = (&'a [u8], Option<&'a [u8]>);
+    type Item = (u32, ValueType, &'a [u8], Option<&'a [u8]>);
     fn next(&mut self) -> Option<Self::Item> {
         if self.ix >= self.batch.entries.len() {
             return None;
         }
 
-        let tag = self.batch.entries[self.ix];
+        let mut tag = self.batch.entries[self.ix];
         self.ix += 1;
 
+        let mut cf = DEFAULT_COLUMN_FAMILY_ID;
+        if tag & COLUMN_FAMILY_FLAG != 0 {
+            tag &= !COLUMN_FAMILY_FLAG;
+            let (id, l) = u32::decode_var(&self.batch.entries[self.ix..]);
+            self.ix += l;
+            cf = id;
+        }
+
         let (klen, l) = usize::decode_var(&self.batch.entries[self.ix..]);
         self.ix += l;
         let k = &self.batch.entries[self.ix..self.ix + klen];
         self.ix += klen;
 
-        if tag == ValueType::TypeValue as u8 {
-            let (vlen, m) = usize::decode_var(&self.batch.entries[self.ix..]);
-            self.ix += m;
-            let v = &self.batch.entries[self.ix..self.ix + vlen];
-            self.ix += vlen;
-
-            return Some((k, Some(v)));
-        } else {
-            return Some((k, None));
+        let (typ, _) = parse_tag(tag as u64);
+        if typ == ValueType::TypeDeletion {
+            return Some((cf, typ, k, None));
         }
+        let (vlen, m) = usize::decode_var(&self.batch.entries[self.ix..]);
+        self.ix += m;
+        let v = &self.batch.entries[self.ix..self.ix + vlen];
+        self.ix += vlen;
+
+        Some((cf, typ, k, Some(v)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cmp::{Cmp, DefaultCmp};
+    use key_types::LookupKey;
     use std::iter::Iterator;
+    use std::sync::Arc;
 
     #[test]
     fn test_write_batch() {
@@ -174,12 +265,19 @@ mod tests {
 
         let mut i = 0;
 
-        for (k, v) in b.iter() {
+        for (cf, typ, k, v) in b.iter() {
+            assert_eq!(0, cf);
             assert_eq!(k, entries[i].0);
 
             match v {
-                None => assert!(entries[i].1.is_empty()),
-                Some(v_) => assert_eq!(v_, entries[i].1),
+                None => {
+                    assert_eq!(ValueType::TypeDeletion, typ);
+                    assert!(entries[i].1.is_empty())
+                }
+                Some(v_) => {
+                    assert_eq!(ValueType::TypeValue, typ);
+                    assert_eq!(v_, entries[i].1)
+                }
             }
 
             i += 1;
@@ -188,4 +286,81 @@ mod tests {
         assert_eq!(i, 5);
         assert_eq!(b.encode(1).len(), 49);
     }
+
+    #[test]
+    fn test_write_batch_delete_range_merge() {
+        let mut b = WriteBatch::new();
+        b.put(b"abc", b"def");
+        b.delete_range(b"a", b"b");
+        b.delete(b"abc");
+        b.merge(b"abc", b"ghi");
+
+        assert_eq!(4, b.count());
+        let entries: Vec<_> = b.iter().collect();
+        assert_eq!(
+            vec![
+                (0, ValueType::TypeValue, &b"abc"[..], Some(&b"def"[..])),
+                (0, ValueType::TypeRangeDeletion, &b"a"[..], Some(&b"b"[..])),
+                (0, ValueType::TypeDeletion, &b"abc"[..], None),
+                (0, ValueType::TypeMerge, &b"abc"[..], Some(&b"ghi"[..])),
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_write_batch_column_families() {
+        let cf = ColumnFamilyHandle::new(300, "blobs");
+        let mut b = WriteBatch::new();
+        b.put(b"abc", b"def");
+        b.put_cf(&cf, b"abc", b"xyz");
+        b.delete_cf(&cf, b"def");
+        b.merge_cf(&cf, b"ghi", b"1");
+        b.delete_range_cf(&cf, b"a", b"b");
+
+        assert_eq!(5, b.count());
+        let entries: Vec<_> = b.iter().collect();
+        assert_eq!(
+            vec![
+                (0, ValueType::TypeValue, &b"abc"[..], Some(&b"def"[..])),
+                (300, ValueType::TypeValue, &b"abc"[..], Some(&b"xyz"[..])),
+                (300, ValueType::TypeDeletion, &b"def"[..], None),
+                (300, ValueType::TypeMerge, &b"ghi"[..], Some(&b"1"[..])),
+                (
+                    300,
+                    ValueType::TypeRangeDeletion,
+                    &b"a"[..],
+                    Some(&b"b"[..])
+                ),
+            ],
+            entries
+        );
+        assert_eq!(
+            vec![0, 300],
+            b.column_families().into_iter().collect::<Vec<_>>()
+        );
+
+        // Entries are routed to the memtables of their families.
+        let cmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(DefaultCmp));
+        let mut mems = BTreeMap::new();
+        mems.insert(0, MemTable::new(cmp.clone()));
+        mems.insert(300, MemTable::new(cmp.clone()));
+        b.insert_into_memtables(10, &mut mems);
+        assert_eq!(1, mems[&0].len());
+        assert_eq!(4, mems[&300].len());
+        let mut ops = vec![];
+        // The range deletion (seq 14) covers abc in "blobs", but not in the default family.
+        assert_eq!(
+            (Some(b"xyz".to_vec()), false),
+            mems[&300].get(&LookupKey::new(b"abc", 13), 0, &mut ops)
+        );
+        assert_eq!(
+            (None, true),
+            mems[&300].get(&LookupKey::new(b"abc", 20), 0, &mut ops)
+        );
+        assert_eq!(
+            (Some(b"def".to_vec()), false),
+            mems[&0].get(&LookupKey::new(b"abc", 20), 0, &mut ops)
+        );
+    }
 }
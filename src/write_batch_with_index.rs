@@ -0,0 +1,483 @@
+//! A WriteBatchWithIndex is a WriteBatch that keeps an index of its entries, so that they can be
+//! read before the batch is written to the database. This lets applications observe their own
+//! uncommitted writes, either by looking up single keys or by iterating over the batch and the
+//! database at once.
+
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use cmp::{Cmp, InternalKeyCmp};
+use db_impl::DB;
+use db_iter::DBIterator;
+use error::{Result, Status};
+use key_types::{parse_internal_key, LookupKey, ValueType};
+use merge_operator::{self, BoxedMergeOperator};
+use merging_iter::MergingIter;
+use options::{Options, ReadOptions};
+use skipmap::SkipMap;
+use types::{Direction, LdbIterator, SequenceNumber, MAX_SEQUENCE_NUMBER};
+use write_batch::WriteBatch;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// WriteBatchWithIndex buffers writes to the default column family like a WriteBatch. Its entries
+/// are additionally indexed in a SkipMap by their key and the order in which they were added.
+///
+/// The options passed to `new()` should be those of the database the batch will be written to:
+/// their comparator orders the index, and their merge operator is applied to merge operands.
+pub struct WriteBatchWithIndex {
+    cmp: Arc<Box<dyn Cmp>>,
+    merge_operator: Option<BoxedMergeOperator>,
+    batch: WriteBatch,
+    // Maps internal keys to values. Entries are numbered from 1 in the order they are added, so
+    // that later writes to a key shadow earlier ones. Entries read from a database are treated as
+    // having the sequence number 0, i.e. as older than all entries of the batch.
+    index: SkipMap,
+    seq: SequenceNumber,
+}
+
+impl WriteBatchWithIndex {
+    pub fn new(opt: &Options) -> WriteBatchWithIndex {
+        WriteBatchWithIndex {
+            cmp: opt.cmp.clone(),
+            merge_operator: opt.merge_operator.clone(),
+            batch: WriteBatch::new(),
+            index: SkipMap::new(Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())))),
+            seq: 0,
+        }
+    }
+
+    /// Adds an entry to the batch.
+    pub fn put(&mut self, k: &[u8], v: &[u8]) {
+        self.batch.put(k, v);
+        self.add_to_index(ValueType::TypeValue, k, v);
+    }
+
+    /// Marks an entry to be deleted.
+    pub fn delete(&mut self, k: &[u8]) {
+        self.batch.delete(k);
+        self.add_to_index(ValueType::TypeDeletion, k, &[]);
+    }
+
+    /// Adds a merge operand for an entry.
+    pub fn merge(&mut self, k: &[u8], v: &[u8]) {
+        self.batch.merge(k, v);
+        self.add_to_index(ValueType::TypeMerge, k, v);
+    }
+
+    fn add_to_index(&mut self, typ: ValueType, k: &[u8], v: &[u8]) {
+        self.seq += 1;
+        let key = LookupKey::new_full(k, self.seq, typ);
+        self.index.insert(key.internal_key().to_vec(), v.to_vec());
+    }
+
+    /// Returns how many operations are in the batch.
+    pub fn count(&self) -> u32 {
+        self.batch.count()
+    }
+
+    /// Clears the batch and its index.
+    pub fn clear(&mut self) {
+        self.batch = WriteBatch::new();
+        self.index = SkipMap::new(Arc::new(Box::new(InternalKeyCmp(self.cmp.clone()))));
+        self.seq = 0;
+    }
+
+    /// into_write_batch returns the underlying WriteBatch, which can be passed to `DB::write()`.
+    pub fn into_write_batch(self) -> WriteBatch {
+        self.batch
+    }
+
+    /// get_from_batch_and_db reads the value of a key as written by this batch, falling back to
+    /// the database if the batch doesn't contain it. Merge operands in the batch are applied to
+    /// the value found in the database.
+    pub fn get_from_batch_and_db(&self, db: &DB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_from_batch_and_db_opt(db, &ReadOptions::default(), key)
+    }
+
+    /// get_from_batch_and_db_opt works like get_from_batch_and_db(), reading the database as
+    /// specified by the supplied ReadOptions.
+    pub fn get_from_batch_and_db_opt(
+        &self,
+        db: &DB,
+        ro: &ReadOptions,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut entries = vec![];
+        let mut it = self.index.iter();
+        it.seek(LookupKey::new(key, MAX_SEQUENCE_NUMBER).internal_key());
+        let (mut k, mut v) = (vec![], vec![]);
+        while it.current(&mut k, &mut v) {
+            let (typ, _, ukey) = parse_internal_key(&k);
+            if self.cmp.cmp(ukey, key) != Ordering::Equal {
+                break;
+            }
+            entries.push((typ, v.clone()));
+            it.advance();
+        }
+        if entries.iter().all(|e| e.0 == ValueType::TypeMerge) {
+            if let Some(v) = db.get_opt(ro, key)? {
+                entries.push((ValueType::TypeValue, v));
+            }
+        }
+        resolve_entries(self.merge_operator.as_ref(), key, &entries)
+    }
+
+    /// iter_with_base returns an iterator over the entries of this batch merged over those of
+    /// `base`. The iterator reflects entries added to the batch after its creation.
+    pub fn iter_with_base(&self, base: DBIterator) -> WriteBatchWithIndexIter {
+        let icmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(InternalKeyCmp(self.cmp.clone())));
+        let iters: Vec<Box<dyn LdbIterator>> = vec![
+            Box::new(self.index.iter()),
+            Box::new(BaseIter { iter: base }),
+        ];
+        WriteBatchWithIndexIter {
+            cmp: self.cmp.clone(),
+            merge_operator: self.merge_operator.clone(),
+            iter: MergingIter::new(icmp, iters),
+            dir: Direction::Forward,
+            valid: false,
+            status: None,
+            key: vec![],
+            val: vec![],
+            keybuf: vec![],
+            valbuf: vec![],
+            entries: vec![],
+        }
+    }
+}
+
+/// resolve_entries computes the value of a key from its entries, ordered from newest to oldest.
+/// It returns None if the key has been deleted or doesn't exist.
+fn resolve_entries(
+    op: Option<&BoxedMergeOperator>,
+    key: &[u8],
+    entries: &[(ValueType, Vec<u8>)],
+) -> Result<Option<Vec<u8>>> {
+    let mut operands = vec![];
+    let mut base = None;
+    for &(typ, ref val) in entries {
+        match typ {
+            ValueType::TypeMerge => operands.push(val.clone()),
+            ValueType::TypeValue => {
+                base = Some(val.as_slice());
+                break;
+            }
+            _ => break,
+        }
+    }
+    if operands.is_empty() {
+        return Ok(base.map(|b| b.to_vec()));
+    }
+    Ok(Some(merge_operator::full_merge(op, key, base, &operands)?))
+}
+
+/// BaseIter presents the entries of a DBIterator as internal keys with the sequence number 0, so
+/// that they can be merged with the entries of a batch.
+struct BaseIter {
+    iter: DBIterator,
+}
+
+impl LdbIterator for BaseIter {
+    fn advance(&mut self) -> bool {
+        self.iter.advance()
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if !self.iter.current(key, val) {
+            return false;
+        }
+        let ikey = LookupKey::new_full(key, 0, ValueType::TypeValue);
+        key.clear();
+        key.extend_from_slice(ikey.internal_key());
+        true
+    }
+    fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(parse_internal_key(key).2)
+    }
+    fn reset(&mut self) {
+        self.iter.reset()
+    }
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+    fn prev(&mut self) -> bool {
+        self.iter.prev()
+    }
+}
+
+/// WriteBatchWithIndexIter iterates over the entries of a WriteBatchWithIndex and a DBIterator,
+/// as obtained from `WriteBatchWithIndex::iter_with_base()`. Entries of the batch take
+/// precedence over those of the database.
+pub struct WriteBatchWithIndexIter {
+    cmp: Arc<Box<dyn Cmp>>,
+    merge_operator: Option<BoxedMergeOperator>,
+    // Merges the batch's index and the database entries, ordered by internal key. When moving
+    // forward, iter is positioned after the entries of the current key; when moving backward, it
+    // is positioned before them.
+    iter: MergingIter,
+    dir: Direction,
+    valid: bool,
+    // The first error encountered while merging operands.
+    status: Option<Status>,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    keybuf: Vec<u8>,
+    valbuf: Vec<u8>,
+    // Entries of the key being assembled.
+    entries: Vec<(ValueType, Vec<u8>)>,
+}
+
+impl WriteBatchWithIndexIter {
+    /// status returns the first error encountered by this iterator, e.g. because a merge operand
+    /// was found without a merge operator being configured. The iterator becomes invalid when an
+    /// error occurs.
+    pub fn status(&self) -> Result<()> {
+        match self.status {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// collect_entries reads all entries of the user key at the current position of iter into
+    /// self.entries, moving iter past them in the current direction.
+    fn collect_entries(&mut self) {
+        self.entries.clear();
+        self.iter.current(&mut self.keybuf, &mut self.valbuf);
+        self.key.clear();
+        self.key
+            .extend_from_slice(parse_internal_key(&self.keybuf).2);
+        loop {
+            let typ = {
+                let (typ, _, ukey) = parse_internal_key(&self.keybuf);
+                if self.cmp.cmp(ukey, &self.key) != Ordering::Equal {
+                    break;
+                }
+                typ
+            };
+            self.entries.push((typ, self.valbuf.clone()));
+            if self.dir == Direction::Forward {
+                self.iter.advance();
+            } else {
+                self.iter.prev();
+            }
+            if !self.iter.current(&mut self.keybuf, &mut self.valbuf) {
+                break;
+            }
+        }
+        if self.dir == Direction::Reverse {
+            // Entries are resolved from newest to oldest.
+            self.entries.reverse();
+        }
+    }
+
+    /// find_entry moves iter in the current direction until a key that hasn't been deleted is
+    /// found.
+    fn find_entry(&mut self) -> bool {
+        while self.iter.valid() {
+            self.collect_entries();
+            match resolve_entries(self.merge_operator.as_ref(), &self.key, &self.entries) {
+                Ok(Some(val)) => {
+                    self.val = val;
+                    self.valid = true;
+                    return true;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.status = Some(e);
+                    break;
+                }
+            }
+        }
+        self.valid = false;
+        self.key.clear();
+        self.val.clear();
+        false
+    }
+}
+
+impl LdbIterator for WriteBatchWithIndexIter {
+    fn advance(&mut self) -> bool {
+        if self.status.is_some() {
+            return false;
+        }
+        if !self.valid {
+            self.seek_to_first();
+            return self.valid;
+        }
+        if self.dir == Direction::Reverse {
+            // Move iter past the entries of the current key.
+            let key = self.key.clone();
+            self.iter
+                .seek(LookupKey::new(&key, MAX_SEQUENCE_NUMBER).internal_key());
+            while self.iter.current(&mut self.keybuf, &mut self.valbuf)
+                && self.cmp.cmp(parse_internal_key(&self.keybuf).2, &key) == Ordering::Equal
+            {
+                self.iter.advance();
+            }
+            self.dir = Direction::Forward;
+        }
+        self.find_entry()
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if !self.valid {
+            return false;
+        }
+        key.clear();
+        key.extend_from_slice(&self.key);
+        val.clear();
+        val.extend_from_slice(&self.val);
+        true
+    }
+    fn seek(&mut self, to: &[u8]) {
+        if self.status.is_some() {
+            return;
+        }
+        self.iter
+            .seek(LookupKey::new(to, MAX_SEQUENCE_NUMBER).internal_key());
+        self.dir = Direction::Forward;
+        self.find_entry();
+    }
+    fn seek_to_first(&mut self) {
+        if self.status.is_some() {
+            return;
+        }
+        self.iter.reset();
+        self.iter.advance();
+        self.dir = Direction::Forward;
+        self.find_entry();
+    }
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.dir = Direction::Forward;
+        self.valid = false;
+        self.key.clear();
+        self.val.clear();
+    }
+    fn valid(&self) -> bool {
+        self.valid
+    }
+    fn prev(&mut self) -> bool {
+        if !self.valid {
+            return false;
+        }
+        if self.dir == Direction::Forward {
+            // Move iter before the entries of the current key.
+            let key = self.key.clone();
+            self.iter
+                .seek(LookupKey::new(&key, MAX_SEQUENCE_NUMBER).internal_key());
+            self.iter.prev();
+            self.dir = Direction::Reverse;
+        }
+        self.find_entry()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merge_operator::testutil::append_operator;
+    use options;
+    use test_util::{test_iterator_properties, LdbIteratorIter};
+    use types::current_key_val;
+
+    fn build_db_and_batch() -> (DB, WriteBatchWithIndex) {
+        let mut opt = options::for_test();
+        opt.merge_operator = Some(append_operator());
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"1").unwrap();
+        db.put(b"abd", b"2").unwrap();
+        db.put(b"abe", b"3").unwrap();
+        db.put(b"abf", b"4").unwrap();
+
+        let mut wb = WriteBatchWithIndex::new(&opt);
+        wb.put(b"abc", b"5");
+        wb.delete(b"abd");
+        wb.merge(b"abe", b"6");
+        wb.put(b"abg", b"7");
+        wb.merge(b"abg", b"8");
+        wb.delete(b"abh");
+        (db, wb)
+    }
+
+    #[test]
+    fn test_write_batch_with_index_get() {
+        let (db, mut wb) = build_db_and_batch();
+        assert_eq!(6, wb.count());
+
+        let get = |wb: &WriteBatchWithIndex, k: &[u8]| wb.get_from_batch_and_db(&db, k).unwrap();
+        assert_eq!(Some(b"5".to_vec()), get(&wb, b"abc"));
+        assert_eq!(None, get(&wb, b"abd"));
+        assert_eq!(Some(b"3,6".to_vec()), get(&wb, b"abe"));
+        assert_eq!(Some(b"4".to_vec()), get(&wb, b"abf"));
+        assert_eq!(Some(b"7,8".to_vec()), get(&wb, b"abg"));
+        assert_eq!(None, get(&wb, b"abh"));
+        assert_eq!(None, get(&wb, b"xyz"));
+
+        // Later writes shadow earlier ones.
+        wb.put(b"abd", b"9");
+        wb.delete(b"abc");
+        assert_eq!(Some(b"9".to_vec()), get(&wb, b"abd"));
+        assert_eq!(None, get(&wb, b"abc"));
+
+        // Nothing is written until the batch is.
+        assert_eq!(Some(b"1".to_vec()), db.get(b"abc"));
+        db.write(wb.into_write_batch(), false).unwrap();
+        assert_eq!(None, db.get(b"abc"));
+        assert_eq!(Some(b"9".to_vec()), db.get(b"abd"));
+        assert_eq!(Some(b"3,6".to_vec()), db.get(b"abe"));
+        assert_eq!(Some(b"7,8".to_vec()), db.get(b"abg"));
+    }
+
+    #[test]
+    fn test_write_batch_with_index_iter() {
+        let (db, wb) = build_db_and_batch();
+        let mut it = wb.iter_with_base(db.new_iter().unwrap());
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut it).collect();
+        assert_eq!(
+            vec![
+                (b"abc".to_vec(), b"5".to_vec()),
+                (b"abe".to_vec(), b"3,6".to_vec()),
+                (b"abf".to_vec(), b"4".to_vec()),
+                (b"abg".to_vec(), b"7,8".to_vec()),
+            ],
+            entries
+        );
+        assert!(it.status().is_ok());
+
+        it.seek(b"abd");
+        assert_eq!(
+            Some((b"abe".to_vec(), b"3,6".to_vec())),
+            current_key_val(&it)
+        );
+        assert!(it.prev());
+        assert_eq!(Some((b"abc".to_vec(), b"5".to_vec())), current_key_val(&it));
+        assert!(it.advance());
+        assert!(it.advance());
+        assert_eq!(Some((b"abf".to_vec(), b"4".to_vec())), current_key_val(&it));
+
+        test_iterator_properties(wb.iter_with_base(db.new_iter().unwrap()));
+    }
+
+    #[test]
+    fn test_write_batch_with_index_iter_batch_only() {
+        let opt = options::for_test();
+        let db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"abc", b"1").unwrap();
+        let mut wb = WriteBatchWithIndex::new(&opt);
+        wb.put(b"aaa", b"2");
+        wb.put(b"abd", b"3");
+        wb.put(b"abe", b"4");
+        wb.merge(b"abf", b"5");
+
+        let mut it = wb.iter_with_base(db.new_iter().unwrap());
+        assert!(it.advance());
+        assert!(it.advance());
+        assert!(it.advance());
+        assert!(it.advance());
+        assert_eq!(Some((b"abe".to_vec(), b"4".to_vec())), current_key_val(&it));
+        assert!(!it.advance());
+        // Without a merge operator, the operand can't be applied.
+        assert!(it.status().is_err());
+    }
+}